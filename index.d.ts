@@ -37,8 +37,11 @@ export declare class DicomFile {
    * without loading the entire dataset. Useful for quickly validating files
    * or extracting SOPInstanceUID without a full file open.
    *
+   * Files without file meta group (as written by StoreScp unless `storeWithFileMeta`
+   * is enabled) are read in full and their transfer syntax is inferred from the content.
+   *
    * @param path - Absolute or relative path to the DICOM file
-   * @returns DicomFileMeta containing SOP Class UID and SOP Instance UID
+   * @returns DicomFileMeta containing SOP Class UID, SOP Instance UID and Transfer Syntax UID
    * @throws Error if the file is not a valid DICOM file or DICOMDIR
    *
    * @example
@@ -60,7 +63,8 @@ export declare class DicomFile {
    * for both backends.
    *
    * **File Format:** Handles DICOM files both with and without file meta header.
-   * Files with meta header (standard .dcm files) and dataset-only files are both supported.
+   * Files with meta header (standard .dcm files) and dataset-only files are both supported;
+   * the transfer syntax of dataset-only files is inferred from their content.
    *
    * @param path - Path to the DICOM file (filesystem path when using Filesystem backend, or S3 key when using S3 backend)
    * @returns Success message if the file was opened successfully
@@ -534,6 +538,345 @@ export declare class DicomFile {
   close(): void
 }
 
+/**
+ * Embedded SQLite index of stored DICOM instances.
+ *
+ * The index holds patient, study, series and instance attributes together with the
+ * storage location of every instance. It is kept up to date by `StoreScp` (option
+ * `indexPath`) and used directly by `QidoServer` and `WadoServer` when they are
+ * configured with the same `indexPath`.
+ *
+ * An index for an existing archive folder or bucket can be created with `rebuild()`.
+ *
+ * @example
+ * ```typescript
+ * import { DicomIndex } from '@nuxthealth/node-dicom';
+ *
+ * const index = new DicomIndex('./archive/index.sqlite');
+ * const result = await index.rebuild({ backend: 'Filesystem', outDir: './archive' });
+ * console.log(`Indexed ${result.indexed} instances`);
+ * console.log(await index.stats());
+ * ```
+ */
+export declare class DicomIndex {
+  /** Open or create the index file at `path` */
+  constructor(path: string)
+  /** * Rebuild the index from the instances stored in a folder or bucket.
+   *
+   * Every `.dcm` object below the storage root is read up to its pixel data, both complete
+   * DICOM files and the dataset-only files written by `StoreScp`. Their modification time is indexed as the time
+   * they were received, so `ReceivedTime` retention keeps their age. The result then replaces
+   * the current entries in one transaction; entries a StoreScp adds during the rebuild are kept.
+   * Objects in the `quarantine/` area are ignored.
+   *
+   * @param target - Storage to scan (same shape as a StoreScp storage target)
+   * @returns Number of indexed and skipped objects
+   *
+   * @example
+   * ```typescript
+   * await index.rebuild({
+   *   backend: 'S3',
+   *   s3Config: { bucket: 'dicom', accessKey: 'minioadmin', secretKey: 'minioadmin', endpoint: 'http://localhost:9000' }
+   * });
+   * ```
+   */
+  rebuild(target: StorageTarget): Promise<DicomIndexRebuildResult>
+  /** Number of patients, studies, series and instances in the index */
+  stats(): Promise<DicomIndexStats>
+  /** Search for Studies, returns a DICOM JSON array string (as expected by QIDO-RS handlers) */
+  searchStudies(query: SearchForStudiesQuery): Promise<string>
+  /** Search for Series in a study, returns a DICOM JSON array string */
+  searchSeries(query: SearchForSeriesQuery): Promise<string>
+  /** Search for Instances in a study, returns a DICOM JSON array string */
+  searchStudyInstances(query: SearchForStudyInstancesQuery): Promise<string>
+  /** Search for Instances in a series, returns a DICOM JSON array string */
+  searchSeriesInstances(query: SearchForSeriesInstancesQuery): Promise<string>
+}
+
+/**
+ * Direct access to the storage used by `StoreScp`, `WadoServer`, `DicomFile` and `StoreScu`.
+ *
+ * The same API works on a local folder and on an S3 bucket. Keys are `/` separated paths
+ * relative to the root directory or the bucket, e.g. the `{study}/{series}/{sop}.dcm`
+ * keys written by `StoreScp`.
+ *
+ * @example
+ * ```typescript
+ * import { DicomStore } from '@nuxthealth/node-dicom';
+ *
+ * const store = new DicomStore({ backend: 'Filesystem', rootDir: './archive' });
+ * for (const key of await store.list('1.2.3.4')) {
+ *   const meta = await store.metadata(key);
+ *   console.log(key, meta.size);
+ * }
+ *
+ * // Read only the first 132 bytes (preamble and DICM magic)
+ * const header = await store.getRange('1.2.3.4/1.2.3.4.5/1.2.3.4.5.6.dcm', 0, 132);
+ * ```
+ */
+export declare class DicomStore {
+  /** * Create a store for a folder or bucket.
+   *
+   * @param storageConfig - Storage configuration (defaults to the filesystem relative to the working directory)
+   *
+   * @example
+   * ```typescript
+   * const store = new DicomStore({
+   *   backend: 'S3',
+   *   s3Config: { bucket: 'dicom', accessKey: 'minioadmin', secretKey: 'minioadmin', endpoint: 'http://localhost:9000' }
+   * });
+   * ```
+   */
+  constructor(storageConfig?: StorageConfig | undefined | null)
+  /** Read a complete object */
+  get(key: string): Promise<Buffer>
+  /** * Read a byte range of an object.
+   *
+   * @param key - Object key
+   * @param start - Offset of the first byte
+   * @param end - Offset after the last byte (exclusive), reads to the end of the object if omitted
+   */
+  getRange(key: string, start: number, end?: number | undefined | null): Promise<Buffer>
+  /** Write an object, replacing an existing one */
+  put(key: string, data: Buffer): Promise<void>
+  /** List all object keys below a prefix (folder), or all objects if no prefix is given */
+  list(prefix?: string | undefined | null): Promise<Array<string>>
+  /** Delete an object */
+  delete(key: string): Promise<void>
+  /** Check whether an object exists */
+  exists(key: string): Promise<boolean>
+  /** * Rewrite all objects below a prefix with the current compression and encryption settings.
+   *
+   * Use this after rotating keys (a new `activeKeyId`) to re-encrypt existing objects,
+   * or after enabling compression. Objects are decoded with whatever key they were
+   * written with, so all old keys must still be available.
+   *
+   * @param prefix - Folder to rewrite (all objects if omitted)
+   * @returns Number of rewritten objects
+   *
+   * @example
+   * ```typescript
+   * const store = new DicomStore({
+   *   backend: 'Filesystem',
+   *   rootDir: './archive',
+   *   filesystemOptions: { encryptionKeyFile: './keys.json', compressionLevel: 3 }
+   * });
+   * const count = await store.reencode();
+   * ```
+   */
+  reencode(prefix?: string | undefined | null): Promise<number>
+  /** Size, modification time and (for S3) content type and ETag of an object */
+  metadata(key: string): Promise<StorageObjectMetadata>
+}
+
+/** * DICOM C-FIND SCU (Service Class User) Client.
+ *
+ * Queries a remote PACS at the patient, study, series or image level using the
+ * Patient Root or Study Root information model. Matches are returned as DICOM JSON
+ * and can be streamed through the `onMatch` callback while the query is running.
+ *
+ * @example
+ * ```typescript
+ * import { FindScu } from '@nuxthealth/node-dicom';
+ *
+ * const scu = new FindScu({ addr: 'PACS@192.168.1.100:104', callingAeTitle: 'MY-SCU' });
+ *
+ * const result = await scu.find({
+ *   level: 'Series',
+ *   keys: { StudyInstanceUID: '1.2.3.4', SeriesInstanceUID: '', Modality: '', NumberOfSeriesRelatedInstances: '' }
+ * }, {
+ *   onMatch: (err, event) => console.log('Match', event.data?.index)
+ * });
+ *
+ * for (const series of JSON.parse(result.matches)) {
+ *   console.log(series['0020000E'].Value[0]);
+ * }
+ * ```
+ */
+export declare class FindScu {
+  /** * Create a new DICOM C-FIND SCU client instance.
+   *
+   * @param options - Client configuration options
+   * @returns New FindScu instance
+   *
+   * @example
+   * ```typescript
+   * const scu = new FindScu({
+   *   addr: 'PACS@192.168.1.100:104',
+   *   callingAeTitle: 'WORKSTATION-01',
+   *   username: 'dicom-user',
+   *   password: 'password123'
+   * });
+   * ```
+   */
+  constructor(options: FindScuOptions)
+  /** * Run a C-FIND query.
+   *
+   * Opens an association, sends the query and collects all matches. Each match is also
+   * passed to `onMatch` as soon as it arrives. The query stops early with a C-CANCEL
+   * when `limit` is reached or `cancel()` is called.
+   *
+   * @param query - Query level, information model, keys and limit
+   * @param callbacks - Optional callbacks
+   * @returns Matches and final status
+   *
+   * @example
+   * ```typescript
+   * const result = await scu.find({
+   *   level: 'Study',
+   *   keys: { PatientName: 'DOE^*', StudyInstanceUID: '', StudyDate: '' },
+   *   limit: 100
+   * });
+   * console.log(`${result.count} studies${result.cancelled ? ' (truncated)' : ''}`);
+   * ```
+   */
+  find(query: FindQuery, callbacks?: { onMatch?: (err: Error | null, event: FindMatchEvent) => void }): Promise<FindResult>
+  /** * Cancel the running query.
+   *
+   * Sends a C-CANCEL to the remote SCP; `find()` resolves with the matches received so far
+   * and `cancelled: true`.
+   *
+   * @example
+   * ```typescript
+   * const pending = scu.find(query, {
+   *   onMatch: (err, event) => {
+   *     if (event.data && event.data.index >= 9) scu.cancel();
+   *   }
+   * });
+   * ```
+   */
+  cancel(): void
+}
+
+/** * DICOM C-GET SCU (Service Class User) Client.
+ *
+ * Retrieves a study, series or list of instances over a single association. Unlike C-MOVE,
+ * the PACS sends the instances back as C-STORE sub-operations on the same association, so
+ * no port has to be opened and the client does not need to be known in the PACS AE table.
+ * The storage SOP classes are proposed with the SCP role (SCP/SCU Role Selection).
+ *
+ * @example
+ * ```typescript
+ * import { GetScu } from '@nuxthealth/node-dicom';
+ *
+ * const scu = new GetScu({
+ *   addr: 'PACS@192.168.1.100:104',
+ *   callingAeTitle: 'MY-SCU',
+ *   storage: { backend: 'Filesystem', rootDir: './retrieved' }
+ * });
+ *
+ * const result = await scu.get({ studyInstanceUid: '1.2.3.4' }, {
+ *   onProgress: (err, event) => console.log(`${event.data?.completed} done, ${event.data?.remaining} remaining`)
+ * });
+ * console.log(result.instances.map(i => i.file));
+ * ```
+ */
+export declare class GetScu {
+  /** * Create a new DICOM C-GET SCU client instance.
+   *
+   * @param options - Client configuration options
+   * @returns New GetScu instance
+   *
+   * @example
+   * ```typescript
+   * // Receive CT and MR instances in memory, accepting JPEG Baseline as well
+   * const scu = new GetScu({
+   *   addr: 'PACS@192.168.1.100:104',
+   *   sopClasses: ['CTImageStorage', 'MRImageStorage'],
+   *   transferSyntaxes: ['ExplicitVRLittleEndian', 'ImplicitVRLittleEndian', 'JPEGBaseline']
+   * });
+   * ```
+   */
+  constructor(options: GetScuOptions)
+  /** * Send a C-GET request and receive the instances.
+   *
+   * Resolves when the PACS sends its final response. Sub-operation counts are reported
+   * through `onProgress`; every received instance is reported through `onInstanceReceived`.
+   *
+   * @param request - Study, series or instances to retrieve
+   * @param callbacks - Optional callbacks
+   * @returns Sub-operation counts and received instances
+   *
+   * @example
+   * ```typescript
+   * const result = await scu.get({
+   *   studyInstanceUid: '1.2.3.4',
+   *   seriesInstanceUid: '1.2.3.4.5'
+   * }, {
+   *   onInstanceReceived: (err, event) => console.log('Received', event.data?.sopInstanceUid)
+   * });
+   * for (const instance of result.instances) {
+   *   await fs.promises.writeFile(`${instance.sopInstanceUid}.dcm`, instance.buffer!);
+   * }
+   * ```
+   */
+  get(request: RetrieveRequest, callbacks?: { onProgress?: (err: Error | null, event: RetrieveProgressEvent) => void, onInstanceReceived?: (err: Error | null, event: InstanceReceivedEvent) => void }): Promise<RetrieveResult>
+}
+
+/** * DICOM C-MOVE SCU (Service Class User) Client.
+ *
+ * Asks a remote PACS to send a study, series or list of instances to a move destination.
+ * With `receiver`, an ephemeral C-STORE SCP is started on the given port and AE title for
+ * the duration of the request, and the received instances are returned as files or Buffers.
+ * The PACS must know the receiver AE title with this host and port.
+ *
+ * @example
+ * ```typescript
+ * import { MoveScu } from '@nuxthealth/node-dicom';
+ *
+ * const scu = new MoveScu({
+ *   addr: 'PACS@192.168.1.100:104',
+ *   callingAeTitle: 'MY-SCU',
+ *   receiver: { port: 11113, aeTitle: 'MY-SCU', outDir: './retrieved' }
+ * });
+ *
+ * const result = await scu.move({ studyInstanceUid: '1.2.3.4' }, {
+ *   onProgress: (err, event) => console.log(`${event.data?.completed} done, ${event.data?.remaining} remaining`)
+ * });
+ * console.log(result.instances.map(i => i.file));
+ * ```
+ */
+export declare class MoveScu {
+  /** * Create a new DICOM C-MOVE SCU client instance.
+   *
+   * @param options - Client configuration options
+   * @returns New MoveScu instance
+   *
+   * @example
+   * ```typescript
+   * // Move to another AE known by the PACS
+   * const scu = new MoveScu({ addr: 'PACS@192.168.1.100:104', destination: 'ARCHIVE' });
+   *
+   * // Collect the instances in memory
+   * const scu2 = new MoveScu({ addr: 'PACS@192.168.1.100:104', receiver: { port: 11113, aeTitle: 'NODE-MOVE' } });
+   * ```
+   */
+  constructor(options: MoveScuOptions)
+  /** * Send a C-MOVE request and wait for all sub-operations.
+   *
+   * Resolves when the PACS sends its final response. Sub-operation counts are reported
+   * through `onProgress`; every instance collected by the internal receiver is reported
+   * through `onInstanceReceived`.
+   *
+   * @param request - Study, series or instances to move
+   * @param callbacks - Optional callbacks
+   * @returns Sub-operation counts and received instances
+   *
+   * @example
+   * ```typescript
+   * const result = await scu.move({
+   *   studyInstanceUid: '1.2.3.4',
+   *   seriesInstanceUid: '1.2.3.4.5',
+   *   sopInstanceUids: ['1.2.3.4.5.6', '1.2.3.4.5.7']
+   * }, {
+   *   onInstanceReceived: (err, event) => console.log('Received', event.data?.sopInstanceUid)
+   * });
+   * if (result.failed > 0) console.warn('Failed:', result.failedSopInstanceUids);
+   * ```
+   */
+  move(request: RetrieveRequest, callbacks?: { onProgress?: (err: Error | null, event: RetrieveProgressEvent) => void, onInstanceReceived?: (err: Error | null, event: InstanceReceivedEvent) => void }): Promise<RetrieveResult>
+}
+
 /** Builder for creating Instance-level DICOM JSON responses */
 export declare class QidoInstanceResult {
   constructor()
@@ -800,6 +1143,79 @@ export declare class StoreScp {
   /** * Register callback for error events
    */
   onError(handler: ((err: Error | null, arg: ScpEventData) => void)): void
+  /** * Register callback for quarantine events
+   *
+   * Called when a received instance could not be parsed, is missing a required UID
+   * or was rejected by `onBeforeStore`. The raw data set is kept in the quarantine
+   * area of the storage backend and the SCU receives a failure status (C000H).
+   * `data.quarantine` holds the sidecar record of the quarantined item.
+   */
+  onQuarantined(handler: ((err: Error | null, arg: ScpEventData) => void)): void
+  /** * Register callback for study purged events
+   *
+   * Called for every study deleted by the retention policy, or selected for deletion
+   * in dry-run mode. `data.purged` describes the study and the rule that selected it.
+   */
+  onStudyPurged(handler: ((err: Error | null, arg: ScpEventData) => void)): void
+  /** * Register callback for replication events
+   *
+   * Called when an instance was written to a best-effort storage target.
+   * `data.locations` holds the location of the replica; failed replications
+   * are reported through `onError`.
+   */
+  onReplicated(handler: ((err: Error | null, arg: ScpEventData) => void)): void
+  /** * Apply the configured retention policy once, independent of the background sweeper.
+   *
+   * @param dryRun - Only report the studies that would be purged (default: `dryRun` of the policy)
+   * @returns Purged (or selected) studies in the order they were processed; studies that could not be deleted are not included
+   * @throws Error if no retention policy or no `indexPath` is configured
+   *
+   * @example
+   * ```typescript
+   * const candidates = await scp.applyRetention(true);
+   * const bytes = candidates.reduce((sum, s) => sum + s.size, 0);
+   * console.log(`${candidates.length} studies (${bytes} bytes) would be purged`);
+   * ```
+   */
+  applyRetention(dryRun?: boolean | undefined | null): Promise<Array<PurgedStudy>>
+  /** * List all items in the quarantine area of the configured storage backend.
+   *
+   * @returns Sidecar records of all quarantined items, oldest first
+   *
+   * @example
+   * ```typescript
+   * const items = await scp.listQuarantined();
+   * for (const item of items) {
+   *   console.log(item.id, item.callingAeTitle, item.error);
+   * }
+   * ```
+   */
+  listQuarantined(): Promise<Array<QuarantineRecord>>
+  /** * Re-process a quarantined item.
+   *
+   * The raw data set is parsed again and, on success, written to its regular
+   * location and removed from the quarantine area. An `OnFileStored` event is
+   * emitted for the stored instance. `onBeforeStore` is not invoked.
+   *
+   * @param id - Quarantine item identifier
+   * @param transferSyntaxUid - Transfer syntax (UID or name) to parse the data set with,
+   *   overriding the negotiated one recorded in the sidecar
+   * @returns Details of the stored instance
+   * @throws Error if the item still cannot be parsed or stored
+   *
+   * @example
+   * ```typescript
+   * // The sender announced the wrong transfer syntax
+   * const stored = await scp.reprocessQuarantined(item.id, 'ExplicitVRLittleEndian');
+   * console.log('Stored', stored.file);
+   * ```
+   */
+  reprocessQuarantined(id: string, transferSyntaxUid?: string | undefined | null): Promise<ScpEventDetails>
+  /** * Delete a quarantined item (raw data set and sidecar) from the storage backend.
+   *
+   * @param id - Quarantine item identifier
+   */
+  deleteQuarantined(id: string): Promise<void>
   /** * Register a callback to modify DICOM tags before files are saved.
    *
    * This callback is invoked **asynchronously** for each received DICOM file, allowing you
@@ -902,6 +1318,36 @@ export declare class StoreScp {
    * ```
    */
   onBeforeStore(callback: (err: Error | null, tagsJson: string) => Promise<string>): void
+  /** * Register the callback that stores instances for 'Custom' storage targets.
+   *
+   * The callback receives the storage key, the serialized DICOM instance and the
+   * extracted metadata. The C-STORE request is acknowledged only after the returned
+   * promise resolves; if it rejects, the SCU receives a failure status (0xA700) and an
+   * OnError event is emitted. Must be registered before `start()` when a target uses
+   * the 'Custom' backend.
+   *
+   * Custom targets can only be written to. Quarantined items are also passed to the
+   * callback if the custom target is the primary target, but they cannot be listed or
+   * reprocessed from there.
+   *
+   * @param callback - Async function storing the instance
+   *
+   * @example
+   * ```typescript
+   * const scp = new StoreScp({ port: 11112, storageBackend: 'Custom', extractTags: ['PatientID', 'Modality'] });
+   *
+   * scp.onCustomStore(async (err, { key, data, metadata }) => {
+   *   if (err) throw err;
+   *   await blobService.upload(key, data, {
+   *     study: metadata.StudyInstanceUID,
+   *     modality: metadata.Modality
+   *   });
+   * });
+   *
+   * scp.start();
+   * ```
+   */
+  onCustomStore(callback: (err: Error | null, request: CustomStoreRequest) => Promise<void>): void
 }
 
 /** * DICOM C-STORE SCU (Service Class User) Client.
@@ -1068,6 +1514,47 @@ export declare class StoreScu {
    * ```
    */
   addFolder(path: string): void
+  /** * Add an in-memory DICOM file to the transfer queue.
+   *
+   * The buffer may hold a complete DICOM file or a data set without file meta
+   * group (as written by StoreScp). SOP class and transfer syntax are detected
+   * from its content, and it goes through the same negotiation, transcoding and
+   * progress events as files on disk. The buffer is copied, so it can be reused
+   * after this call.
+   *
+   * @param buffer - DICOM file or data set bytes
+   * @param name - Name reported as `file` in events (default: `buffer:<n>`)
+   *
+   * @example
+   * ```typescript
+   * const scu = new StoreScu({ addr: '192.168.1.100:11112' });
+   * const response = await fetch('https://example.com/wado/instance.dcm');
+   * scu.addBuffer(Buffer.from(await response.arrayBuffer()), 'instance.dcm');
+   * await scu.send();
+   * ```
+   */
+  addBuffer(buffer: Buffer, name?: string | undefined | null): void
+  /** * Add an opened DicomFile to the transfer queue.
+   *
+   * The current state of the file is serialized when this method is called, so
+   * modifications made with `updateTags()` before it are sent, and later ones are not.
+   *
+   * @param dicomFile - DicomFile opened with `open()` or `openJson()`
+   * @param name - Name reported as `file` in events (default: `dicomfile:<n>`)
+   * @throws Error if the file is not opened
+   *
+   * @example
+   * ```typescript
+   * const file = new DicomFile();
+   * await file.open('input.dcm');
+   * file.updateTags({ PatientName: 'ANONYMOUS' });
+   *
+   * const scu = new StoreScu({ addr: '192.168.1.100:11112' });
+   * scu.addDicomFile(file, 'anonymized.dcm');
+   * await scu.send();
+   * ```
+   */
+  addDicomFile(dicomFile: DicomFile, name?: string | undefined | null): void
   /** * Clear all files from the transfer queue.
    *
    * Removes all files that were previously added with `addFile()` or `addFolder()`.
@@ -1126,7 +1613,7 @@ export declare class StoreScu {
    * 1. Validate all queued files
    * 2. Establish association with the SCP
    * 3. Negotiate presentation contexts
-   * 4. Transfer each file
+   * 4. Apply `filter`, `coerceTags` and `onBeforeSend`, then transfer each file
    * 5. Optionally transcode if needed (unless `neverTranscode` is true)
    * 6. Release the association
    *
@@ -1198,7 +1685,31 @@ export declare class StoreScu {
    * });
    * ```
    */
-  send(callbacks?: { onTransferStarted?: (err: Error | null, event: TransferStartedEvent) => void, onFileSending?: (err: Error | null, event: FileSendingEvent) => void, onFileSent?: (err: Error | null, event: FileSentEvent) => void, onFileError?: (err: Error | null, event: FileErrorEvent) => void, onTransferCompleted?: (err: Error | null, event: TransferCompletedEvent) => void }): Promise<Array<ResultObject>>
+  send(callbacks?: { onTransferStarted?: (err: Error | null, event: TransferStartedEvent) => void, onFileSending?: (err: Error | null, event: FileSendingEvent) => void, onFileSent?: (err: Error | null, event: FileSentEvent) => void, onFileError?: (err: Error | null, event: FileErrorEvent) => void, onFileSkipped?: (err: Error | null, event: FileSkippedEvent) => void, onBeforeSend?: (err: Error | null, tagsJson: string) => Promise<string | null>, onTransferCompleted?: (err: Error | null, event: TransferCompletedEvent) => void }): Promise<Array<ResultObject>>
+  /** * Cancel the transfer started by the last `send()`.
+   *
+   * No new files are sent. With the default 'Graceful' mode the C-STOREs in flight
+   * complete and the associations are released; with 'Abort' the associations are
+   * aborted at once. `send()` then resolves with the status 'Cancelled' and the files
+   * that were not sent reported as 'Cancelled'. A manifest keeps them pending, so the
+   * transfer can be resumed later. A `send()` started earlier on the same instance is
+   * not affected.
+   *
+   * @param mode - What happens to the C-STOREs in flight (default: 'Graceful')
+   *
+   * @example
+   * ```typescript
+   * const controller = new AbortController();
+   * controller.signal.addEventListener('abort', () => scu.cancel());
+   * setTimeout(() => controller.abort(), 30_000);
+   *
+   * const [result] = await scu.send();
+   * if (result.status === 'Cancelled') {
+   *   console.log('Transfer cancelled after 30 s');
+   * }
+   * ```
+   */
+  cancel(mode?: CancelMode | undefined | null): void
 }
 
 /** WADO-RS Server */
@@ -1293,6 +1804,14 @@ export interface BeforeStoreArgs {
  */
 export declare function combineTags(tagArrays: Array<Array<string>>): Array<string>
 
+/** * What happens to the C-STOREs in flight when a transfer is cancelled. */
+export declare const enum CancelMode {
+  /** Wait for the responses of the C-STOREs in flight, then release the associations */
+  Graceful = 'Graceful',
+  /** Abort the associations at once, the files in flight are reported as cancelled */
+  Abort = 'Abort'
+}
+
 /** * Predefined sets of commonly used DICOM tags organized by category.
  *
  * Provides convenient access to curated tag lists for different use cases,
@@ -1474,6 +1993,19 @@ export declare function createQidoSeriesResponse(series: Array<QidoSeriesResult>
 /** Create final JSON response from Study results */
 export declare function createQidoStudiesResponse(studies: Array<QidoStudyResult>): string
 
+/** Object passed to the `onCustomStore` callback of a StoreScp */
+export interface CustomStoreRequest {
+  /** Storage key of the instance (`{study}/{series}/{sop}.dcm`, or a `quarantine/` key) */
+  key: string
+  /** Serialized DICOM instance */
+  data: Buffer
+  /**
+   * Extracted tags (`extractTags`/`extractCustomTags`) plus StudyInstanceUID, SeriesInstanceUID,
+   * SOPInstanceUID, SOPClassUID and TransferSyntaxUID (empty for quarantined items)
+   */
+  metadata: Record<string, string>
+}
+
 /** * Custom tag specification for extracting non-standard or private DICOM tags.
  */
 export interface CustomTag {
@@ -1488,6 +2020,24 @@ export interface DicomFileMeta {
   sopClassUid: string
   /** Storage SOP Instance UID */
   sopInstanceUid: string
+  /** Transfer Syntax UID, inferred from the content for files without file meta group */
+  transferSyntaxUid: string
+}
+
+/** Result of rebuilding a DICOM index from storage */
+export interface DicomIndexRebuildResult {
+  /** Number of instances added to the index */
+  indexed: number
+  /** Number of objects that could not be read as DICOM and were skipped */
+  skipped: number
+}
+
+/** Number of entries per level in a DICOM index */
+export interface DicomIndexStats {
+  patients: number
+  studies: number
+  series: number
+  instances: number
 }
 
 /** DICOM JSON Value representation (PS3.18 Section F.2.2) */
@@ -1498,26 +2048,148 @@ export interface DicomJsonValue {
   value?: Array<string>
 }
 
-export interface FileErrorData {
-  file: string
-  error: string
-  sopInstanceUid?: string
-  sopClassUid?: string
-  fileTransferSyntax?: string
-}
-
-/** * Event data for OnFileError event.
+/** * Verify connectivity to a remote AE with a C-ECHO (DICOM "ping").
  *
- * Emitted when an error occurs while sending a file.
+ * Only the Verification SOP class is proposed, so no files are needed. The promise always
+ * resolves; check `success`, `rejectionReason` and `error` for the outcome.
+ * User identity negotiation works like in `StoreScu`. With `tls`, the association runs over a
+ * TLS connection whose peer certificate is verified against the system roots and `caCertPath`.
+ *
+ * @param addr - Address of the remote AE, e.g. `ANY-SCP@127.0.0.1:4446` or `127.0.0.1:4446`
+ * @param options - AE titles, timeout, user identity and TLS
+ *
+ * @example
+ * ```typescript
+ * const result = await echo('PACS@10.0.0.5:104', { callingAeTitle: 'MY-SCU', timeout: 5000 });
+ * if (result.success) {
+ *   console.log(`Echo OK in ${result.roundTripTime.toFixed(1)} ms (${result.implementationVersionName})`);
+ * } else {
+ *   console.error(result.rejectionReason ?? result.error);
+ * }
+ *
+ * const secure = await echo('PACS@pacs.example.org:2762', { tls: { caCertPath: './hospital-ca.pem' } });
+ * ```
  */
-export interface FileErrorEvent {
-  message: string
-  data?: FileErrorData
-}
+export declare function echo(addr: string, options?: EchoOptions | undefined | null): Promise<EchoResult>
 
-export interface FileSendingData {
-  file: string
-  sopInstanceUid: string
+/** * C-STORE response of the SCP for a file.
+ *
+ * @example
+ * ```typescript
+ * scu.addListener(StoreScuEvent.OnFileSent, (_err, event) => {
+ *   const response = event.data?.response;
+ *   if (response?.category === 'Warning') {
+ *     // B000: the SCP coerced data elements
+ *     console.warn(response.meaning, response.offendingElements, response.errorComment);
+ *   }
+ * });
+ * ```
+ */
+export interface DimseResponse {
+  /** Status (0000,0900) as sent by the SCP */
+  status: number
+  category: StatusCategory
+  /** Meaning of the status for C-STORE, e.g. "Coercion of Data Elements" */
+  meaning: string
+  /** Error Comment (0000,0902) */
+  errorComment?: string
+  /** Offending Element (0000,0901), as tag names or `GGGGEEEE` */
+  offendingElements?: Array<string>
+  /** Error ID (0000,0903) */
+  errorId?: number
+}
+
+/** Options of a C-ECHO request */
+export interface EchoOptions {
+  /** Calling AE title (default: STORE-SCU) */
+  callingAeTitle?: string
+  /** Called AE title (default: taken from the address, e.g. `ANY-SCP@host:port`) */
+  calledAeTitle?: string
+  /** Maximum PDU length proposed to the remote AE (default: 16384) */
+  maxPduLength?: number
+  /** Connection and response timeout in milliseconds (default: no timeout) */
+  timeout?: number
+  /** User identity: username */
+  username?: string
+  /** User identity: password (used together with username) */
+  password?: string
+  /** User identity: Kerberos service ticket */
+  kerberosServiceTicket?: string
+  /** User identity: SAML assertion */
+  samlAssertion?: string
+  /** User identity: JSON Web Token */
+  jwt?: string
+  /** Secure the association with TLS (DICOM TLS port, commonly 2762) */
+  tls?: TlsOptions
+}
+
+/** Outcome of a C-ECHO request */
+export interface EchoResult {
+  /** True if the association was accepted and the C-ECHO returned status 0000H */
+  success: boolean
+  /** Status of the C-ECHO response, if one was received */
+  status?: number
+  /** Time from connecting until the C-ECHO response, in milliseconds */
+  roundTripTime: number
+  /** Maximum PDU length accepted by the remote AE */
+  maxPduLength?: number
+  /** Implementation class UID announced by the remote AE */
+  implementationClassUid?: string
+  /** Implementation version name announced by the remote AE */
+  implementationVersionName?: string
+  /** Reason given by the remote AE for rejecting the association */
+  rejectionReason?: string
+  /** Error message if the verification failed for another reason */
+  error?: string
+}
+
+export interface FileErrorData {
+  file: string
+  error: string
+  sopInstanceUid?: string
+  sopClassUid?: string
+  fileTransferSyntax?: string
+  /** Response of the SCP, if the file was refused by it */
+  response?: DimseResponse
+}
+
+/** * Event data for OnFileError event.
+ *
+ * Emitted when an error occurs while sending a file.
+ */
+export interface FileErrorEvent {
+  message: string
+  data?: FileErrorData
+}
+
+/** * Outcome of a file in the results of `send()`. */
+export interface FileResult {
+  file: string
+  sopClassUid: string
+  status: FileResultStatus
+  /** Response of the SCP, if one was received */
+  response?: DimseResponse
+  /** Error or skip reason */
+  error?: string
+}
+
+/** * Outcome of a file sent by StoreScu. */
+export declare const enum FileResultStatus {
+  /** Stored by the SCP */
+  Sent = 'Sent',
+  /** Stored by the SCP with a warning status */
+  Warning = 'Warning',
+  /** Refused by the SCP or not sent because of an error */
+  Failed = 'Failed',
+  /** Not sent because of the filter or onBeforeSend */
+  Skipped = 'Skipped',
+  /** Not sent, or its C-STORE aborted, because the transfer was cancelled */
+  Cancelled = 'Cancelled'
+}
+
+export interface FileSendingData {
+  file: string
+  sopInstanceUid: string
   sopClassUid: string
 }
 
@@ -1534,19 +2206,156 @@ export interface FileSentData {
   file: string
   sopInstanceUid: string
   sopClassUid: string
+  /** Transfer syntax negotiated for the presentation context the file was sent on */
   transferSyntax: string
+  /** Transfer syntax of the file as read from its source */
+  fileTransferSyntax: string
+  /** Whether the file was transcoded to the negotiated transfer syntax */
+  transcoded: boolean
+  /** Tags changed by `coerceTags` or `onBeforeSend`, with their new value */
+  coercedTags?: Record<string, string>
+  /** Response of the SCP, with a Success or Warning category */
+  response: DimseResponse
   durationSeconds: number
 }
 
 /** * Event data for OnFileSent event.
  *
- * Emitted when a file has been successfully sent.
+ * Emitted when a file has been successfully sent, including when the SCP
+ * answered with a warning status.
  */
 export interface FileSentEvent {
   message: string
   data?: FileSentData
 }
 
+export interface FileSkippedData {
+  file: string
+  sopInstanceUid: string
+  sopClassUid: string
+  /** "filter" or "onBeforeSend" */
+  reason: string
+}
+
+/** * Event data for OnFileSkipped event.
+ *
+ * Emitted when a file is not sent because it does not pass the `filter` option
+ * or `onBeforeSend` returned null.
+ */
+export interface FileSkippedEvent {
+  message: string
+  data?: FileSkippedData
+}
+
+/** Durability and permission options of filesystem storage */
+export interface FilesystemOptions {
+  /** Flush written files and their directory to disk before a write is confirmed (default: false) */
+  fsync?: boolean
+  /** Permission bits of written files, e.g. `0o640` (Unix only, default: from umask) */
+  fileMode?: number
+  /** Permission bits of created directories, e.g. `0o2750` (Unix only, default: from umask) */
+  dirMode?: number
+  /** Owner user ID of written files and created directories (Unix only, requires privileges) */
+  uid?: number
+  /** Owner group ID of written files and created directories (Unix only) */
+  gid?: number
+  /** Compress written files with zstd at this level (1-22, e.g. 3); disabled if omitted */
+  compressionLevel?: number
+  /** JSON key file (`{ activeKeyId, keys: { id: base64 } }`) used to encrypt written files with AES-256-GCM */
+  encryptionKeyFile?: string
+  /**
+   * Key used to encrypt written files (default: `activeKeyId` of the key file).
+   * Keys not in the key file are requested from the provider registered with `setStorageKeyProvider`.
+   */
+  encryptionKeyId?: string
+}
+
+export interface FindMatchData {
+  /** Position of the match, starting at 0 */
+  index: number
+  /** The match as a DICOM JSON object */
+  dataset: string
+}
+
+/** * Event data for OnMatch event.
+ *
+ * Emitted for every pending C-FIND response as it arrives.
+ */
+export interface FindMatchEvent {
+  message: string
+  data?: FindMatchData
+}
+
+/** * A C-FIND query.
+ *
+ * Keys are keywords (`PatientName`), hex tags (`00100010`) or `(GGGG,EEEE)` tags mapped to
+ * their matching value; an empty value requests the attribute without matching on it.
+ * Attributes inside sequences are addressed with a dotted path, optionally with an item
+ * index (`ReferencedStudySequence[0].ReferencedSOPInstanceUID`); items are numbered from 0
+ * without gaps. A sequence key with an empty value requests the whole sequence.
+ *
+ * @example
+ * ```typescript
+ * const query: FindQuery = {
+ *   level: 'Study',
+ *   keys: {
+ *     PatientID: 'PAT001',
+ *     StudyDate: '20240101-20241231',
+ *     StudyInstanceUID: '',
+ *     ModalitiesInStudy: '',
+ *     '00081030': ''            // StudyDescription
+ *   },
+ *   limit: 50
+ * };
+ * ```
+ */
+export interface FindQuery {
+  /** Query level */
+  level: QueryLevel
+  /** Information model (default: StudyRoot) */
+  model?: QueryModel
+  /** Matching and return keys */
+  keys: Record<string, string>
+  /** Maximum number of matches; the query is cancelled with C-CANCEL once reached */
+  limit?: number
+}
+
+/** Result of a C-FIND query. */
+export interface FindResult {
+  /** Matches as a DICOM JSON array (PS3.18 Section F.2) */
+  matches: string
+  /** Number of matches */
+  count: number
+  /** Status of the final C-FIND response (0 = success, 0xFE00 = cancelled) */
+  status: number
+  /** True if the query was cancelled by `cancel()` or `limit` */
+  cancelled: boolean
+}
+
+/** Options for creating a FindScu instance. */
+export interface FindScuOptions {
+  /** Address of the remote SCP, optionally with AE title (e.g., "PACS@192.168.1.100:104") */
+  addr: string
+  /** Calling Application Entity title for this SCU (default: "FIND-SCU") */
+  callingAeTitle?: string
+  /** Called Application Entity title, overrides AE title in address if present (default: "ANY-SCP") */
+  calledAeTitle?: string
+  /** Maximum PDU length in bytes (default: 16384) */
+  maxPduLength?: number
+  /** Enable verbose logging (default: false) */
+  verbose?: boolean
+  /** User Identity username for authentication */
+  username?: string
+  /** User Identity password for authentication */
+  password?: string
+  /** User Identity Kerberos service ticket */
+  kerberosServiceTicket?: string
+  /** User Identity SAML assertion */
+  samlAssertion?: string
+  /** User Identity JWT (JSON Web Token) */
+  jwt?: string
+}
+
 /** * Get a comprehensive list of 300+ commonly used DICOM tag names.
  *
  * Returns an array of standard DICOM tag names covering all major
@@ -1788,6 +2597,36 @@ export declare function getCommonTagSets(): CommonTagSets
  */
 export declare function getCommonTransferSyntaxes(): TransferSyntaxConfig
 
+/** Options for creating a GetScu instance. */
+export interface GetScuOptions {
+  /** Address of the remote SCP, optionally with AE title (e.g., "PACS@192.168.1.100:104") */
+  addr: string
+  /** Calling Application Entity title for this SCU (default: "GET-SCU") */
+  callingAeTitle?: string
+  /** Called Application Entity title, overrides AE title in address if present (default: "ANY-SCP") */
+  calledAeTitle?: string
+  /** Maximum PDU length in bytes (default: 16384) */
+  maxPduLength?: number
+  /** Storage SOP classes to receive, as names (e.g. "CTImageStorage") or UIDs (default: all storage SOP classes accepted by StoreScp) */
+  sopClasses?: Array<string>
+  /** Transfer syntaxes to accept, as names (e.g. "JPEGBaseline") or UIDs (default: Explicit and Implicit VR Little Endian) */
+  transferSyntaxes?: Array<string>
+  /** Write received instances as `<study>/<series>/<sop>.dcm` to this storage instead of returning Buffers */
+  storage?: StorageConfig
+  /** Enable verbose logging (default: false) */
+  verbose?: boolean
+  /** User Identity username for authentication */
+  username?: string
+  /** User Identity password for authentication */
+  password?: string
+  /** User Identity Kerberos service ticket */
+  kerberosServiceTicket?: string
+  /** User Identity SAML assertion */
+  samlAssertion?: string
+  /** User Identity JWT (JSON Web Token) */
+  jwt?: string
+}
+
 /** Instance (file) data within a series */
 export interface InstanceHierarchyData {
   sopInstanceUid: string
@@ -1798,6 +2637,79 @@ export interface InstanceHierarchyData {
   tags?: Record<string, string>
 }
 
+export interface InstanceReceivedData {
+  sopInstanceUid: string
+  sopClassUid: string
+  studyInstanceUid: string
+  seriesInstanceUid: string
+  /** Location of the stored file (with MoveScu `outDir` or GetScu `storage`) */
+  file?: string
+}
+
+/** * Event data for OnInstanceReceived event.
+ *
+ * Emitted when an instance of a C-MOVE or C-GET has been received.
+ */
+export interface InstanceReceivedEvent {
+  message: string
+  data?: InstanceReceivedData
+}
+
+/** * Load storage encryption keys from a key file.
+ *
+ * Encrypted objects can then be read by every component (`WadoServer`, `DicomFile`,
+ * `DicomStore`, `StoreScu`) without further configuration. The file is JSON with
+ * base64 encoded 32 byte AES-256 keys. Old keys stay readable after rotation.
+ *
+ * @param keyFile - Path of the key file
+ * @returns Id of the active key, if the file defines one
+ *
+ * @example
+ * ```typescript
+ * // keys.json: { "activeKeyId": "2024-06", "keys": { "2024-01": "...", "2024-06": "..." } }
+ * loadStorageKeys('/etc/dicom/keys.json');
+ * ```
+ */
+export declare function loadStorageKeys(keyFile: string): string | null
+
+/** Internal C-STORE receiver started for the duration of a C-MOVE */
+export interface MoveReceiverOptions {
+  /** Port to listen on; the PACS must know this AE title with this port */
+  port: number
+  /** AE title of the receiver and default move destination (default: the calling AE title) */
+  aeTitle?: string
+  /** Write received instances as `<study>/<series>/<sop>.dcm` below this folder instead of returning Buffers */
+  outDir?: string
+}
+
+/** Options for creating a MoveScu instance. */
+export interface MoveScuOptions {
+  /** Address of the remote SCP, optionally with AE title (e.g., "PACS@192.168.1.100:104") */
+  addr: string
+  /** Calling Application Entity title for this SCU (default: "MOVE-SCU") */
+  callingAeTitle?: string
+  /** Called Application Entity title, overrides AE title in address if present (default: "ANY-SCP") */
+  calledAeTitle?: string
+  /** Maximum PDU length in bytes (default: 16384) */
+  maxPduLength?: number
+  /** Move destination AE title (default: the receiver AE title) */
+  destination?: string
+  /** Start an internal C-STORE receiver collecting the sub-operations */
+  receiver?: MoveReceiverOptions
+  /** Enable verbose logging (default: false) */
+  verbose?: boolean
+  /** User Identity username for authentication */
+  username?: string
+  /** User Identity password for authentication */
+  password?: string
+  /** User Identity Kerberos service ticket */
+  kerberosServiceTicket?: string
+  /** User Identity SAML assertion */
+  samlAssertion?: string
+  /** User Identity JWT (JSON Web Token) */
+  jwt?: string
+}
+
 /** Output format for pixel data */
 export declare const enum PixelDataFormat {
   /** Raw binary data (no processing) */
@@ -1882,6 +2794,38 @@ export interface PixelDataProcessingOptions {
   convertTo8Bit?: boolean
 }
 
+/** A study purged (or, in dry-run mode, selected for purging) by a retention sweep */
+export interface PurgedStudy {
+  /** Study Instance UID */
+  studyInstanceUid: string
+  /** Patient ID */
+  patientId?: string
+  /** StudyDate (YYYYMMDD) */
+  studyDate?: string
+  /** Time the last instance was received (milliseconds since UNIX epoch) */
+  receivedAt: number
+  /** Modalities of the series of the study */
+  modalities: Array<string>
+  /** Number of instances */
+  instances: number
+  /** Size of all instances in bytes */
+  size: number
+  /** Rule that selected the study */
+  reason: PurgeReason
+  /** True if the study was only reported and not deleted */
+  dryRun: boolean
+}
+
+/** Rule that caused a study to be purged */
+export declare const enum PurgeReason {
+  /** Older than `maxAgeDays` */
+  MaxAge = 'MaxAge',
+  /** A modality exceeded its entry in `modalityQuotas` */
+  ModalityQuota = 'ModalityQuota',
+  /** The total size exceeded `maxTotalSize` */
+  MaxTotalSize = 'MaxTotalSize'
+}
+
 /** QIDO-RS Server Configuration */
 export interface QidoServerConfig {
   /**
@@ -1895,10 +2839,78 @@ export interface QidoServerConfig {
    * If not specified, allows all origins (*) when CORS is enabled
    */
   corsAllowedOrigins?: string
+  /**
+   * Embedded index (see `StoreScp` option `indexPath`) used to answer queries
+   * for which no handler is registered
+   */
+  indexPath?: string
   /** Enable verbose logging for debugging */
   verbose?: boolean
 }
 
+/** * Sidecar record describing a quarantined instance.
+ *
+ * Instances that cannot be parsed, are missing required UIDs or are rejected by
+ * `onBeforeStore` are not dropped. The raw received bytes are kept in the
+ * `quarantine/` area of the configured storage backend together with this record
+ * as a JSON sidecar (`quarantine/<id>.json`).
+ */
+export interface QuarantineRecord {
+  /** Unique identifier of the quarantined item */
+  id: string
+  /** Reason why the instance was quarantined */
+  error: string
+  /** AE title of the sending application */
+  callingAeTitle: string
+  /** Presentation context ID the data set was received on */
+  presentationContextId: number
+  /** Abstract syntax negotiated for the presentation context (if known) */
+  abstractSyntax?: string
+  /** Transfer syntax negotiated for the presentation context (if known) */
+  transferSyntaxUid?: string
+  /** Affected SOP Class UID from the C-STORE request */
+  sopClassUid?: string
+  /** Affected SOP Instance UID from the C-STORE request */
+  sopInstanceUid?: string
+  /** Time the instance was quarantined (milliseconds since UNIX epoch) */
+  quarantinedAt: number
+  /** Size of the raw data set in bytes */
+  size: number
+  /** Storage key of the raw data set */
+  dataKey: string
+}
+
+/** Level of a query (QueryRetrieveLevel) */
+export declare const enum QueryLevel {
+  Patient = 'Patient',
+  Study = 'Study',
+  Series = 'Series',
+  Image = 'Image'
+}
+
+/** Query/Retrieve information model */
+export declare const enum QueryModel {
+  /** Patient Root: queries start at the PATIENT level */
+  PatientRoot = 'PatientRoot',
+  /** Study Root: queries start at the STUDY level (no PATIENT level) */
+  StudyRoot = 'StudyRoot'
+}
+
+/** A DICOM instance received by a C-MOVE or C-GET */
+export interface ReceivedInstance {
+  sopInstanceUid: string
+  sopClassUid: string
+  studyInstanceUid: string
+  seriesInstanceUid: string
+  transferSyntaxUid: string
+  /** Size of the DICOM file in bytes */
+  size: number
+  /** Location of the stored file (with MoveScu `outDir` or GetScu `storage`) */
+  file?: string
+  /** Content of the DICOM file (without `outDir` or `storage`) */
+  buffer?: Buffer
+}
+
 /** * Result of a DICOM transfer operation.
  *
  * Returned by the `send()` method to indicate the outcome of the transfer.
@@ -1912,6 +2924,9 @@ export interface QidoServerConfig {
  *   } else {
  *     console.error('✗', result.message);
  *   }
+ *   for (const [sopInstanceUid, file] of Object.entries(result.files ?? {})) {
+ *     if (file.status === 'Warning') console.warn(sopInstanceUid, file.response?.meaning);
+ *   }
  * }
  * ```
  */
@@ -1920,6 +2935,8 @@ export interface ResultObject {
   status: ResultStatus
   /** Descriptive message about the result */
   message: string
+  /** Outcome of each file of the transfer, keyed by SOP Instance UID (as read from the file) */
+  files?: Record<string, FileResult>
 }
 
 /** * Status of a DICOM transfer operation.
@@ -1930,19 +2947,177 @@ export declare const enum ResultStatus {
   /** Transfer completed successfully */
   Success = 'Success',
   /** Transfer failed with an error */
-  Error = 'Error'
+  Error = 'Error',
+  /** Transfer stopped by `cancel()`, the results cover the files handled until then */
+  Cancelled = 'Cancelled'
+}
+
+/** Time a study's age is measured from */
+export declare const enum RetentionAgeBasis {
+  /** Time the last instance of the study was received */
+  ReceivedTime = 'ReceivedTime',
+  /** StudyDate of the study (studies without StudyDate use the receive time) */
+  StudyDate = 'StudyDate'
+}
+
+/**
+ * Retention rules applied to the studies stored by a StoreScp.
+ *
+ * Rules are evaluated against the embedded index (`indexPath` is required): first
+ * `maxAgeDays`, then `modalityQuotas`, then `maxTotalSize`. Quota and size rules purge
+ * the oldest studies first. Purged studies are deleted from every storage target
+ * (except 'Custom' targets) and from the index, and an `OnStudyPurged` event is emitted
+ * for each of them. A study with files that cannot be deleted stays in the index and is
+ * retried by the next sweep; an `OnError` event is emitted for it instead.
+ *
+ * @example
+ * ```typescript
+ * const scp = new StoreScp({
+ *   port: 11112,
+ *   outDir: './teaching',
+ *   indexPath: './teaching/index.sqlite',
+ *   retention: {
+ *     maxAgeDays: 90,
+ *     maxTotalSize: 500 * 1024 ** 3,
+ *     modalityQuotas: { MG: 100 * 1024 ** 3 },
+ *     sweepInterval: 900
+ *   }
+ * });
+ * ```
+ */
+export interface RetentionPolicy {
+  /** Purge studies older than this many days */
+  maxAgeDays?: number
+  /** Time the age is measured from (default: 'ReceivedTime') */
+  ageBasis?: RetentionAgeBasis
+  /** Keep the total size of all studies below this many bytes */
+  maxTotalSize?: number
+  /** Maximum size in bytes of the instances of each modality (e.g. `{ CT: 1e12 }`) */
+  modalityQuotas?: Record<string, number>
+  /** Seconds between two background sweeps (default: 3600) */
+  sweepInterval?: number
+  /** Only report the studies that would be purged, without deleting them (default: false) */
+  dryRun?: boolean
+}
+
+export interface RetrieveProgressData {
+  remaining: number
+  completed: number
+  failed: number
+  warning: number
+}
+
+/** * Event data for OnProgress event.
+ *
+ * Emitted for every pending C-MOVE or C-GET response.
+ */
+export interface RetrieveProgressEvent {
+  message: string
+  data?: RetrieveProgressData
+}
+
+/** * A C-MOVE (or C-GET) request for a study, a series or a list of instances.
+ *
+ * The retrieve level follows from the given UIDs: IMAGE with `sopInstanceUids`,
+ * SERIES with `seriesInstanceUid`, STUDY otherwise.
+ */
+export interface RetrieveRequest {
+  /** Information model (default: StudyRoot) */
+  model?: QueryModel
+  /** Patient ID, required by some SCPs for the Patient Root model */
+  patientId?: string
+  /** Study to retrieve */
+  studyInstanceUid: string
+  /** Series to retrieve (required with `sopInstanceUids`) */
+  seriesInstanceUid?: string
+  /** Instances to retrieve */
+  sopInstanceUids?: Array<string>
+}
+
+/** Result of a C-MOVE or C-GET request */
+export interface RetrieveResult {
+  /** Status of the final response (0 = success, 0xB000 = some sub-operations failed) */
+  status: number
+  /** Number of sub-operations that completed successfully */
+  completed: number
+  /** Number of failed sub-operations */
+  failed: number
+  /** Number of sub-operations that completed with a warning */
+  warning: number
+  /** SOP Instance UIDs of the failed sub-operations, as reported by the SCP */
+  failedSopInstanceUids: Array<string>
+  /** Received instances (empty for a C-MOVE without `receiver`) */
+  instances: Array<ReceivedInstance>
 }
 
 /** S3 storage configuration */
 export interface S3Config {
   /** S3 bucket name */
   bucket: string
-  /** AWS access key ID */
-  accessKey: string
+  /**
+   * AWS access key ID. If omitted, credentials are taken from the environment
+   * (`AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, `AWS_SESSION_TOKEN`) or the shared credentials file
+   */
+  accessKey?: string
   /** AWS secret access key */
-  secretKey: string
-  /** S3 endpoint (e.g., "http://localhost:9000" for MinIO) */
+  secretKey?: string
+  /** Session token for temporary credentials (STS) */
+  sessionToken?: string
+  /** Profile in the shared credentials file (`~/.aws/credentials`) used when no access key is given */
+  profile?: string
+  /** Region (default: "us-east-1") */
+  region?: string
+  /** S3 endpoint (e.g., "http://localhost:9000" for MinIO). If omitted, the AWS endpoint of the region is used */
   endpoint?: string
+  /**
+   * Use path-style addressing (`endpoint/bucket/key`) instead of virtual-hosted style (`bucket.endpoint/key`).
+   * Default: true when a custom endpoint is set, false for AWS
+   */
+  pathStyle?: boolean
+  /** Prefix prepended to all object keys (e.g. "archive/") */
+  keyPrefix?: string
+  /** Storage class for uploaded objects (e.g. "STANDARD_IA", "GLACIER_IR") */
+  storageClass?: string
+  /** Objects of at least this size in MiB are uploaded with multipart upload (default: 16) */
+  multipartThresholdMb?: number
+  /** Part size in MiB for multipart uploads (default: 8, minimum: 5) */
+  multipartPartSizeMb?: number
+  /** Number of retries of failed S3 requests, with exponential backoff (default: 3) */
+  maxRetries?: number
+  /** PEM file with CA certificates trusted in addition to the system roots (e.g. the CA of an on-premises MinIO) */
+  caCertPath?: string
+  /** Accept invalid TLS certificates and host names (testing only) */
+  insecureTls?: boolean
+}
+
+/** * DICOM tags attached to objects written to S3 targets.
+ *
+ * Values are written as user metadata (`x-amz-meta-<tag>`) and/or S3 object tags so
+ * lifecycle rules, inventories and search can use them without reading the object.
+ * Objects are always stored with `Content-Type: application/dicom`.
+ * Tags that may identify the patient or staff (names, IDs, dates of birth, accession
+ * number, institution, private tags) are skipped unless `allowPhi` is set.
+ *
+ * @example
+ * ```typescript
+ * const scp = new StoreScp({
+ *   port: 11112,
+ *   storageBackend: 'S3',
+ *   s3Config: { bucket: 'dicom' },
+ *   s3Metadata: {
+ *     metadataTags: ['Modality', 'StudyDate', 'SOPClassUID'],
+ *     objectTags: ['Modality']
+ *   }
+ * });
+ * ```
+ */
+export interface S3MetadataOptions {
+  /** Tags written as `x-amz-meta-*` metadata (default: Modality, StudyDate, SOPClassUID, TransferSyntaxUID) */
+  metadataTags?: Array<string>
+  /** Tags written as S3 object tags, at most 10 (default: none) */
+  objectTags?: Array<string>
+  /** Allow tags containing protected health information (default: false) */
+  allowPhi?: boolean
 }
 
 /** * Event data passed to event listeners.
@@ -1976,6 +3151,17 @@ export interface ScpEventDetails {
   error?: string
   /** Study completion data with full hierarchy */
   study?: StudyHierarchyData
+  /** Quarantine record (for OnQuarantined events) */
+  quarantine?: QuarantineRecord
+  /**
+   * Location of the instance in each storage target (for OnFileStored events),
+   * or of the replica (for OnReplicated events)
+   */
+  locations?: Array<StorageLocation>
+  /** Hex SHA-256 of the stored instance (for OnFileStored events with content-addressed targets) */
+  contentHash?: string
+  /** Purged study (for OnStudyPurged events) */
+  purged?: PurgedStudy
 }
 
 /**
@@ -2056,6 +3242,21 @@ export interface SeriesHierarchyData {
   instances: Array<InstanceHierarchyData>
 }
 
+/** * Register a callback that provides storage encryption keys by id.
+ *
+ * The callback is called once per unknown key id (results are cached) and must resolve
+ * to a 32 byte Buffer, e.g. fetched from a KMS or secret store.
+ *
+ * @example
+ * ```typescript
+ * setStorageKeyProvider(async (err, keyId) => {
+ *   if (err) throw err;
+ *   return Buffer.from(await secrets.get(`dicom-key-${keyId}`), 'base64');
+ * });
+ * ```
+ */
+export declare function setStorageKeyProvider(callback: (err: Error | null, keyId: string) => Promise<Buffer>): void
+
 /** SOP Class configuration object */
 export interface SopClassConfig {
   /** CT imaging SOP classes */
@@ -2084,6 +3285,15 @@ export interface SopClassConfig {
   all: Array<string>
 }
 
+/** * Category of a DIMSE status code. */
+export declare const enum StatusCategory {
+  Success = 'Success',
+  Warning = 'Warning',
+  Failure = 'Failure',
+  Cancel = 'Cancel',
+  Pending = 'Pending'
+}
+
 /** Storage backend type */
 export declare const enum StorageBackend {
   /** Local filesystem storage */
@@ -2116,16 +3326,21 @@ export declare const enum StorageBackend {
  *     endpoint: 'http://localhost:9000'
  *   }
  * });
+ *
+ * // Hand instances to your own code (see onCustomStore)
+ * const scpCustom = new StoreScp({ port: 11111, storageBackend: 'Custom' });
  * ```
  */
 export declare const enum StorageBackendType {
   /** Store files on local filesystem */
   Filesystem = 'Filesystem',
   /** Store files in S3-compatible object storage */
-  S3 = 'S3'
+  S3 = 'S3',
+  /** Pass files to the JavaScript callback registered with `onCustomStore` */
+  Custom = 'Custom'
 }
 
-/** Storage configuration for DicomFile */
+/** Storage configuration for DicomFile and DicomStore */
 export interface StorageConfig {
   /** Storage backend type */
   backend: StorageBackend
@@ -2133,6 +3348,91 @@ export interface StorageConfig {
   rootDir?: string
   /** S3 configuration (required if backend is S3) */
   s3Config?: S3Config
+  /** Durability and permission options for filesystem storage */
+  filesystemOptions?: FilesystemOptions
+  /** Resolve and write content-addressed references (see `StorageTarget.contentAddressed`) */
+  contentAddressed?: boolean
+}
+
+/** Location of a stored instance in one storage target */
+export interface StorageLocation {
+  /** Name of the storage target */
+  name: string
+  /** Storage backend type */
+  backend: StorageBackendType
+  /** Success policy of the target */
+  policy: StoragePolicy
+  /** File path or `s3://` URL of the instance */
+  location: string
+  /**
+   * Whether the write was confirmed when the event was emitted
+   * (false for pending best-effort replication)
+   */
+  stored: boolean
+}
+
+/** Metadata of a stored object */
+export interface StorageObjectMetadata {
+  /** Key of the object relative to the storage root */
+  key: string
+  /** Size in bytes */
+  size: number
+  /** Last modification time in milliseconds since the Unix epoch */
+  lastModified?: number
+  /** Content type (S3 only) */
+  contentType?: string
+  /** Entity tag (S3 only) */
+  etag?: string
+}
+
+/** Success policy of a storage target */
+export declare const enum StoragePolicy {
+  /** The C-STORE is only acknowledged after the file was written to this target */
+  Required = 'Required',
+  /** The file is replicated to this target in the background; failures are reported via OnError */
+  BestEffort = 'BestEffort'
+}
+
+/** * A storage target of the SCP.
+ *
+ * Multiple targets can be configured with `storageTargets` to write every received
+ * instance to several backends at once (e.g. local disk for fast WADO access and S3
+ * for long-term archive).
+ *
+ * @example
+ * ```typescript
+ * const scp = new StoreScp({
+ *   port: 11111,
+ *   storageTargets: [
+ *     { name: 'local', backend: 'Filesystem', outDir: './dicom-data' },
+ *     {
+ *       name: 'archive',
+ *       backend: 'S3',
+ *       policy: 'BestEffort',
+ *       s3Config: { bucket: 'dicom-archive', accessKey: 'KEY', secretKey: 'SECRET' }
+ *     }
+ *   ]
+ * });
+ * ```
+ */
+export interface StorageTarget {
+  /** Name used to identify the target in events (default: backend type) */
+  name?: string
+  /** Storage backend type */
+  backend: StorageBackendType
+  /** Success policy (default: 'Required') */
+  policy?: StoragePolicy
+  /** Output directory (required for 'Filesystem') */
+  outDir?: string
+  /** S3 configuration (required for 'S3') */
+  s3Config?: S3Config
+  /** Durability and permission options (for 'Filesystem') */
+  filesystemOptions?: FilesystemOptions
+  /**
+   * Store every distinct instance content once, keyed by its SHA-256, and write
+   * `{study}/{series}/{sop}.dcm` as references to it (not for 'Custom', default: false)
+   */
+  contentAddressed?: boolean
 }
 
 /** * Events emitted by the DICOM C-STORE SCP server.
@@ -2170,7 +3470,13 @@ export declare const enum StoreScpEvent {
   /** A DICOM file has been successfully stored */
   OnFileStored = 'OnFileStored',
   /** A complete study (all files) has been received and stored */
-  OnStudyCompleted = 'OnStudyCompleted'
+  OnStudyCompleted = 'OnStudyCompleted',
+  /** A received instance could not be stored and was moved to the quarantine area */
+  OnQuarantined = 'OnQuarantined',
+  /** A study was purged (or selected in dry-run mode) by the retention policy */
+  OnStudyPurged = 'OnStudyPurged',
+  /** An instance was replicated to a best-effort storage target */
+  OnReplicated = 'OnReplicated'
 }
 
 /** * Configuration options for the DICOM C-STORE SCP server.
@@ -2236,6 +3542,24 @@ export interface StoreScpOptions {
   s3Config?: S3Config
   /** Output directory for filesystem storage (default: current directory) */
   outDir?: string
+  /** Atomic write durability (fsync) and file permission/ownership options for filesystem storage */
+  filesystemOptions?: FilesystemOptions
+  /** Deduplicate instances by content (see `StorageTarget.contentAddressed`, default: false) */
+  contentAddressed?: boolean
+  /**
+   * Write every instance to several storage targets.
+   * Overrides `storageBackend`, `s3Config` and `outDir` when set.
+   */
+  storageTargets?: Array<StorageTarget>
+  /**
+   * Path of an embedded SQLite index updated for every stored instance.
+   * Use the same path for `QidoServer`/`WadoServer` or open it with `DicomIndex`.
+   */
+  indexPath?: string
+  /** DICOM tags attached as metadata and object tags to objects written to S3 */
+  s3Metadata?: S3MetadataOptions
+  /** Retention rules applied by a background sweeper (requires `indexPath`) */
+  retention?: RetentionPolicy
   /** Store complete DICOM files with meta header vs dataset-only (default: false) */
   storeWithFileMeta?: boolean
   /** DICOM tags to extract from received files (e.g., ['PatientName', 'StudyDate']) */
@@ -2337,6 +3661,8 @@ export interface StoreScuOptions {
   failFirst?: boolean
   /** Fail transfer if transcoding would be required (default: true) */
   neverTranscode?: boolean
+  /** Transfer syntaxes proposed after each file's own, in order of preference, as names (e.g. "JPEG2000Lossless") or UIDs (default: Explicit and Implicit VR Little Endian) */
+  transferSyntaxes?: Array<'ImplicitVRLittleEndian' | 'ExplicitVRLittleEndian' | 'ExplicitVRBigEndian' | 'DeflatedExplicitVRLittleEndian' | 'JPEGBaseline' | 'JPEGExtended' | 'JPEGLossless' | 'JPEGLosslessNonHierarchical' | 'JPEGLSLossless' | 'JPEGLSLossy' | 'JPEG2000Lossless' | 'JPEG2000' | 'RLELossless' | 'MPEG2MainProfile' | 'MPEG2MainProfileHighLevel' | 'MPEG4AVCH264HighProfile' | 'MPEG4AVCH264BDCompatibleHighProfile' | (string & {})>
   /** Accept files with any SOP class UID, not only those in presentation contexts (default: false) */
   ignoreSopClass?: boolean
   /** User Identity username for authentication */
@@ -2351,6 +3677,25 @@ export interface StoreScuOptions {
   jwt?: string
   /** Number of parallel connections for concurrent file transfer (default: 1) */
   concurrency?: number
+  /** Maximum number of presentation contexts proposed per association, range 1-128; larger batches are split across several associations (default: 128) */
+  maxPresentationContexts?: number
+  /** Release and re-establish the association after this many files (default: unlimited) */
+  maxOperationsPerAssociation?: number
+  /**
+   * Maximum number of C-STORE requests outstanding on each association, range 1-65535; above 1 the
+   * Asynchronous Operations Window is proposed, with a fallback to one at a time if the SCP does not accept it (default: 1)
+   */
+  asyncOperationsWindow?: number
+  /** Tag conditions selecting the data sets to send; skipped files emit onFileSkipped */
+  filter?: TagFilter
+  /** Tag values set on each data set before it is sent (e.g. { PatientID: 'EXT-001' }); an empty string clears the value */
+  coerceTags?: Record<string, string>
+  /** Path of a JSON Lines file recording the status of each file; files recorded as sent are skipped when the transfer is resumed */
+  manifest?: string
+  /** Number of times failed files are sent again after the other files (default: 0) */
+  maxRetries?: number
+  /** Delay before the first retry in milliseconds, doubled for each further retry up to 60s (default: 1000) */
+  retryDelayMs?: number
   /** S3 configuration for reading files from S3 storage (required if using S3 paths) */
   s3Config?: S3Config
 }
@@ -2363,6 +3708,27 @@ export interface StudyHierarchyData {
   series: Array<SeriesHierarchyData>
 }
 
+/** * Tag conditions selecting the data sets sent by StoreScu.
+ *
+ * Keys are tag names or hex tags, values are matched as in C-FIND:
+ * `\`-separated alternatives with `*` and `?` wildcards, tested against the
+ * whole value and each of its values. Absent attributes have an empty value.
+ *
+ * @example
+ * ```typescript
+ * const filter: TagFilter = {
+ *   include: { Modality: 'CT\\MR' },
+ *   exclude: { ImageType: 'LOCALIZER', SOPClassUID: '1.2.840.10008.5.1.4.1.1.88.67' }
+ * };
+ * ```
+ */
+export interface TagFilter {
+  /** Send only data sets matching all of these conditions */
+  include?: Record<string, string>
+  /** Skip data sets matching any of these conditions */
+  exclude?: Record<string, string>
+}
+
 /** Tag scope classification based on DICOM hierarchy */
 export declare const enum TagScope {
   Patient = 'Patient',
@@ -2372,10 +3738,32 @@ export declare const enum TagScope {
   Equipment = 'Equipment'
 }
 
+/** TLS configuration of a DICOM association (PS3.15 B.1 TLS Secure Transport Connection Profile) */
+export interface TlsOptions {
+  /** PEM file with CA certificates trusted in addition to the system roots */
+  caCertPath?: string
+  /** PEM file with the client certificate, for peers requiring mutual authentication */
+  certPath?: string
+  /** PEM file with the PKCS#8 private key of the client certificate */
+  keyPath?: string
+  /** Host name the peer certificate is verified against (default: host of the address) */
+  serverName?: string
+  /** Accept invalid certificates and host names (testing only) */
+  insecure?: boolean
+}
+
 export interface TransferCompletedData {
   totalFiles: number
   successful: number
+  /** Files stored with a warning status (not included in `successful`) */
+  warning: number
   failed: number
+  /** Files not sent because of the filter or onBeforeSend */
+  skipped: number
+  /** Files not sent again because the manifest records them as sent */
+  alreadySent: number
+  /** Files not sent because the transfer was cancelled */
+  cancelled: number
   durationSeconds: number
 }
 
@@ -2495,6 +3883,16 @@ export interface WadoServerConfig {
   enableCompression?: boolean
   /** Default rendering options for thumbnails */
   thumbnailOptions?: WadoRenderingOptions
+  /**
+   * Embedded index (see `StoreScp` option `indexPath`) used to look up the
+   * instances of a study or series instead of scanning the storage
+   */
+  indexPath?: string
+  /**
+   * Resolve references written by content-addressed storage targets
+   * (see `StorageTarget.contentAddressed`)
+   */
+  contentAddressed?: boolean
   /** Enable verbose logging */
   verbose?: boolean
 }
@@ -573,6 +573,11 @@ if (!nativeBinding) {
 
 module.exports = nativeBinding
 module.exports.DicomFile = nativeBinding.DicomFile
+module.exports.DicomIndex = nativeBinding.DicomIndex
+module.exports.DicomStore = nativeBinding.DicomStore
+module.exports.FindScu = nativeBinding.FindScu
+module.exports.GetScu = nativeBinding.GetScu
+module.exports.MoveScu = nativeBinding.MoveScu
 module.exports.QidoInstanceResult = nativeBinding.QidoInstanceResult
 module.exports.QidoSeriesResult = nativeBinding.QidoSeriesResult
 module.exports.QidoServer = nativeBinding.QidoServer
@@ -581,20 +586,31 @@ module.exports.StoreScp = nativeBinding.StoreScp
 module.exports.StoreScu = nativeBinding.StoreScu
 module.exports.WadoServer = nativeBinding.WadoServer
 module.exports.AbstractSyntaxMode = nativeBinding.AbstractSyntaxMode
+module.exports.CancelMode = nativeBinding.CancelMode
 module.exports.combineTags = nativeBinding.combineTags
 module.exports.createCustomTag = nativeBinding.createCustomTag
 module.exports.createQidoEmptyResponse = nativeBinding.createQidoEmptyResponse
 module.exports.createQidoInstancesResponse = nativeBinding.createQidoInstancesResponse
 module.exports.createQidoSeriesResponse = nativeBinding.createQidoSeriesResponse
 module.exports.createQidoStudiesResponse = nativeBinding.createQidoStudiesResponse
+module.exports.echo = nativeBinding.echo
+module.exports.FileResultStatus = nativeBinding.FileResultStatus
 module.exports.getAvailableTagNames = nativeBinding.getAvailableTagNames
 module.exports.getCommonSopClasses = nativeBinding.getCommonSopClasses
 module.exports.getCommonTagSets = nativeBinding.getCommonTagSets
 module.exports.getCommonTransferSyntaxes = nativeBinding.getCommonTransferSyntaxes
+module.exports.loadStorageKeys = nativeBinding.loadStorageKeys
 module.exports.PixelDataFormat = nativeBinding.PixelDataFormat
+module.exports.PurgeReason = nativeBinding.PurgeReason
+module.exports.QueryLevel = nativeBinding.QueryLevel
+module.exports.QueryModel = nativeBinding.QueryModel
 module.exports.ResultStatus = nativeBinding.ResultStatus
+module.exports.RetentionAgeBasis = nativeBinding.RetentionAgeBasis
+module.exports.setStorageKeyProvider = nativeBinding.setStorageKeyProvider
+module.exports.StatusCategory = nativeBinding.StatusCategory
 module.exports.StorageBackend = nativeBinding.StorageBackend
 module.exports.StorageBackendType = nativeBinding.StorageBackendType
+module.exports.StoragePolicy = nativeBinding.StoragePolicy
 module.exports.StoreScpEvent = nativeBinding.StoreScpEvent
 module.exports.StoreScuEvent = nativeBinding.StoreScuEvent
 module.exports.TagScope = nativeBinding.TagScope
@@ -8,6 +8,11 @@ const nativeAddon = require('./index.js');
 // Re-export everything
 export const {
   DicomFile,
+  DicomIndex,
+  DicomStore,
+  FindScu,
+  GetScu,
+  MoveScu,
   QidoInstanceResult,
   QidoSeriesResult,
   QidoStudyResult,
@@ -17,6 +22,14 @@ export const {
   WadoServer,
   WadoStorageType,
   StorageBackendType,
+  StoragePolicy,
+  PurgeReason,
+  RetentionAgeBasis,
+  QueryLevel,
+  QueryModel,
+  CancelMode,
+  FileResultStatus,
+  StatusCategory,
   createQidoEmptyResponse,
   createQidoInstancesResponse,
   createQidoSeriesResponse,
@@ -26,7 +39,10 @@ export const {
   getCommonSopClasses,
   getCommonTagSets,
   getCommonTransferSyntaxes,
-  combineTags
+  combineTags,
+  echo,
+  loadStorageKeys,
+  setStorageKeyProvider
 } = nativeAddon;
 
 export default nativeAddon;
@@ -0,0 +1,860 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use dicom_core::{dicom_value, header::Tag, DataDictionary, DataElement, PrimitiveValue, VR};
+use dicom_dictionary_std::{tags, uids, StandardDataDictionary};
+use dicom_encoding::TransferSyntaxIndex;
+use dicom_object::InMemDicomObject;
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+use dicom_ul::{
+    pdu::{PDataValue, PDataValueType},
+    ClientAssociation, ClientAssociationOptions, Pdu,
+};
+use napi::bindgen_prelude::{AsyncTask, Object};
+use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi::{Env, Result as NapiResult};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use tracing::{debug, info, warn};
+
+use crate::utils::parse_tag;
+
+/// Level of a query (QueryRetrieveLevel)
+#[napi(string_enum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryLevel {
+    Patient,
+    Study,
+    Series,
+    Image,
+}
+
+impl QueryLevel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            QueryLevel::Patient => "PATIENT",
+            QueryLevel::Study => "STUDY",
+            QueryLevel::Series => "SERIES",
+            QueryLevel::Image => "IMAGE",
+        }
+    }
+}
+
+/// Query/Retrieve information model
+#[napi(string_enum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryModel {
+    /// Patient Root: queries start at the PATIENT level
+    PatientRoot,
+    /// Study Root: queries start at the STUDY level (no PATIENT level)
+    StudyRoot,
+}
+
+impl QueryModel {
+    fn find_sop_class(&self) -> &'static str {
+        match self {
+            QueryModel::PatientRoot => uids::PATIENT_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_FIND,
+            QueryModel::StudyRoot => uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_FIND,
+        }
+    }
+
+    pub(crate) fn move_sop_class(&self) -> &'static str {
+        match self {
+            QueryModel::PatientRoot => uids::PATIENT_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_MOVE,
+            QueryModel::StudyRoot => uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_MOVE,
+        }
+    }
+
+    pub(crate) fn get_sop_class(&self) -> &'static str {
+        match self {
+            QueryModel::PatientRoot => uids::PATIENT_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_GET,
+            QueryModel::StudyRoot => uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_GET,
+        }
+    }
+}
+
+/**
+ * A C-FIND query.
+ *
+ * Keys are keywords (`PatientName`), hex tags (`00100010`) or `(GGGG,EEEE)` tags mapped to
+ * their matching value; an empty value requests the attribute without matching on it.
+ * Attributes inside sequences are addressed with a dotted path, optionally with an item
+ * index (`ReferencedStudySequence[0].ReferencedSOPInstanceUID`); items are numbered from 0
+ * without gaps. A sequence key with an empty value requests the whole sequence.
+ *
+ * @example
+ * ```typescript
+ * const query: FindQuery = {
+ *   level: 'Study',
+ *   keys: {
+ *     PatientID: 'PAT001',
+ *     StudyDate: '20240101-20241231',
+ *     StudyInstanceUID: '',
+ *     ModalitiesInStudy: '',
+ *     '00081030': ''            // StudyDescription
+ *   },
+ *   limit: 50
+ * };
+ * ```
+ */
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct FindQuery {
+    /// Query level
+    pub level: QueryLevel,
+    /// Information model (default: StudyRoot)
+    pub model: Option<QueryModel>,
+    /// Matching and return keys
+    pub keys: HashMap<String, String>,
+    /// Maximum number of matches; the query is cancelled with C-CANCEL once reached
+    pub limit: Option<u32>,
+}
+
+/// Result of a C-FIND query.
+#[napi(object)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FindResult {
+    /// Matches as a DICOM JSON array (PS3.18 Section F.2)
+    pub matches: String,
+    /// Number of matches
+    pub count: u32,
+    /// Status of the final C-FIND response (0 = success, 0xFE00 = cancelled)
+    pub status: u32,
+    /// True if the query was cancelled by `cancel()` or `limit`
+    pub cancelled: bool,
+}
+
+/**
+ * Event data for OnMatch event.
+ *
+ * Emitted for every pending C-FIND response as it arrives.
+ */
+#[napi(object)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FindMatchEvent {
+    pub message: String,
+    pub data: Option<FindMatchData>,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FindMatchData {
+    /// Position of the match, starting at 0
+    pub index: u32,
+    /// The match as a DICOM JSON object
+    pub dataset: String,
+}
+
+/// Options for creating a FindScu instance.
+#[napi(object)]
+pub struct FindScuOptions {
+    /// Address of the remote SCP, optionally with AE title (e.g., "PACS@192.168.1.100:104")
+    pub addr: String,
+    /// Calling Application Entity title for this SCU (default: "FIND-SCU")
+    pub calling_ae_title: Option<String>,
+    /// Called Application Entity title, overrides AE title in address if present (default: "ANY-SCP")
+    pub called_ae_title: Option<String>,
+    /// Maximum PDU length in bytes (default: 16384)
+    pub max_pdu_length: Option<u32>,
+    /// Enable verbose logging (default: false)
+    pub verbose: Option<bool>,
+    /// User Identity username for authentication
+    pub username: Option<String>,
+    /// User Identity password for authentication
+    pub password: Option<String>,
+    /// User Identity Kerberos service ticket
+    pub kerberos_service_ticket: Option<String>,
+    /// User Identity SAML assertion
+    pub saml_assertion: Option<String>,
+    /// User Identity JWT (JSON Web Token)
+    pub jwt: Option<String>,
+}
+
+/**
+ * DICOM C-FIND SCU (Service Class User) Client.
+ *
+ * Queries a remote PACS at the patient, study, series or image level using the
+ * Patient Root or Study Root information model. Matches are returned as DICOM JSON
+ * and can be streamed through the `onMatch` callback while the query is running.
+ *
+ * @example
+ * ```typescript
+ * import { FindScu } from '@nuxthealth/node-dicom';
+ *
+ * const scu = new FindScu({ addr: 'PACS@192.168.1.100:104', callingAeTitle: 'MY-SCU' });
+ *
+ * const result = await scu.find({
+ *   level: 'Series',
+ *   keys: { StudyInstanceUID: '1.2.3.4', SeriesInstanceUID: '', Modality: '', NumberOfSeriesRelatedInstances: '' }
+ * }, {
+ *   onMatch: (err, event) => console.log('Match', event.data?.index)
+ * });
+ *
+ * for (const series of JSON.parse(result.matches)) {
+ *   console.log(series['0020000E'].Value[0]);
+ * }
+ * ```
+ */
+#[napi]
+pub struct FindScu {
+    addr: String,
+    calling_ae_title: String,
+    called_ae_title: Option<String>,
+    max_pdu_length: u32,
+    verbose: bool,
+    username: Option<String>,
+    password: Option<String>,
+    kerberos_service_ticket: Option<String>,
+    saml_assertion: Option<String>,
+    jwt: Option<String>,
+    cancelled: Arc<AtomicBool>,
+}
+
+#[napi]
+impl FindScu {
+    /**
+     * Create a new DICOM C-FIND SCU client instance.
+     *
+     * @param options - Client configuration options
+     * @returns New FindScu instance
+     *
+     * @example
+     * ```typescript
+     * const scu = new FindScu({
+     *   addr: 'PACS@192.168.1.100:104',
+     *   callingAeTitle: 'WORKSTATION-01',
+     *   username: 'dicom-user',
+     *   password: 'password123'
+     * });
+     * ```
+     */
+    #[napi(constructor)]
+    pub fn new(options: FindScuOptions) -> napi::Result<Self> {
+        let verbose = options.verbose.unwrap_or(false);
+
+        use tracing_subscriber::EnvFilter;
+        let filter = EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| {
+                if verbose {
+                    EnvFilter::new("debug")
+                } else {
+                    EnvFilter::new("error")
+                }
+            });
+
+        let _ = tracing::subscriber::set_global_default(
+            tracing_subscriber::FmtSubscriber::builder()
+                .with_env_filter(filter)
+                .finish(),
+        );
+
+        Ok(FindScu {
+            addr: options.addr,
+            calling_ae_title: options.calling_ae_title.unwrap_or_else(|| "FIND-SCU".to_string()),
+            called_ae_title: options.called_ae_title,
+            max_pdu_length: options.max_pdu_length.unwrap_or(16384),
+            verbose,
+            username: options.username,
+            password: options.password,
+            kerberos_service_ticket: options.kerberos_service_ticket,
+            saml_assertion: options.saml_assertion,
+            jwt: options.jwt,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /**
+     * Run a C-FIND query.
+     *
+     * Opens an association, sends the query and collects all matches. Each match is also
+     * passed to `onMatch` as soon as it arrives. The query stops early with a C-CANCEL
+     * when `limit` is reached or `cancel()` is called.
+     *
+     * @param query - Query level, information model, keys and limit
+     * @param callbacks - Optional callbacks
+     * @returns Matches and final status
+     *
+     * @example
+     * ```typescript
+     * const result = await scu.find({
+     *   level: 'Study',
+     *   keys: { PatientName: 'DOE^*', StudyInstanceUID: '', StudyDate: '' },
+     *   limit: 100
+     * });
+     * console.log(`${result.count} studies${result.cancelled ? ' (truncated)' : ''}`);
+     * ```
+     */
+    #[napi(
+        ts_args_type = "query: FindQuery, callbacks?: { onMatch?: (err: Error | null, event: FindMatchEvent) => void }",
+        ts_return_type = "Promise<FindResult>"
+    )]
+    pub fn find(&self, _env: Env, query: FindQuery, callbacks: Option<Object>) -> NapiResult<AsyncTask<FindScuHandler>> {
+        let on_match = match callbacks {
+            Some(callbacks_obj) => callbacks_obj.get::<ThreadsafeFunction<FindMatchEvent, ()>>("onMatch")?,
+            None => None,
+        };
+
+        let model = query.model.unwrap_or(QueryModel::StudyRoot);
+        if model == QueryModel::StudyRoot && query.level == QueryLevel::Patient {
+            return Err(napi::Error::from_reason(
+                "The Study Root information model has no PATIENT level".to_string(),
+            ));
+        }
+        let identifier = build_identifier(query.level, &query.keys).map_err(napi::Error::from_reason)?;
+
+        self.cancelled.store(false, Ordering::SeqCst);
+
+        Ok(AsyncTask::new(FindScuHandler {
+            association: self.association_options(model.find_sop_class()),
+            addr: self.addr.clone(),
+            verbose: self.verbose,
+            sop_class_uid: model.find_sop_class().to_string(),
+            identifier,
+            limit: query.limit,
+            cancelled: self.cancelled.clone(),
+            on_match: on_match.map(Arc::new),
+        }))
+    }
+
+    /**
+     * Cancel the running query.
+     *
+     * Sends a C-CANCEL to the remote SCP; `find()` resolves with the matches received so far
+     * and `cancelled: true`.
+     *
+     * @example
+     * ```typescript
+     * const pending = scu.find(query, {
+     *   onMatch: (err, event) => {
+     *     if (event.data && event.data.index >= 9) scu.cancel();
+     *   }
+     * });
+     * ```
+     */
+    #[napi]
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    fn association_options(&self, sop_class_uid: &'static str) -> ClientAssociationOptions<'static> {
+        let mut scu_init = ClientAssociationOptions::new()
+            .calling_ae_title(self.calling_ae_title.clone())
+            .max_pdu_length(self.max_pdu_length)
+            .with_presentation_context(
+                sop_class_uid,
+                vec![uids::EXPLICIT_VR_LITTLE_ENDIAN, uids::IMPLICIT_VR_LITTLE_ENDIAN],
+            );
+
+        if let Some(called_ae_title) = self.called_ae_title.clone() {
+            scu_init = scu_init.called_ae_title(called_ae_title);
+        }
+        if let Some(username) = self.username.clone() {
+            scu_init = scu_init.username(username);
+        }
+        if let Some(password) = self.password.clone() {
+            scu_init = scu_init.password(password);
+        }
+        if let Some(kerberos_service_ticket) = self.kerberos_service_ticket.clone() {
+            scu_init = scu_init.kerberos_service_ticket(kerberos_service_ticket);
+        }
+        if let Some(saml_assertion) = self.saml_assertion.clone() {
+            scu_init = scu_init.saml_assertion(saml_assertion);
+        }
+        if let Some(jwt) = self.jwt.clone() {
+            scu_init = scu_init.jwt(jwt);
+        }
+        scu_init
+    }
+}
+
+pub struct FindScuHandler {
+    association: ClientAssociationOptions<'static>,
+    addr: String,
+    verbose: bool,
+    sop_class_uid: String,
+    identifier: InMemDicomObject,
+    limit: Option<u32>,
+    cancelled: Arc<AtomicBool>,
+    on_match: Option<Arc<ThreadsafeFunction<FindMatchEvent, ()>>>,
+}
+
+#[napi]
+impl napi::Task for FindScuHandler {
+    type JsValue = FindResult;
+    type Output = FindResult;
+
+    fn compute(&mut self) -> napi::bindgen_prelude::Result<Self::Output> {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        let identifier = std::mem::replace(&mut self.identifier, InMemDicomObject::new_empty());
+        rt.block_on(run_find(self, identifier))
+            .map_err(|e| napi::Error::from_reason(format!("C-FIND failed: {}", e)))
+    }
+
+    fn resolve(&mut self, _env: napi::Env, output: Self::Output) -> napi::bindgen_prelude::Result<Self::JsValue> {
+        Ok(output)
+    }
+}
+
+async fn run_find(task: &FindScuHandler, identifier: InMemDicomObject) -> Result<FindResult, String> {
+    if task.verbose {
+        info!("Establishing association with '{}'...", &task.addr);
+    }
+    let mut scu = task
+        .association
+        .clone()
+        .establish_with_async(&task.addr)
+        .await
+        .map_err(|e| format!("Could not establish association: {}", e))?;
+
+    let pc = scu
+        .presentation_contexts()
+        .iter()
+        .find(|pc| pc.reason == dicom_ul::pdu::PresentationContextResultReason::Acceptance)
+        .cloned()
+        .ok_or_else(|| format!("Information model {} was not accepted", task.sop_class_uid))?;
+
+    let message_id = 1;
+    let command = find_req_command(&task.sop_class_uid, message_id);
+    send_message(&mut scu, pc.id, &command, Some((&identifier, &pc.transfer_syntax))).await?;
+
+    let mut matches = Vec::new();
+    let mut cancel_sent = false;
+    let status = loop {
+        let message = receive_message(&mut scu).await?;
+        let status = message.status()?;
+        if !matches!(status, 0xFF00 | 0xFF01) {
+            break status;
+        }
+
+        if !cancel_sent {
+            if let Some(data) = message.data {
+                let dataset = read_dataset(&data, &pc.transfer_syntax)?;
+                let json = dicom_json::to_value(&dataset).map_err(|e| e.to_string())?;
+                if let Some(cb) = &task.on_match {
+                    cb.call(Ok(FindMatchEvent {
+                        message: "Match received".to_string(),
+                        data: Some(FindMatchData {
+                            index: matches.len() as u32,
+                            dataset: json.to_string(),
+                        }),
+                    }), ThreadsafeFunctionCallMode::NonBlocking);
+                }
+                matches.push(json);
+            }
+        }
+
+        let limit_reached = task.limit.is_some_and(|limit| matches.len() >= limit as usize);
+        if !cancel_sent && (limit_reached || task.cancelled.load(Ordering::SeqCst)) {
+            debug!("Sending C-CANCEL after {} matches", matches.len());
+            send_message(&mut scu, pc.id, &cancel_req_command(message_id), None).await?;
+            cancel_sent = true;
+        }
+    };
+
+    if let Err(e) = scu.release().await {
+        warn!("Failed to release association: {}", e);
+    }
+
+    match status {
+        0x0000 | 0xFE00 => {}
+        0xB000..=0xBFFF => warn!("C-FIND completed with warning status {:04X}H", status),
+        _ => return Err(format!("C-FIND failed with status {:04X}H", status)),
+    }
+
+    if task.verbose {
+        info!("C-FIND completed with {} matches", matches.len());
+    }
+
+    Ok(FindResult {
+        count: matches.len() as u32,
+        matches: serde_json::Value::Array(matches).to_string(),
+        status: status as u32,
+        cancelled: cancel_sent || status == 0xFE00,
+    })
+}
+
+fn find_req_command(sop_class_uid: &str, message_id: u16) -> InMemDicomObject {
+    InMemDicomObject::command_from_element_iter([
+        // SOP Class UID
+        DataElement::new(tags::AFFECTED_SOP_CLASS_UID, VR::UI, dicom_value!(Str, sop_class_uid)),
+        // command field
+        DataElement::new(tags::COMMAND_FIELD, VR::US, dicom_value!(U16, [0x0020])),
+        // message ID
+        DataElement::new(tags::MESSAGE_ID, VR::US, dicom_value!(U16, [message_id])),
+        // priority
+        DataElement::new(tags::PRIORITY, VR::US, dicom_value!(U16, [0x0000])),
+        // data set type
+        DataElement::new(tags::COMMAND_DATA_SET_TYPE, VR::US, dicom_value!(U16, [0x0000])),
+    ])
+}
+
+pub(crate) fn cancel_req_command(message_id: u16) -> InMemDicomObject {
+    InMemDicomObject::command_from_element_iter([
+        // command field
+        DataElement::new(tags::COMMAND_FIELD, VR::US, dicom_value!(U16, [0x0FFF])),
+        // message ID being responded to
+        DataElement::new(tags::MESSAGE_ID_BEING_RESPONDED_TO, VR::US, dicom_value!(U16, [message_id])),
+        // data set type
+        DataElement::new(tags::COMMAND_DATA_SET_TYPE, VR::US, dicom_value!(U16, [0x0101])),
+    ])
+}
+
+/// Matching keys of a query, keyed by tag
+type KeyTree = BTreeMap<Tag, KeyNode>;
+
+enum KeyNode {
+    Value(String),
+    Sequence(Vec<KeyTree>),
+}
+
+/// Parse one segment of a key path: a tag with an optional `[n]` item index
+fn parse_key_segment(segment: &str) -> Result<(Tag, usize), String> {
+    let (tag, index) = match segment.strip_suffix(']').and_then(|s| s.rsplit_once('[')) {
+        Some((tag, index)) => (
+            tag,
+            index.parse::<usize>().map_err(|_| format!("Invalid item index in key: {}", segment))?,
+        ),
+        None => (segment, 0),
+    };
+    Ok((parse_tag(tag.trim())?, index))
+}
+
+fn insert_key(tree: &mut KeyTree, path: &[(Tag, usize)], value: &str) -> Result<(), String> {
+    let ((tag, index), rest) = path.split_first().ok_or("Empty key")?;
+    if rest.is_empty() {
+        match tree.get(tag) {
+            Some(KeyNode::Sequence(_)) if value.is_empty() => {}
+            Some(KeyNode::Sequence(_)) => return Err(format!("Cannot match on sequence {}", tag)),
+            _ => {
+                tree.insert(*tag, KeyNode::Value(value.to_string()));
+            }
+        }
+        return Ok(());
+    }
+
+    let node = tree.entry(*tag).or_insert_with(|| KeyNode::Sequence(Vec::new()));
+    if matches!(node, KeyNode::Value(v) if v.is_empty()) {
+        *node = KeyNode::Sequence(Vec::new());
+    }
+    match node {
+        KeyNode::Sequence(items) => {
+            if *index > items.len() {
+                return Err(format!("Item index {} of {} skips items (next item is {})", index, tag, items.len()));
+            }
+            if *index == items.len() {
+                items.push(KeyTree::new());
+            }
+            insert_key(&mut items[*index], rest, value)
+        }
+        KeyNode::Value(_) => Err(format!("Cannot match on sequence {}", tag)),
+    }
+}
+
+fn build_object(tree: KeyTree) -> Result<InMemDicomObject, String> {
+    let mut obj = InMemDicomObject::new_empty();
+    for (tag, node) in tree {
+        let vr = StandardDataDictionary
+            .by_tag(tag)
+            .map(|entry| entry.vr.relaxed())
+            .unwrap_or(VR::LO);
+        let element = match node {
+            KeyNode::Sequence(_) if vr != VR::SQ && vr != VR::LO => {
+                return Err(format!("{} is not a sequence", tag));
+            }
+            KeyNode::Sequence(items) => {
+                let items = items.into_iter().map(build_object).collect::<Result<Vec<_>, _>>()?;
+                DataElement::new(tag, VR::SQ, dicom_core::value::DataSetSequence::from(items))
+            }
+            KeyNode::Value(value) if vr == VR::SQ => {
+                if !value.is_empty() {
+                    return Err(format!("Cannot match on sequence {}", tag));
+                }
+                DataElement::new(tag, VR::SQ, dicom_core::value::DataSetSequence::<InMemDicomObject>::empty())
+            }
+            KeyNode::Value(value) if value.is_empty() => DataElement::new(tag, vr, PrimitiveValue::Empty),
+            KeyNode::Value(value) => match vr {
+                VR::OB | VR::OD | VR::OF | VR::OL | VR::OV | VR::OW | VR::UN => {
+                    return Err(format!("Cannot match on binary attribute {}", tag));
+                }
+                // Multiple values (e.g. UID list matching) are separated by backslashes
+                _ if value.contains('\\') => DataElement::new(
+                    tag,
+                    vr,
+                    PrimitiveValue::Strs(value.split('\\').map(str::to_string).collect()),
+                ),
+                _ => DataElement::new(tag, vr, PrimitiveValue::from(value)),
+            },
+        };
+        obj.put(element);
+    }
+    Ok(obj)
+}
+
+/// Build the identifier of a query: QueryRetrieveLevel plus the given keys
+pub(crate) fn build_identifier(level: QueryLevel, keys: &HashMap<String, String>) -> Result<InMemDicomObject, String> {
+    let mut paths = keys
+        .iter()
+        .map(|(key, value)| Ok((key.split('.').map(parse_key_segment).collect::<Result<Vec<_>, String>>()?, value)))
+        .collect::<Result<Vec<_>, String>>()?;
+    // Items are added in index order
+    paths.sort();
+    let mut tree = KeyTree::new();
+    for (path, value) in paths {
+        insert_key(&mut tree, &path, value)?;
+    }
+    tree.insert(tags::QUERY_RETRIEVE_LEVEL, KeyNode::Value(level.as_str().to_string()));
+    build_object(tree)
+}
+
+/// Client side of an association carrying DIMSE messages
+pub(crate) trait DimseAssociation {
+    async fn send(&mut self, pdu: &Pdu) -> Result<(), String>;
+    async fn receive(&mut self) -> Result<Pdu, String>;
+    /// Maximum PDU length accepted by the remote AE
+    fn acceptor_max_pdu_length(&self) -> u32;
+
+    /// P-DATA values received after the end of the last DIMSE message, if the association keeps them.
+    /// Associations established by dicom-ul do not, their operations have one response outstanding.
+    fn received_values(&mut self) -> Option<&mut VecDeque<PDataValue>> {
+        None
+    }
+}
+
+impl DimseAssociation for ClientAssociation<TcpStream> {
+    async fn send(&mut self, pdu: &Pdu) -> Result<(), String> {
+        ClientAssociation::<TcpStream>::send(self, pdu).await.map_err(|e| e.to_string())
+    }
+
+    async fn receive(&mut self) -> Result<Pdu, String> {
+        ClientAssociation::<TcpStream>::receive(self).await.map_err(|e| e.to_string())
+    }
+
+    fn acceptor_max_pdu_length(&self) -> u32 {
+        ClientAssociation::<TcpStream>::acceptor_max_pdu_length(self)
+    }
+}
+
+/// A DIMSE message received on a client association
+pub(crate) struct DimseMessage {
+    pub presentation_context_id: u8,
+    pub command: InMemDicomObject,
+    pub data: Option<Vec<u8>>,
+}
+
+impl DimseMessage {
+    pub fn command_field(&self) -> u16 {
+        self.command
+            .element(tags::COMMAND_FIELD)
+            .ok()
+            .and_then(|e| e.to_int::<u16>().ok())
+            .unwrap_or_default()
+    }
+
+    pub fn status(&self) -> Result<u16, String> {
+        self.command
+            .element(tags::STATUS)
+            .ok()
+            .and_then(|e| e.to_int::<u16>().ok())
+            .ok_or_else(|| "Response without status".to_string())
+    }
+}
+
+/// Receive the next DIMSE message, reassembling command and data set fragments.
+///
+/// A P-DATA-TF may hold several messages, as when an SCP answers pipelined requests at once:
+/// the values following the message are kept by the association for the next call.
+pub(crate) async fn receive_message(scu: &mut impl DimseAssociation) -> Result<DimseMessage, String> {
+    let mut command_data = Vec::new();
+    let mut command = None;
+    let mut data = Vec::new();
+    let mut values = scu.received_values().map(std::mem::take).unwrap_or_default();
+    loop {
+        while let Some(mut value) = values.pop_front() {
+            match value.value_type {
+                PDataValueType::Command => {
+                    command_data.append(&mut value.data);
+                    if !value.is_last {
+                        continue;
+                    }
+                    let cmd = InMemDicomObject::read_dataset_with_ts(
+                        &command_data[..],
+                        &dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN.erased(),
+                    )
+                    .map_err(|e| format!("Could not read response command: {}", e))?;
+                    let has_data = cmd
+                        .element(tags::COMMAND_DATA_SET_TYPE)
+                        .ok()
+                        .and_then(|e| e.to_int::<u16>().ok())
+                        .is_some_and(|t| t != 0x0101);
+                    if !has_data {
+                        keep_received_values(scu, values);
+                        return Ok(DimseMessage {
+                            presentation_context_id: value.presentation_context_id,
+                            command: cmd,
+                            data: None,
+                        });
+                    }
+                    command = Some(cmd);
+                }
+                PDataValueType::Data => {
+                    data.append(&mut value.data);
+                    if value.is_last {
+                        if let Some(command) = command.take() {
+                            keep_received_values(scu, values);
+                            return Ok(DimseMessage {
+                                presentation_context_id: value.presentation_context_id,
+                                command,
+                                data: Some(data),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        match scu.receive().await.map_err(|e| format!("Could not receive response: {}", e))? {
+            Pdu::PData { data } => values.extend(data),
+            Pdu::AbortRQ { .. } => return Err("Association aborted by the peer".to_string()),
+            Pdu::ReleaseRQ => return Err("Association released by the peer".to_string()),
+            pdu => return Err(format!("Unexpected PDU: {:?}", pdu)),
+        }
+    }
+}
+
+/// Keep the values received after the end of a message for the next one
+fn keep_received_values(scu: &mut impl DimseAssociation, values: VecDeque<PDataValue>) {
+    if values.is_empty() {
+        return;
+    }
+    match scu.received_values() {
+        Some(received) => *received = values,
+        None => warn!("Ignoring {} P-DATA values received after the end of a message", values.len()),
+    }
+}
+
+/// Send a DIMSE command, optionally followed by a data set in the given transfer syntax
+pub(crate) async fn send_message(
+    scu: &mut impl DimseAssociation,
+    presentation_context_id: u8,
+    command: &InMemDicomObject,
+    data: Option<(&InMemDicomObject, &str)>,
+) -> Result<(), String> {
+    let mut cmd_data = Vec::with_capacity(128);
+    command
+        .write_dataset_with_ts(
+            &mut cmd_data,
+            &dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN.erased(),
+        )
+        .map_err(|e| format!("Could not write command: {}", e))?;
+
+    let object_data = match data {
+        Some((dataset, ts_uid)) => {
+            let ts = TransferSyntaxRegistry
+                .get(ts_uid.trim_end_matches('\0'))
+                .ok_or_else(|| format!("Unsupported transfer syntax {}", ts_uid))?;
+            let mut object_data = Vec::with_capacity(512);
+            dataset
+                .write_dataset_with_ts(&mut object_data, ts)
+                .map_err(|e| format!("Could not write data set: {}", e))?;
+            Some(object_data)
+        }
+        None => None,
+    };
+    send_encoded_message(scu, presentation_context_id, cmd_data, object_data).await
+}
+
+/// Send an encoded DIMSE command and data set, splitting the data set into PDUs the peer accepts
+pub(crate) async fn send_encoded_message(
+    scu: &mut impl DimseAssociation,
+    presentation_context_id: u8,
+    cmd_data: Vec<u8>,
+    object_data: Option<Vec<u8>>,
+) -> Result<(), String> {
+    let mut values = vec![PDataValue {
+        presentation_context_id,
+        value_type: PDataValueType::Command,
+        is_last: true,
+        data: cmd_data,
+    }];
+
+    let Some(object_data) = object_data else {
+        return scu.send(&Pdu::PData { data: values }).await;
+    };
+
+    let fragment_size = scu.acceptor_max_pdu_length().saturating_sub(100) as usize;
+    if object_data.len() < fragment_size {
+        values.push(PDataValue {
+            presentation_context_id,
+            value_type: PDataValueType::Data,
+            is_last: true,
+            data: object_data,
+        });
+        return scu.send(&Pdu::PData { data: values }).await;
+    }
+
+    scu.send(&Pdu::PData { data: values }).await?;
+    let mut fragments = object_data.chunks(fragment_size.max(1)).peekable();
+    while let Some(fragment) = fragments.next() {
+        let value = PDataValue {
+            presentation_context_id,
+            value_type: PDataValueType::Data,
+            is_last: fragments.peek().is_none(),
+            data: fragment.to_vec(),
+        };
+        scu.send(&Pdu::PData { data: vec![value] }).await?;
+    }
+    Ok(())
+}
+
+/// Parse a data set received in the given transfer syntax
+pub(crate) fn read_dataset(data: &[u8], ts_uid: &str) -> Result<InMemDicomObject, String> {
+    let ts = TransferSyntaxRegistry
+        .get(ts_uid.trim_end_matches('\0'))
+        .ok_or_else(|| format!("Unsupported transfer syntax {}", ts_uid))?;
+    InMemDicomObject::read_dataset_with_ts(data, ts).map_err(|e| format!("Could not read data set: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_identifier() {
+        let keys = HashMap::from([
+            ("PatientID".to_string(), "PAT*".to_string()),
+            ("00080020".to_string(), "20240101-".to_string()),
+            ("StudyInstanceUID".to_string(), "".to_string()),
+            ("ReferencedStudySequence[1].ReferencedSOPInstanceUID".to_string(), "1.2.3".to_string()),
+            ("ReferencedStudySequence[0].ReferencedSOPClassUID".to_string(), "".to_string()),
+            ("ProcedureCodeSequence".to_string(), "".to_string()),
+        ]);
+        let obj = build_identifier(QueryLevel::Study, &keys).unwrap();
+
+        assert_eq!(obj.element(tags::QUERY_RETRIEVE_LEVEL).unwrap().to_str().unwrap(), "STUDY");
+        assert_eq!(obj.element(tags::PATIENT_ID).unwrap().to_str().unwrap(), "PAT*");
+        assert_eq!(obj.element(tags::STUDY_DATE).unwrap().to_str().unwrap(), "20240101-");
+        assert_eq!(obj.element(tags::STUDY_INSTANCE_UID).unwrap().to_str().unwrap(), "");
+        assert_eq!(obj.element(tags::PROCEDURE_CODE_SEQUENCE).unwrap().items().unwrap().len(), 0);
+
+        let items = obj.element(tags::REFERENCED_STUDY_SEQUENCE).unwrap().items().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(
+            items[1].element(tags::REFERENCED_SOP_INSTANCE_UID).unwrap().to_str().unwrap(),
+            "1.2.3"
+        );
+
+        let not_a_sequence = HashMap::from([("PatientName.PatientID".to_string(), "X".to_string())]);
+        assert!(build_identifier(QueryLevel::Study, &not_a_sequence).is_err());
+        let unknown = HashMap::from([("NoSuchKeyword".to_string(), String::new())]);
+        assert!(build_identifier(QueryLevel::Study, &unknown).is_err());
+        // Item indexes must not skip items, so a huge index cannot allocate
+        let gap = HashMap::from([("ReferencedStudySequence[2].ReferencedSOPInstanceUID".to_string(), String::new())]);
+        assert!(build_identifier(QueryLevel::Study, &gap).is_err());
+        let huge = HashMap::from([("ReferencedStudySequence[99999999999].ReferencedSOPInstanceUID".to_string(), String::new())]);
+        assert!(build_identifier(QueryLevel::Study, &huge).is_err());
+    }
+}
@@ -0,0 +1,426 @@
+use dicom_ul::pdu::{
+    AbortRQSource, AssociationAC, AssociationRQ, PDataValue, PresentationContextNegotiated, PresentationContextProposed,
+    PresentationContextResultReason, UserIdentity, UserIdentityType, UserVariableItem,
+};
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Duration;
+
+use dicom_ul::pdu::DEFAULT_MAX_PDU;
+use dicom_ul::{read_pdu, write_pdu, AeAddr, Pdu, IMPLEMENTATION_CLASS_UID, IMPLEMENTATION_VERSION_NAME};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tracing::{debug, warn};
+
+use crate::findscu::DimseAssociation;
+use crate::utils::tls::{connect_tls, TlsOptions};
+
+/// Asynchronous Operations Window sub-item (PS3.7 D.3.3.3)
+const ASYNC_OPERATIONS_WINDOW_ITEM: u8 = 0x53;
+
+/// SCP/SCU Role Selection sub-item (PS3.7 D.3.3.4)
+const ROLE_SELECTION_ITEM: u8 = 0x54;
+
+/// Size of the PDU header (type, reserved, length)
+const PDU_HEADER_LENGTH: usize = 6;
+
+/// Accepted excess over the maximum PDU length we announced, for peers that slightly exceed it
+const PDU_LENGTH_SLACK: usize = 16 * 1024;
+
+/// Time allowed to connect to the peer
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time allowed to send a PDU or to wait for the next one before the peer is considered gone
+const IO_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// A presentation context to propose, optionally taking the SCP role for its SOP class
+#[derive(Clone)]
+pub(crate) struct ProposedContext {
+    pub abstract_syntax: String,
+    pub transfer_syntaxes: Vec<String>,
+    pub scp_role: bool,
+}
+
+/// Parameters of the A-ASSOCIATE-RQ
+#[derive(Clone)]
+pub(crate) struct AssociationRequest {
+    pub calling_ae_title: String,
+    pub called_ae_title: Option<String>,
+    pub max_pdu_length: u32,
+    pub contexts: Vec<ProposedContext>,
+    pub user_identity: Option<UserIdentity>,
+    /// Maximum number of operations invoked and performed asynchronously, if proposed
+    pub async_operations_window: Option<(u16, u16)>,
+    /// Secure the connection with TLS before negotiating the association
+    pub tls: Option<TlsOptions>,
+}
+
+/// Why an association could not be established
+#[derive(Debug)]
+pub(crate) enum EstablishError {
+    /// The peer answered with an A-ASSOCIATE-RJ for this reason
+    Rejected(String),
+    Failed(String),
+}
+
+impl fmt::Display for EstablishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EstablishError::Rejected(reason) => write!(f, "Association rejected: {}", reason),
+            EstablishError::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<String> for EstablishError {
+    fn from(message: String) -> Self {
+        EstablishError::Failed(message)
+    }
+}
+
+/// Connection an association runs on, plain TCP or TLS
+pub(crate) trait AssociationStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> AssociationStream for S {}
+
+/// Client association negotiated by hand.
+///
+/// `ClientAssociationOptions` cannot propose SCP/SCU Role Selection, which C-GET needs so the
+/// SCP may send its C-STORE sub-operations back on the same association, nor the
+/// Asynchronous Operations Window, which lets C-STORE requests be pipelined.
+pub(crate) struct RoleSelectingAssociation {
+    socket: Box<dyn AssociationStream>,
+    presentation_contexts: Vec<PresentationContextNegotiated>,
+    /// User information items of the A-ASSOCIATE-AC
+    user_variables: Vec<UserVariableItem>,
+    requestor_max_pdu_length: u32,
+    acceptor_max_pdu_length: u32,
+    scp_roles: Vec<String>,
+    async_operations_window: Option<(u16, u16)>,
+    /// P-DATA values received after the end of the last DIMSE message
+    received: VecDeque<PDataValue>,
+}
+
+impl RoleSelectingAssociation {
+    /// Connect to `addr` (`AE@host:port` or `host:port`) and negotiate the association
+    pub async fn establish(addr: &str, request: AssociationRequest) -> Result<Self, EstablishError> {
+        let addr: AeAddr<String> = addr.parse().map_err(|e| format!("Invalid address {}: {:?}", addr, e))?;
+        let called_ae_title = request
+            .called_ae_title
+            .clone()
+            .or_else(|| addr.ae_title().map(str::to_string))
+            .unwrap_or_else(|| "ANY-SCP".to_string());
+
+        let proposed: Vec<PresentationContextProposed> = request
+            .contexts
+            .iter()
+            .enumerate()
+            .map(|(i, context)| PresentationContextProposed {
+                id: (2 * i + 1) as u8,
+                abstract_syntax: context.abstract_syntax.clone(),
+                transfer_syntaxes: context.transfer_syntaxes.clone(),
+            })
+            .collect();
+
+        let mut user_variables = vec![
+            UserVariableItem::MaxLength(request.max_pdu_length),
+            UserVariableItem::ImplementationClassUID(IMPLEMENTATION_CLASS_UID.to_string()),
+            UserVariableItem::ImplementationVersionName(IMPLEMENTATION_VERSION_NAME.to_string()),
+        ];
+        for context in request.contexts.iter().filter(|context| context.scp_role) {
+            user_variables.push(role_selection_item(&context.abstract_syntax, false, true));
+        }
+        if let Some((invoked, performed)) = request.async_operations_window {
+            user_variables.push(async_operations_window_item(invoked, performed));
+        }
+        if let Some(user_identity) = request.user_identity {
+            user_variables.push(UserVariableItem::UserIdentityItem(user_identity));
+        }
+
+        let socket = timeout(CONNECT_TIMEOUT, TcpStream::connect(addr.socket_addr().as_str()))
+            .await
+            .map_err(|_| format!("Timed out connecting to {}", addr.socket_addr()))?
+            .map_err(|e| format!("Could not connect to {}: {}", addr.socket_addr(), e))?;
+        let socket: Box<dyn AssociationStream> = match &request.tls {
+            Some(tls) => {
+                let host = addr.socket_addr().rsplit_once(':').map_or(addr.socket_addr().as_str(), |(host, _)| host);
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                Box::new(
+                    timeout(CONNECT_TIMEOUT, connect_tls(socket, host, tls))
+                        .await
+                        .map_err(|_| format!("Timed out in the TLS handshake with {}", addr.socket_addr()))??,
+                )
+            }
+            None => Box::new(socket),
+        };
+        let mut association = RoleSelectingAssociation {
+            socket,
+            presentation_contexts: Vec::new(),
+            user_variables: Vec::new(),
+            requestor_max_pdu_length: request.max_pdu_length,
+            acceptor_max_pdu_length: request.max_pdu_length,
+            scp_roles: Vec::new(),
+            async_operations_window: None,
+            received: VecDeque::new(),
+        };
+
+        association
+            .send(&Pdu::AssociationRQ(AssociationRQ {
+                protocol_version: 1,
+                calling_ae_title: request.calling_ae_title,
+                called_ae_title,
+                application_context_name: "1.2.840.10008.3.1.1.1".to_string(),
+                presentation_contexts: proposed.clone(),
+                user_variables,
+            }))
+            .await?;
+
+        match association.receive().await? {
+            Pdu::AssociationAC(AssociationAC { presentation_contexts, user_variables, .. }) => {
+                association.presentation_contexts = presentation_contexts
+                    .into_iter()
+                    .filter(|pc| pc.reason == PresentationContextResultReason::Acceptance)
+                    .filter_map(|pc| {
+                        let abstract_syntax = proposed.iter().find(|p| p.id == pc.id)?.abstract_syntax.clone();
+                        Some(PresentationContextNegotiated {
+                            id: pc.id,
+                            reason: pc.reason,
+                            transfer_syntax: pc.transfer_syntax,
+                            abstract_syntax,
+                        })
+                    })
+                    .collect();
+                association.user_variables = user_variables.clone();
+                for item in user_variables {
+                    match item {
+                        // 0 means no limit
+                        UserVariableItem::MaxLength(0) => association.acceptor_max_pdu_length = u32::MAX,
+                        UserVariableItem::MaxLength(length) => association.acceptor_max_pdu_length = length,
+                        UserVariableItem::Unknown(ROLE_SELECTION_ITEM, data) => {
+                            if let Some((uid, _, true)) = parse_role_selection(&data) {
+                                association.scp_roles.push(uid);
+                            }
+                        }
+                        UserVariableItem::Unknown(ASYNC_OPERATIONS_WINDOW_ITEM, data) => {
+                            association.async_operations_window = parse_async_operations_window(&data);
+                        }
+                        _ => {}
+                    }
+                }
+                debug!(
+                    "Association established: {} presentation contexts, SCP role accepted for {} SOP classes",
+                    association.presentation_contexts.len(),
+                    association.scp_roles.len()
+                );
+                Ok(association)
+            }
+            Pdu::AssociationRJ(rj) => Err(EstablishError::Rejected(rj.source.to_string())),
+            pdu => {
+                let _ = association.abort().await;
+                Err(format!("Unexpected response to association request: {:?}", pdu).into())
+            }
+        }
+    }
+
+    /// Accepted presentation contexts
+    pub fn presentation_contexts(&self) -> &[PresentationContextNegotiated] {
+        &self.presentation_contexts
+    }
+
+    /// User information items sent by the peer, e.g. its implementation class UID
+    pub fn user_variables(&self) -> &[UserVariableItem] {
+        &self.user_variables
+    }
+
+    /// True if the SCP acknowledged our SCP role for the SOP class.
+    ///
+    /// An SCP may omit the acknowledgement and still send sub-operations, so this is informative only.
+    pub fn has_scp_role(&self, sop_class_uid: &str) -> bool {
+        self.scp_roles.iter().any(|uid| uid == sop_class_uid)
+    }
+
+    /// Maximum number of operations the SCP invokes and performs asynchronously, as accepted
+    /// by it. None if the SCP did not answer the Asynchronous Operations Window, in which
+    /// case operations are synchronous.
+    pub fn async_operations_window(&self) -> Option<(u16, u16)> {
+        self.async_operations_window
+    }
+
+    /// Gracefully release the association
+    pub async fn release(mut self) -> Result<(), String> {
+        self.send(&Pdu::ReleaseRQ).await?;
+        loop {
+            match self.receive().await? {
+                Pdu::ReleaseRP => break,
+                Pdu::AbortRQ { .. } => return Err("Association aborted by the peer".to_string()),
+                pdu => warn!("Ignoring PDU while releasing the association: {:?}", pdu),
+            }
+        }
+        let _ = self.socket.shutdown().await;
+        Ok(())
+    }
+
+    /// Abort the association
+    pub async fn abort(mut self) -> Result<(), String> {
+        let result = self.send(&Pdu::AbortRQ { source: AbortRQSource::ServiceUser }).await;
+        let _ = self.socket.shutdown().await;
+        result
+    }
+
+    /// Largest PDU accepted from the peer, so a bogus length cannot make us allocate gigabytes
+    fn max_receive_length(&self) -> usize {
+        self.requestor_max_pdu_length.max(DEFAULT_MAX_PDU) as usize + PDU_LENGTH_SLACK
+    }
+}
+
+impl DimseAssociation for RoleSelectingAssociation {
+    async fn send(&mut self, pdu: &Pdu) -> Result<(), String> {
+        let mut buffer = Vec::new();
+        write_pdu(&mut buffer, pdu).map_err(|e| format!("Could not write PDU: {}", e))?;
+        timeout(IO_TIMEOUT, self.socket.write_all(&buffer))
+            .await
+            .map_err(|_| "Timed out sending PDU".to_string())?
+            .map_err(|e| format!("Could not send PDU: {}", e))
+    }
+
+    async fn receive(&mut self) -> Result<Pdu, String> {
+        let mut buffer = vec![0u8; PDU_HEADER_LENGTH];
+        timeout(IO_TIMEOUT, self.socket.read_exact(&mut buffer))
+            .await
+            .map_err(|_| "Timed out waiting for PDU".to_string())?
+            .map_err(|e| format!("Connection closed: {}", e))?;
+        let length = u32::from_be_bytes([buffer[2], buffer[3], buffer[4], buffer[5]]) as usize;
+        if length > self.max_receive_length() {
+            return Err(format!(
+                "PDU length {} exceeds the maximum PDU length of {}",
+                length, self.requestor_max_pdu_length
+            ));
+        }
+        buffer.resize(PDU_HEADER_LENGTH + length, 0);
+        timeout(IO_TIMEOUT, self.socket.read_exact(&mut buffer[PDU_HEADER_LENGTH..]))
+            .await
+            .map_err(|_| "Timed out receiving PDU".to_string())?
+            .map_err(|e| format!("Connection closed: {}", e))?;
+        read_pdu(&buffer[..], self.requestor_max_pdu_length, false)
+            .map_err(|e| format!("Invalid PDU: {}", e))?
+            .ok_or_else(|| "Incomplete PDU".to_string())
+    }
+
+    fn acceptor_max_pdu_length(&self) -> u32 {
+        self.acceptor_max_pdu_length
+    }
+
+    fn received_values(&mut self) -> Option<&mut VecDeque<PDataValue>> {
+        Some(&mut self.received)
+    }
+}
+
+/// User identity negotiation item, following the precedence of `ClientAssociationOptions`
+pub(crate) fn user_identity(
+    username: Option<&str>,
+    password: Option<&str>,
+    kerberos_service_ticket: Option<&str>,
+    saml_assertion: Option<&str>,
+    jwt: Option<&str>,
+) -> Option<UserIdentity> {
+    let (identity_type, primary, secondary) = match (username, password) {
+        (Some(username), Some(password)) => (UserIdentityType::UsernamePassword, username, password),
+        (Some(username), None) => (UserIdentityType::Username, username, ""),
+        _ => match (kerberos_service_ticket, saml_assertion, jwt) {
+            (Some(ticket), _, _) => (UserIdentityType::KerberosServiceTicket, ticket, ""),
+            (None, Some(assertion), _) => (UserIdentityType::SamlAssertion, assertion, ""),
+            (None, None, Some(jwt)) => (UserIdentityType::Jwt, jwt, ""),
+            (None, None, None) => return None,
+        },
+    };
+    Some(UserIdentity::new(false, identity_type, primary.as_bytes().to_vec(), secondary.as_bytes().to_vec()))
+}
+
+/// SCP/SCU Role Selection sub-item for a SOP class
+fn role_selection_item(sop_class_uid: &str, scu_role: bool, scp_role: bool) -> UserVariableItem {
+    let mut data = Vec::with_capacity(sop_class_uid.len() + 4);
+    data.extend_from_slice(&(sop_class_uid.len() as u16).to_be_bytes());
+    data.extend_from_slice(sop_class_uid.as_bytes());
+    data.push(scu_role as u8);
+    data.push(scp_role as u8);
+    UserVariableItem::Unknown(ROLE_SELECTION_ITEM, data)
+}
+
+/// Asynchronous Operations Window sub-item (0 means unlimited)
+fn async_operations_window_item(invoked: u16, performed: u16) -> UserVariableItem {
+    let mut data = Vec::with_capacity(4);
+    data.extend_from_slice(&invoked.to_be_bytes());
+    data.extend_from_slice(&performed.to_be_bytes());
+    UserVariableItem::Unknown(ASYNC_OPERATIONS_WINDOW_ITEM, data)
+}
+
+/// Maximum number of operations invoked and performed of an Asynchronous Operations Window sub-item
+fn parse_async_operations_window(data: &[u8]) -> Option<(u16, u16)> {
+    let data = data.get(..4)?;
+    Some((u16::from_be_bytes([data[0], data[1]]), u16::from_be_bytes([data[2], data[3]])))
+}
+
+/// SOP class, SCU role and SCP role of a Role Selection sub-item
+fn parse_role_selection(data: &[u8]) -> Option<(String, bool, bool)> {
+    let uid_length = u16::from_be_bytes([*data.first()?, *data.get(1)?]) as usize;
+    let uid = data.get(2..2 + uid_length)?;
+    let roles = data.get(2 + uid_length..4 + uid_length)?;
+    let uid = String::from_utf8_lossy(uid).trim_end_matches('\0').to_string();
+    Some((uid, roles[0] == 1, roles[1] == 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_selection_item() {
+        let UserVariableItem::Unknown(item_type, data) = role_selection_item("1.2.840.10008.5.1.4.1.1.7", false, true) else {
+            panic!("unexpected item");
+        };
+        assert_eq!(item_type, ROLE_SELECTION_ITEM);
+        assert_eq!(&data[..2], &[0x00, 0x19]);
+        assert_eq!(
+            parse_role_selection(&data),
+            Some(("1.2.840.10008.5.1.4.1.1.7".to_string(), false, true))
+        );
+        assert_eq!(parse_role_selection(&data[..10]), None);
+    }
+
+    #[test]
+    fn test_async_operations_window_item() {
+        let UserVariableItem::Unknown(item_type, data) = async_operations_window_item(16, 1) else {
+            panic!("unexpected item");
+        };
+        assert_eq!(item_type, ASYNC_OPERATIONS_WINDOW_ITEM);
+        assert_eq!(data, [0x00, 0x10, 0x00, 0x01]);
+        assert_eq!(parse_async_operations_window(&data), Some((16, 1)));
+        assert_eq!(parse_async_operations_window(&data[..3]), None);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_oversized_pdu() {
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peer = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            // P-DATA-TF header announcing almost 4 GiB
+            socket.write_all(&[0x04, 0x00, 0xFF, 0xFF, 0xFF, 0xF0]).await.unwrap();
+            socket
+        });
+        let mut association = RoleSelectingAssociation {
+            socket: Box::new(TcpStream::connect(addr).await.unwrap()),
+            presentation_contexts: Vec::new(),
+            user_variables: Vec::new(),
+            requestor_max_pdu_length: 16384,
+            acceptor_max_pdu_length: 16384,
+            scp_roles: Vec::new(),
+            async_operations_window: None,
+            received: VecDeque::new(),
+        };
+        let error = association.receive().await.unwrap_err();
+        assert!(error.contains("exceeds the maximum PDU length"), "{}", error);
+        drop(peer.await.unwrap());
+    }
+}
@@ -0,0 +1,454 @@
+use std::sync::Arc;
+
+use dicom_core::{dicom_value, DataElement, VR};
+use dicom_dictionary_std::{tags, uids};
+use dicom_encoding::TransferSyntaxIndex;
+use dicom_object::InMemDicomObject;
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+use napi::bindgen_prelude::{AsyncTask, Object};
+use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi::{Env, Result as NapiResult};
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::findscu::{receive_message, send_message, QueryModel};
+use crate::movescu::receiver::{command_string, message_id, received_event, store_instance, CollectedInstance, InstanceCallback};
+use crate::movescu::{
+    failed_sop_instance_uids, suboperations, InstanceReceivedEvent, ReceivedInstance, RetrieveProgressEvent, RetrieveRequest,
+    RetrieveResult,
+};
+use crate::object::StorageConfig;
+use crate::storage::{build_storage, StorageBackend};
+use crate::storescp::create_cstore_response;
+use crate::storescp::sop_classes::{map_sop_class_name, map_transfer_syntax_name};
+use crate::storescp::store_async::is_valid_uid;
+use crate::storescp::transfer::ABSTRACT_SYNTAXES;
+
+pub(crate) mod association;
+
+use association::{user_identity, AssociationRequest, ProposedContext, RoleSelectingAssociation};
+
+/// Presentation context IDs are odd numbers up to 255
+const MAX_PRESENTATION_CONTEXTS: usize = 128;
+
+/// Options for creating a GetScu instance.
+#[napi(object)]
+pub struct GetScuOptions {
+    /// Address of the remote SCP, optionally with AE title (e.g., "PACS@192.168.1.100:104")
+    pub addr: String,
+    /// Calling Application Entity title for this SCU (default: "GET-SCU")
+    pub calling_ae_title: Option<String>,
+    /// Called Application Entity title, overrides AE title in address if present (default: "ANY-SCP")
+    pub called_ae_title: Option<String>,
+    /// Maximum PDU length in bytes (default: 16384)
+    pub max_pdu_length: Option<u32>,
+    /// Storage SOP classes to receive, as names (e.g. "CTImageStorage") or UIDs (default: all storage SOP classes accepted by StoreScp)
+    pub sop_classes: Option<Vec<String>>,
+    /// Transfer syntaxes to accept, as names (e.g. "JPEGBaseline") or UIDs (default: Explicit and Implicit VR Little Endian)
+    pub transfer_syntaxes: Option<Vec<String>>,
+    /// Write received instances as `<study>/<series>/<sop>.dcm` to this storage instead of returning Buffers
+    pub storage: Option<StorageConfig>,
+    /// Enable verbose logging (default: false)
+    pub verbose: Option<bool>,
+    /// User Identity username for authentication
+    pub username: Option<String>,
+    /// User Identity password for authentication
+    pub password: Option<String>,
+    /// User Identity Kerberos service ticket
+    pub kerberos_service_ticket: Option<String>,
+    /// User Identity SAML assertion
+    pub saml_assertion: Option<String>,
+    /// User Identity JWT (JSON Web Token)
+    pub jwt: Option<String>,
+}
+
+/**
+ * DICOM C-GET SCU (Service Class User) Client.
+ *
+ * Retrieves a study, series or list of instances over a single association. Unlike C-MOVE,
+ * the PACS sends the instances back as C-STORE sub-operations on the same association, so
+ * no port has to be opened and the client does not need to be known in the PACS AE table.
+ * The storage SOP classes are proposed with the SCP role (SCP/SCU Role Selection).
+ *
+ * @example
+ * ```typescript
+ * import { GetScu } from '@nuxthealth/node-dicom';
+ *
+ * const scu = new GetScu({
+ *   addr: 'PACS@192.168.1.100:104',
+ *   callingAeTitle: 'MY-SCU',
+ *   storage: { backend: 'Filesystem', rootDir: './retrieved' }
+ * });
+ *
+ * const result = await scu.get({ studyInstanceUid: '1.2.3.4' }, {
+ *   onProgress: (err, event) => console.log(`${event.data?.completed} done, ${event.data?.remaining} remaining`)
+ * });
+ * console.log(result.instances.map(i => i.file));
+ * ```
+ */
+#[napi]
+pub struct GetScu {
+    addr: String,
+    calling_ae_title: String,
+    called_ae_title: Option<String>,
+    max_pdu_length: u32,
+    sop_classes: Vec<String>,
+    transfer_syntaxes: Vec<String>,
+    storage: Option<Arc<dyn StorageBackend>>,
+    verbose: bool,
+    username: Option<String>,
+    password: Option<String>,
+    kerberos_service_ticket: Option<String>,
+    saml_assertion: Option<String>,
+    jwt: Option<String>,
+}
+
+/// UID of a SOP class given by name or UID, so that typos fail here instead of in the association
+fn sop_class_uid(name: &str) -> Result<String, String> {
+    match map_sop_class_name(name) {
+        Some(uid) => Ok(uid.to_string()),
+        None if is_valid_uid(name) && name.split('.').all(|c| !c.is_empty()) => Ok(name.to_string()),
+        None => Err(format!("Unknown SOP class: {}", name)),
+    }
+}
+
+/// UID of a transfer syntax given by name or UID
+fn transfer_syntax_uid(name: &str) -> Result<String, String> {
+    let uid = map_transfer_syntax_name(name).unwrap_or(name);
+    match TransferSyntaxRegistry.get(uid) {
+        Some(_) => Ok(uid.to_string()),
+        None => Err(format!("Unsupported transfer syntax: {}", name)),
+    }
+}
+
+#[napi]
+impl GetScu {
+    /**
+     * Create a new DICOM C-GET SCU client instance.
+     *
+     * @param options - Client configuration options
+     * @returns New GetScu instance
+     *
+     * @example
+     * ```typescript
+     * // Receive CT and MR instances in memory, accepting JPEG Baseline as well
+     * const scu = new GetScu({
+     *   addr: 'PACS@192.168.1.100:104',
+     *   sopClasses: ['CTImageStorage', 'MRImageStorage'],
+     *   transferSyntaxes: ['ExplicitVRLittleEndian', 'ImplicitVRLittleEndian', 'JPEGBaseline']
+     * });
+     * ```
+     */
+    #[napi(constructor)]
+    pub fn new(options: GetScuOptions) -> napi::Result<Self> {
+        let verbose = options.verbose.unwrap_or(false);
+
+        use tracing_subscriber::EnvFilter;
+        let filter = EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| {
+                if verbose {
+                    EnvFilter::new("debug")
+                } else {
+                    EnvFilter::new("error")
+                }
+            });
+
+        let _ = tracing::subscriber::set_global_default(
+            tracing_subscriber::FmtSubscriber::builder()
+                .with_env_filter(filter)
+                .finish(),
+        );
+
+        let sop_classes: Vec<String> = match options.sop_classes {
+            Some(names) => names
+                .iter()
+                .map(|name| sop_class_uid(name))
+                .collect::<Result<_, _>>()
+                .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e))?,
+            None => ABSTRACT_SYNTAXES.iter().map(|uid| uid.to_string()).collect(),
+        };
+        if sop_classes.is_empty() || sop_classes.len() >= MAX_PRESENTATION_CONTEXTS {
+            return Err(napi::Error::from_reason(format!(
+                "Between 1 and {} storage SOP classes are supported",
+                MAX_PRESENTATION_CONTEXTS - 1
+            )));
+        }
+        let transfer_syntaxes = match options.transfer_syntaxes {
+            Some(names) if !names.is_empty() => names
+                .iter()
+                .map(|name| transfer_syntax_uid(name))
+                .collect::<Result<_, _>>()
+                .map_err(|e| napi::Error::new(napi::Status::InvalidArg, e))?,
+            _ => vec![uids::EXPLICIT_VR_LITTLE_ENDIAN.to_string(), uids::IMPLICIT_VR_LITTLE_ENDIAN.to_string()],
+        };
+        let storage = options
+            .storage
+            .as_ref()
+            .map(build_storage)
+            .transpose()
+            .map_err(napi::Error::from_reason)?;
+
+        Ok(GetScu {
+            addr: options.addr,
+            calling_ae_title: options.calling_ae_title.unwrap_or_else(|| "GET-SCU".to_string()),
+            called_ae_title: options.called_ae_title,
+            max_pdu_length: options.max_pdu_length.unwrap_or(16384),
+            sop_classes,
+            transfer_syntaxes,
+            storage,
+            verbose,
+            username: options.username,
+            password: options.password,
+            kerberos_service_ticket: options.kerberos_service_ticket,
+            saml_assertion: options.saml_assertion,
+            jwt: options.jwt,
+        })
+    }
+
+    /**
+     * Send a C-GET request and receive the instances.
+     *
+     * Resolves when the PACS sends its final response. Sub-operation counts are reported
+     * through `onProgress`; every received instance is reported through `onInstanceReceived`.
+     *
+     * @param request - Study, series or instances to retrieve
+     * @param callbacks - Optional callbacks
+     * @returns Sub-operation counts and received instances
+     *
+     * @example
+     * ```typescript
+     * const result = await scu.get({
+     *   studyInstanceUid: '1.2.3.4',
+     *   seriesInstanceUid: '1.2.3.4.5'
+     * }, {
+     *   onInstanceReceived: (err, event) => console.log('Received', event.data?.sopInstanceUid)
+     * });
+     * for (const instance of result.instances) {
+     *   await fs.promises.writeFile(`${instance.sopInstanceUid}.dcm`, instance.buffer!);
+     * }
+     * ```
+     */
+    #[napi(
+        ts_args_type = "request: RetrieveRequest, callbacks?: { onProgress?: (err: Error | null, event: RetrieveProgressEvent) => void, onInstanceReceived?: (err: Error | null, event: InstanceReceivedEvent) => void }",
+        ts_return_type = "Promise<RetrieveResult>"
+    )]
+    pub fn get(&self, _env: Env, request: RetrieveRequest, callbacks: Option<Object>) -> NapiResult<AsyncTask<GetScuHandler>> {
+        let (on_progress, on_instance_received) = match callbacks {
+            Some(callbacks_obj) => (
+                callbacks_obj.get::<ThreadsafeFunction<RetrieveProgressEvent, ()>>("onProgress")?,
+                callbacks_obj.get::<ThreadsafeFunction<InstanceReceivedEvent, ()>>("onInstanceReceived")?,
+            ),
+            None => (None, None),
+        };
+
+        let model = request.model.unwrap_or(QueryModel::StudyRoot);
+        let identifier = request.identifier().map_err(napi::Error::from_reason)?;
+
+        let mut contexts = vec![ProposedContext {
+            abstract_syntax: model.get_sop_class().to_string(),
+            transfer_syntaxes: vec![uids::EXPLICIT_VR_LITTLE_ENDIAN.to_string(), uids::IMPLICIT_VR_LITTLE_ENDIAN.to_string()],
+            scp_role: false,
+        }];
+        contexts.extend(self.sop_classes.iter().map(|sop_class_uid| ProposedContext {
+            abstract_syntax: sop_class_uid.clone(),
+            transfer_syntaxes: self.transfer_syntaxes.clone(),
+            scp_role: true,
+        }));
+
+        Ok(AsyncTask::new(GetScuHandler {
+            addr: self.addr.clone(),
+            association: AssociationRequest {
+                calling_ae_title: self.calling_ae_title.clone(),
+                called_ae_title: self.called_ae_title.clone(),
+                max_pdu_length: self.max_pdu_length,
+                contexts,
+                user_identity: user_identity(
+                    self.username.as_deref(),
+                    self.password.as_deref(),
+                    self.kerberos_service_ticket.as_deref(),
+                    self.saml_assertion.as_deref(),
+                    self.jwt.as_deref(),
+                ),
+                async_operations_window: None,
+                tls: None,
+            },
+            verbose: self.verbose,
+            sop_class_uid: model.get_sop_class(),
+            identifier,
+            storage: self.storage.clone(),
+            on_progress: on_progress.map(Arc::new),
+            on_instance_received: on_instance_received.map(Arc::new),
+        }))
+    }
+}
+
+pub struct GetScuHandler {
+    addr: String,
+    association: AssociationRequest,
+    verbose: bool,
+    sop_class_uid: &'static str,
+    identifier: InMemDicomObject,
+    storage: Option<Arc<dyn StorageBackend>>,
+    on_progress: Option<Arc<ThreadsafeFunction<RetrieveProgressEvent, ()>>>,
+    on_instance_received: Option<Arc<ThreadsafeFunction<InstanceReceivedEvent, ()>>>,
+}
+
+#[napi]
+impl napi::Task for GetScuHandler {
+    type JsValue = RetrieveResult;
+    type Output = (RetrieveResult, Vec<CollectedInstance>);
+
+    fn compute(&mut self) -> napi::bindgen_prelude::Result<Self::Output> {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        rt.block_on(run_get(self))
+            .map_err(|e| napi::Error::from_reason(format!("C-GET failed: {}", e)))
+    }
+
+    fn resolve(&mut self, _env: napi::Env, output: Self::Output) -> napi::bindgen_prelude::Result<Self::JsValue> {
+        let (mut result, instances) = output;
+        result.instances = instances.into_iter().map(ReceivedInstance::from).collect();
+        Ok(result)
+    }
+}
+
+async fn run_get(task: &GetScuHandler) -> Result<(RetrieveResult, Vec<CollectedInstance>), String> {
+    let on_instance_received = task.on_instance_received.clone().map(|cb| -> InstanceCallback {
+        Arc::new(move |instance| {
+            cb.call(Ok(received_event(instance)), ThreadsafeFunctionCallMode::NonBlocking);
+        })
+    });
+
+    if task.verbose {
+        info!("Establishing association with '{}'...", &task.addr);
+    }
+    let mut scu = RoleSelectingAssociation::establish(&task.addr, task.association.clone())
+        .await
+        .map_err(|e| format!("Could not establish association: {}", e))?;
+
+    let Some(pc) = scu
+        .presentation_contexts()
+        .iter()
+        .find(|pc| pc.abstract_syntax == task.sop_class_uid)
+        .cloned()
+    else {
+        let _ = scu.abort().await;
+        return Err(format!("Information model {} was not accepted", task.sop_class_uid));
+    };
+    let storage_contexts = scu.presentation_contexts().len() - 1;
+    if storage_contexts == 0 {
+        let _ = scu.abort().await;
+        return Err("None of the storage SOP classes was accepted".to_string());
+    }
+    for storage_pc in scu.presentation_contexts().iter().filter(|p| p.id != pc.id) {
+        if !scu.has_scp_role(&storage_pc.abstract_syntax) {
+            debug!("SCP role for {} was not acknowledged", storage_pc.abstract_syntax);
+        }
+    }
+
+    let command = get_req_command(task.sop_class_uid, 1);
+    send_message(&mut scu, pc.id, &command, Some((&task.identifier, &pc.transfer_syntax))).await?;
+
+    let instances = Mutex::new(Vec::new());
+    let (status, progress, message) = loop {
+        let message = receive_message(&mut scu).await?;
+        match message.command_field() {
+            // C-STORE-RQ
+            0x0001 => {
+                let ts_uid = scu
+                    .presentation_contexts()
+                    .iter()
+                    .find(|pc| pc.id == message.presentation_context_id)
+                    .map(|pc| pc.transfer_syntax.clone())
+                    .unwrap_or_default();
+                let status = match &message.data {
+                    Some(data) => store_instance(data, &ts_uid, task.storage.as_deref(), &instances, &on_instance_received).await,
+                    // Processing failure
+                    None => 0x0110,
+                };
+                let response = create_cstore_response(
+                    message_id(&message.command),
+                    &command_string(&message.command, tags::AFFECTED_SOP_CLASS_UID),
+                    &command_string(&message.command, tags::AFFECTED_SOP_INSTANCE_UID),
+                    status,
+                );
+                send_message(&mut scu, message.presentation_context_id, &response, None).await?;
+            }
+            // C-GET-RSP
+            0x8010 => {
+                let status = message.status()?;
+                let progress = suboperations(&message);
+                if !matches!(status, 0xFF00 | 0xFF01) {
+                    break (status, progress, message);
+                }
+                if let Some(cb) = &task.on_progress {
+                    cb.call(Ok(RetrieveProgressEvent {
+                        message: "Sub-operations pending".to_string(),
+                        data: Some(progress),
+                    }), ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+            command_field => warn!("Ignoring unexpected command {:04X}H", command_field),
+        }
+    };
+
+    if let Err(e) = scu.release().await {
+        warn!("Failed to release association: {}", e);
+    }
+
+    match status {
+        0x0000 | 0xFE00 => {}
+        0xB000..=0xBFFF => warn!("C-GET completed with warning status {:04X}H", status),
+        _ => return Err(format!("C-GET failed with status {:04X}H", status)),
+    }
+
+    if task.verbose {
+        info!("C-GET completed: {} completed, {} failed", progress.completed, progress.failed);
+    }
+
+    let result = RetrieveResult {
+        status: status as u32,
+        completed: progress.completed,
+        failed: progress.failed,
+        warning: progress.warning,
+        failed_sop_instance_uids: failed_sop_instance_uids(&message, &pc.transfer_syntax),
+        instances: Vec::new(),
+    };
+    Ok((result, instances.into_inner()))
+}
+
+fn get_req_command(sop_class_uid: &str, message_id: u16) -> InMemDicomObject {
+    InMemDicomObject::command_from_element_iter([
+        // SOP Class UID
+        DataElement::new(tags::AFFECTED_SOP_CLASS_UID, VR::UI, dicom_value!(Str, sop_class_uid)),
+        // command field
+        DataElement::new(tags::COMMAND_FIELD, VR::US, dicom_value!(U16, [0x0010])),
+        // message ID
+        DataElement::new(tags::MESSAGE_ID, VR::US, dicom_value!(U16, [message_id])),
+        // priority
+        DataElement::new(tags::PRIORITY, VR::US, dicom_value!(U16, [0x0000])),
+        // data set type
+        DataElement::new(tags::COMMAND_DATA_SET_TYPE, VR::US, dicom_value!(U16, [0x0000])),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_names() {
+        assert_eq!(sop_class_uid("CTImageStorage").unwrap(), uids::CT_IMAGE_STORAGE);
+        assert_eq!(sop_class_uid("1.2.840.10008.5.1.4.1.1.2").unwrap(), uids::CT_IMAGE_STORAGE);
+        assert!(sop_class_uid("CTImageStorag").is_err());
+        assert!(sop_class_uid("1.2..3").is_err());
+
+        assert_eq!(transfer_syntax_uid("JPEGBaseline").unwrap(), uids::JPEG_BASELINE8_BIT);
+        assert_eq!(transfer_syntax_uid(uids::EXPLICIT_VR_LITTLE_ENDIAN).unwrap(), uids::EXPLICIT_VR_LITTLE_ENDIAN);
+        assert!(transfer_syntax_uid("ExplicitVRLittleEndain").is_err());
+        assert!(transfer_syntax_uid("1.2.3").is_err());
+    }
+}
@@ -0,0 +1,817 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use dicom_core::Tag;
+use dicom_dictionary_std::tags;
+use dicom_object::InMemDicomObject;
+use rusqlite::{params, params_from_iter, Connection, Row};
+use tracing::info;
+
+use crate::web::qido::{
+    DicomJsonAttributes, DicomJsonValue, SearchForSeriesInstancesQuery, SearchForSeriesQuery,
+    SearchForStudiesQuery, SearchForStudyInstancesQuery,
+};
+
+pub(crate) type IndexResult<T> = Result<T, String>;
+
+lazy_static::lazy_static! {
+    // One connection per index file, shared by StoreScp, QidoServer, WadoServer and DicomIndex
+    static ref OPEN_INDEXES: Mutex<HashMap<String, Arc<IndexDb>>> = Mutex::new(HashMap::new());
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS patients (
+    patient_id TEXT NOT NULL,
+    issuer_of_patient_id TEXT NOT NULL,
+    patient_name TEXT,
+    patient_birth_date TEXT,
+    patient_sex TEXT,
+    PRIMARY KEY (patient_id, issuer_of_patient_id)
+);
+CREATE TABLE IF NOT EXISTS studies (
+    study_instance_uid TEXT PRIMARY KEY,
+    patient_id TEXT NOT NULL,
+    issuer_of_patient_id TEXT NOT NULL,
+    study_date TEXT,
+    study_time TEXT,
+    accession_number TEXT,
+    study_id TEXT,
+    study_description TEXT,
+    referring_physician_name TEXT
+);
+CREATE TABLE IF NOT EXISTS series (
+    series_instance_uid TEXT PRIMARY KEY,
+    study_instance_uid TEXT NOT NULL,
+    modality TEXT,
+    series_number TEXT,
+    series_description TEXT,
+    body_part_examined TEXT,
+    performed_procedure_step_start_date TEXT,
+    performed_procedure_step_start_time TEXT
+);
+CREATE TABLE IF NOT EXISTS instances (
+    sop_instance_uid TEXT PRIMARY KEY,
+    series_instance_uid TEXT NOT NULL,
+    study_instance_uid TEXT NOT NULL,
+    sop_class_uid TEXT,
+    instance_number TEXT,
+    transfer_syntax_uid TEXT,
+    number_of_frames TEXT,
+    rows INTEGER,
+    columns INTEGER,
+    storage_key TEXT NOT NULL,
+    location TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    indexed_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_studies_patient ON studies(patient_id, issuer_of_patient_id);
+CREATE INDEX IF NOT EXISTS idx_series_study ON series(study_instance_uid);
+CREATE INDEX IF NOT EXISTS idx_instances_study ON instances(study_instance_uid);
+CREATE INDEX IF NOT EXISTS idx_instances_series ON instances(series_instance_uid);
+";
+
+/// Open (or create) the index stored at `path`.
+///
+/// Connections are cached per path so every component in the process shares the same one.
+pub(crate) fn open_index(path: &str) -> IndexResult<Arc<IndexDb>> {
+    let mut open = OPEN_INDEXES.lock().map_err(|e| e.to_string())?;
+    if let Some(db) = open.get(path) {
+        return Ok(db.clone());
+    }
+    let db = Arc::new(IndexDb::open(path)?);
+    open.insert(path.to_string(), db.clone());
+    Ok(db)
+}
+
+/// Attributes of an instance to be indexed, extracted from its data set so that
+/// the data set itself does not have to be moved to the blocking thread pool
+pub(crate) struct InstanceRecord {
+    values: HashMap<Tag, String>,
+    rows: Option<u32>,
+    columns: Option<u32>,
+    transfer_syntax_uid: String,
+    storage_key: String,
+    location: String,
+    size: usize,
+    /// Time the instance was received in milliseconds since the Unix epoch (`None` for now)
+    received_at: Option<i64>,
+}
+
+/// Attributes stored in the index
+const INDEXED_TAGS: [Tag; 23] = [
+    tags::PATIENT_ID,
+    tags::ISSUER_OF_PATIENT_ID,
+    tags::PATIENT_NAME,
+    tags::PATIENT_BIRTH_DATE,
+    tags::PATIENT_SEX,
+    tags::STUDY_INSTANCE_UID,
+    tags::STUDY_DATE,
+    tags::STUDY_TIME,
+    tags::ACCESSION_NUMBER,
+    tags::STUDY_ID,
+    tags::STUDY_DESCRIPTION,
+    tags::REFERRING_PHYSICIAN_NAME,
+    tags::SERIES_INSTANCE_UID,
+    tags::MODALITY,
+    tags::SERIES_NUMBER,
+    tags::SERIES_DESCRIPTION,
+    tags::BODY_PART_EXAMINED,
+    tags::PERFORMED_PROCEDURE_STEP_START_DATE,
+    tags::PERFORMED_PROCEDURE_STEP_START_TIME,
+    tags::SOP_INSTANCE_UID,
+    tags::SOP_CLASS_UID,
+    tags::INSTANCE_NUMBER,
+    tags::NUMBER_OF_FRAMES,
+];
+
+impl InstanceRecord {
+    pub fn new(obj: &InMemDicomObject, transfer_syntax_uid: &str, storage_key: &str, location: &str, size: usize) -> Self {
+        InstanceRecord {
+            values: INDEXED_TAGS.iter().filter_map(|tag| Some((*tag, text(obj, *tag)?))).collect(),
+            rows: obj.element(tags::ROWS).ok().and_then(|e| e.to_int::<u32>().ok()),
+            columns: obj.element(tags::COLUMNS).ok().and_then(|e| e.to_int::<u32>().ok()),
+            transfer_syntax_uid: transfer_syntax_uid.trim_end_matches('\0').to_string(),
+            storage_key: storage_key.to_string(),
+            location: location.to_string(),
+            size,
+            received_at: None,
+        }
+    }
+
+    /// Index the instance as received at `received_at` instead of now, e.g. the modification
+    /// time of an object found by a rebuild
+    pub fn with_received_at(mut self, received_at: Option<i64>) -> Self {
+        self.received_at = received_at;
+        self
+    }
+
+    fn text(&self, tag: Tag) -> Option<&str> {
+        self.values.get(&tag).map(String::as_str)
+    }
+}
+
+/// UIDs identifying an indexed instance
+#[derive(Debug, Clone)]
+pub(crate) struct IndexedInstance {
+    pub study_instance_uid: String,
+    pub series_instance_uid: String,
+    pub sop_instance_uid: String,
+}
+
+/// Size and age of an indexed study, used by retention policies
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IndexedStudy {
+    pub study_instance_uid: String,
+    pub patient_id: Option<String>,
+    pub study_date: Option<String>,
+    /// Time the last instance was received, in milliseconds since the Unix epoch
+    pub received_at: i64,
+    pub instances: u32,
+    pub size: i64,
+    /// Size of the instances per series modality
+    pub modality_sizes: HashMap<String, i64>,
+}
+
+/// Number of entries per level
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IndexCounts {
+    pub patients: u32,
+    pub studies: u32,
+    pub series: u32,
+    pub instances: u32,
+}
+
+/// SQLite database holding patient, study, series and instance attributes
+pub(crate) struct IndexDb {
+    conn: Mutex<Connection>,
+}
+
+impl IndexDb {
+    fn open(path: &str) -> IndexResult<Self> {
+        if let Some(parent) = std::path::Path::new(path).parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent).map_err(|e| format!("could not create index directory: {}", e))?;
+            }
+        }
+        let conn = Connection::open(path).map_err(|e| format!("could not open index {}: {}", path, e))?;
+        conn.pragma_update(None, "journal_mode", "WAL").map_err(|e| e.to_string())?;
+        conn.pragma_update(None, "synchronous", "NORMAL").map_err(|e| e.to_string())?;
+        conn.execute_batch(SCHEMA).map_err(|e| format!("could not create index schema: {}", e))?;
+        info!("Opened DICOM index {}", path);
+        Ok(IndexDb { conn: Mutex::new(conn) })
+    }
+
+    fn conn(&self) -> IndexResult<std::sync::MutexGuard<'_, Connection>> {
+        self.conn.lock().map_err(|e| e.to_string())
+    }
+
+    /// Run index operations on the blocking thread pool, so that async tasks
+    /// (store loop, QIDO/WADO handlers, retention sweeps) do not block a runtime worker
+    pub async fn run<T, F>(self: &Arc<Self>, f: F) -> IndexResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&IndexDb) -> IndexResult<T> + Send + 'static,
+    {
+        let db = self.clone();
+        tokio::task::spawn_blocking(move || f(&db))
+            .await
+            .map_err(|e| format!("index task failed: {}", e))?
+    }
+
+    /// Insert or update an instance together with its patient, study and series
+    pub fn index_instance(&self, record: &InstanceRecord) -> IndexResult<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        insert_instance(&tx, record, record.received_at.unwrap_or_else(now_millis))?;
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    /// Replace the content of the index with the given instances in one transaction, so readers see
+    /// either the old or the new content. Instances indexed from `since` on, by a StoreScp writing
+    /// meanwhile, are kept. Returns the position and error of the records that could not be indexed.
+    pub fn replace_all(&self, records: &[InstanceRecord], since: i64) -> IndexResult<Vec<(usize, String)>> {
+        let now = now_millis();
+        let mut conn = self.conn()?;
+        let mut tx = conn.transaction().map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM instances WHERE indexed_at < ?1", [since])
+            .map_err(|e| e.to_string())?;
+        let mut failed = Vec::new();
+        for (i, record) in records.iter().enumerate() {
+            // a failed record leaves no partial rows behind
+            let savepoint = tx.savepoint().map_err(|e| e.to_string())?;
+            match insert_instance(&savepoint, record, record.received_at.unwrap_or(now)) {
+                Ok(()) => savepoint.commit().map_err(|e| e.to_string())?,
+                Err(e) => failed.push((i, e)),
+            }
+        }
+        tx.execute_batch(
+            "DELETE FROM series WHERE NOT EXISTS (
+                SELECT 1 FROM instances i WHERE i.series_instance_uid = series.series_instance_uid
+             );
+             DELETE FROM studies WHERE NOT EXISTS (
+                SELECT 1 FROM series se WHERE se.study_instance_uid = studies.study_instance_uid
+             );
+             DELETE FROM patients WHERE NOT EXISTS (
+                SELECT 1 FROM studies st
+                WHERE st.patient_id = patients.patient_id AND st.issuer_of_patient_id = patients.issuer_of_patient_id
+             );",
+        )
+        .map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(failed)
+    }
+
+    pub fn counts(&self) -> IndexResult<IndexCounts> {
+        let conn = self.conn()?;
+        let count = |table: &str| -> IndexResult<u32> {
+            conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0))
+                .map_err(|e| e.to_string())
+        };
+        Ok(IndexCounts {
+            patients: count("patients")?,
+            studies: count("studies")?,
+            series: count("series")?,
+            instances: count("instances")?,
+        })
+    }
+
+    /// Instances of a study, optionally restricted to one series
+    pub fn find_instances(&self, study_instance_uid: &str, series_instance_uid: Option<&str>) -> IndexResult<Vec<IndexedInstance>> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT study_instance_uid, series_instance_uid, sop_instance_uid
+                 FROM instances
+                 WHERE study_instance_uid = ?1 AND (?2 IS NULL OR series_instance_uid = ?2)
+                 ORDER BY series_instance_uid, CAST(instance_number AS INTEGER), sop_instance_uid",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![study_instance_uid, series_instance_uid], |row| {
+                Ok(IndexedInstance {
+                    study_instance_uid: row.get(0)?,
+                    series_instance_uid: row.get(1)?,
+                    sop_instance_uid: row.get(2)?,
+                })
+            })
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+    }
+
+    /// All studies with their size, last receive time and size per modality
+    pub fn studies_with_sizes(&self) -> IndexResult<Vec<IndexedStudy>> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT i.study_instance_uid, st.patient_id, st.study_date, se.modality,
+                    COUNT(*), SUM(i.size), MAX(i.indexed_at)
+                 FROM instances i
+                 LEFT JOIN studies st ON st.study_instance_uid = i.study_instance_uid
+                 LEFT JOIN series se ON se.series_instance_uid = i.series_instance_uid
+                 GROUP BY i.study_instance_uid, se.modality
+                 ORDER BY i.study_instance_uid",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, u32>(4)?,
+                    row.get::<_, i64>(5)?,
+                    row.get::<_, i64>(6)?,
+                ))
+            })
+            .map_err(|e| e.to_string())?;
+        let mut studies: Vec<IndexedStudy> = Vec::new();
+        for row in rows {
+            let (study_instance_uid, patient_id, study_date, modality, instances, size, received_at) = row.map_err(|e| e.to_string())?;
+            if studies.last().is_none_or(|s| s.study_instance_uid != study_instance_uid) {
+                studies.push(IndexedStudy { study_instance_uid, patient_id, study_date, ..Default::default() });
+            }
+            let study = studies.last_mut().expect("study was just pushed");
+            study.instances += instances;
+            study.size += size;
+            study.received_at = study.received_at.max(received_at);
+            *study.modality_sizes.entry(modality.unwrap_or_default()).or_default() += size;
+        }
+        Ok(studies)
+    }
+
+    /// Storage keys of all instances of a study
+    pub fn study_storage_keys(&self, study_instance_uid: &str) -> IndexResult<Vec<String>> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare("SELECT storage_key FROM instances WHERE study_instance_uid = ?1")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt.query_map([study_instance_uid], |row| row.get(0)).map_err(|e| e.to_string())?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+    }
+
+    /// Remove a study with its series and instances, and its patient if no other study refers to it
+    pub fn remove_study(&self, study_instance_uid: &str) -> IndexResult<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM instances WHERE study_instance_uid = ?1", [study_instance_uid])
+            .map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM series WHERE study_instance_uid = ?1", [study_instance_uid])
+            .map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM studies WHERE study_instance_uid = ?1", [study_instance_uid])
+            .map_err(|e| e.to_string())?;
+        tx.execute(
+            "DELETE FROM patients WHERE NOT EXISTS (
+                SELECT 1 FROM studies st
+                WHERE st.patient_id = patients.patient_id AND st.issuer_of_patient_id = patients.issuer_of_patient_id
+             )",
+            [],
+        )
+        .map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    /// Search for Studies, returning DICOM JSON study attributes
+    pub fn search_studies(&self, query: &SearchForStudiesQuery) -> IndexResult<Vec<DicomJsonAttributes>> {
+        let mut filter = Filter::default();
+        filter.range("st.study_date", query.study_date.as_deref());
+        filter.range("st.study_time", query.study_time.as_deref());
+        filter.matching("st.accession_number", query.accession_number.as_deref());
+        filter.matching("st.referring_physician_name", query.referring_physician_name.as_deref());
+        filter.matching("p.patient_name", query.patient_name.as_deref());
+        filter.matching("st.patient_id", query.patient_id.as_deref());
+        filter.matching("st.study_instance_uid", query.study_instance_uid.as_deref());
+        filter.matching("st.study_id", query.study_id.as_deref());
+        if let Some(modality) = query.modalities_in_study.as_deref().filter(|m| !m.is_empty()) {
+            filter.clauses.push(
+                "EXISTS (SELECT 1 FROM series se WHERE se.study_instance_uid = st.study_instance_uid AND se.modality = ?)".to_string(),
+            );
+            filter.params.push(modality.to_string());
+        }
+
+        let sql = format!(
+            "SELECT st.study_instance_uid, st.patient_id, st.study_date, st.study_time, st.accession_number,
+                st.study_id, st.study_description, st.referring_physician_name,
+                p.patient_name, p.patient_birth_date, p.patient_sex, st.issuer_of_patient_id,
+                (SELECT GROUP_CONCAT(DISTINCT se.modality) FROM series se WHERE se.study_instance_uid = st.study_instance_uid),
+                (SELECT COUNT(*) FROM series se WHERE se.study_instance_uid = st.study_instance_uid),
+                (SELECT COUNT(*) FROM instances i WHERE i.study_instance_uid = st.study_instance_uid)
+             FROM studies st LEFT JOIN patients p
+                ON p.patient_id = st.patient_id AND p.issuer_of_patient_id = st.issuer_of_patient_id
+             {} ORDER BY st.study_date DESC, st.study_time DESC {}",
+            filter.where_clause(),
+            paging(query.limit, query.offset),
+        );
+        self.query(&sql, &filter.params, |row| {
+            let mut attrs = DicomJsonAttributes::new();
+            put(&mut attrs, "0020000D", "UI", row.get(0)?);
+            put(&mut attrs, "00100020", "LO", row.get(1)?);
+            put(&mut attrs, "00080020", "DA", row.get(2)?);
+            put(&mut attrs, "00080030", "TM", row.get(3)?);
+            put(&mut attrs, "00080050", "SH", row.get(4)?);
+            put(&mut attrs, "00200010", "SH", row.get(5)?);
+            put(&mut attrs, "00081030", "LO", row.get(6)?);
+            put(&mut attrs, "00080090", "PN", row.get(7)?);
+            put(&mut attrs, "00100010", "PN", row.get(8)?);
+            put(&mut attrs, "00100030", "DA", row.get(9)?);
+            put(&mut attrs, "00100040", "CS", row.get(10)?);
+            put(&mut attrs, "00100021", "LO", Some(row.get::<_, String>(11)?).filter(|v| !v.is_empty()));
+            let modalities: Option<String> = row.get(12)?;
+            attrs.insert("00080061".to_string(), DicomJsonValue {
+                vr: "CS".to_string(),
+                value: modalities.map(|m| m.split(',').map(str::to_string).collect()),
+            });
+            put(&mut attrs, "00201206", "IS", Some(row.get::<_, i64>(13)?.to_string()));
+            put(&mut attrs, "00201208", "IS", Some(row.get::<_, i64>(14)?.to_string()));
+            Ok(attrs)
+        })
+    }
+
+    /// Search for Series in a study, returning DICOM JSON series attributes
+    pub fn search_series(&self, query: &SearchForSeriesQuery) -> IndexResult<Vec<DicomJsonAttributes>> {
+        let mut filter = Filter::default();
+        filter.matching("se.study_instance_uid", Some(query.study_instance_uid.as_str()));
+        filter.matching("se.modality", query.modality.as_deref());
+        filter.matching("se.series_instance_uid", query.series_instance_uid.as_deref());
+        filter.matching("se.series_number", query.series_number.as_deref());
+        filter.range("se.performed_procedure_step_start_date", query.performed_procedure_step_start_date.as_deref());
+        filter.range("se.performed_procedure_step_start_time", query.performed_procedure_step_start_time.as_deref());
+
+        let sql = format!(
+            "SELECT se.study_instance_uid, se.series_instance_uid, se.modality, se.series_number,
+                se.series_description, se.body_part_examined, se.performed_procedure_step_start_date,
+                se.performed_procedure_step_start_time,
+                (SELECT COUNT(*) FROM instances i WHERE i.series_instance_uid = se.series_instance_uid)
+             FROM series se
+             {} ORDER BY CAST(se.series_number AS INTEGER), se.series_instance_uid {}",
+            filter.where_clause(),
+            paging(query.limit, query.offset),
+        );
+        self.query(&sql, &filter.params, |row| {
+            let mut attrs = DicomJsonAttributes::new();
+            put(&mut attrs, "0020000D", "UI", row.get(0)?);
+            put(&mut attrs, "0020000E", "UI", row.get(1)?);
+            put(&mut attrs, "00080060", "CS", row.get(2)?);
+            put(&mut attrs, "00200011", "IS", row.get(3)?);
+            put(&mut attrs, "0008103E", "LO", row.get(4)?);
+            put(&mut attrs, "00180015", "CS", row.get(5)?);
+            put(&mut attrs, "00400244", "DA", row.get(6)?);
+            put(&mut attrs, "00400245", "TM", row.get(7)?);
+            put(&mut attrs, "00201209", "IS", Some(row.get::<_, i64>(8)?.to_string()));
+            Ok(attrs)
+        })
+    }
+
+    /// Search for Instances in a study
+    pub fn search_study_instances(&self, query: &SearchForStudyInstancesQuery) -> IndexResult<Vec<DicomJsonAttributes>> {
+        self.search_instances(
+            &query.study_instance_uid,
+            None,
+            [query.sop_class_uid.as_deref(), query.sop_instance_uid.as_deref(), query.instance_number.as_deref()],
+            query.limit,
+            query.offset,
+        )
+    }
+
+    /// Search for Instances in a series
+    pub fn search_series_instances(&self, query: &SearchForSeriesInstancesQuery) -> IndexResult<Vec<DicomJsonAttributes>> {
+        self.search_instances(
+            &query.study_instance_uid,
+            Some(&query.series_instance_uid),
+            [query.sop_class_uid.as_deref(), query.sop_instance_uid.as_deref(), query.instance_number.as_deref()],
+            query.limit,
+            query.offset,
+        )
+    }
+
+    fn search_instances(
+        &self,
+        study_instance_uid: &str,
+        series_instance_uid: Option<&str>,
+        [sop_class_uid, sop_instance_uid, instance_number]: [Option<&str>; 3],
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> IndexResult<Vec<DicomJsonAttributes>> {
+        let mut filter = Filter::default();
+        filter.matching("i.study_instance_uid", Some(study_instance_uid));
+        filter.matching("i.series_instance_uid", series_instance_uid);
+        filter.matching("i.sop_class_uid", sop_class_uid);
+        filter.matching("i.sop_instance_uid", sop_instance_uid);
+        filter.matching("i.instance_number", instance_number);
+
+        let sql = format!(
+            "SELECT i.study_instance_uid, i.series_instance_uid, i.sop_instance_uid, i.sop_class_uid,
+                i.instance_number, i.transfer_syntax_uid, i.number_of_frames, i.rows, i.columns, se.modality
+             FROM instances i LEFT JOIN series se ON se.series_instance_uid = i.series_instance_uid
+             {} ORDER BY i.series_instance_uid, CAST(i.instance_number AS INTEGER), i.sop_instance_uid {}",
+            filter.where_clause(),
+            paging(limit, offset),
+        );
+        self.query(&sql, &filter.params, |row| {
+            let mut attrs = DicomJsonAttributes::new();
+            put(&mut attrs, "0020000D", "UI", row.get(0)?);
+            put(&mut attrs, "0020000E", "UI", row.get(1)?);
+            put(&mut attrs, "00080018", "UI", row.get(2)?);
+            put(&mut attrs, "00080016", "UI", row.get(3)?);
+            put(&mut attrs, "00200013", "IS", row.get(4)?);
+            put(&mut attrs, "00020010", "UI", row.get(5)?);
+            put(&mut attrs, "00280008", "IS", row.get(6)?);
+            put(&mut attrs, "00280010", "US", row.get::<_, Option<i64>>(7)?.map(|v| v.to_string()));
+            put(&mut attrs, "00280011", "US", row.get::<_, Option<i64>>(8)?.map(|v| v.to_string()));
+            put(&mut attrs, "00080060", "CS", row.get(9)?);
+            Ok(attrs)
+        })
+    }
+
+    fn query(
+        &self,
+        sql: &str,
+        params: &[String],
+        map: impl Fn(&Row<'_>) -> rusqlite::Result<DicomJsonAttributes>,
+    ) -> IndexResult<Vec<DicomJsonAttributes>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(sql).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params_from_iter(params.iter()), map).map_err(|e| e.to_string())?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+    }
+}
+
+/// Read a trimmed string value, `None` if absent or empty
+fn text(obj: &InMemDicomObject, tag: Tag) -> Option<String> {
+    obj.element(tag)
+        .ok()
+        .and_then(|e| e.to_str().ok())
+        .map(|s| s.trim_end_matches(['\0', ' ']).trim_start().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+fn put(attrs: &mut DicomJsonAttributes, tag: &str, vr: &str, value: Option<String>) {
+    attrs.insert(tag.to_string(), DicomJsonValue { vr: vr.to_string(), value: value.map(|v| vec![v]) });
+}
+
+fn paging(limit: Option<u32>, offset: Option<u32>) -> String {
+    format!("LIMIT {} OFFSET {}", limit.map(i64::from).unwrap_or(-1), offset.unwrap_or(0))
+}
+
+/// SQL conditions built from QIDO-RS matching keys (PS3.4 C.2.2.2)
+#[derive(Default)]
+struct Filter {
+    clauses: Vec<String>,
+    params: Vec<String>,
+}
+
+impl Filter {
+    /// Single value, wildcard (`*`, `?`) or UID list (`\`-separated) matching
+    fn matching(&mut self, column: &str, value: Option<&str>) {
+        let Some(value) = value.filter(|v| !v.is_empty() && *v != "*") else {
+            return;
+        };
+        if value.contains(['*', '?']) {
+            let pattern = value
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+                .replace('*', "%")
+                .replace('?', "_");
+            self.clauses.push(format!("{} LIKE ? ESCAPE '\\'", column));
+            self.params.push(pattern);
+        } else if value.contains('\\') {
+            let values: Vec<&str> = value.split('\\').collect();
+            self.clauses.push(format!("{} IN ({})", column, vec!["?"; values.len()].join(", ")));
+            self.params.extend(values.into_iter().map(str::to_string));
+        } else {
+            self.clauses.push(format!("{} = ?", column));
+            self.params.push(value.to_string());
+        }
+    }
+
+    /// Range matching for dates and times (`from-to`, `from-`, `-to`)
+    fn range(&mut self, column: &str, value: Option<&str>) {
+        let Some(value) = value.filter(|v| !v.is_empty()) else {
+            return;
+        };
+        match value.split_once('-') {
+            Some((from, to)) => {
+                if !from.is_empty() {
+                    self.clauses.push(format!("{} >= ?", column));
+                    self.params.push(from.to_string());
+                }
+                if !to.is_empty() {
+                    self.clauses.push(format!("{} <= ?", column));
+                    self.params.push(to.to_string());
+                }
+            }
+            None => self.matching(column, Some(value)),
+        }
+    }
+
+    fn where_clause(&self) -> String {
+        if self.clauses.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", self.clauses.join(" AND "))
+        }
+    }
+}
+
+
+/// Milliseconds since UNIX epoch
+pub(crate) fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// Insert or update an instance together with its patient, study and series
+fn insert_instance(conn: &Connection, record: &InstanceRecord, indexed_at: i64) -> IndexResult<()> {
+    let sop_instance_uid = record.text(tags::SOP_INSTANCE_UID).ok_or("missing SOP Instance UID")?;
+    let series_instance_uid = record.text(tags::SERIES_INSTANCE_UID).ok_or("missing Series Instance UID")?;
+    let study_instance_uid = record.text(tags::STUDY_INSTANCE_UID).ok_or("missing Study Instance UID")?;
+    let patient_id = record.text(tags::PATIENT_ID).unwrap_or_default();
+    let issuer_of_patient_id = record.text(tags::ISSUER_OF_PATIENT_ID).unwrap_or_default();
+
+    conn.execute(
+        "INSERT INTO patients (patient_id, issuer_of_patient_id, patient_name, patient_birth_date, patient_sex)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT(patient_id, issuer_of_patient_id) DO UPDATE SET
+            patient_name = excluded.patient_name,
+            patient_birth_date = excluded.patient_birth_date,
+            patient_sex = excluded.patient_sex",
+        params![
+            patient_id,
+            issuer_of_patient_id,
+            record.text(tags::PATIENT_NAME),
+            record.text(tags::PATIENT_BIRTH_DATE),
+            record.text(tags::PATIENT_SEX),
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT INTO studies (study_instance_uid, patient_id, issuer_of_patient_id, study_date, study_time,
+            accession_number, study_id, study_description, referring_physician_name)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+         ON CONFLICT(study_instance_uid) DO UPDATE SET
+            patient_id = excluded.patient_id,
+            issuer_of_patient_id = excluded.issuer_of_patient_id,
+            study_date = excluded.study_date,
+            study_time = excluded.study_time,
+            accession_number = excluded.accession_number,
+            study_id = excluded.study_id,
+            study_description = excluded.study_description,
+            referring_physician_name = excluded.referring_physician_name",
+        params![
+            study_instance_uid,
+            patient_id,
+            issuer_of_patient_id,
+            record.text(tags::STUDY_DATE),
+            record.text(tags::STUDY_TIME),
+            record.text(tags::ACCESSION_NUMBER),
+            record.text(tags::STUDY_ID),
+            record.text(tags::STUDY_DESCRIPTION),
+            record.text(tags::REFERRING_PHYSICIAN_NAME),
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT INTO series (series_instance_uid, study_instance_uid, modality, series_number,
+            series_description, body_part_examined, performed_procedure_step_start_date,
+            performed_procedure_step_start_time)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+         ON CONFLICT(series_instance_uid) DO UPDATE SET
+            study_instance_uid = excluded.study_instance_uid,
+            modality = excluded.modality,
+            series_number = excluded.series_number,
+            series_description = excluded.series_description,
+            body_part_examined = excluded.body_part_examined,
+            performed_procedure_step_start_date = excluded.performed_procedure_step_start_date,
+            performed_procedure_step_start_time = excluded.performed_procedure_step_start_time",
+        params![
+            series_instance_uid,
+            study_instance_uid,
+            record.text(tags::MODALITY),
+            record.text(tags::SERIES_NUMBER),
+            record.text(tags::SERIES_DESCRIPTION),
+            record.text(tags::BODY_PART_EXAMINED),
+            record.text(tags::PERFORMED_PROCEDURE_STEP_START_DATE),
+            record.text(tags::PERFORMED_PROCEDURE_STEP_START_TIME),
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT OR REPLACE INTO instances (sop_instance_uid, series_instance_uid, study_instance_uid,
+            sop_class_uid, instance_number, transfer_syntax_uid, number_of_frames, rows, columns,
+            storage_key, location, size, indexed_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        params![
+            sop_instance_uid,
+            series_instance_uid,
+            study_instance_uid,
+            record.text(tags::SOP_CLASS_UID),
+            record.text(tags::INSTANCE_NUMBER),
+            record.transfer_syntax_uid,
+            record.text(tags::NUMBER_OF_FRAMES),
+            record.rows,
+            record.columns,
+            record.storage_key,
+            record.location,
+            record.size as i64,
+            indexed_at,
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom_core::{DataElement, PrimitiveValue, VR};
+
+    fn instance(study: &str, series: &str, sop: &str, modality: &str) -> InMemDicomObject {
+        let mut obj = InMemDicomObject::new_empty();
+        for (tag, vr, value) in [
+            (tags::PATIENT_ID, VR::LO, "PAT1"),
+            (tags::ISSUER_OF_PATIENT_ID, VR::LO, "HOSP_A"),
+            (tags::PATIENT_NAME, VR::PN, "Doe^Jane"),
+            (tags::STUDY_DATE, VR::DA, "20240315"),
+            (tags::STUDY_INSTANCE_UID, VR::UI, study),
+            (tags::SERIES_INSTANCE_UID, VR::UI, series),
+            (tags::SOP_INSTANCE_UID, VR::UI, sop),
+            (tags::SOP_CLASS_UID, VR::UI, "1.2.840.10008.5.1.4.1.1.2"),
+            (tags::MODALITY, VR::CS, modality),
+        ] {
+            obj.put(DataElement::new(tag, vr, PrimitiveValue::from(value)));
+        }
+        obj
+    }
+
+    #[test]
+    fn test_index_and_search() {
+        let path = std::env::temp_dir().join(format!("index-test-{}.sqlite", uuid::Uuid::new_v4()));
+        let db = IndexDb::open(&path.to_string_lossy()).unwrap();
+        let index = |obj: &InMemDicomObject, key: &str, location: &str| {
+            db.index_instance(&InstanceRecord::new(obj, "1.2.840.10008.1.2.1", key, location, 10)).unwrap()
+        };
+        index(&instance("1.1", "1.1.1", "1.1.1.1", "CT"), "1.1/1.1.1/1.1.1.1.dcm", "/a");
+        index(&instance("1.1", "1.1.2", "1.1.2.1", "SR"), "1.1/1.1.2/1.1.2.1.dcm", "/b");
+        // Re-indexing the same instance must not duplicate it
+        index(&instance("1.1", "1.1.1", "1.1.1.1", "CT"), "1.1/1.1.1/1.1.1.1.dcm", "/a");
+        // Same Patient ID from another issuer is another patient
+        let mut other = instance("2.1", "2.1.1", "2.1.1.1", "MR");
+        other.put(DataElement::new(tags::ISSUER_OF_PATIENT_ID, VR::LO, PrimitiveValue::from("HOSP_B")));
+        other.put(DataElement::new(tags::PATIENT_NAME, VR::PN, PrimitiveValue::from("Roe^Richard")));
+        index(&other, "2.1/2.1.1/2.1.1.1.dcm", "/c");
+
+        let counts = db.counts().unwrap();
+        assert_eq!((counts.patients, counts.studies, counts.series, counts.instances), (2, 2, 3, 3));
+
+        let studies = db.search_studies(&SearchForStudiesQuery {
+            patient_name: Some("Doe*".to_string()),
+            study_date: Some("20240101-".to_string()),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(studies.len(), 1);
+        assert_eq!(studies[0]["00201208"].value, Some(vec!["2".to_string()]));
+        assert_eq!(studies[0]["00100021"].value, Some(vec!["HOSP_A".to_string()]));
+
+        let none = db.search_studies(&SearchForStudiesQuery {
+            modalities_in_study: Some("US".to_string()),
+            ..Default::default()
+        }).unwrap();
+        assert!(none.is_empty());
+
+        assert_eq!(db.find_instances("1.1", Some("1.1.2")).unwrap().len(), 1);
+        assert_eq!(db.find_instances("1.1", None).unwrap().len(), 2);
+
+        let studies = db.studies_with_sizes().unwrap();
+        assert_eq!((studies.len(), studies[0].instances, studies[0].size), (2, 2, 20));
+        assert_eq!(studies[0].modality_sizes["CT"], 10);
+        assert_eq!(db.study_storage_keys("1.1").unwrap().len(), 2);
+        db.remove_study("1.1").unwrap();
+        assert_eq!(db.counts().unwrap().patients, 1);
+
+        // a rebuild replaces the entries, keeping those stored since it started
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let since = now_millis();
+        index(&instance("3.1", "3.1.1", "3.1.1.1", "US"), "3.1/3.1.1/3.1.1.1.dcm", "/d");
+        let mut missing_uid = instance("4.1", "4.1.1", "4.1.1.1", "CT");
+        missing_uid.remove_element(tags::SOP_INSTANCE_UID);
+        let records = [
+            InstanceRecord::new(&instance("1.1", "1.1.1", "1.1.1.1", "CT"), "1.2.840.10008.1.2.1", "1.1/1.1.1/1.1.1.1.dcm", "/a", 10),
+            InstanceRecord::new(&missing_uid, "1.2.840.10008.1.2.1", "4.1/4.1.1/4.1.1.1.dcm", "/e", 10),
+        ];
+        let failed = db.replace_all(&records, since).unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, 1);
+        let counts = db.counts().unwrap();
+        // study 2.1 and its patient are gone, 1.1 is back and 3.1 was kept
+        assert_eq!((counts.patients, counts.studies, counts.series, counts.instances), (1, 2, 2, 2));
+        assert!(db.find_instances("2.1", None).unwrap().is_empty());
+        assert_eq!(db.find_instances("3.1", None).unwrap().len(), 1);
+
+        drop(db);
+        let _ = std::fs::remove_file(path);
+    }
+}
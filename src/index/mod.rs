@@ -0,0 +1,290 @@
+use std::sync::Arc;
+
+use dicom_object::DefaultDicomObject;
+use tracing::{info, warn};
+
+use crate::storage::StorageBackend;
+use crate::storescp::store_async::TargetBackend;
+use crate::storescp::StorageTarget;
+use crate::utils::dataset::{read_stored_header, read_stored_object};
+use crate::web::qido::{
+    SearchForSeriesInstancesQuery, SearchForSeriesQuery, SearchForStudiesQuery, SearchForStudyInstancesQuery,
+};
+
+mod db;
+
+pub(crate) use db::{open_index, IndexDb, IndexedStudy, InstanceRecord};
+
+/// Bytes read first when looking for the end of a header during a rebuild, doubled as needed
+const HEADER_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Number of entries per level in a DICOM index
+#[napi(object)]
+pub struct DicomIndexStats {
+    pub patients: u32,
+    pub studies: u32,
+    pub series: u32,
+    pub instances: u32,
+}
+
+/// Result of rebuilding a DICOM index from storage
+#[napi(object)]
+pub struct DicomIndexRebuildResult {
+    /// Number of instances added to the index
+    pub indexed: u32,
+    /// Number of objects that could not be read as DICOM and were skipped
+    pub skipped: u32,
+}
+
+/**
+ * Embedded SQLite index of stored DICOM instances.
+ *
+ * The index holds patient, study, series and instance attributes together with the
+ * storage location of every instance. It is kept up to date by `StoreScp` (option
+ * `indexPath`) and used directly by `QidoServer` and `WadoServer` when they are
+ * configured with the same `indexPath`.
+ *
+ * An index for an existing archive folder or bucket can be created with `rebuild()`.
+ *
+ * @example
+ * ```typescript
+ * import { DicomIndex } from '@nuxthealth/node-dicom';
+ *
+ * const index = new DicomIndex('./archive/index.sqlite');
+ * const result = await index.rebuild({ backend: 'Filesystem', outDir: './archive' });
+ * console.log(`Indexed ${result.indexed} instances`);
+ * console.log(await index.stats());
+ * ```
+ */
+#[napi]
+pub struct DicomIndex {
+    db: Arc<IndexDb>,
+}
+
+#[napi]
+impl DicomIndex {
+    /// Open or create the index file at `path`
+    #[napi(constructor)]
+    pub fn new(path: String) -> napi::Result<Self> {
+        let db = open_index(&path).map_err(napi::Error::from_reason)?;
+        Ok(DicomIndex { db })
+    }
+
+    /**
+     * Rebuild the index from the instances stored in a folder or bucket.
+     *
+     * Every `.dcm` object below the storage root is read up to its pixel data, both complete
+     * DICOM files and the dataset-only files written by `StoreScp`. Their modification time is indexed as the time
+     * they were received, so `ReceivedTime` retention keeps their age. The result then replaces
+     * the current entries in one transaction; entries a StoreScp adds during the rebuild are kept.
+     * Objects in the `quarantine/` area are ignored.
+     *
+     * @param target - Storage to scan (same shape as a StoreScp storage target)
+     * @returns Number of indexed and skipped objects
+     *
+     * @example
+     * ```typescript
+     * await index.rebuild({
+     *   backend: 'S3',
+     *   s3Config: { bucket: 'dicom', accessKey: 'minioadmin', secretKey: 'minioadmin', endpoint: 'http://localhost:9000' }
+     * });
+     * ```
+     */
+    #[napi]
+    pub async fn rebuild(&self, target: StorageTarget) -> napi::Result<DicomIndexRebuildResult> {
+        let target = TargetBackend::new(&target, None).map_err(|e| napi::Error::from_reason(e.to_string()))?;
+        let result = rebuild_index(&self.db, target.backend.as_ref()).await.map_err(napi::Error::from_reason)?;
+        info!("Rebuilt index from {}: {} instances indexed, {} skipped", target.name, result.indexed, result.skipped);
+        Ok(result)
+    }
+
+    /// Number of patients, studies, series and instances in the index
+    #[napi]
+    pub async fn stats(&self) -> napi::Result<DicomIndexStats> {
+        let counts = self.db.run(|db| db.counts()).await.map_err(napi::Error::from_reason)?;
+        Ok(DicomIndexStats {
+            patients: counts.patients,
+            studies: counts.studies,
+            series: counts.series,
+            instances: counts.instances,
+        })
+    }
+
+    /// Search for Studies, returns a DICOM JSON array string (as expected by QIDO-RS handlers)
+    #[napi]
+    pub async fn search_studies(&self, query: SearchForStudiesQuery) -> napi::Result<String> {
+        let results = self.db.run(move |db| db.search_studies(&query)).await.map_err(napi::Error::from_reason)?;
+        serde_json::to_string(&results).map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Search for Series in a study, returns a DICOM JSON array string
+    #[napi]
+    pub async fn search_series(&self, query: SearchForSeriesQuery) -> napi::Result<String> {
+        let results = self.db.run(move |db| db.search_series(&query)).await.map_err(napi::Error::from_reason)?;
+        serde_json::to_string(&results).map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Search for Instances in a study, returns a DICOM JSON array string
+    #[napi]
+    pub async fn search_study_instances(&self, query: SearchForStudyInstancesQuery) -> napi::Result<String> {
+        let results = self.db.run(move |db| db.search_study_instances(&query)).await.map_err(napi::Error::from_reason)?;
+        serde_json::to_string(&results).map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Search for Instances in a series, returns a DICOM JSON array string
+    #[napi]
+    pub async fn search_series_instances(&self, query: SearchForSeriesInstancesQuery) -> napi::Result<String> {
+        let results = self.db.run(move |db| db.search_series_instances(&query)).await.map_err(napi::Error::from_reason)?;
+        serde_json::to_string(&results).map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+}
+
+/// Index the instances found in `backend` and replace the content of the index with them
+async fn rebuild_index(index: &Arc<IndexDb>, backend: &dyn StorageBackend) -> db::IndexResult<DicomIndexRebuildResult> {
+    let since = db::now_millis();
+    let keys = backend
+        .list_files("")
+        .await
+        .map_err(|e| format!("Failed to list storage: {}", e))?;
+
+    let mut result = DicomIndexRebuildResult { indexed: 0, skipped: 0 };
+    let mut records = Vec::new();
+    let mut record_keys = Vec::new();
+    for key in keys.iter().filter(|k| k.ends_with(".dcm") && !k.starts_with("quarantine/")) {
+        // the modification time stands in for the receive time, so retention keeps its age
+        let (file, size, received_at) = match backend.metadata(key).await {
+            Ok(metadata) => {
+                let file = read_header(backend, key, metadata.size as u64).await;
+                (file, metadata.size as usize, metadata.last_modified)
+            }
+            Err(e) => {
+                warn!("No metadata for {}, reading it completely and indexing it as received now: {}", key, e);
+                match backend.read_file(key).await {
+                    Ok(data) => (read_stored_object(&data), data.len(), None),
+                    Err(e) => (Err(e.to_string()), 0, None),
+                }
+            }
+        };
+        match file {
+            Ok(file) => {
+                records.push(
+                    InstanceRecord::new(&file, file.meta().transfer_syntax(), key, &backend.location(key), size)
+                        .with_received_at(received_at),
+                );
+                record_keys.push(key.clone());
+            }
+            Err(e) => {
+                warn!("Skipping {}: {}", key, e);
+                result.skipped += 1;
+            }
+        }
+    }
+
+    // the new content replaces the old one at once, entries stored meanwhile are kept
+    let total = records.len() as u32;
+    let failed = index.run(move |db| db.replace_all(&records, since)).await?;
+    for (i, e) in &failed {
+        warn!("Skipping {}: {}", record_keys[*i], e);
+    }
+    result.skipped += failed.len() as u32;
+    result.indexed = total - failed.len() as u32;
+    Ok(result)
+}
+
+/// Read a stored object up to its pixel data, in growing chunks from the start
+async fn read_header(backend: &dyn StorageBackend, key: &str, size: u64) -> Result<DefaultDicomObject, String> {
+    let mut data = Vec::new();
+    let mut chunk = HEADER_CHUNK_SIZE;
+    loop {
+        let end = (data.len() as u64 + chunk).min(size);
+        let bytes = backend.read_range(key, data.len() as u64, Some(end)).await.map_err(|e| e.to_string())?;
+        let complete = bytes.is_empty() || end == size;
+        data.extend_from_slice(&bytes);
+        if complete {
+            return read_stored_object(&data);
+        }
+        if let Some(file) = read_stored_header(&data) {
+            return Ok(file);
+        }
+        chunk *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::FilesystemBackend;
+    use dicom_core::{DataElement, PrimitiveValue, VR};
+    use dicom_dictionary_std::{tags, uids};
+    use dicom_object::{FileMetaTableBuilder, InMemDicomObject};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    #[tokio::test]
+    async fn test_rebuild_keeps_received_time() {
+        let dir = std::env::temp_dir().join(format!("index-rebuild-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("1.1/1.1.1")).unwrap();
+        let obj = InMemDicomObject::from_element_iter([
+            DataElement::new(tags::SOP_CLASS_UID, VR::UI, PrimitiveValue::from(uids::CT_IMAGE_STORAGE)),
+            DataElement::new(tags::SOP_INSTANCE_UID, VR::UI, PrimitiveValue::from("1.1.1.1")),
+            DataElement::new(tags::STUDY_INSTANCE_UID, VR::UI, PrimitiveValue::from("1.1")),
+            DataElement::new(tags::SERIES_INSTANCE_UID, VR::UI, PrimitiveValue::from("1.1.1")),
+            DataElement::new(tags::MODALITY, VR::CS, PrimitiveValue::from("CT")),
+        ]);
+        let meta = FileMetaTableBuilder::new()
+            .media_storage_sop_class_uid(uids::CT_IMAGE_STORAGE)
+            .media_storage_sop_instance_uid("1.1.1.1")
+            .transfer_syntax(uids::EXPLICIT_VR_LITTLE_ENDIAN)
+            .build()
+            .unwrap();
+        let path = dir.join("1.1/1.1.1/1.1.1.1.dcm");
+        obj.with_exact_meta(meta).write_to_file(&path).unwrap();
+        // received a year ago
+        let received = SystemTime::now() - Duration::from_secs(365 * 24 * 3600);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(received).unwrap();
+
+        let index = open_index(&dir.join("index.sqlite").to_string_lossy()).unwrap();
+        let storage = FilesystemBackend { root: dir.display().to_string(), ..Default::default() };
+        let result = rebuild_index(&index, &storage).await.unwrap();
+        assert_eq!((result.indexed, result.skipped), (1, 0));
+
+        let studies = index.studies_with_sizes().unwrap();
+        let received_at = received.duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
+        assert_eq!(studies.len(), 1);
+        assert_eq!(studies[0].received_at, received_at);
+
+        drop(index);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_reads_headers() {
+        let dir = std::env::temp_dir().join(format!("index-headers-{}", uuid::Uuid::new_v4()));
+        let obj = InMemDicomObject::from_element_iter([
+            DataElement::new(tags::SOP_CLASS_UID, VR::UI, PrimitiveValue::from(uids::CT_IMAGE_STORAGE)),
+            DataElement::new(tags::SOP_INSTANCE_UID, VR::UI, PrimitiveValue::from("1.2.1.1")),
+            DataElement::new(tags::STUDY_INSTANCE_UID, VR::UI, PrimitiveValue::from("1.2")),
+            DataElement::new(tags::SERIES_INSTANCE_UID, VR::UI, PrimitiveValue::from("1.2.1")),
+            DataElement::new(tags::MODALITY, VR::CS, PrimitiveValue::from("CT")),
+            DataElement::new(tags::PIXEL_DATA, VR::OW, PrimitiveValue::from(vec![0u8; 300_000])),
+        ]);
+        let mut data = Vec::new();
+        obj.write_dataset_with_ts(&mut data, &dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN.erased())
+            .unwrap();
+        // the pixel data is cut off, so reading the object completely would fail
+        data.truncate(200_000);
+        let storage = FilesystemBackend {
+            root: dir.display().to_string(),
+            options: crate::storage::FilesystemOptions { compression_level: Some(3), ..Default::default() },
+        };
+        storage.store_file("1.2/1.2.1/1.2.1.1.dcm", &data).await.unwrap();
+
+        let index = open_index(&dir.join("index.sqlite").to_string_lossy()).unwrap();
+        let result = rebuild_index(&index, &storage).await.unwrap();
+        assert_eq!((result.indexed, result.skipped), (1, 0));
+        let studies = index.studies_with_sizes().unwrap();
+        assert_eq!((studies[0].instances, studies[0].size), (1, 200_000));
+
+        drop(index);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
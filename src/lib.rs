@@ -1,10 +1,15 @@
 #![deny(clippy::all)]
 
 pub mod storescu;
+pub mod findscu;
+pub mod movescu;
+pub mod getscu;
 pub mod object;
 pub mod storescp;
 pub mod utils;
 pub mod web;
+pub mod index;
+pub mod storage;
 
 // Re-export utils for backward compatibility
 pub use utils::dicom_tags;
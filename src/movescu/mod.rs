@@ -0,0 +1,572 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use dicom_core::{dicom_value, DataElement, VR};
+use dicom_dictionary_std::{tags, uids};
+use dicom_object::InMemDicomObject;
+use dicom_ul::{pdu::PresentationContextResultReason, ClientAssociationOptions};
+use napi::bindgen_prelude::{AsyncTask, Buffer, Object};
+use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi::{Env, Result as NapiResult};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::storage::{FilesystemBackend, StorageBackend};
+use crate::findscu::{build_identifier, read_dataset, receive_message, send_message, DimseMessage, QueryLevel, QueryModel};
+
+pub(crate) mod receiver;
+
+use receiver::{received_event, CollectedInstance, InstanceCallback, Receiver};
+
+/**
+ * A C-MOVE (or C-GET) request for a study, a series or a list of instances.
+ *
+ * The retrieve level follows from the given UIDs: IMAGE with `sopInstanceUids`,
+ * SERIES with `seriesInstanceUid`, STUDY otherwise.
+ */
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct RetrieveRequest {
+    /// Information model (default: StudyRoot)
+    pub model: Option<QueryModel>,
+    /// Patient ID, required by some SCPs for the Patient Root model
+    pub patient_id: Option<String>,
+    /// Study to retrieve
+    pub study_instance_uid: String,
+    /// Series to retrieve (required with `sopInstanceUids`)
+    pub series_instance_uid: Option<String>,
+    /// Instances to retrieve
+    pub sop_instance_uids: Option<Vec<String>>,
+}
+
+impl RetrieveRequest {
+    /// Identifier of the request: the unique keys down to the retrieve level
+    pub(crate) fn identifier(&self) -> Result<InMemDicomObject, String> {
+        let mut keys = HashMap::from([("StudyInstanceUID".to_string(), self.study_instance_uid.clone())]);
+        if let Some(patient_id) = &self.patient_id {
+            keys.insert("PatientID".to_string(), patient_id.clone());
+        }
+        if let Some(series_instance_uid) = &self.series_instance_uid {
+            keys.insert("SeriesInstanceUID".to_string(), series_instance_uid.clone());
+        }
+        let level = match (&self.series_instance_uid, &self.sop_instance_uids) {
+            (Some(_), Some(sop_instance_uids)) if !sop_instance_uids.is_empty() => {
+                keys.insert("SOPInstanceUID".to_string(), sop_instance_uids.join("\\"));
+                QueryLevel::Image
+            }
+            (None, Some(sop_instance_uids)) if !sop_instance_uids.is_empty() => {
+                return Err("seriesInstanceUid is required to retrieve instances".to_string());
+            }
+            (Some(_), _) => QueryLevel::Series,
+            _ => QueryLevel::Study,
+        };
+        build_identifier(level, &keys)
+    }
+}
+
+/// A DICOM instance received by a C-MOVE or C-GET
+#[napi(object)]
+pub struct ReceivedInstance {
+    pub sop_instance_uid: String,
+    pub sop_class_uid: String,
+    pub study_instance_uid: String,
+    pub series_instance_uid: String,
+    pub transfer_syntax_uid: String,
+    /// Size of the DICOM file in bytes
+    pub size: i64,
+    /// Location of the stored file (with MoveScu `outDir` or GetScu `storage`)
+    pub file: Option<String>,
+    /// Content of the DICOM file (without `outDir` or `storage`)
+    pub buffer: Option<Buffer>,
+}
+
+/// Result of a C-MOVE or C-GET request
+#[napi(object)]
+pub struct RetrieveResult {
+    /// Status of the final response (0 = success, 0xB000 = some sub-operations failed)
+    pub status: u32,
+    /// Number of sub-operations that completed successfully
+    pub completed: u32,
+    /// Number of failed sub-operations
+    pub failed: u32,
+    /// Number of sub-operations that completed with a warning
+    pub warning: u32,
+    /// SOP Instance UIDs of the failed sub-operations, as reported by the SCP
+    pub failed_sop_instance_uids: Vec<String>,
+    /// Received instances (empty for a C-MOVE without `receiver`)
+    pub instances: Vec<ReceivedInstance>,
+}
+
+/**
+ * Event data for OnProgress event.
+ *
+ * Emitted for every pending C-MOVE or C-GET response.
+ */
+#[napi(object)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetrieveProgressEvent {
+    pub message: String,
+    pub data: Option<RetrieveProgressData>,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RetrieveProgressData {
+    pub remaining: u32,
+    pub completed: u32,
+    pub failed: u32,
+    pub warning: u32,
+}
+
+/**
+ * Event data for OnInstanceReceived event.
+ *
+ * Emitted when an instance of a C-MOVE or C-GET has been received.
+ */
+#[napi(object)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceReceivedEvent {
+    pub message: String,
+    pub data: Option<InstanceReceivedData>,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceReceivedData {
+    pub sop_instance_uid: String,
+    pub sop_class_uid: String,
+    pub study_instance_uid: String,
+    pub series_instance_uid: String,
+    /// Location of the stored file (with MoveScu `outDir` or GetScu `storage`)
+    pub file: Option<String>,
+}
+
+/// Internal C-STORE receiver started for the duration of a C-MOVE
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct MoveReceiverOptions {
+    /// Port to listen on; the PACS must know this AE title with this port
+    pub port: u16,
+    /// AE title of the receiver and default move destination (default: the calling AE title)
+    pub ae_title: Option<String>,
+    /// Write received instances as `<study>/<series>/<sop>.dcm` below this folder instead of returning Buffers
+    pub out_dir: Option<String>,
+}
+
+/// Options for creating a MoveScu instance.
+#[napi(object)]
+pub struct MoveScuOptions {
+    /// Address of the remote SCP, optionally with AE title (e.g., "PACS@192.168.1.100:104")
+    pub addr: String,
+    /// Calling Application Entity title for this SCU (default: "MOVE-SCU")
+    pub calling_ae_title: Option<String>,
+    /// Called Application Entity title, overrides AE title in address if present (default: "ANY-SCP")
+    pub called_ae_title: Option<String>,
+    /// Maximum PDU length in bytes (default: 16384)
+    pub max_pdu_length: Option<u32>,
+    /// Move destination AE title (default: the receiver AE title)
+    pub destination: Option<String>,
+    /// Start an internal C-STORE receiver collecting the sub-operations
+    pub receiver: Option<MoveReceiverOptions>,
+    /// Enable verbose logging (default: false)
+    pub verbose: Option<bool>,
+    /// User Identity username for authentication
+    pub username: Option<String>,
+    /// User Identity password for authentication
+    pub password: Option<String>,
+    /// User Identity Kerberos service ticket
+    pub kerberos_service_ticket: Option<String>,
+    /// User Identity SAML assertion
+    pub saml_assertion: Option<String>,
+    /// User Identity JWT (JSON Web Token)
+    pub jwt: Option<String>,
+}
+
+/**
+ * DICOM C-MOVE SCU (Service Class User) Client.
+ *
+ * Asks a remote PACS to send a study, series or list of instances to a move destination.
+ * With `receiver`, an ephemeral C-STORE SCP is started on the given port and AE title for
+ * the duration of the request, and the received instances are returned as files or Buffers.
+ * The PACS must know the receiver AE title with this host and port.
+ *
+ * @example
+ * ```typescript
+ * import { MoveScu } from '@nuxthealth/node-dicom';
+ *
+ * const scu = new MoveScu({
+ *   addr: 'PACS@192.168.1.100:104',
+ *   callingAeTitle: 'MY-SCU',
+ *   receiver: { port: 11113, aeTitle: 'MY-SCU', outDir: './retrieved' }
+ * });
+ *
+ * const result = await scu.move({ studyInstanceUid: '1.2.3.4' }, {
+ *   onProgress: (err, event) => console.log(`${event.data?.completed} done, ${event.data?.remaining} remaining`)
+ * });
+ * console.log(result.instances.map(i => i.file));
+ * ```
+ */
+#[napi]
+pub struct MoveScu {
+    addr: String,
+    calling_ae_title: String,
+    called_ae_title: Option<String>,
+    max_pdu_length: u32,
+    destination: Option<String>,
+    receiver: Option<MoveReceiverOptions>,
+    verbose: bool,
+    username: Option<String>,
+    password: Option<String>,
+    kerberos_service_ticket: Option<String>,
+    saml_assertion: Option<String>,
+    jwt: Option<String>,
+}
+
+#[napi]
+impl MoveScu {
+    /**
+     * Create a new DICOM C-MOVE SCU client instance.
+     *
+     * @param options - Client configuration options
+     * @returns New MoveScu instance
+     *
+     * @example
+     * ```typescript
+     * // Move to another AE known by the PACS
+     * const scu = new MoveScu({ addr: 'PACS@192.168.1.100:104', destination: 'ARCHIVE' });
+     *
+     * // Collect the instances in memory
+     * const scu2 = new MoveScu({ addr: 'PACS@192.168.1.100:104', receiver: { port: 11113, aeTitle: 'NODE-MOVE' } });
+     * ```
+     */
+    #[napi(constructor)]
+    pub fn new(options: MoveScuOptions) -> napi::Result<Self> {
+        let verbose = options.verbose.unwrap_or(false);
+        if options.destination.is_none() && options.receiver.is_none() {
+            return Err(napi::Error::from_reason(
+                "Either destination or receiver is required".to_string(),
+            ));
+        }
+
+        use tracing_subscriber::EnvFilter;
+        let filter = EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| {
+                if verbose {
+                    EnvFilter::new("debug")
+                } else {
+                    EnvFilter::new("error")
+                }
+            });
+
+        let _ = tracing::subscriber::set_global_default(
+            tracing_subscriber::FmtSubscriber::builder()
+                .with_env_filter(filter)
+                .finish(),
+        );
+
+        Ok(MoveScu {
+            addr: options.addr,
+            calling_ae_title: options.calling_ae_title.unwrap_or_else(|| "MOVE-SCU".to_string()),
+            called_ae_title: options.called_ae_title,
+            max_pdu_length: options.max_pdu_length.unwrap_or(16384),
+            destination: options.destination,
+            receiver: options.receiver,
+            verbose,
+            username: options.username,
+            password: options.password,
+            kerberos_service_ticket: options.kerberos_service_ticket,
+            saml_assertion: options.saml_assertion,
+            jwt: options.jwt,
+        })
+    }
+
+    /**
+     * Send a C-MOVE request and wait for all sub-operations.
+     *
+     * Resolves when the PACS sends its final response. Sub-operation counts are reported
+     * through `onProgress`; every instance collected by the internal receiver is reported
+     * through `onInstanceReceived`.
+     *
+     * @param request - Study, series or instances to move
+     * @param callbacks - Optional callbacks
+     * @returns Sub-operation counts and received instances
+     *
+     * @example
+     * ```typescript
+     * const result = await scu.move({
+     *   studyInstanceUid: '1.2.3.4',
+     *   seriesInstanceUid: '1.2.3.4.5',
+     *   sopInstanceUids: ['1.2.3.4.5.6', '1.2.3.4.5.7']
+     * }, {
+     *   onInstanceReceived: (err, event) => console.log('Received', event.data?.sopInstanceUid)
+     * });
+     * if (result.failed > 0) console.warn('Failed:', result.failedSopInstanceUids);
+     * ```
+     */
+    #[napi(
+        js_name = "move",
+        ts_args_type = "request: RetrieveRequest, callbacks?: { onProgress?: (err: Error | null, event: RetrieveProgressEvent) => void, onInstanceReceived?: (err: Error | null, event: InstanceReceivedEvent) => void }",
+        ts_return_type = "Promise<RetrieveResult>"
+    )]
+    pub fn move_instances(&self, _env: Env, request: RetrieveRequest, callbacks: Option<Object>) -> NapiResult<AsyncTask<MoveScuHandler>> {
+        let (on_progress, on_instance_received) = match callbacks {
+            Some(callbacks_obj) => (
+                callbacks_obj.get::<ThreadsafeFunction<RetrieveProgressEvent, ()>>("onProgress")?,
+                callbacks_obj.get::<ThreadsafeFunction<InstanceReceivedEvent, ()>>("onInstanceReceived")?,
+            ),
+            None => (None, None),
+        };
+
+        let model = request.model.unwrap_or(QueryModel::StudyRoot);
+        let identifier = request.identifier().map_err(napi::Error::from_reason)?;
+        let receiver_ae_title = self
+            .receiver
+            .as_ref()
+            .map(|receiver| receiver.ae_title.clone().unwrap_or_else(|| self.calling_ae_title.clone()));
+        let destination = self
+            .destination
+            .clone()
+            .or_else(|| receiver_ae_title.clone())
+            .unwrap_or_default();
+
+        let mut scu_init = ClientAssociationOptions::new()
+            .calling_ae_title(self.calling_ae_title.clone())
+            .max_pdu_length(self.max_pdu_length)
+            .with_presentation_context(
+                model.move_sop_class(),
+                vec![uids::EXPLICIT_VR_LITTLE_ENDIAN, uids::IMPLICIT_VR_LITTLE_ENDIAN],
+            );
+        if let Some(called_ae_title) = self.called_ae_title.clone() {
+            scu_init = scu_init.called_ae_title(called_ae_title);
+        }
+        if let Some(username) = self.username.clone() {
+            scu_init = scu_init.username(username);
+        }
+        if let Some(password) = self.password.clone() {
+            scu_init = scu_init.password(password);
+        }
+        if let Some(kerberos_service_ticket) = self.kerberos_service_ticket.clone() {
+            scu_init = scu_init.kerberos_service_ticket(kerberos_service_ticket);
+        }
+        if let Some(saml_assertion) = self.saml_assertion.clone() {
+            scu_init = scu_init.saml_assertion(saml_assertion);
+        }
+        if let Some(jwt) = self.jwt.clone() {
+            scu_init = scu_init.jwt(jwt);
+        }
+
+        Ok(AsyncTask::new(MoveScuHandler {
+            association: scu_init,
+            addr: self.addr.clone(),
+            verbose: self.verbose,
+            sop_class_uid: model.move_sop_class(),
+            identifier,
+            destination,
+            receiver: self.receiver.clone().zip(receiver_ae_title),
+            max_pdu_length: self.max_pdu_length,
+            on_progress: on_progress.map(Arc::new),
+            on_instance_received: on_instance_received.map(Arc::new),
+        }))
+    }
+}
+
+pub struct MoveScuHandler {
+    association: ClientAssociationOptions<'static>,
+    addr: String,
+    verbose: bool,
+    sop_class_uid: &'static str,
+    identifier: InMemDicomObject,
+    destination: String,
+    receiver: Option<(MoveReceiverOptions, String)>,
+    max_pdu_length: u32,
+    on_progress: Option<Arc<ThreadsafeFunction<RetrieveProgressEvent, ()>>>,
+    on_instance_received: Option<Arc<ThreadsafeFunction<InstanceReceivedEvent, ()>>>,
+}
+
+#[napi]
+impl napi::Task for MoveScuHandler {
+    type JsValue = RetrieveResult;
+    type Output = (RetrieveResult, Vec<CollectedInstance>);
+
+    fn compute(&mut self) -> napi::bindgen_prelude::Result<Self::Output> {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        rt.block_on(run_move(self))
+            .map_err(|e| napi::Error::from_reason(format!("C-MOVE failed: {}", e)))
+    }
+
+    fn resolve(&mut self, _env: napi::Env, output: Self::Output) -> napi::bindgen_prelude::Result<Self::JsValue> {
+        let (mut result, instances) = output;
+        result.instances = instances.into_iter().map(ReceivedInstance::from).collect();
+        Ok(result)
+    }
+}
+
+async fn run_move(task: &MoveScuHandler) -> Result<(RetrieveResult, Vec<CollectedInstance>), String> {
+    let on_instance_received = task.on_instance_received.clone().map(|cb| -> InstanceCallback {
+        Arc::new(move |instance| {
+            cb.call(Ok(received_event(instance)), ThreadsafeFunctionCallMode::NonBlocking);
+        })
+    });
+    let receiver = match &task.receiver {
+        Some((options, ae_title)) => Some(
+            Receiver::start(
+                options.port,
+                ae_title.clone(),
+                task.max_pdu_length,
+                options.out_dir.clone().map(|root| -> Arc<dyn StorageBackend> {
+                    Arc::new(FilesystemBackend { root, options: Default::default() })
+                }),
+                on_instance_received,
+            )
+            .await?,
+        ),
+        None => None,
+    };
+
+    if task.verbose {
+        info!("Establishing association with '{}'...", &task.addr);
+    }
+    let mut scu = task
+        .association
+        .clone()
+        .establish_with_async(&task.addr)
+        .await
+        .map_err(|e| format!("Could not establish association: {}", e))?;
+
+    let pc = scu
+        .presentation_contexts()
+        .iter()
+        .find(|pc| pc.reason == PresentationContextResultReason::Acceptance)
+        .cloned()
+        .ok_or_else(|| format!("Information model {} was not accepted", task.sop_class_uid))?;
+
+    let command = move_req_command(task.sop_class_uid, 1, &task.destination);
+    send_message(&mut scu, pc.id, &command, Some((&task.identifier, &pc.transfer_syntax))).await?;
+
+    let (status, progress, message) = loop {
+        let message = receive_message(&mut scu).await?;
+        let status = message.status()?;
+        let progress = suboperations(&message);
+        if !matches!(status, 0xFF00 | 0xFF01) {
+            break (status, progress, message);
+        }
+        if let Some(cb) = &task.on_progress {
+            cb.call(Ok(RetrieveProgressEvent {
+                message: "Sub-operations pending".to_string(),
+                data: Some(progress),
+            }), ThreadsafeFunctionCallMode::NonBlocking);
+        }
+    };
+
+    if let Err(e) = scu.release().await {
+        warn!("Failed to release association: {}", e);
+    }
+
+    let instances = match receiver {
+        Some(receiver) => receiver.finish().await,
+        None => Vec::new(),
+    };
+
+    match status {
+        0x0000 | 0xFE00 => {}
+        0xB000..=0xBFFF => warn!("C-MOVE completed with warning status {:04X}H", status),
+        _ => return Err(format!("C-MOVE failed with status {:04X}H", status)),
+    }
+
+    if task.verbose {
+        info!("C-MOVE completed: {} completed, {} failed", progress.completed, progress.failed);
+    }
+
+    let result = RetrieveResult {
+        status: status as u32,
+        completed: progress.completed,
+        failed: progress.failed,
+        warning: progress.warning,
+        failed_sop_instance_uids: failed_sop_instance_uids(&message, &pc.transfer_syntax),
+        instances: Vec::new(),
+    };
+    Ok((result, instances))
+}
+
+fn move_req_command(sop_class_uid: &str, message_id: u16, destination: &str) -> InMemDicomObject {
+    InMemDicomObject::command_from_element_iter([
+        // SOP Class UID
+        DataElement::new(tags::AFFECTED_SOP_CLASS_UID, VR::UI, dicom_value!(Str, sop_class_uid)),
+        // command field
+        DataElement::new(tags::COMMAND_FIELD, VR::US, dicom_value!(U16, [0x0021])),
+        // message ID
+        DataElement::new(tags::MESSAGE_ID, VR::US, dicom_value!(U16, [message_id])),
+        // priority
+        DataElement::new(tags::PRIORITY, VR::US, dicom_value!(U16, [0x0000])),
+        // data set type
+        DataElement::new(tags::COMMAND_DATA_SET_TYPE, VR::US, dicom_value!(U16, [0x0000])),
+        // move destination
+        DataElement::new(tags::MOVE_DESTINATION, VR::AE, dicom_value!(Str, destination)),
+    ])
+}
+
+/// Sub-operation counts of a C-MOVE or C-GET response
+pub(crate) fn suboperations(message: &DimseMessage) -> RetrieveProgressData {
+    let count = |tag| {
+        message
+            .command
+            .element(tag)
+            .ok()
+            .and_then(|e| e.to_int::<u32>().ok())
+            .unwrap_or(0)
+    };
+    RetrieveProgressData {
+        remaining: count(tags::NUMBER_OF_REMAINING_SUBOPERATIONS),
+        completed: count(tags::NUMBER_OF_COMPLETED_SUBOPERATIONS),
+        failed: count(tags::NUMBER_OF_FAILED_SUBOPERATIONS),
+        warning: count(tags::NUMBER_OF_WARNING_SUBOPERATIONS),
+    }
+}
+
+/// Failed SOP Instance UID List of a final C-MOVE or C-GET response
+pub(crate) fn failed_sop_instance_uids(message: &DimseMessage, ts_uid: &str) -> Vec<String> {
+    message
+        .data
+        .as_ref()
+        .and_then(|data| read_dataset(data, ts_uid).ok())
+        .and_then(|obj| {
+            obj.element(tags::FAILED_SOP_INSTANCE_UID_LIST)
+                .ok()
+                .and_then(|e| e.to_multi_str().ok().map(|uids| uids.to_vec()))
+        })
+        .unwrap_or_default()
+        .into_iter()
+        .map(|uid| uid.trim_end_matches('\0').to_string())
+        .filter(|uid| !uid.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retrieve_request_identifier() {
+        let mut request = RetrieveRequest {
+            model: None,
+            patient_id: None,
+            study_instance_uid: "1.2.3".to_string(),
+            series_instance_uid: None,
+            sop_instance_uids: None,
+        };
+        let obj = request.identifier().unwrap();
+        assert_eq!(obj.element(tags::QUERY_RETRIEVE_LEVEL).unwrap().to_str().unwrap(), "STUDY");
+
+        request.sop_instance_uids = Some(vec!["1.2.3.4.5".to_string(), "1.2.3.4.6".to_string()]);
+        assert!(request.identifier().is_err());
+
+        request.series_instance_uid = Some("1.2.3.4".to_string());
+        let obj = request.identifier().unwrap();
+        assert_eq!(obj.element(tags::QUERY_RETRIEVE_LEVEL).unwrap().to_str().unwrap(), "IMAGE");
+        assert_eq!(obj.element(tags::SOP_INSTANCE_UID).unwrap().to_multi_str().unwrap().len(), 2);
+    }
+}
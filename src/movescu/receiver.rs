@@ -0,0 +1,382 @@
+use std::sync::Arc;
+
+use dicom_dictionary_std::tags;
+use dicom_object::InMemDicomObject;
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+use dicom_ul::association::ServerAssociation;
+use dicom_ul::pdu::{PDataValue, PDataValueType, PresentationContextResultReason};
+use dicom_ul::Pdu;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+use tracing::{debug, info, warn};
+
+use crate::storescp::store_async::{instance_storage_key, is_valid_uid, parse_instance, serialize_instance, ParsedInstance};
+use crate::storage::StorageBackend;
+use crate::storescp::{create_cecho_response, create_cstore_response};
+
+use napi::bindgen_prelude::Buffer;
+
+use super::{InstanceReceivedData, InstanceReceivedEvent, ReceivedInstance};
+
+/// A received instance; `data` is turned into a Buffer when the result is passed to JS
+pub struct CollectedInstance {
+    pub sop_instance_uid: String,
+    pub sop_class_uid: String,
+    pub study_instance_uid: String,
+    pub series_instance_uid: String,
+    pub transfer_syntax_uid: String,
+    pub size: i64,
+    pub file: Option<String>,
+    pub data: Option<Vec<u8>>,
+}
+
+impl From<CollectedInstance> for ReceivedInstance {
+    fn from(instance: CollectedInstance) -> Self {
+        ReceivedInstance {
+            sop_instance_uid: instance.sop_instance_uid,
+            sop_class_uid: instance.sop_class_uid,
+            study_instance_uid: instance.study_instance_uid,
+            series_instance_uid: instance.series_instance_uid,
+            transfer_syntax_uid: instance.transfer_syntax_uid,
+            size: instance.size,
+            file: instance.file,
+            buffer: instance.data.map(Buffer::from),
+        }
+    }
+}
+
+/// Turn a received data set into a DICOM file, written to `storage` as `<study>/<series>/<sop>.dcm` or kept in memory.
+/// Instances with UIDs that are not plain digits and dots are rejected, as the UIDs become part of the file path.
+pub(crate) async fn collect_instance(data: &[u8], ts_uid: &str, storage: Option<&dyn StorageBackend>) -> Result<CollectedInstance, String> {
+    let ParsedInstance { obj, file_meta, study_instance_uid, series_instance_uid } =
+        parse_instance(data, ts_uid.trim_end_matches('\0')).map_err(|e| e.to_string())?;
+    let sop_class_uid = file_meta.media_storage_sop_class_uid().trim_end_matches('\0').to_string();
+    let sop_instance_uid = file_meta.media_storage_sop_instance_uid().trim_end_matches('\0').to_string();
+    let study_instance_uid = study_instance_uid.trim_end_matches('\0').to_string();
+    let series_instance_uid = series_instance_uid.trim_end_matches('\0').to_string();
+    if let Some(uid) = [&study_instance_uid, &series_instance_uid, &sop_instance_uid].into_iter().find(|uid| !is_valid_uid(uid)) {
+        return Err(format!("Invalid UID {:?}", uid));
+    }
+    let bytes = serialize_instance(obj, file_meta, true).map_err(|e| e.to_string())?;
+
+    let mut instance = CollectedInstance {
+        study_instance_uid,
+        series_instance_uid,
+        sop_instance_uid,
+        sop_class_uid,
+        transfer_syntax_uid: ts_uid.trim_end_matches('\0').to_string(),
+        size: bytes.len() as i64,
+        file: None,
+        data: None,
+    };
+    match storage {
+        Some(storage) => {
+            let key = instance_storage_key(&instance.study_instance_uid, &instance.series_instance_uid, &instance.sop_instance_uid);
+            storage
+                .store_file(&key, &bytes)
+                .await
+                .map_err(|e| format!("Could not store {}: {}", key, e))?;
+            instance.file = Some(storage.location(&key));
+        }
+        None => instance.data = Some(bytes),
+    }
+    Ok(instance)
+}
+
+/// Metadata of a received instance, without its content, for OnInstanceReceived
+pub(crate) fn received_event(instance: &CollectedInstance) -> InstanceReceivedEvent {
+    InstanceReceivedEvent {
+        message: "Instance received".to_string(),
+        data: Some(InstanceReceivedData {
+            sop_instance_uid: instance.sop_instance_uid.clone(),
+            sop_class_uid: instance.sop_class_uid.clone(),
+            study_instance_uid: instance.study_instance_uid.clone(),
+            series_instance_uid: instance.series_instance_uid.clone(),
+            file: instance.file.clone(),
+        }),
+    }
+}
+
+/// Called for every instance collected by a receiver
+pub(crate) type InstanceCallback = Arc<dyn Fn(&CollectedInstance) + Send + Sync>;
+
+/// Ephemeral C-STORE SCP collecting the sub-operations of a C-MOVE
+pub(crate) struct Receiver {
+    accept: tokio::task::JoinHandle<()>,
+    instances: Arc<Mutex<Vec<CollectedInstance>>>,
+}
+
+impl Receiver {
+    /// Start listening on `port`; the listener is bound before the C-MOVE is sent
+    pub async fn start(
+        port: u16,
+        ae_title: String,
+        max_pdu_length: u32,
+        storage: Option<Arc<dyn StorageBackend>>,
+        on_instance_received: Option<InstanceCallback>,
+    ) -> Result<Receiver, String> {
+        let listener = TcpListener::bind(("0.0.0.0", port))
+            .await
+            .map_err(|e| format!("Could not listen on port {}: {}", port, e))?;
+        info!("Receiving C-MOVE sub-operations as {} on port {}", ae_title, port);
+        Ok(Self::listen(listener, ae_title, max_pdu_length, storage, on_instance_received))
+    }
+
+    /// Accept sub-operation associations on a bound listener
+    fn listen(
+        listener: TcpListener,
+        ae_title: String,
+        max_pdu_length: u32,
+        storage: Option<Arc<dyn StorageBackend>>,
+        on_instance_received: Option<InstanceCallback>,
+    ) -> Receiver {
+
+        let instances = Arc::new(Mutex::new(Vec::new()));
+        let collected = instances.clone();
+        // Connection handlers live in the accept task's JoinSet and stop together with it
+        let accept = tokio::spawn(async move {
+            let mut handlers = JoinSet::new();
+            while let Ok((stream, addr)) = listener.accept().await {
+                debug!("Sub-operation connection from {}", addr);
+                let ae_title = ae_title.clone();
+                let storage = storage.clone();
+                let instances = collected.clone();
+                let on_instance_received = on_instance_received.clone();
+                handlers.spawn(async move {
+                    if let Err(e) = handle_connection(stream, &ae_title, max_pdu_length, storage.as_deref(), &instances, &on_instance_received).await {
+                        warn!("Sub-operation association failed: {}", e);
+                    }
+                });
+            }
+        });
+        Receiver { accept, instances }
+    }
+
+    /// Stop listening and return the instances received so far
+    pub async fn finish(self) -> Vec<CollectedInstance> {
+        self.accept.abort();
+        std::mem::take(&mut *self.instances.lock().await)
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.accept.abort();
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    ae_title: &str,
+    max_pdu_length: u32,
+    storage: Option<&dyn StorageBackend>,
+    instances: &Mutex<Vec<CollectedInstance>>,
+    on_instance_received: &Option<InstanceCallback>,
+) -> Result<(), String> {
+    let mut options = dicom_ul::association::ServerAssociationOptions::new()
+        .accept_any()
+        .ae_title(ae_title)
+        .max_pdu_length(max_pdu_length)
+        .promiscuous(true);
+    for ts in TransferSyntaxRegistry.iter() {
+        if !ts.is_unsupported() {
+            options = options.with_transfer_syntax(ts.uid());
+        }
+    }
+    let mut association = options
+        .establish_async(stream)
+        .await
+        .map_err(|e| format!("Could not establish association: {}", e))?;
+
+    let mut command: Option<InMemDicomObject> = None;
+    let mut command_data = Vec::new();
+    let mut instance_buffer = Vec::new();
+    loop {
+        let pdu = match association.receive().await {
+            Ok(pdu) => pdu,
+            Err(e) => {
+                debug!("Sub-operation association closed: {}", e);
+                return Ok(());
+            }
+        };
+        match pdu {
+            Pdu::PData { data } => {
+                for mut value in data {
+                    match value.value_type {
+                        PDataValueType::Command => {
+                            command_data.append(&mut value.data);
+                            if !value.is_last {
+                                continue;
+                            }
+                            let cmd = InMemDicomObject::read_dataset_with_ts(
+                                &command_data[..],
+                                &dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN.erased(),
+                            )
+                            .map_err(|e| format!("Could not read command: {}", e))?;
+                            command_data.clear();
+                            instance_buffer.clear();
+                            let command_field = cmd
+                                .element(tags::COMMAND_FIELD)
+                                .ok()
+                                .and_then(|e| e.to_int::<u16>().ok())
+                                .unwrap_or_default();
+                            if command_field == 0x0030 {
+                                let message_id = message_id(&cmd);
+                                send_command(&mut association, value.presentation_context_id, &create_cecho_response(message_id)).await?;
+                            } else {
+                                command = Some(cmd);
+                            }
+                        }
+                        PDataValueType::Data => {
+                            instance_buffer.append(&mut value.data);
+                            if !value.is_last {
+                                continue;
+                            }
+                            let Some(cmd) = command.take() else {
+                                // Without a C-STORE-RQ there is no message to respond to
+                                let _ = association.abort().await;
+                                return Err("data set received without a C-STORE-RQ command, association aborted".to_string());
+                            };
+                            let ts_uid = association
+                                .presentation_contexts()
+                                .iter()
+                                .find(|pc| pc.id == value.presentation_context_id && pc.reason == PresentationContextResultReason::Acceptance)
+                                .map(|pc| pc.transfer_syntax.clone())
+                                .unwrap_or_default();
+                            let status = store_instance(
+                                &instance_buffer,
+                                &ts_uid,
+                                storage,
+                                instances,
+                                on_instance_received,
+                            )
+                            .await;
+                            let sop_class_uid = command_string(&cmd, tags::AFFECTED_SOP_CLASS_UID);
+                            let sop_instance_uid = command_string(&cmd, tags::AFFECTED_SOP_INSTANCE_UID);
+                            let response = create_cstore_response(message_id(&cmd), &sop_class_uid, &sop_instance_uid, status);
+                            send_command(&mut association, value.presentation_context_id, &response).await?;
+                            instance_buffer.clear();
+                        }
+                    }
+                }
+            }
+            Pdu::ReleaseRQ => {
+                let _ = association.send(&Pdu::ReleaseRP).await;
+                return Ok(());
+            }
+            Pdu::AbortRQ { source } => {
+                warn!("Sub-operation association aborted: {:?}", source);
+                return Ok(());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Collect a C-STORE sub-operation and return the C-STORE-RSP status
+pub(crate) async fn store_instance(
+    data: &[u8],
+    ts_uid: &str,
+    storage: Option<&dyn StorageBackend>,
+    instances: &Mutex<Vec<CollectedInstance>>,
+    on_instance_received: &Option<InstanceCallback>,
+) -> u16 {
+    match collect_instance(data, ts_uid, storage).await {
+        Ok(instance) => {
+            if let Some(cb) = on_instance_received {
+                cb(&instance);
+            }
+            instances.lock().await.push(instance);
+            0x0000
+        }
+        Err(e) => {
+            warn!("Could not store received instance: {}", e);
+            // Processing failure
+            0x0110
+        }
+    }
+}
+
+pub(crate) fn message_id(cmd: &InMemDicomObject) -> u16 {
+    cmd.element(tags::MESSAGE_ID)
+        .ok()
+        .and_then(|e| e.to_int::<u16>().ok())
+        .unwrap_or(1)
+}
+
+pub(crate) fn command_string(cmd: &InMemDicomObject, tag: dicom_core::Tag) -> String {
+    cmd.element(tag)
+        .ok()
+        .and_then(|e| e.to_str().ok())
+        .map(|s| s.trim_end_matches('\0').to_string())
+        .unwrap_or_default()
+}
+
+async fn send_command(
+    association: &mut ServerAssociation<TcpStream>,
+    presentation_context_id: u8,
+    command: &InMemDicomObject,
+) -> Result<(), String> {
+    let mut data = Vec::new();
+    command
+        .write_dataset_with_ts(&mut data, &dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN.erased())
+        .map_err(|e| format!("Could not write response: {}", e))?;
+    association
+        .send(&Pdu::PData {
+            data: vec![PDataValue {
+                presentation_context_id,
+                value_type: PDataValueType::Command,
+                is_last: true,
+                data,
+            }],
+        })
+        .await
+        .map_err(|e| format!("Could not send response: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom_core::{dicom_value, DataElement, VR};
+    use dicom_dictionary_std::uids;
+    use dicom_ul::ClientAssociationOptions;
+
+    use crate::findscu::{receive_message, send_message};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_receiver_collects_instances() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let receiver = Receiver::listen(listener, "MOVE-DEST".to_string(), 16384, None, None);
+
+        let mut scu = ClientAssociationOptions::new()
+            .with_presentation_context(uids::SECONDARY_CAPTURE_IMAGE_STORAGE, vec![uids::EXPLICIT_VR_LITTLE_ENDIAN])
+            .establish_with_async(&format!("MOVE-DEST@127.0.0.1:{}", port))
+            .await
+            .unwrap();
+        let pc = scu.presentation_contexts()[0].clone();
+
+        let dataset = |sop_instance_uid: &str| {
+            InMemDicomObject::from_element_iter([
+                DataElement::new(tags::SOP_CLASS_UID, VR::UI, dicom_value!(Str, uids::SECONDARY_CAPTURE_IMAGE_STORAGE)),
+                DataElement::new(tags::SOP_INSTANCE_UID, VR::UI, dicom_value!(Str, sop_instance_uid)),
+                DataElement::new(tags::STUDY_INSTANCE_UID, VR::UI, dicom_value!(Str, "1.2.3")),
+                DataElement::new(tags::SERIES_INSTANCE_UID, VR::UI, dicom_value!(Str, "1.2.3.4")),
+            ])
+        };
+        for (message_id, sop_instance_uid, status) in [(1, "1.2.3.4.5.6", 0x0000), (2, "../../x", 0x0110)] {
+            let command = crate::storescu::store_req_command(uids::SECONDARY_CAPTURE_IMAGE_STORAGE, sop_instance_uid, message_id);
+            send_message(&mut scu, pc.id, &command, Some((&dataset(sop_instance_uid), &pc.transfer_syntax))).await.unwrap();
+            let response = receive_message(&mut scu).await.unwrap();
+            assert_eq!(response.status().unwrap(), status);
+        }
+        scu.release().await.unwrap();
+
+        let instances = receiver.finish().await;
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].sop_instance_uid, "1.2.3.4.5.6");
+        assert_eq!(instances[0].series_instance_uid, "1.2.3.4");
+        assert!(instances[0].data.is_some());
+    }
+}
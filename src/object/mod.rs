@@ -7,12 +7,12 @@ use dicom_core::header::Tag;
 use dicom_object::{ open_file, DefaultDicomObject};
 use snafu::prelude::*;
 use napi::JsError;
-use s3::Bucket;
 
 #[cfg(feature = "transcode")]
 use dicom_pixeldata::{DecodedPixelData, PixelDecoder};
 
-use crate::utils::{extract_tags_flat, CustomTag, S3Config, build_s3_bucket, s3_get_object, s3_put_object};
+use crate::storage::build_storage;
+use crate::utils::{extract_tags_flat, CustomTag, S3Config};
 
 #[derive(Debug, Snafu)]
 enum Error {
@@ -34,7 +34,7 @@ pub enum StorageBackend {
     S3,
 }
 
-/// Storage configuration for DicomFile
+/// Storage configuration for DicomFile and DicomStore
 #[derive(Debug, Clone)]
 #[napi(object)]
 pub struct StorageConfig {
@@ -44,6 +44,10 @@ pub struct StorageConfig {
     pub root_dir: Option<String>,
     /// S3 configuration (required if backend is S3)
     pub s3_config: Option<S3Config>,
+    /// Durability and permission options for filesystem storage
+    pub filesystem_options: Option<crate::storage::FilesystemOptions>,
+    /// Resolve and write content-addressed references (see `StorageTarget.contentAddressed`)
+    pub content_addressed: Option<bool>,
 }
 
 #[napi(object)]
@@ -52,6 +56,8 @@ pub struct DicomFileMeta {
     pub sop_class_uid: String,
     /// Storage SOP Instance UID
     pub sop_instance_uid: String,
+    /// Transfer Syntax UID, inferred from the content for files without file meta group
+    pub transfer_syntax_uid: String,
 }
 
 /// Options for pixel data processing
@@ -175,10 +181,8 @@ pub struct PixelDataInfo {
 pub struct DicomFile{
     /// DICOM object (wrapped in Mutex for thread-safe async operations)
     dicom_file: Mutex<Option<DefaultDicomObject>>,
-    /// Storage configuration
-    storage_config: StorageConfig,
-    /// S3 bucket instance (if using S3)
-    s3_bucket: Option<Bucket>,
+    /// Storage backend built from the storage configuration
+    storage: std::sync::Arc<dyn crate::storage::StorageBackend>,
 }
 
 #[napi]
@@ -220,61 +224,19 @@ impl DicomFile {
             backend: StorageBackend::Filesystem,
             root_dir: None,
             s3_config: None,
+            filesystem_options: None,
+            content_addressed: None,
         });
         
-        // Validate and build S3 bucket if S3 backend is used
-        let s3_bucket = if config.backend == StorageBackend::S3 {
-            let s3_cfg = config.s3_config.as_ref()
-                .ok_or_else(|| JsError::from(napi::Error::from_reason(
-                    "S3 backend requires s3_config to be provided".to_string()
-                )))?;
-            
-            Some(build_s3_bucket(s3_cfg))
-        } else {
-            None
-        };
+        let storage = build_storage(&config)
+            .map_err(|e| JsError::from(napi::Error::from_reason(e)))?;
         
         Ok(DicomFile {
             dicom_file: Mutex::new(None),
-            storage_config: config,
-            s3_bucket,
+            storage,
         })
     }
 
-    // Helper method to resolve file path with root_dir
-    fn resolve_path(&self, path: &str) -> PathBuf {
-        if let Some(root_dir) = &self.storage_config.root_dir {
-            let root = PathBuf::from(root_dir);
-            root.join(path)
-        } else {
-            PathBuf::from(path)
-        }
-    }
-
-    // Helper method to read from S3
-    async fn read_from_s3(&self, path: &str) -> Result<Vec<u8>, napi::Error> {
-        let bucket = self.s3_bucket.as_ref()
-            .ok_or_else(|| napi::Error::from_reason("S3 bucket not initialized".to_string()))?;
-        
-        let result = s3_get_object(bucket, path).await;
-        match result {
-            Ok(data) => Ok(data),
-            Err(_e) => Err(napi::Error::from_reason(format!("Failed to read from S3: {}", path)))
-        }
-    }
-
-    // Helper method to write to S3
-    async fn write_to_s3(&self, path: &str, data: &[u8]) -> Result<(), napi::Error> {
-        let bucket = self.s3_bucket.as_ref()
-            .ok_or_else(|| napi::Error::from_reason("S3 bucket not initialized".to_string()))?;
-        
-        let result = s3_put_object(bucket, path, data).await;
-        match result {
-            Ok(()) => Ok(()),
-            Err(_e) => Err(napi::Error::from_reason(format!("Failed to write to S3: {}", path)))
-        }
-    }
-
     /**
      * Check if a file is a valid DICOM file and extract its metadata.
      * 
@@ -282,8 +244,11 @@ impl DicomFile {
      * without loading the entire dataset. Useful for quickly validating files
      * or extracting SOPInstanceUID without a full file open.
      * 
+     * Files without file meta group (as written by StoreScp unless `storeWithFileMeta`
+     * is enabled) are read in full and their transfer syntax is inferred from the content.
+     * 
      * @param path - Absolute or relative path to the DICOM file
-     * @returns DicomFileMeta containing SOP Class UID and SOP Instance UID
+     * @returns DicomFileMeta containing SOP Class UID, SOP Instance UID and Transfer Syntax UID
      * @throws Error if the file is not a valid DICOM file or DICOMDIR
      * 
      * @example
@@ -312,7 +277,8 @@ impl DicomFile {
      * for both backends.
      * 
      * **File Format:** Handles DICOM files both with and without file meta header.
-     * Files with meta header (standard .dcm files) and dataset-only files are both supported.
+     * Files with meta header (standard .dcm files) and dataset-only files are both supported;
+     * the transfer syntax of dataset-only files is inferred from their content.
      * 
      * @param path - Path to the DICOM file (filesystem path when using Filesystem backend, or S3 key when using S3 backend)
      * @returns Success message if the file was opened successfully
@@ -348,44 +314,19 @@ impl DicomFile {
         use dicom_object::OpenFileOptions;
         use dicom_object::file::ReadPreamble;
         
-        match self.storage_config.backend {
-            StorageBackend::S3 => {
-                // Read from S3
-                let data = self.read_from_s3(&path).await?;
-                
-                // Try to parse DICOM from bytes with auto-detection of preamble
-                // If that fails (e.g., dataset-only file), try without preamble
-                let dicom_file = OpenFileOptions::new()
-                    .read_preamble(ReadPreamble::Auto)
-                    .from_reader(&data[..])
-                    .or_else(|_| {
-                        OpenFileOptions::new()
-                            .read_preamble(ReadPreamble::Never)
-                            .from_reader(&data[..])
-                    })
-                    .map_err(|e| napi::Error::from_reason(format!("Failed to parse DICOM from S3: {}", e)))?;
-                
-                *self.dicom_file.lock().unwrap() = Some(dicom_file);
-                Ok(format!("File opened successfully from S3: {}", path))
-            },
-            StorageBackend::Filesystem => {
-                // Read from filesystem with auto-detection of preamble
-                // If that fails (e.g., dataset-only file), try without preamble
-                let resolved_path = self.resolve_path(&path);
-                let dicom_file = OpenFileOptions::new()
-                    .read_preamble(ReadPreamble::Auto)
-                    .open_file(&resolved_path)
-                    .or_else(|_| {
-                        OpenFileOptions::new()
-                            .read_preamble(ReadPreamble::Never)
-                            .open_file(&resolved_path)
-                    })
-                    .map_err(|e| napi::Error::from_reason(format!("Failed to open DICOM file: {}", e)))?;
-                
-                *self.dicom_file.lock().unwrap() = Some(dicom_file);
-                Ok(format!("File opened successfully: {}", resolved_path.display()))
-            }
-        }
+        let data = self.storage.read_file(&path).await
+            .map_err(|e| napi::Error::from_reason(format!("Failed to read {}: {}", self.storage.location(&path), e)))?;
+        
+        // Try to parse DICOM from bytes with auto-detection of preamble
+        // If that fails (e.g., dataset-only file), infer the transfer syntax of the data set
+        let dicom_file = OpenFileOptions::new()
+            .read_preamble(ReadPreamble::Auto)
+            .from_reader(&data[..])
+            .or_else(|_| crate::utils::dataset::read_stored_object(&data))
+            .map_err(|e| napi::Error::from_reason(format!("Failed to open DICOM file: {}", e)))?;
+        
+        *self.dicom_file.lock().unwrap() = Some(dicom_file);
+        Ok(format!("File opened successfully: {}", self.storage.location(&path)))
     }
 
     /**
@@ -416,19 +357,10 @@ impl DicomFile {
      */
     #[napi]
     pub async fn open_json(&self, path: String) -> napi::Result<String> {
-        // Read JSON content from S3 or filesystem
-        let json_content = match self.storage_config.backend {
-            StorageBackend::S3 => {
-                let data = self.read_from_s3(&path).await?;
-                String::from_utf8(data)
-                    .map_err(|e| napi::Error::from_reason(format!("Invalid UTF-8 in JSON file: {}", e)))?
-            },
-            StorageBackend::Filesystem => {
-                let resolved_path = self.resolve_path(&path);
-                std::fs::read_to_string(&resolved_path)
-                    .map_err(|e| napi::Error::from_reason(format!("Failed to read JSON file: {}", e)))?
-            }
-        };
+        let data = self.storage.read_file(&path).await
+            .map_err(|e| napi::Error::from_reason(format!("Failed to read JSON file: {}", e)))?;
+        let json_content = String::from_utf8(data)
+            .map_err(|e| napi::Error::from_reason(format!("Invalid UTF-8 in JSON file: {}", e)))?;
         
         self.parse_and_set_json(json_content, &path)
     }
@@ -1139,18 +1071,9 @@ impl DicomFile {
         // Use helper method to get JSON string
         let json_string = self.dicom_to_json_string(pretty)?;
         
-        match self.storage_config.backend {
-            StorageBackend::S3 => {
-                self.write_to_s3(&path, json_string.as_bytes()).await?;
-                Ok(format!("DICOM saved as JSON to S3: {} ({} bytes)", path, json_string.len()))
-            },
-            StorageBackend::Filesystem => {
-                let resolved_path = self.resolve_path(&path);
-                std::fs::write(&resolved_path, &json_string)
-                    .map_err(|e| napi::Error::from_reason(format!("Failed to write JSON file: {}", e)))?;
-                Ok(format!("DICOM saved as JSON to {} ({} bytes)", resolved_path.display(), json_string.len()))
-            }
-        }
+        self.storage.store_file(&path, json_string.as_bytes()).await
+            .map_err(|e| napi::Error::from_reason(format!("Failed to write JSON file: {}", e)))?;
+        Ok(format!("DICOM saved as JSON to {} ({} bytes)", self.storage.location(&path), json_string.len()))
     }    /**
      * Save the currently opened DICOM file (regardless of original format) as standard DICOM.
      * 
@@ -1184,35 +1107,12 @@ impl DicomFile {
      */
     #[napi]
     pub async fn save_as_dicom(&self, path: String) -> napi::Result<String> {
-        if self.dicom_file.lock().unwrap().is_none() {
-            return Err(napi::Error::from_reason("File not opened. Call open() first.".to_string()));
-        }
+        // Write to buffer without holding borrow across await
+        let buffer = self.to_bytes()?;
         
-        match self.storage_config.backend {
-            StorageBackend::S3 => {
-                // Write to buffer without holding borrow across await
-                let buffer = {
-                    let dicom_ref = self.dicom_file.lock().unwrap();
-                    let obj = dicom_ref.as_ref().unwrap();
-                    let mut buf = Vec::new();
-                    obj.write_all(&mut buf)
-                        .map_err(|e| napi::Error::from_reason(format!("Failed to write DICOM to buffer: {}", e)))?;
-                    buf
-                }; // borrow dropped here
-                
-                // Upload to S3
-                self.write_to_s3(&path, &buffer).await?;
-                Ok(format!("DICOM file saved to S3: {} ({} bytes)", path, buffer.len()))
-            },
-            StorageBackend::Filesystem => {
-                let resolved_path = self.resolve_path(&path);
-                let dicom_ref = self.dicom_file.lock().unwrap();
-                let obj = dicom_ref.as_ref().unwrap();
-                obj.write_to_file(&resolved_path)
-                    .map_err(|e| napi::Error::from_reason(format!("Failed to write DICOM file: {}", e)))?;
-                Ok(format!("DICOM file saved to {}", resolved_path.display()))
-            }
-        }
+        self.storage.store_file(&path, &buffer).await
+            .map_err(|e| napi::Error::from_reason(format!("Failed to write DICOM file: {}", e)))?;
+        Ok(format!("DICOM file saved to {} ({} bytes)", self.storage.location(&path), buffer.len()))
     }
 
     /**
@@ -1501,15 +1401,38 @@ impl DicomFile {
         *self.dicom_file.lock().unwrap() = None;
     }
 
+    /// Serialize the opened file, including preamble and file meta group
+    pub(crate) fn to_bytes(&self) -> napi::Result<Vec<u8>> {
+        let dicom_ref = self.dicom_file.lock().unwrap();
+        let obj = dicom_ref
+            .as_ref()
+            .ok_or_else(|| napi::Error::from_reason("File not opened. Call open() first.".to_string()))?;
+        let mut buf = Vec::new();
+        obj.write_all(&mut buf)
+            .map_err(|e| napi::Error::from_reason(format!("Failed to write DICOM to buffer: {}", e)))?;
+        Ok(buf)
+    }
+
     fn check_file(file: &Path) -> Result<DicomFileMeta, Error> {
         // Ignore DICOMDIR files until better support is added
         let _ = (file.file_name() != Some(OsStr::new("DICOMDIR")))
             .then_some(false)
             .whatever_context("DICOMDIR file not supported")?;
-        let dicom_file = dicom_object::OpenFileOptions::new()
+        let dicom_file = match dicom_object::OpenFileOptions::new()
             .read_until(Tag(0x0001, 0x000))
             .open_file(file)
-            .with_whatever_context(|_| format!("Could not open DICOM file {}", file.display()))?;
+        {
+            Ok(dicom_file) => dicom_file,
+            // Data set without file meta group
+            Err(_) => {
+                let data = std::fs::read(file)
+                    .with_whatever_context(|_| format!("Could not open DICOM file {}", file.display()))?;
+                match crate::utils::dataset::read_stored_object(&data) {
+                    Ok(dicom_file) => dicom_file,
+                    Err(message) => whatever!("Could not open DICOM file {}: {}", file.display(), message),
+                }
+            }
+        };
 
         let meta = dicom_file.meta();
 
@@ -1517,8 +1440,9 @@ impl DicomFile {
         let storage_sop_instance_uid = &meta.media_storage_sop_instance_uid;
 
         Ok(DicomFileMeta {
-            sop_class_uid: storage_sop_class_uid.to_string(),
-            sop_instance_uid: storage_sop_instance_uid.to_string(),
+            sop_class_uid: storage_sop_class_uid.trim_end_matches('\0').to_string(),
+            sop_instance_uid: storage_sop_instance_uid.trim_end_matches('\0').to_string(),
+            transfer_syntax_uid: meta.transfer_syntax().to_string(),
         })
     }
 }
\ No newline at end of file
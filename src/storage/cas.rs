@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+use super::{ObjectAttributes, StorageBackend, StorageObjectMetadata, StorageResult};
+
+/// Prefix of the blobs and back-references (UIDs never start with a dot)
+const CAS_PREFIX: &str = ".cas/";
+
+/// First bytes of a reference object, followed by the hex SHA-256 of the content
+const REFERENCE_MAGIC: &[u8] = b"NDCR1 sha256:";
+
+/// Size of a reference object: magic, 64 hex digits and a newline
+const REFERENCE_LEN: usize = REFERENCE_MAGIC.len() + 64 + 1;
+
+/// Number of locks serializing reference updates of blobs with the same hash prefix
+const LOCK_STRIPES: usize = 64;
+
+lazy_static::lazy_static! {
+    // Lock stripes per storage root. Backends are created in several places (StoreScp targets,
+    // retention sweeps, DicomStore, WADO), and all of them must serialize on the same locks
+    static ref ROOT_LOCKS: std::sync::Mutex<HashMap<String, Arc<[Mutex<()>]>>> = std::sync::Mutex::new(HashMap::new());
+}
+
+/// Hex encoded SHA-256 of `data`
+pub fn content_hash(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Hash stored in a reference object, or None if `data` is a regular object
+fn parse_reference(data: &[u8]) -> Option<String> {
+    let hash = data.strip_prefix(REFERENCE_MAGIC)?.strip_suffix(b"\n")?;
+    if hash.len() != 64 || !hash.iter().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(String::from_utf8_lossy(hash).into_owned())
+}
+
+fn blob_key(hash: &str) -> String {
+    format!("{}blobs/{}/{}", CAS_PREFIX, &hash[..2], hash)
+}
+
+fn back_references_prefix(hash: &str) -> String {
+    format!("{}refs/{}/", CAS_PREFIX, hash)
+}
+
+/// Content-addressable storage on top of another backend.
+///
+/// The content of every object is stored once as a blob keyed by its SHA-256
+/// (`.cas/blobs/{hash[..2]}/{hash}`); the object key itself only holds a small
+/// reference to the blob. Every reference has a back-reference object
+/// (`.cas/refs/{hash}/{key}`), so the blob is deleted together with its last reference.
+/// Regular objects written before enabling the mode are read and deleted as before.
+pub struct ContentAddressedBackend {
+    pub inner: Arc<dyn StorageBackend>,
+    pub(super) locks: Arc<[Mutex<()>]>,
+}
+
+impl ContentAddressedBackend {
+    pub fn new(inner: Arc<dyn StorageBackend>) -> Self {
+        let locks = ROOT_LOCKS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(inner.location(""))
+            .or_insert_with(|| (0..LOCK_STRIPES).map(|_| Mutex::new(())).collect())
+            .clone();
+        ContentAddressedBackend { inner, locks }
+    }
+
+    fn lock(&self, hash: &str) -> &Mutex<()> {
+        let stripe = u8::from_str_radix(&hash[..2], 16).unwrap_or(0) as usize;
+        &self.locks[stripe % LOCK_STRIPES]
+    }
+
+    /// Hash referenced by the object at `path`, or None for regular objects
+    async fn reference(&self, path: &str) -> StorageResult<Option<String>> {
+        let data = self.inner.read_range(path, 0, Some(REFERENCE_LEN as u64 + 1)).await?;
+        Ok(parse_reference(&data))
+    }
+
+    /// Drop the reference of `path` to `hash`, deleting the blob if it was the last one
+    async fn release(&self, path: &str, hash: &str) -> StorageResult<()> {
+        let _guard = self.lock(hash).lock().await;
+        let back_reference = format!("{}{}", back_references_prefix(hash), path);
+        if self.inner.exists(&back_reference).await? {
+            self.inner.delete_file(&back_reference).await?;
+        }
+        if self.inner.list_files(&back_references_prefix(hash)).await?.is_empty() {
+            let blob = blob_key(hash);
+            if self.inner.exists(&blob).await? {
+                self.inner.delete_file(&blob).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of keys referencing the blob with the given hash
+    pub async fn reference_count(&self, hash: &str) -> StorageResult<usize> {
+        Ok(self.inner.list_files(&back_references_prefix(hash)).await?.len())
+    }
+}
+
+#[async_trait]
+impl StorageBackend for ContentAddressedBackend {
+    async fn store_file(&self, path: &str, data: &[u8]) -> StorageResult<()> {
+        self.store_object(path, data, &ObjectAttributes::default()).await
+    }
+
+    async fn store_object(&self, path: &str, data: &[u8], attributes: &ObjectAttributes) -> StorageResult<()> {
+        let hash = content_hash(data);
+        let previous = match self.inner.exists(path).await? {
+            true => self.reference(path).await?,
+            false => None,
+        };
+        {
+            // Blob first, then the back-reference, then the reference: an interrupted
+            // write leaves at most an unreferenced blob behind
+            let _guard = self.lock(&hash).lock().await;
+            let blob = blob_key(&hash);
+            if !self.inner.exists(&blob).await? {
+                self.inner.store_object(&blob, data, attributes).await?;
+            }
+            let back_reference = format!("{}{}", back_references_prefix(&hash), path);
+            self.inner.store_file(&back_reference, b"").await?;
+            let reference = format!("{}{}\n", String::from_utf8_lossy(REFERENCE_MAGIC), hash);
+            self.inner.store_file(path, reference.as_bytes()).await?;
+        }
+        if let Some(previous) = previous.filter(|previous| *previous != hash) {
+            self.release(path, &previous).await?;
+        }
+        Ok(())
+    }
+
+    async fn read_file(&self, path: &str) -> StorageResult<Vec<u8>> {
+        let data = self.inner.read_file(path).await?;
+        match parse_reference(&data) {
+            Some(hash) => self.inner.read_file(&blob_key(&hash)).await,
+            None => Ok(data),
+        }
+    }
+
+    async fn read_range(&self, path: &str, start: u64, end: Option<u64>) -> StorageResult<Vec<u8>> {
+        match self.reference(path).await? {
+            Some(hash) => self.inner.read_range(&blob_key(&hash), start, end).await,
+            None => self.inner.read_range(path, start, end).await,
+        }
+    }
+
+    async fn list_files(&self, prefix: &str) -> StorageResult<Vec<String>> {
+        let keys = self.inner.list_files(prefix).await?;
+        Ok(keys.into_iter().filter(|key| !key.starts_with(CAS_PREFIX)).collect())
+    }
+
+    async fn delete_file(&self, path: &str) -> StorageResult<()> {
+        let reference = self.reference(path).await?;
+        self.inner.delete_file(path).await?;
+        if let Some(hash) = reference {
+            self.release(path, &hash).await?;
+        }
+        Ok(())
+    }
+
+    async fn exists(&self, path: &str) -> StorageResult<bool> {
+        self.inner.exists(path).await
+    }
+
+    async fn metadata(&self, path: &str) -> StorageResult<StorageObjectMetadata> {
+        let metadata = self.inner.metadata(path).await?;
+        match self.reference(path).await? {
+            Some(hash) => Ok(StorageObjectMetadata {
+                key: path.to_string(),
+                last_modified: metadata.last_modified,
+                ..self.inner.metadata(&blob_key(&hash)).await?
+            }),
+            None => Ok(metadata),
+        }
+    }
+
+    fn location(&self, path: &str) -> String {
+        self.inner.location(path)
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use napi::bindgen_prelude::{Buffer, Promise};
+use napi::threadsafe_function::ThreadsafeFunction;
+
+use super::{ObjectAttributes, StorageBackend, StorageObjectMetadata, StorageResult};
+
+/// Object passed to the `onCustomStore` callback of a StoreScp
+#[napi(object)]
+pub struct CustomStoreRequest {
+    /// Storage key of the instance (`{study}/{series}/{sop}.dcm`, or a `quarantine/` key)
+    pub key: String,
+    /// Serialized DICOM instance
+    pub data: Buffer,
+    /// Extracted tags (`extractTags`/`extractCustomTags`) plus StudyInstanceUID, SeriesInstanceUID,
+    /// SOPInstanceUID, SOPClassUID and TransferSyntaxUID (empty for quarantined items)
+    pub metadata: HashMap<String, String>,
+}
+
+pub(crate) type CustomStoreCallback = ThreadsafeFunction<CustomStoreRequest, Promise<()>>;
+
+/// Objects handed to a JavaScript callback; the backend can only be written to
+pub struct CustomBackend {
+    pub callback: Arc<CustomStoreCallback>,
+}
+
+fn unsupported<T>(operation: &str) -> StorageResult<T> {
+    Err(format!("{} is not supported by the Custom storage backend", operation).into())
+}
+
+#[async_trait]
+impl StorageBackend for CustomBackend {
+    async fn store_file(&self, path: &str, data: &[u8]) -> StorageResult<()> {
+        self.store_object(path, data, &ObjectAttributes::default()).await
+    }
+
+    async fn store_object(&self, path: &str, data: &[u8], attributes: &ObjectAttributes) -> StorageResult<()> {
+        let request = CustomStoreRequest {
+            key: path.to_string(),
+            data: data.to_vec().into(),
+            metadata: attributes.dicom_tags.clone(),
+        };
+        let promise = self
+            .callback
+            .call_async(Ok(request))
+            .await
+            .map_err(|e| format!("onCustomStore callback failed: {}", e.reason))?;
+        promise
+            .await
+            .map_err(|e| format!("onCustomStore rejected: {}", e.reason))?;
+        Ok(())
+    }
+
+    async fn read_file(&self, _path: &str) -> StorageResult<Vec<u8>> {
+        unsupported("Reading")
+    }
+
+    async fn read_range(&self, _path: &str, _start: u64, _end: Option<u64>) -> StorageResult<Vec<u8>> {
+        unsupported("Reading")
+    }
+
+    async fn list_files(&self, _prefix: &str) -> StorageResult<Vec<String>> {
+        unsupported("Listing")
+    }
+
+    async fn delete_file(&self, _path: &str) -> StorageResult<()> {
+        unsupported("Deleting")
+    }
+
+    async fn exists(&self, _path: &str) -> StorageResult<bool> {
+        unsupported("Checking existence")
+    }
+
+    async fn metadata(&self, _path: &str) -> StorageResult<StorageObjectMetadata> {
+        unsupported("Reading metadata")
+    }
+
+    fn location(&self, path: &str) -> String {
+        format!("custom:{}", path)
+    }
+}
@@ -0,0 +1,385 @@
+//! Compression and encryption of stored objects.
+//!
+//! Encoded objects start with a small header so readers can tell them apart from plain
+//! DICOM data and find the key they were encrypted with:
+//!
+//! ```text
+//! "NDSE" | version (1) | flags (1) | key id length (1) | key id | length (8) | nonce (12, if encrypted) | payload
+//! ```
+//!
+//! The payload is zstd compressed (flag 1) and/or AES-256-GCM encrypted (flag 2) with the
+//! header as associated data. Compression is applied before encryption. The length is the
+//! size of the original data (big endian), so it can be reported without decoding.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use napi::bindgen_prelude::{Buffer, Promise};
+use napi::threadsafe_function::ThreadsafeFunction;
+use serde::Deserialize;
+
+use super::StorageResult;
+
+const MAGIC: &[u8; 4] = b"NDSE";
+const VERSION: u8 = 1;
+const FLAG_COMPRESSED: u8 = 1;
+const FLAG_ENCRYPTED: u8 = 2;
+const NONCE_LENGTH: usize = 12;
+const KEY_LENGTH: usize = 32;
+/// Longest possible header before the nonce: magic, version, flags, key id length, key id and length
+pub(crate) const MAX_HEADER_LENGTH: usize = 7 + u8::MAX as usize + 8;
+
+/// Resolves a key id to the key data (wraps the JavaScript callback, so the storage code
+/// does not depend on N-API symbols directly)
+type KeyProvider = Arc<dyn Fn(String) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, String>> + Send>> + Send + Sync>;
+
+/// Modification time and active key id of a loaded key file
+type LoadedKeyFile = (Option<SystemTime>, Option<String>);
+
+lazy_static::lazy_static! {
+    // Keys by key id, loaded from key files or requested from the key provider
+    static ref KEYRING: Mutex<HashMap<String, [u8; KEY_LENGTH]>> = Mutex::new(HashMap::new());
+    // Loaded key files with their modification time and active key id
+    static ref KEY_FILES: Mutex<HashMap<String, LoadedKeyFile>> = Mutex::new(HashMap::new());
+    static ref KEY_PROVIDER: Mutex<Option<KeyProvider>> = Mutex::new(None);
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KeyFile {
+    active_key_id: Option<String>,
+    keys: HashMap<String, String>,
+}
+
+fn parse_key(id: &str, data: &[u8]) -> Result<[u8; KEY_LENGTH], String> {
+    data.try_into()
+        .map_err(|_| format!("Key '{}' must be {} bytes, got {}", id, KEY_LENGTH, data.len()))
+}
+
+/// Load the keys of a key file into the keyring and return its active key id.
+///
+/// Key files are JSON: `{ "activeKeyId": "2024-01", "keys": { "2024-01": "<base64>", ... } }`.
+/// The file is read again when it was modified, so keys can be rotated without a restart.
+pub(crate) fn load_key_file(path: &str) -> Result<Option<String>, String> {
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    if let Some((loaded, active)) = KEY_FILES.lock().unwrap().get(path) {
+        if *loaded == modified {
+            return Ok(active.clone());
+        }
+    }
+    let content = std::fs::read_to_string(path).map_err(|e| format!("Failed to read key file {}: {}", path, e))?;
+    let key_file: KeyFile =
+        serde_json::from_str(&content).map_err(|e| format!("Invalid key file {}: {}", path, e))?;
+    let mut keys = HashMap::with_capacity(key_file.keys.len());
+    for (id, encoded) in &key_file.keys {
+        let data = base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| format!("Invalid base64 for key '{}' in {}: {}", id, path, e))?;
+        keys.insert(id.clone(), parse_key(id, &data)?);
+    }
+    if let Some(active) = &key_file.active_key_id {
+        if !keys.contains_key(active) {
+            return Err(format!("Active key '{}' not found in {}", active, path));
+        }
+    }
+    KEYRING.lock().unwrap().extend(keys);
+    KEY_FILES
+        .lock()
+        .unwrap()
+        .insert(path.to_string(), (modified, key_file.active_key_id.clone()));
+    Ok(key_file.active_key_id)
+}
+
+/// [`load_key_file`] on the blocking thread pool, for callers on the async runtime
+pub(crate) async fn load_key_file_async(path: &str) -> StorageResult<Option<String>> {
+    let path = path.to_string();
+    Ok(tokio::task::spawn_blocking(move || load_key_file(&path)).await??)
+}
+
+/// Look up a key in the keyring, asking the key provider for unknown keys
+pub(crate) async fn key(id: &str) -> StorageResult<[u8; KEY_LENGTH]> {
+    if let Some(key) = KEYRING.lock().unwrap().get(id) {
+        return Ok(*key);
+    }
+    let provider = KEY_PROVIDER.lock().unwrap().clone();
+    let Some(provider) = provider else {
+        return Err(format!("Unknown storage key '{}' (load a key file or register a key provider)", id).into());
+    };
+    let data = provider(id.to_string()).await?;
+    let key = parse_key(id, &data)?;
+    KEYRING.lock().unwrap().insert(id.to_string(), key);
+    Ok(key)
+}
+
+/// Whether data starts with the envelope header
+pub(crate) fn is_encoded(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Whether a file starts with the envelope header
+pub(crate) fn is_encoded_file(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    std::fs::File::open(path).and_then(|mut f| f.read_exact(&mut magic)).is_ok() && is_encoded(&magic)
+}
+
+/// Read and decode an encoded file outside of a storage backend (e.g. files sent by StoreScu)
+pub(crate) async fn read_encoded_file(path: &Path) -> StorageResult<Vec<u8>> {
+    decode(tokio::fs::read(path).await?).await
+}
+
+/// Compress (zstd level `compression_level`) and/or encrypt data
+pub(crate) fn encode(
+    data: &[u8],
+    compression_level: Option<i32>,
+    key: Option<(&str, &[u8; KEY_LENGTH])>,
+) -> StorageResult<Vec<u8>> {
+    let mut flags = 0;
+    let mut payload = match compression_level {
+        Some(level) => {
+            flags |= FLAG_COMPRESSED;
+            zstd::bulk::compress(data, level)?
+        },
+        None => data.to_vec(),
+    };
+    let key_id = key.map(|(id, _)| id).unwrap_or_default();
+    if key_id.len() > u8::MAX as usize {
+        return Err(format!("Key id '{}' is too long", key_id).into());
+    }
+    if key.is_some() {
+        flags |= FLAG_ENCRYPTED;
+    }
+    let mut header = Vec::with_capacity(7 + key_id.len() + 8 + NONCE_LENGTH);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&[VERSION, flags, key_id.len() as u8]);
+    header.extend_from_slice(key_id.as_bytes());
+    header.extend_from_slice(&(data.len() as u64).to_be_bytes());
+    if let Some((_, key)) = key {
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        header.extend_from_slice(&nonce);
+        payload = cipher
+            .encrypt(&nonce, Payload { msg: &payload, aad: &header })
+            .map_err(|_| "Encryption failed".to_string())?;
+    }
+    header.extend_from_slice(&payload);
+    Ok(header)
+}
+
+/// Fields of an envelope header
+struct Header<'a> {
+    flags: u8,
+    key_id: &'a [u8],
+    /// Size of the original data
+    length: u64,
+    /// Offset of the nonce, or of the payload if the object is not encrypted
+    end: usize,
+}
+
+fn parse_header(data: &[u8]) -> StorageResult<Header<'_>> {
+    if data.len() < 7 || data[4] != VERSION {
+        return Err("Invalid storage envelope header".into());
+    }
+    let key_id_end = 7 + data[6] as usize;
+    let key_id = data.get(7..key_id_end).ok_or("Invalid storage envelope header")?;
+    let length = data.get(key_id_end..key_id_end + 8).ok_or("Invalid storage envelope header")?;
+    Ok(Header { flags: data[5], key_id, length: u64::from_be_bytes(length.try_into()?), end: key_id_end + 8 })
+}
+
+/// Id of the key an encoded object was encrypted with (`None` if it is not encrypted)
+fn encryption_key_id(data: &[u8]) -> StorageResult<Option<String>> {
+    if data.len() < 7 || data[4] != VERSION {
+        return Err("Invalid storage envelope header".into());
+    }
+    if data[5] & FLAG_ENCRYPTED == 0 {
+        return Ok(None);
+    }
+    Ok(Some(std::str::from_utf8(parse_header(data)?.key_id)?.to_string()))
+}
+
+/// Size of the original data of an encoded object, from the first [`MAX_HEADER_LENGTH`] bytes
+pub(crate) fn decoded_length(header: &[u8]) -> StorageResult<u64> {
+    Ok(parse_header(header)?.length)
+}
+
+/// Read the original bytes `start..end` of an encoded file that is not encrypted, decompressing
+/// only up to the end of the range. Returns `None` for encrypted files, which can only be
+/// authenticated as a whole.
+pub(crate) fn read_compressed_range(path: &Path, start: u64, end: Option<u64>) -> StorageResult<Option<Vec<u8>>> {
+    let mut file = std::fs::File::open(path)?;
+    let mut prefix = Vec::with_capacity(MAX_HEADER_LENGTH);
+    file.by_ref().take(MAX_HEADER_LENGTH as u64).read_to_end(&mut prefix)?;
+    let header = parse_header(&prefix)?;
+    if header.flags & FLAG_ENCRYPTED != 0 {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::Start(header.end as u64))?;
+    let mut payload: Box<dyn Read> = match header.flags & FLAG_COMPRESSED {
+        0 => Box::new(file),
+        _ => Box::new(zstd::stream::read::Decoder::new(file)?),
+    };
+    std::io::copy(&mut payload.by_ref().take(start), &mut std::io::sink())?;
+    let end = end.unwrap_or(header.length).min(header.length);
+    let mut data = Vec::new();
+    payload.take(end.saturating_sub(start)).read_to_end(&mut data)?;
+    Ok(Some(data))
+}
+
+/// Decrypt and decompress an encoded object with the key it was encrypted with
+fn decode_with_key(data: &[u8], key: Option<&[u8; KEY_LENGTH]>) -> StorageResult<Vec<u8>> {
+    let header = parse_header(data)?;
+    let mut payload = match key {
+        Some(key) => {
+            let (aad, ciphertext) = data
+                .split_at_checked(header.end + NONCE_LENGTH)
+                .ok_or("Invalid storage envelope header")?;
+            let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+            let nonce = Nonce::from_slice(&aad[header.end..]);
+            cipher
+                .decrypt(nonce, Payload { msg: ciphertext, aad })
+                .map_err(|_| "Decryption failed (wrong key or corrupted data)".to_string())?
+        },
+        None => data[header.end..].to_vec(),
+    };
+    if header.flags & FLAG_COMPRESSED != 0 {
+        payload = zstd::stream::decode_all(&payload[..])?;
+    }
+    if header.length != payload.len() as u64 {
+        return Err("Decoded size does not match the storage envelope header".into());
+    }
+    Ok(payload)
+}
+
+/// Restore the original data of an encoded object; plain data is returned unchanged
+pub(crate) async fn decode(data: Vec<u8>) -> StorageResult<Vec<u8>> {
+    if !is_encoded(&data) {
+        return Ok(data);
+    }
+    let key = match encryption_key_id(&data)? {
+        Some(key_id) => Some(key(&key_id).await?),
+        None => None,
+    };
+    tokio::task::spawn_blocking(move || decode_with_key(&data, key.as_ref())).await?
+}
+
+/**
+ * Load storage encryption keys from a key file.
+ *
+ * Encrypted objects can then be read by every component (`WadoServer`, `DicomFile`,
+ * `DicomStore`, `StoreScu`) without further configuration. The file is JSON with
+ * base64 encoded 32 byte AES-256 keys. Old keys stay readable after rotation.
+ *
+ * @param keyFile - Path of the key file
+ * @returns Id of the active key, if the file defines one
+ *
+ * @example
+ * ```typescript
+ * // keys.json: { "activeKeyId": "2024-06", "keys": { "2024-01": "...", "2024-06": "..." } }
+ * loadStorageKeys('/etc/dicom/keys.json');
+ * ```
+ */
+#[napi]
+pub fn load_storage_keys(key_file: String) -> napi::Result<Option<String>> {
+    load_key_file(&key_file).map_err(napi::Error::from_reason)
+}
+
+/**
+ * Register a callback that provides storage encryption keys by id.
+ *
+ * The callback is called once per unknown key id (results are cached) and must resolve
+ * to a 32 byte Buffer, e.g. fetched from a KMS or secret store.
+ *
+ * @example
+ * ```typescript
+ * setStorageKeyProvider(async (err, keyId) => {
+ *   if (err) throw err;
+ *   return Buffer.from(await secrets.get(`dicom-key-${keyId}`), 'base64');
+ * });
+ * ```
+ */
+#[napi(ts_args_type = "callback: (err: Error | null, keyId: string) => Promise<Buffer>")]
+pub fn set_storage_key_provider(callback: ThreadsafeFunction<String, Promise<Buffer>>) {
+    let callback = Arc::new(callback);
+    let provider: KeyProvider = Arc::new(move |id: String| {
+        let callback = callback.clone();
+        Box::pin(async move {
+            let promise = callback
+                .call_async(Ok(id.clone()))
+                .await
+                .map_err(|e| format!("Key provider failed for '{}': {}", id, e.reason))?;
+            let data = promise
+                .await
+                .map_err(|e| format!("Key provider rejected '{}': {}", id, e.reason))?;
+            Ok(data.to_vec())
+        })
+    });
+    *KEY_PROVIDER.lock().unwrap() = Some(provider);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_round_trip() {
+        let key = [7u8; KEY_LENGTH];
+        let data: Vec<u8> = (0..10_000).map(|i| (i % 17) as u8).collect();
+
+        let compressed = encode(&data, Some(3), None).unwrap();
+        assert!(is_encoded(&compressed) && compressed.len() < data.len());
+        assert_eq!(encryption_key_id(&compressed).unwrap(), None);
+        assert_eq!(decode_with_key(&compressed, None).unwrap(), data);
+
+        let mut encrypted = encode(&data, Some(3), Some(("2024-06", &key))).unwrap();
+        assert_eq!(encryption_key_id(&encrypted).unwrap().as_deref(), Some("2024-06"));
+        assert_eq!(decode_with_key(&encrypted, Some(&key)).unwrap(), data);
+        assert!(decode_with_key(&encrypted, Some(&[8u8; KEY_LENGTH])).is_err());
+
+        // The header is authenticated as well as the payload
+        encrypted[5] &= !FLAG_COMPRESSED;
+        assert!(decode_with_key(&encrypted, Some(&key)).is_err());
+
+        // A key id running past the end of the data is rejected, not a panic
+        let mut truncated = compressed[..7].to_vec();
+        truncated[6] = 20;
+        assert!(encryption_key_id(&truncated).unwrap().is_none());
+        assert!(decode_with_key(&truncated, None).is_err());
+        assert!(decode_with_key(&compressed[..5], None).is_err());
+
+        assert!(!is_encoded(b"plain"));
+    }
+
+    #[test]
+    fn test_read_compressed_range() {
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let path = std::env::temp_dir().join(format!("envelope-range-{}", uuid::Uuid::new_v4()));
+
+        std::fs::write(&path, encode(&data, Some(3), None).unwrap()).unwrap();
+        let range = read_compressed_range(&path, 1000, Some(5000)).unwrap().unwrap();
+        assert_eq!(range, data[1000..5000]);
+        let range = read_compressed_range(&path, 99_000, Some(200_000)).unwrap().unwrap();
+        assert_eq!(range, data[99_000..]);
+
+        std::fs::write(&path, encode(&data, Some(3), Some(("k", &[7u8; KEY_LENGTH]))).unwrap()).unwrap();
+        assert!(read_compressed_range(&path, 0, Some(10)).unwrap().is_none());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_envelope_length() {
+        let data: Vec<u8> = (0..10_000).map(|i| (i % 17) as u8).collect();
+        let mut compressed = encode(&data, Some(3), None).unwrap();
+        assert_eq!(decoded_length(&compressed[..15]).unwrap(), data.len() as u64);
+
+        // The length is checked when decoding
+        compressed[14] ^= 1;
+        assert!(decode_with_key(&compressed, None).is_err());
+    }
+}
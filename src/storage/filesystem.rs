@@ -0,0 +1,268 @@
+use std::io::{SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use super::{envelope, StorageBackend, StorageObjectMetadata, StorageResult};
+
+/// Durability and permission options of filesystem storage
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct FilesystemOptions {
+    /// Flush written files and their directory to disk before a write is confirmed (default: false)
+    pub fsync: Option<bool>,
+    /// Permission bits of written files, e.g. `0o640` (Unix only, default: from umask)
+    pub file_mode: Option<u32>,
+    /// Permission bits of created directories, e.g. `0o2750` (Unix only, default: from umask)
+    pub dir_mode: Option<u32>,
+    /// Owner user ID of written files and created directories (Unix only, requires privileges)
+    pub uid: Option<u32>,
+    /// Owner group ID of written files and created directories (Unix only)
+    pub gid: Option<u32>,
+    /// Compress written files with zstd at this level (1-22, e.g. 3); disabled if omitted
+    pub compression_level: Option<i32>,
+    /// JSON key file (`{ activeKeyId, keys: { id: base64 } }`) used to encrypt written files with AES-256-GCM
+    pub encryption_key_file: Option<String>,
+    /// Key used to encrypt written files (default: `activeKeyId` of the key file).
+    /// Keys not in the key file are requested from the provider registered with `setStorageKeyProvider`.
+    pub encryption_key_id: Option<String>,
+}
+
+/// Objects stored as files below a root directory (keys are relative paths).
+///
+/// Files are written to a temporary file in the target directory and renamed into
+/// place, so readers never see partially written objects. With compression or
+/// encryption enabled files are stored as envelopes (see `envelope`), which are
+/// decoded transparently when reading.
+#[derive(Debug, Clone, Default)]
+pub struct FilesystemBackend {
+    pub root: String,
+    pub options: FilesystemOptions,
+}
+
+/// Temporary files are hidden from listings
+fn is_temp_file(name: &str) -> bool {
+    name.starts_with('.') && name.ends_with(".tmp")
+}
+
+impl FilesystemBackend {
+    /// File of an object; keys must be relative paths that stay below the root.
+    /// Without a root directory, keys are plain paths resolved from the working directory.
+    fn path(&self, key: &str) -> StorageResult<PathBuf> {
+        let relative = Path::new(key);
+        if self.root.is_empty() {
+            return Ok(relative.to_path_buf());
+        }
+        if relative.components().any(|c| !matches!(c, Component::Normal(_) | Component::CurDir)) {
+            return Err(format!("Invalid storage key {}: keys must be relative paths below the storage root", key).into());
+        }
+        Ok(Path::new(&self.root).join(relative))
+    }
+
+    /// Id and value of the key encrypting new files, if encryption is enabled
+    async fn write_key(&self) -> StorageResult<Option<(String, [u8; 32])>> {
+        let active = match &self.options.encryption_key_file {
+            Some(key_file) => envelope::load_key_file_async(key_file).await?,
+            None => None,
+        };
+        let key_id = match (&self.options.encryption_key_id, &self.options.encryption_key_file) {
+            (Some(key_id), _) => key_id.clone(),
+            (None, Some(key_file)) => active.ok_or_else(|| format!("Key file {} has no activeKeyId", key_file))?,
+            (None, None) => return Ok(None),
+        };
+        let key = envelope::key(&key_id).await?;
+        Ok(Some((key_id, key)))
+    }
+
+    /// Create missing directories of `dir`, applying mode and owner to the new ones
+    fn create_dirs(&self, dir: &Path) -> std::io::Result<()> {
+        let mut missing = Vec::new();
+        let mut current = Some(dir);
+        while let Some(path) = current.filter(|p| !p.as_os_str().is_empty() && !p.exists()) {
+            missing.push(path.to_path_buf());
+            current = path.parent();
+        }
+        if missing.is_empty() {
+            return Ok(());
+        }
+        std::fs::create_dir_all(dir)?;
+        for path in missing.iter().rev() {
+            self.apply_permissions(path, self.options.dir_mode)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn apply_permissions(&self, path: &Path, mode: Option<u32>) -> std::io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        if let Some(mode) = mode {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        }
+        if self.options.uid.is_some() || self.options.gid.is_some() {
+            std::os::unix::fs::chown(path, self.options.uid, self.options.gid)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn apply_permissions(&self, _path: &Path, _mode: Option<u32>) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Write `data` to a temporary file next to `path` and rename it into place
+    fn write_atomic(&self, path: &Path, data: &[u8]) -> std::io::Result<()> {
+        let dir = path.parent().unwrap_or(Path::new("."));
+        self.create_dirs(dir)?;
+        let file_name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        let temp_path = dir.join(format!(".{}.{}.tmp", file_name, uuid::Uuid::new_v4()));
+        let fsync = self.options.fsync.unwrap_or(false);
+
+        let result = (|| {
+            let mut file = std::fs::File::create(&temp_path)?;
+            file.write_all(data)?;
+            if fsync {
+                file.sync_all()?;
+            }
+            self.apply_permissions(&temp_path, self.options.file_mode)?;
+            std::fs::rename(&temp_path, path)
+        })();
+        if let Err(e) = result {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(e);
+        }
+
+        // Persist the rename itself
+        #[cfg(unix)]
+        if fsync {
+            std::fs::File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StorageBackend for FilesystemBackend {
+    async fn store_file(&self, path: &str, data: &[u8]) -> StorageResult<()> {
+        let backend = self.clone();
+        let full_path = self.path(path)?;
+        let key = self.write_key().await?;
+        let compression_level = self.options.compression_level;
+        let data = data.to_vec();
+        tokio::task::spawn_blocking(move || -> StorageResult<()> {
+            let data = match (compression_level, &key) {
+                (None, None) => data,
+                _ => envelope::encode(&data, compression_level, key.as_ref().map(|(id, key)| (id.as_str(), key)))?,
+            };
+            backend.write_atomic(&full_path, &data)?;
+            Ok(())
+        })
+        .await??;
+        Ok(())
+    }
+
+    async fn read_file(&self, path: &str) -> StorageResult<Vec<u8>> {
+        let data = tokio::fs::read(self.path(path)?).await?;
+        if !envelope::is_encoded(&data) {
+            return Ok(data);
+        }
+        if let Some(key_file) = &self.options.encryption_key_file {
+            envelope::load_key_file_async(key_file).await?;
+        }
+        envelope::decode(data).await
+    }
+
+    async fn read_range(&self, path: &str, start: u64, end: Option<u64>) -> StorageResult<Vec<u8>> {
+        let mut file = tokio::fs::File::open(self.path(path)?).await?;
+        // Compressed files are decoded up to the end of the range, encrypted files completely
+        let mut magic = [0u8; 4];
+        if file.read_exact(&mut magic).await.is_ok() && envelope::is_encoded(&magic) {
+            let full_path = self.path(path)?;
+            if let Some(data) =
+                tokio::task::spawn_blocking(move || envelope::read_compressed_range(&full_path, start, end)).await??
+            {
+                return Ok(data);
+            }
+            let data = self.read_file(path).await?;
+            let start = (start as usize).min(data.len());
+            let end = end.map_or(data.len(), |end| (end as usize).clamp(start, data.len()));
+            return Ok(data[start..end].to_vec());
+        }
+        file.seek(SeekFrom::Start(start)).await?;
+        let mut data = Vec::new();
+        match end {
+            Some(end) => {
+                file.take(end.saturating_sub(start)).read_to_end(&mut data).await?;
+            },
+            None => {
+                file.read_to_end(&mut data).await?;
+            },
+        }
+        Ok(data)
+    }
+
+    async fn list_files(&self, prefix: &str) -> StorageResult<Vec<String>> {
+        let root = PathBuf::from(&self.root);
+        let dir = self.path(prefix)?;
+        let keys = tokio::task::spawn_blocking(move || {
+            if !dir.exists() {
+                return Vec::new();
+            }
+            let mut keys = Vec::new();
+            for entry in walkdir::WalkDir::new(&dir).into_iter().filter_map(|e| e.ok()) {
+                if entry.file_type().is_file() && !is_temp_file(&entry.file_name().to_string_lossy()) {
+                    if let Ok(relative) = entry.path().strip_prefix(&root) {
+                        keys.push(relative.to_string_lossy().replace('\\', "/"));
+                    }
+                }
+            }
+            keys.sort();
+            keys
+        })
+        .await?;
+        Ok(keys)
+    }
+
+    async fn delete_file(&self, path: &str) -> StorageResult<()> {
+        tokio::fs::remove_file(self.path(path)?).await?;
+        Ok(())
+    }
+
+    async fn exists(&self, path: &str) -> StorageResult<bool> {
+        Ok(tokio::fs::metadata(self.path(path)?).await.is_ok_and(|m| m.is_file()))
+    }
+
+    async fn metadata(&self, path: &str) -> StorageResult<StorageObjectMetadata> {
+        let full_path = self.path(path)?;
+        let metadata = tokio::fs::metadata(&full_path).await?;
+        let last_modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as i64);
+        // Encoded files report the size of the decoded data, as returned by `read_file`
+        let mut header = Vec::with_capacity(envelope::MAX_HEADER_LENGTH);
+        tokio::fs::File::open(&full_path)
+            .await?
+            .take(envelope::MAX_HEADER_LENGTH as u64)
+            .read_to_end(&mut header)
+            .await?;
+        let size = if envelope::is_encoded(&header) {
+            envelope::decoded_length(&header)?
+        } else {
+            metadata.len()
+        };
+        Ok(StorageObjectMetadata {
+            key: path.to_string(),
+            size: size as i64,
+            last_modified,
+            content_type: None,
+            etag: None,
+        })
+    }
+
+    fn location(&self, path: &str) -> String {
+        Path::new(&self.root).join(path).display().to_string()
+    }
+}
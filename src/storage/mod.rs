@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use napi::bindgen_prelude::Buffer;
+
+use crate::object::{StorageBackend as StorageBackendType, StorageConfig};
+
+mod cas;
+mod custom;
+pub mod envelope;
+mod filesystem;
+mod s3;
+
+pub use cas::{content_hash, ContentAddressedBackend};
+pub use custom::{CustomBackend, CustomStoreRequest};
+pub(crate) use custom::CustomStoreCallback;
+pub use filesystem::{FilesystemBackend, FilesystemOptions};
+pub use s3::S3Backend;
+
+pub(crate) type StorageResult<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Metadata of a stored object
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct StorageObjectMetadata {
+    /// Key of the object relative to the storage root
+    pub key: String,
+    /// Size in bytes
+    pub size: i64,
+    /// Last modification time in milliseconds since the Unix epoch
+    pub last_modified: Option<i64>,
+    /// Content type (S3 only)
+    pub content_type: Option<String>,
+    /// Entity tag (S3 only)
+    pub etag: Option<String>,
+}
+
+/// Object attributes applied by backends that support them (S3, Custom); ignored on the filesystem
+#[derive(Debug, Clone, Default)]
+pub struct ObjectAttributes {
+    /// Content-Type of the object
+    pub content_type: Option<String>,
+    /// User metadata (`x-amz-meta-<key>`), keys and values already sanitized
+    pub metadata: Vec<(String, String)>,
+    /// Object tags, keys and values already sanitized
+    pub tags: Vec<(String, String)>,
+    /// Unsanitized DICOM tags of the instance, passed to custom backends
+    pub dicom_tags: HashMap<String, String>,
+}
+
+/// Common interface of the filesystem and S3 storage used by all services.
+///
+/// Keys are `/` separated paths relative to the storage root (directory or bucket).
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    async fn store_file(&self, path: &str, data: &[u8]) -> StorageResult<()>;
+    /// Write an object together with content type, metadata and tags
+    async fn store_object(&self, path: &str, data: &[u8], _attributes: &ObjectAttributes) -> StorageResult<()> {
+        self.store_file(path, data).await
+    }
+    async fn read_file(&self, path: &str) -> StorageResult<Vec<u8>>;
+    /// Read the bytes `start..end` of an object (`end` exclusive, `None` reads to the end)
+    async fn read_range(&self, path: &str, start: u64, end: Option<u64>) -> StorageResult<Vec<u8>>;
+    /// List all keys below the given prefix (relative to the storage root)
+    async fn list_files(&self, prefix: &str) -> StorageResult<Vec<String>>;
+    async fn delete_file(&self, path: &str) -> StorageResult<()>;
+    async fn exists(&self, path: &str) -> StorageResult<bool>;
+    async fn metadata(&self, path: &str) -> StorageResult<StorageObjectMetadata>;
+    /// Location of an object as reported to users (file path or `s3://` URL)
+    fn location(&self, path: &str) -> String;
+}
+
+/// Create the storage backend for a `StorageConfig` (filesystem root defaults to the working directory)
+pub(crate) fn build_storage(config: &StorageConfig) -> Result<Arc<dyn StorageBackend>, String> {
+    let backend: Arc<dyn StorageBackend> = match config.backend {
+        StorageBackendType::Filesystem => Arc::new(FilesystemBackend {
+            root: config.root_dir.clone().unwrap_or_default(),
+            options: config.filesystem_options.clone().unwrap_or_default(),
+        }),
+        StorageBackendType::S3 => {
+            let s3_config = config
+                .s3_config
+                .as_ref()
+                .ok_or_else(|| "S3 backend requires s3_config to be provided".to_string())?;
+            Arc::new(S3Backend::new(s3_config)?)
+        },
+    };
+    if config.content_addressed.unwrap_or(false) {
+        return Ok(Arc::new(ContentAddressedBackend::new(backend)));
+    }
+    Ok(backend)
+}
+
+fn storage_error(action: &str, key: &str, e: Box<dyn std::error::Error + Send + Sync>) -> napi::Error {
+    napi::Error::from_reason(format!("Failed to {} {}: {}", action, key, e))
+}
+
+/**
+ * Direct access to the storage used by `StoreScp`, `WadoServer`, `DicomFile` and `StoreScu`.
+ *
+ * The same API works on a local folder and on an S3 bucket. Keys are `/` separated paths
+ * relative to the root directory or the bucket, e.g. the `{study}/{series}/{sop}.dcm`
+ * keys written by `StoreScp`.
+ *
+ * @example
+ * ```typescript
+ * import { DicomStore } from '@nuxthealth/node-dicom';
+ *
+ * const store = new DicomStore({ backend: 'Filesystem', rootDir: './archive' });
+ * for (const key of await store.list('1.2.3.4')) {
+ *   const meta = await store.metadata(key);
+ *   console.log(key, meta.size);
+ * }
+ *
+ * // Read only the first 132 bytes (preamble and DICM magic)
+ * const header = await store.getRange('1.2.3.4/1.2.3.4.5/1.2.3.4.5.6.dcm', 0, 132);
+ * ```
+ */
+#[napi]
+pub struct DicomStore {
+    storage: Arc<dyn StorageBackend>,
+}
+
+#[napi]
+impl DicomStore {
+    /**
+     * Create a store for a folder or bucket.
+     *
+     * @param storageConfig - Storage configuration (defaults to the filesystem relative to the working directory)
+     *
+     * @example
+     * ```typescript
+     * const store = new DicomStore({
+     *   backend: 'S3',
+     *   s3Config: { bucket: 'dicom', accessKey: 'minioadmin', secretKey: 'minioadmin', endpoint: 'http://localhost:9000' }
+     * });
+     * ```
+     */
+    #[napi(constructor)]
+    pub fn new(storage_config: Option<StorageConfig>) -> napi::Result<Self> {
+        let config = storage_config.unwrap_or(StorageConfig {
+            backend: StorageBackendType::Filesystem,
+            root_dir: None,
+            s3_config: None,
+            filesystem_options: None,
+            content_addressed: None,
+        });
+        let storage = build_storage(&config).map_err(napi::Error::from_reason)?;
+        Ok(DicomStore { storage })
+    }
+
+    /// Read a complete object
+    #[napi]
+    pub async fn get(&self, key: String) -> napi::Result<Buffer> {
+        let data = self.storage.read_file(&key).await.map_err(|e| storage_error("read", &key, e))?;
+        Ok(data.into())
+    }
+
+    /**
+     * Read a byte range of an object.
+     *
+     * @param key - Object key
+     * @param start - Offset of the first byte
+     * @param end - Offset after the last byte (exclusive), reads to the end of the object if omitted
+     */
+    #[napi]
+    pub async fn get_range(&self, key: String, start: i64, end: Option<i64>) -> napi::Result<Buffer> {
+        if start < 0 || end.is_some_and(|end| end < start) {
+            return Err(napi::Error::from_reason(format!("Invalid range {}..{:?}", start, end)));
+        }
+        let data = self
+            .storage
+            .read_range(&key, start as u64, end.map(|end| end as u64))
+            .await
+            .map_err(|e| storage_error("read", &key, e))?;
+        Ok(data.into())
+    }
+
+    /// Write an object, replacing an existing one
+    #[napi]
+    pub async fn put(&self, key: String, data: Buffer) -> napi::Result<()> {
+        self.storage.store_file(&key, &data).await.map_err(|e| storage_error("write", &key, e))
+    }
+
+    /// List all object keys below a prefix (folder), or all objects if no prefix is given
+    #[napi]
+    pub async fn list(&self, prefix: Option<String>) -> napi::Result<Vec<String>> {
+        let prefix = prefix.unwrap_or_default();
+        self.storage.list_files(&prefix).await.map_err(|e| storage_error("list", &prefix, e))
+    }
+
+    /// Delete an object
+    #[napi]
+    pub async fn delete(&self, key: String) -> napi::Result<()> {
+        self.storage.delete_file(&key).await.map_err(|e| storage_error("delete", &key, e))
+    }
+
+    /// Check whether an object exists
+    #[napi]
+    pub async fn exists(&self, key: String) -> napi::Result<bool> {
+        self.storage.exists(&key).await.map_err(|e| storage_error("check", &key, e))
+    }
+
+    /**
+     * Rewrite all objects below a prefix with the current compression and encryption settings.
+     *
+     * Use this after rotating keys (a new `activeKeyId`) to re-encrypt existing objects,
+     * or after enabling compression. Objects are decoded with whatever key they were
+     * written with, so all old keys must still be available.
+     *
+     * @param prefix - Folder to rewrite (all objects if omitted)
+     * @returns Number of rewritten objects
+     *
+     * @example
+     * ```typescript
+     * const store = new DicomStore({
+     *   backend: 'Filesystem',
+     *   rootDir: './archive',
+     *   filesystemOptions: { encryptionKeyFile: './keys.json', compressionLevel: 3 }
+     * });
+     * const count = await store.reencode();
+     * ```
+     */
+    #[napi]
+    pub async fn reencode(&self, prefix: Option<String>) -> napi::Result<u32> {
+        let prefix = prefix.unwrap_or_default();
+        let keys = self.storage.list_files(&prefix).await.map_err(|e| storage_error("list", &prefix, e))?;
+        for key in &keys {
+            let data = self.storage.read_file(key).await.map_err(|e| storage_error("read", key, e))?;
+            self.storage.store_file(key, &data).await.map_err(|e| storage_error("write", key, e))?;
+        }
+        Ok(keys.len() as u32)
+    }
+
+    /// Size, modification time and (for S3) content type and ETag of an object
+    #[napi]
+    pub async fn metadata(&self, key: String) -> napi::Result<StorageObjectMetadata> {
+        self.storage.metadata(&key).await.map_err(|e| storage_error("read metadata of", &key, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_filesystem_backend() {
+        let root = std::env::temp_dir().join(format!("node-dicom-storage-{}", uuid::Uuid::new_v4()));
+        let storage = FilesystemBackend { root: root.display().to_string(), ..Default::default() };
+
+        storage.store_file("a/b/one.dcm", b"0123456789").await.unwrap();
+        storage.store_file("a/two.dcm", b"abc").await.unwrap();
+
+        assert_eq!(storage.list_files("a").await.unwrap(), vec!["a/b/one.dcm", "a/two.dcm"]);
+        assert_eq!(storage.read_range("a/b/one.dcm", 2, Some(5)).await.unwrap(), b"234");
+        assert_eq!(storage.read_range("a/b/one.dcm", 7, None).await.unwrap(), b"789");
+        assert!(storage.exists("a/two.dcm").await.unwrap());
+        assert_eq!(storage.metadata("a/b/one.dcm").await.unwrap().size, 10);
+
+        storage.delete_file("a/two.dcm").await.unwrap();
+        assert!(!storage.exists("a/two.dcm").await.unwrap());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_filesystem_backend_compressed() {
+        let root = std::env::temp_dir().join(format!("node-dicom-storage-{}", uuid::Uuid::new_v4()));
+        let storage = FilesystemBackend {
+            root: root.display().to_string(),
+            options: FilesystemOptions { compression_level: Some(3), ..Default::default() },
+        };
+        let data: Vec<u8> = (0..10_000).map(|i| (i % 17) as u8).collect();
+
+        storage.store_file("a/one.dcm", &data).await.unwrap();
+        assert!(std::fs::metadata(root.join("a/one.dcm")).unwrap().len() < data.len() as u64);
+        assert_eq!(storage.read_file("a/one.dcm").await.unwrap(), data);
+        assert_eq!(storage.read_range("a/one.dcm", 100, Some(110)).await.unwrap(), &data[100..110]);
+        // The size is that of the decoded data, not of the file on disk
+        assert_eq!(storage.metadata("a/one.dcm").await.unwrap().size, data.len() as i64);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_filesystem_backend_encrypted() {
+        use base64::Engine;
+
+        let root = std::env::temp_dir().join(format!("node-dicom-storage-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        let key_file = root.join("keys.json");
+        let (old_id, new_id) = (uuid::Uuid::new_v4().to_string(), uuid::Uuid::new_v4().to_string());
+        let write_key_file = |active: &str, modified: u64| {
+            let key = base64::engine::general_purpose::STANDARD.encode([1u8; 32]);
+            let json = format!(r#"{{ "activeKeyId": "{}", "keys": {{ "{}": "{}", "{}": "{}" }} }}"#, active, old_id, key, new_id, key);
+            std::fs::write(&key_file, json).unwrap();
+            let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(modified);
+            std::fs::File::options().write(true).open(&key_file).unwrap().set_modified(time).unwrap();
+        };
+        let contains = |path: &str, id: &str| {
+            std::fs::read(root.join(path)).unwrap().windows(id.len()).any(|w| w == id.as_bytes())
+        };
+        write_key_file(&old_id, 1_000_000);
+        let storage = FilesystemBackend {
+            root: root.display().to_string(),
+            options: FilesystemOptions {
+                encryption_key_file: Some(key_file.display().to_string()),
+                ..Default::default()
+            },
+        };
+
+        storage.store_file("a/one.dcm", b"0123456789").await.unwrap();
+        assert!(contains("a/one.dcm", &old_id));
+        // A rotated key file is picked up without a restart
+        write_key_file(&new_id, 2_000_000);
+        storage.store_file("a/two.dcm", b"abc").await.unwrap();
+        assert!(contains("a/two.dcm", &new_id));
+
+        assert_eq!(storage.read_file("a/one.dcm").await.unwrap(), b"0123456789");
+        assert_eq!(storage.read_file("a/two.dcm").await.unwrap(), b"abc");
+        assert_eq!(storage.metadata("a/one.dcm").await.unwrap().size, 10);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_filesystem_backend_rejects_escaping_keys() {
+        let root = std::env::temp_dir().join(format!("node-dicom-storage-{}", uuid::Uuid::new_v4()));
+        let storage = FilesystemBackend { root: root.join("store").display().to_string(), ..Default::default() };
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("outside.dcm"), b"secret").unwrap();
+
+        for key in ["../outside.dcm", "a/../../outside.dcm", "/etc/passwd"] {
+            assert!(storage.read_file(key).await.is_err(), "{}", key);
+            assert!(storage.store_file(key, b"x").await.is_err(), "{}", key);
+            assert!(storage.delete_file(key).await.is_err(), "{}", key);
+            assert!(storage.list_files(key).await.is_err(), "{}", key);
+        }
+        assert_eq!(std::fs::read(root.join("outside.dcm")).unwrap(), b"secret");
+        storage.store_file("./a/one.dcm", b"ok").await.unwrap();
+        assert_eq!(storage.read_file("a/one.dcm").await.unwrap(), b"ok");
+
+        // without a root directory, as used by DicomFile, keys are plain paths
+        let storage = build_storage(&StorageConfig {
+            backend: StorageBackendType::Filesystem,
+            root_dir: None,
+            s3_config: None,
+            filesystem_options: None,
+            content_addressed: None,
+        })
+        .unwrap();
+        let outside = root.join("outside.dcm").display().to_string();
+        assert!(std::path::Path::new(&outside).is_absolute());
+        assert_eq!(storage.read_file(&outside).await.unwrap(), b"secret");
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_content_addressed_backend() {
+        let root = std::env::temp_dir().join(format!("node-dicom-storage-{}", uuid::Uuid::new_v4()));
+        let inner = Arc::new(FilesystemBackend { root: root.display().to_string(), ..Default::default() });
+        let storage = ContentAddressedBackend::new(inner.clone());
+        let hash = content_hash(b"0123456789");
+        // Backends on the same root share their locks
+        assert!(Arc::ptr_eq(&storage.locks, &ContentAddressedBackend::new(inner.clone()).locks));
+
+        storage.store_file("a/one.dcm", b"0123456789").await.unwrap();
+        storage.store_file("b/one.dcm", b"0123456789").await.unwrap();
+        storage.store_file("b/two.dcm", b"abc").await.unwrap();
+
+        assert_eq!(storage.list_files("").await.unwrap(), vec!["a/one.dcm", "b/one.dcm", "b/two.dcm"]);
+        assert_eq!(storage.read_file("b/one.dcm").await.unwrap(), b"0123456789");
+        assert_eq!(storage.read_range("a/one.dcm", 2, Some(5)).await.unwrap(), b"234");
+        assert_eq!(storage.metadata("a/one.dcm").await.unwrap().size, 10);
+        assert_eq!(storage.reference_count(&hash).await.unwrap(), 2);
+        assert_eq!(inner.list_files(".cas/blobs").await.unwrap().len(), 2);
+
+        // Overwriting with other content releases the old blob reference
+        storage.store_file("a/one.dcm", b"abc").await.unwrap();
+        assert_eq!(storage.reference_count(&hash).await.unwrap(), 1);
+
+        storage.delete_file("b/one.dcm").await.unwrap();
+        assert_eq!(storage.reference_count(&hash).await.unwrap(), 0);
+        assert_eq!(inner.list_files(".cas/blobs").await.unwrap().len(), 1);
+        storage.delete_file("a/one.dcm").await.unwrap();
+        assert_eq!(storage.read_file("b/two.dcm").await.unwrap(), b"abc");
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_filesystem_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = std::env::temp_dir().join(format!("node-dicom-storage-{}", uuid::Uuid::new_v4()));
+        let storage = FilesystemBackend {
+            root: root.display().to_string(),
+            options: FilesystemOptions { fsync: Some(true), file_mode: Some(0o600), dir_mode: Some(0o700), ..Default::default() },
+        };
+
+        storage.store_file("a/b/one.dcm", b"first").await.unwrap();
+        storage.store_file("a/b/one.dcm", b"second").await.unwrap();
+
+        assert_eq!(storage.read_file("a/b/one.dcm").await.unwrap(), b"second");
+        assert_eq!(std::fs::read_dir(root.join("a/b")).unwrap().count(), 1);
+        let mode = |path: &str| std::fs::metadata(root.join(path)).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode("a/b/one.dcm"), 0o600);
+        assert_eq!(mode("a"), 0o700);
+        assert_eq!(mode("a/b"), 0o700);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
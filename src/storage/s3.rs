@@ -0,0 +1,165 @@
+use std::time::UNIX_EPOCH;
+
+use async_trait::async_trait;
+use tracing::error;
+
+use super::{ObjectAttributes, StorageBackend, StorageObjectMetadata, StorageResult};
+use crate::utils::{
+    build_s3_bucket, S3Bucket, s3_delete_object, s3_get_object, s3_get_object_range, s3_head_object, s3_list_objects,
+    s3_object_exists, s3_upload, S3Config, S3UploadOptions,
+};
+
+/// Objects stored in an S3 bucket (keys are object keys below the configured key prefix)
+pub struct S3Backend {
+    pub bucket: S3Bucket,
+    /// Prefix prepended to all keys, empty or ending with `/`
+    key_prefix: String,
+    upload: S3UploadOptions,
+}
+
+impl S3Backend {
+    pub fn new(config: &S3Config) -> Result<Self, String> {
+        let key_prefix = match config.key_prefix.as_deref().map(|p| p.trim_matches('/')) {
+            Some(prefix) if !prefix.is_empty() => format!("{}/", prefix),
+            _ => String::new(),
+        };
+        Ok(S3Backend {
+            bucket: build_s3_bucket(config)?,
+            key_prefix,
+            upload: S3UploadOptions::from_config(config),
+        })
+    }
+
+    fn object_key(&self, path: &str) -> String {
+        format!("{}{}", self.key_prefix, path.replace('\\', "/"))
+    }
+}
+
+#[async_trait]
+impl StorageBackend for S3Backend {
+    async fn store_file(&self, path: &str, data: &[u8]) -> StorageResult<()> {
+        self.store_object(path, data, &ObjectAttributes::default()).await
+    }
+
+    async fn store_object(&self, path: &str, data: &[u8], attributes: &ObjectAttributes) -> StorageResult<()> {
+        let key = self.object_key(path);
+        match s3_upload(&self.bucket, &key, data, &self.upload, attributes).await {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                error!("Failed to upload file to S3: {}: {}", key, e);
+                Err(format!("S3 upload failed: {}: {}", key, e).into())
+            }
+        }
+    }
+
+    async fn read_file(&self, path: &str) -> StorageResult<Vec<u8>> {
+        s3_get_object(&self.bucket, &self.object_key(path)).await
+    }
+
+    async fn read_range(&self, path: &str, start: u64, end: Option<u64>) -> StorageResult<Vec<u8>> {
+        if end.is_some_and(|end| end <= start) {
+            return Ok(Vec::new());
+        }
+        // S3 ranges are inclusive
+        s3_get_object_range(&self.bucket, &self.object_key(path), start, end.map(|end| end - 1)).await
+    }
+
+    async fn list_files(&self, prefix: &str) -> StorageResult<Vec<String>> {
+        let prefix = if prefix.is_empty() {
+            self.key_prefix.clone()
+        } else {
+            format!("{}/", self.object_key(prefix.trim_end_matches('/')))
+        };
+        let mut keys: Vec<String> = s3_list_objects(&self.bucket, &prefix)
+            .await?
+            .into_iter()
+            .filter(|key| !key.ends_with('/'))
+            .filter_map(|key| key.strip_prefix(&self.key_prefix).map(str::to_string))
+            .collect();
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn delete_file(&self, path: &str) -> StorageResult<()> {
+        s3_delete_object(&self.bucket, &self.object_key(path)).await
+    }
+
+    async fn exists(&self, path: &str) -> StorageResult<bool> {
+        s3_object_exists(&self.bucket, &self.object_key(path)).await
+    }
+
+    async fn metadata(&self, path: &str) -> StorageResult<StorageObjectMetadata> {
+        let head = s3_head_object(&self.bucket, &self.object_key(path)).await?;
+        let last_modified = head
+            .last_modified
+            .as_deref()
+            .and_then(|date| httpdate::parse_http_date(date).ok())
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as i64);
+        Ok(StorageObjectMetadata {
+            key: path.replace('\\', "/"),
+            size: head.content_length.unwrap_or_default(),
+            last_modified,
+            content_type: head.content_type,
+            etag: head.e_tag.map(|etag| etag.trim_matches('"').to_string()),
+        })
+    }
+
+    fn location(&self, path: &str) -> String {
+        format!("s3://{}/{}", self.bucket.name(), self.object_key(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Round trip against a local MinIO, e.g.
+    /// `docker run -p 9000:9000 minio/minio server /data` and
+    /// `S3_TEST_ENDPOINT=http://localhost:9000 cargo test -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn test_minio_upload() {
+        let endpoint = std::env::var("S3_TEST_ENDPOINT").unwrap_or_else(|_| "http://localhost:9000".to_string());
+        let config = S3Config {
+            bucket: std::env::var("S3_TEST_BUCKET").unwrap_or_else(|_| "node-dicom-test".to_string()),
+            access_key: Some(std::env::var("S3_TEST_ACCESS_KEY").unwrap_or_else(|_| "minioadmin".to_string())),
+            secret_key: Some(std::env::var("S3_TEST_SECRET_KEY").unwrap_or_else(|_| "minioadmin".to_string())),
+            session_token: None,
+            profile: None,
+            region: None,
+            endpoint: Some(endpoint),
+            path_style: None,
+            key_prefix: Some(format!("test-{}", uuid::Uuid::new_v4())),
+            storage_class: None,
+            multipart_threshold_mb: Some(5),
+            multipart_part_size_mb: Some(5),
+            max_retries: None,
+            ca_cert_path: None,
+            insecure_tls: None,
+        };
+        let backend = S3Backend::new(&config).unwrap();
+        let _ = s3::Bucket::create_with_path_style(
+            &config.bucket,
+            backend.bucket.bucket.region.clone(),
+            backend.bucket.bucket.credentials().await.unwrap(),
+            s3::BucketConfiguration::default(),
+        )
+        .await;
+
+        let small = b"0123456789".to_vec();
+        let large: Vec<u8> = (0..12 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        backend.store_file("a/small.dcm", &small).await.unwrap();
+        backend.store_file("a/large.dcm", &large).await.unwrap();
+
+        assert_eq!(backend.list_files("a").await.unwrap(), vec!["a/large.dcm", "a/small.dcm"]);
+        assert_eq!(backend.read_file("a/small.dcm").await.unwrap(), small);
+        assert_eq!(backend.read_file("a/large.dcm").await.unwrap(), large);
+        assert_eq!(backend.read_range("a/small.dcm", 2, Some(5)).await.unwrap(), b"234");
+        assert_eq!(backend.metadata("a/large.dcm").await.unwrap().size, large.len() as i64);
+
+        backend.delete_file("a/small.dcm").await.unwrap();
+        backend.delete_file("a/large.dcm").await.unwrap();
+        assert!(!backend.exists("a/small.dcm").await.unwrap());
+    }
+}
@@ -5,7 +5,7 @@ use std::net::{Ipv4Addr, SocketAddrV4};
 use std::sync::Arc;
 
 use snafu::Report;
-use tracing::{error, info, Level};
+use tracing::{error, info, warn, Level};
 
 use tokio::sync::{broadcast, Notify, Mutex};
 use tokio::runtime::Runtime;
@@ -14,13 +14,18 @@ use dicom_core::{dicom_value, DataElement, VR};
 use dicom_dictionary_std::tags;
 use dicom_object::{InMemDicomObject, StandardDataDictionary};
 
-mod sop_classes;
+pub(crate) mod sop_classes;
 
+use crate::storage::{CustomStoreCallback, CustomStoreRequest, FilesystemOptions};
 use crate::utils::{CustomTag, S3Config, build_s3_bucket, check_s3_connectivity};
 
-mod transfer;
-mod store_async;
+pub(crate) mod transfer;
+pub(crate) mod store_async;
+mod quarantine;
+mod retention;
 use store_async::run_store_async;
+pub use quarantine::QuarantineRecord;
+pub use retention::{PurgeReason, PurgedStudy, RetentionAgeBasis, RetentionPolicy};
 
 type EventSender = broadcast::Sender<(StoreScpEvent, ScpEventData)>;
 type EventReceiver = broadcast::Receiver<(StoreScpEvent, ScpEventData)>;
@@ -55,6 +60,9 @@ lazy_static::lazy_static! {
  *     endpoint: 'http://localhost:9000'
  *   }
  * });
+ *
+ * // Hand instances to your own code (see onCustomStore)
+ * const scpCustom = new StoreScp({ port: 11111, storageBackend: 'Custom' });
  * ```
  */
 #[napi(string_enum)]
@@ -64,6 +72,111 @@ pub enum StorageBackendType {
     Filesystem,
     /// Store files in S3-compatible object storage
     S3,
+    /// Pass files to the JavaScript callback registered with `onCustomStore`
+    Custom,
+}
+
+/// Success policy of a storage target
+#[napi(string_enum)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoragePolicy {
+    /// The C-STORE is only acknowledged after the file was written to this target
+    Required,
+    /// The file is replicated to this target in the background; failures are reported via OnError
+    BestEffort,
+}
+
+/**
+ * A storage target of the SCP.
+ * 
+ * Multiple targets can be configured with `storageTargets` to write every received
+ * instance to several backends at once (e.g. local disk for fast WADO access and S3
+ * for long-term archive).
+ * 
+ * @example
+ * ```typescript
+ * const scp = new StoreScp({
+ *   port: 11111,
+ *   storageTargets: [
+ *     { name: 'local', backend: 'Filesystem', outDir: './dicom-data' },
+ *     {
+ *       name: 'archive',
+ *       backend: 'S3',
+ *       policy: 'BestEffort',
+ *       s3Config: { bucket: 'dicom-archive', accessKey: 'KEY', secretKey: 'SECRET' }
+ *     }
+ *   ]
+ * });
+ * ```
+ */
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct StorageTarget {
+    /// Name used to identify the target in events (default: backend type)
+    pub name: Option<String>,
+    /// Storage backend type
+    pub backend: StorageBackendType,
+    /// Success policy (default: 'Required')
+    pub policy: Option<StoragePolicy>,
+    /// Output directory (required for 'Filesystem')
+    pub out_dir: Option<String>,
+    /// S3 configuration (required for 'S3')
+    pub s3_config: Option<S3Config>,
+    /// Durability and permission options (for 'Filesystem')
+    pub filesystem_options: Option<FilesystemOptions>,
+    /// Store every distinct instance content once, keyed by its SHA-256, and write
+    /// `{study}/{series}/{sop}.dcm` as references to it (not for 'Custom', default: false)
+    pub content_addressed: Option<bool>,
+}
+
+/// Location of a stored instance in one storage target
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct StorageLocation {
+    /// Name of the storage target
+    pub name: String,
+    /// Storage backend type
+    pub backend: StorageBackendType,
+    /// Success policy of the target
+    pub policy: StoragePolicy,
+    /// File path or `s3://` URL of the instance
+    pub location: String,
+    /// Whether the write was confirmed when the event was emitted
+    /// (false for pending best-effort replication)
+    pub stored: bool,
+}
+
+/**
+ * DICOM tags attached to objects written to S3 targets.
+ *
+ * Values are written as user metadata (`x-amz-meta-<tag>`) and/or S3 object tags so
+ * lifecycle rules, inventories and search can use them without reading the object.
+ * Objects are always stored with `Content-Type: application/dicom`.
+ * Tags that may identify the patient or staff (names, IDs, dates of birth, accession
+ * number, institution, private tags) are skipped unless `allowPhi` is set.
+ *
+ * @example
+ * ```typescript
+ * const scp = new StoreScp({
+ *   port: 11112,
+ *   storageBackend: 'S3',
+ *   s3Config: { bucket: 'dicom' },
+ *   s3Metadata: {
+ *     metadataTags: ['Modality', 'StudyDate', 'SOPClassUID'],
+ *     objectTags: ['Modality']
+ *   }
+ * });
+ * ```
+ */
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct S3MetadataOptions {
+    /// Tags written as `x-amz-meta-*` metadata (default: Modality, StudyDate, SOPClassUID, TransferSyntaxUID)
+    pub metadata_tags: Option<Vec<String>>,
+    /// Tags written as S3 object tags, at most 10 (default: none)
+    pub object_tags: Option<Vec<String>>,
+    /// Allow tags containing protected health information (default: false)
+    pub allow_phi: Option<bool>,
 }
 
 /// Abstract syntax (SOP Class) acceptance mode
@@ -119,6 +232,14 @@ pub struct StoreScp {
     /// Output directory for incoming objects using Filesystem storage backend
     // short = 'o', default_value = "."
     pub(crate) out_dir: Option<String>,
+    /// Storage targets each instance is written to (the first one is the primary target)
+    pub(crate) storage_targets: Vec<StorageTarget>,
+    /// Path of the embedded index updated for every stored instance
+    pub(crate) index_path: Option<String>,
+    /// DICOM tags attached to objects written to S3
+    pub(crate) s3_metadata: S3MetadataOptions,
+    /// Retention rules applied by the background sweeper
+    pub(crate) retention: Option<RetentionPolicy>,
     /// Store files with complete DICOM file meta header (true) or dataset-only (false)
     /// Default is false (dataset-only), which is more efficient and standard for PACS systems
     pub(crate) store_with_file_meta: bool,
@@ -137,6 +258,8 @@ pub struct StoreScp {
     /// Callback for modifying tags before storage (async, returns Promise)
     /// Tags are passed as JSON string due to NAPI-RS ThreadsafeFunction limitations with HashMap
     pub(crate) on_before_store: Option<Arc<ThreadsafeFunction<String, napi::bindgen_prelude::Promise<String>>>>,
+    /// Callback storing instances for 'Custom' storage targets (async, returns Promise)
+    pub(crate) on_custom_store: Option<Arc<CustomStoreCallback>>,
     /// Shutdown channel for graceful shutdown
     pub(crate) shutdown_tx: Option<tokio::sync::oneshot::Sender<()>>,
 }
@@ -180,7 +303,13 @@ pub enum StoreScpEvent {
     /// A DICOM file has been successfully stored
     OnFileStored,
     /// A complete study (all files) has been received and stored
-    OnStudyCompleted
+    OnStudyCompleted,
+    /// A received instance could not be stored and was moved to the quarantine area
+    OnQuarantined,
+    /// A study was purged (or selected in dry-run mode) by the retention policy
+    OnStudyPurged,
+    /// An instance was replicated to a best-effort storage target
+    OnReplicated
 }
 
 /**
@@ -199,7 +328,7 @@ pub struct ScpEventData {
 
 /// Details about SCP events with typed tag extraction
 #[napi(object)]
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct ScpEventDetails {
     /// File path where DICOM file was stored
     pub file: Option<String>,
@@ -219,6 +348,15 @@ pub struct ScpEventDetails {
     pub error: Option<String>,
     /// Study completion data with full hierarchy
     pub study: Option<StudyHierarchyData>,
+    /// Quarantine record (for OnQuarantined events)
+    pub quarantine: Option<QuarantineRecord>,
+    /// Location of the instance in each storage target (for OnFileStored events),
+    /// or of the replica (for OnReplicated events)
+    pub locations: Option<Vec<StorageLocation>>,
+    /// Hex SHA-256 of the stored instance (for OnFileStored events with content-addressed targets)
+    pub content_hash: Option<String>,
+    /// Purged study (for OnStudyPurged events)
+    pub purged: Option<PurgedStudy>,
 }
 
 /// Study hierarchy data for OnStudyCompleted event
@@ -264,12 +402,18 @@ pub struct InstanceHierarchyData {
 
 
 
-async fn run(args: StoreScp, mut shutdown_rx: tokio::sync::oneshot::Receiver<()>) -> Result<(), Box<dyn std::error::Error>> {
+async fn run(
+    args: StoreScp,
+    sweeper: Option<(retention::RetentionScope, RetentionPolicy)>,
+    mut shutdown_rx: tokio::sync::oneshot::Receiver<()>,
+) -> Result<(), Box<dyn std::error::Error>> {
 
-  std::fs::create_dir_all(args.out_dir.as_deref().unwrap_or(".")).unwrap_or_else(|e| {
-      error!("Could not create output directory: {}", e);
-      std::process::exit(-2);
-  });
+  for target in args.storage_targets.iter().filter(|t| t.backend == StorageBackendType::Filesystem) {
+      std::fs::create_dir_all(target.out_dir.as_deref().unwrap_or(".")).unwrap_or_else(|e| {
+          error!("Could not create output directory: {}", e);
+          std::process::exit(-2);
+      });
+  }
 
   let listen_addr = SocketAddrV4::new(Ipv4Addr::from(0), args.port);
   let listener = tokio::net::TcpListener::bind(listen_addr).await?;
@@ -283,6 +427,9 @@ async fn run(args: StoreScp, mut shutdown_rx: tokio::sync::oneshot::Receiver<()>
       data: None,
   });
 
+  // Stopped when the server stops (the handle is dropped)
+  let _sweeper = sweeper.map(|(scope, policy)| retention::spawn_sweeper(scope, policy));
+
   loop {
       tokio::select! {
           _ = &mut shutdown_rx => {
@@ -303,6 +450,10 @@ async fn run(args: StoreScp, mut shutdown_rx: tokio::sync::oneshot::Receiver<()>
                   max_pdu_length: args.max_pdu_length,
                   port: args.port,
                   out_dir: args.out_dir.clone(),
+                  storage_targets: args.storage_targets.clone(),
+                  index_path: args.index_path.clone(),
+                  s3_metadata: args.s3_metadata.clone(),
+                  retention: args.retention.clone(),
                   study_timeout: args.study_timeout,
                   storage_backend: args.storage_backend.clone(),
                   s3_config: args.s3_config.clone(),
@@ -314,6 +465,7 @@ async fn run(args: StoreScp, mut shutdown_rx: tokio::sync::oneshot::Receiver<()>
                   transfer_syntax_mode: args.transfer_syntax_mode.clone(),
                   transfer_syntaxes: args.transfer_syntaxes.clone(),
                   on_before_store: args.on_before_store.clone(),
+                  on_custom_store: args.on_custom_store.clone(),
                   shutdown_tx: None,
               };
 
@@ -340,9 +492,18 @@ async fn run(args: StoreScp, mut shutdown_rx: tokio::sync::oneshot::Receiver<()>
                                   tags: None,
                                   error: None,
                                   study: Some(study_hierarchy),
+                                  quarantine: None,
+                                  locations: None,
+                                  content_hash: None,
+                                  purged: None,
                               }),
                           });
-                      }))) => {
+                      })), move |event_details| {
+                          StoreScp::emit_event(StoreScpEvent::OnQuarantined, ScpEventData {
+                              message: "Instance quarantined".to_string(),
+                              data: Some(event_details),
+                          });
+                      }) => {
                           if let Err(e) = result {
                               StoreScp::emit_event(StoreScpEvent::OnError, ScpEventData {
                                   message: "Error storing file".to_string(),
@@ -356,6 +517,10 @@ async fn run(args: StoreScp, mut shutdown_rx: tokio::sync::oneshot::Receiver<()>
                                       tags: None,
                                       error: Some(e.to_string()),
                                       study: None,
+                                      quarantine: None,
+                                      locations: None,
+                                      content_hash: None,
+                                      purged: None,
                                   }),
                               });
                               error!("{}", Report::from_error(e));
@@ -437,6 +602,20 @@ pub struct StoreScpOptions {
     pub s3_config: Option<S3Config>,
     /// Output directory for filesystem storage (default: current directory)
     pub out_dir: Option<String>,
+    /// Atomic write durability (fsync) and file permission/ownership options for filesystem storage
+    pub filesystem_options: Option<FilesystemOptions>,
+    /// Deduplicate instances by content (see `StorageTarget.contentAddressed`, default: false)
+    pub content_addressed: Option<bool>,
+    /// Write every instance to several storage targets.
+    /// Overrides `storageBackend`, `s3Config` and `outDir` when set.
+    pub storage_targets: Option<Vec<StorageTarget>>,
+    /// Path of an embedded SQLite index updated for every stored instance.
+    /// Use the same path for `QidoServer`/`WadoServer` or open it with `DicomIndex`.
+    pub index_path: Option<String>,
+    /// DICOM tags attached as metadata and object tags to objects written to S3
+    pub s3_metadata: Option<S3MetadataOptions>,
+    /// Retention rules applied by a background sweeper (requires `indexPath`)
+    pub retention: Option<RetentionPolicy>,
     /// Store complete DICOM files with meta header vs dataset-only (default: false)
     pub store_with_file_meta: Option<bool>,
     /// DICOM tags to extract from received files (e.g., ['PatientName', 'StudyDate'])
@@ -593,6 +772,20 @@ impl StoreScp {
         
         let abstract_syntaxes = options.abstract_syntaxes.unwrap_or_default();
         let transfer_syntaxes = options.transfer_syntaxes.unwrap_or_default();
+
+        // Without explicit targets the single configured backend is the only (required) target
+        let storage_targets = match options.storage_targets {
+            Some(targets) if !targets.is_empty() => targets,
+            _ => vec![StorageTarget {
+                name: None,
+                backend: storage_backend.clone(),
+                policy: Some(StoragePolicy::Required),
+                out_dir: Some(options.out_dir.clone().unwrap_or_else(|| ".".to_string())),
+                s3_config: s3_config.clone(),
+                filesystem_options: options.filesystem_options.clone(),
+                content_addressed: options.content_addressed,
+            }],
+        };
         
         StoreScp {
             verbose,
@@ -601,6 +794,10 @@ impl StoreScp {
             max_pdu_length,
             port: options.port,
             out_dir: options.out_dir,
+            storage_targets,
+            index_path: options.index_path,
+            s3_metadata: options.s3_metadata.unwrap_or_default(),
+            retention: options.retention,
             study_timeout,
             storage_backend,
             s3_config,
@@ -612,6 +809,7 @@ impl StoreScp {
             transfer_syntax_mode,
             transfer_syntaxes,
             on_before_store: None,
+            on_custom_store: None,
             shutdown_tx: None,
         }
     }
@@ -652,28 +850,63 @@ impl StoreScp {
     #[napi]
     pub fn start(&mut self) -> napi::Result<()> {
         info!("Starting server...");
-        if self.storage_backend == StorageBackendType::S3 {
-            if let Some(ref s3_config) = self.s3_config {
-                info!("Using S3 storage backend");
-                info!("S3 Bucket: {}", s3_config.bucket);
-                if let Some(ref endpoint) = s3_config.endpoint {
-                    info!("S3 Endpoint: {}", endpoint);
+        for target in &self.storage_targets {
+            if target.backend == StorageBackendType::S3 {
+                if let Some(ref s3_config) = target.s3_config {
+                    info!("Using S3 storage backend");
+                    info!("S3 Bucket: {}", s3_config.bucket);
+                    if let Some(ref endpoint) = s3_config.endpoint {
+                        info!("S3 Endpoint: {}", endpoint);
+                    } else {
+                        info!("S3 Endpoint: Not specified");
+                    }
+                    // S3 connectivity check at server startup
+                    let config = s3_config.clone();
+                    let bucket = build_s3_bucket(&config).map_err(napi::Error::from_reason)?;
+                    RUNTIME.block_on(async move {
+                        check_s3_connectivity(&bucket).await;
+                    });
                 } else {
-                    info!("S3 Endpoint: Not specified");
+                    error!("S3 storage backend selected, but no S3 config provided!");
+                    return Err(napi::Error::from_reason("S3 config required for S3 backend"));
+                }
+            } else if target.backend == StorageBackendType::Custom {
+                if self.on_custom_store.is_none() {
+                    error!("Custom storage backend selected, but no onCustomStore callback registered!");
+                    return Err(napi::Error::from_reason("onCustomStore callback required for Custom backend"));
+                }
+                if target.content_addressed.unwrap_or(false) {
+                    return Err(napi::Error::from_reason("Custom backend does not support contentAddressed"));
                 }
-                // S3 connectivity check at server startup
-                let config = s3_config.clone();
-                RUNTIME.block_on(async move {
-                    let bucket = build_s3_bucket(&config);
-                    check_s3_connectivity(&bucket).await;
-                });
+                info!("Using Custom storage backend");
             } else {
-                error!("S3 storage backend selected, but no S3 config provided!");
-                return Err(napi::Error::from_reason("S3 config required for S3 backend"));
+                info!("Using Filesystem storage backend");
+            }
+            if target.content_addressed.unwrap_or(false) {
+                info!("Content-addressed storage enabled");
             }
-        } else {
-            info!("Using Filesystem storage backend");
         }
+        let s3_metadata_tags = self.s3_metadata.metadata_tags.iter().chain(self.s3_metadata.object_tags.iter()).flatten();
+        for name in s3_metadata_tags {
+            match crate::utils::parse_tag(name) {
+                Err(e) => warn!("Ignoring S3 metadata tag '{}': {}", name, e),
+                Ok(tag) if crate::utils::dicom_tags::is_phi_tag(tag) && !self.s3_metadata.allow_phi.unwrap_or(false) => {
+                    warn!("Ignoring S3 metadata tag '{}': may contain PHI (set allowPhi to include it)", name)
+                },
+                Ok(_) => {},
+            }
+        }
+        if self.s3_metadata.object_tags.as_ref().is_some_and(|tags| tags.len() > 10) {
+            warn!("S3 allows at most 10 object tags, only the first 10 are written");
+        }
+        if let Some(ref index_path) = self.index_path {
+            crate::index::open_index(index_path).map_err(napi::Error::from_reason)?;
+            info!("Indexing stored instances in {}", index_path);
+        }
+        let sweeper = match &self.retention {
+            Some(policy) => Some((retention::RetentionScope::from_args(self).map_err(napi::Error::from_reason)?, policy.clone())),
+            None => None,
+        };
 
         let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
         
@@ -684,6 +917,10 @@ impl StoreScp {
             max_pdu_length: self.max_pdu_length,
             port: self.port,
             out_dir: self.out_dir.clone(),
+            storage_targets: self.storage_targets.clone(),
+            index_path: self.index_path.clone(),
+            s3_metadata: self.s3_metadata.clone(),
+            retention: self.retention.clone(),
             study_timeout: self.study_timeout,
             storage_backend: self.storage_backend.clone(),
             s3_config: self.s3_config.clone(),
@@ -695,11 +932,12 @@ impl StoreScp {
             transfer_syntax_mode: self.transfer_syntax_mode.clone(),
             transfer_syntaxes: self.transfer_syntaxes.clone(),
             on_before_store: self.on_before_store.clone(),
+            on_custom_store: self.on_custom_store.clone(),
             shutdown_tx: None,
         };
 
         RUNTIME.spawn(async move {
-            if let Err(e) = run(args, shutdown_rx).await {
+            if let Err(e) = run(args, sweeper, shutdown_rx).await {
                 error!("Server error: {:?}", e);
             }
             info!("Server stopped");
@@ -816,6 +1054,162 @@ impl StoreScp {
         });
     }
 
+    /**
+     * Register callback for quarantine events
+     * 
+     * Called when a received instance could not be parsed, is missing a required UID
+     * or was rejected by `onBeforeStore`. The raw data set is kept in the quarantine
+     * area of the storage backend and the SCU receives a failure status (C000H).
+     * `data.quarantine` holds the sidecar record of the quarantined item.
+     */
+    #[napi]
+    pub fn on_quarantined(&self, handler: ThreadsafeFunction<ScpEventData, ()>) {
+        let mut receiver = EVENT_CHANNEL.0.subscribe();
+        RUNTIME.spawn(async move {
+            loop {
+                if let Ok((StoreScpEvent::OnQuarantined, data)) = receiver.recv().await {
+                    handler.call(Ok(data), ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+        });
+    }
+
+    /**
+     * Register callback for study purged events
+     * 
+     * Called for every study deleted by the retention policy, or selected for deletion
+     * in dry-run mode. `data.purged` describes the study and the rule that selected it.
+     */
+    #[napi]
+    pub fn on_study_purged(&self, handler: ThreadsafeFunction<ScpEventData, ()>) {
+        let mut receiver = EVENT_CHANNEL.0.subscribe();
+        RUNTIME.spawn(async move {
+            loop {
+                if let Ok((StoreScpEvent::OnStudyPurged, data)) = receiver.recv().await {
+                    handler.call(Ok(data), ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+        });
+    }
+
+    /**
+     * Register callback for replication events
+     * 
+     * Called when an instance was written to a best-effort storage target.
+     * `data.locations` holds the location of the replica; failed replications
+     * are reported through `onError`.
+     */
+    #[napi]
+    pub fn on_replicated(&self, handler: ThreadsafeFunction<ScpEventData, ()>) {
+        let mut receiver = EVENT_CHANNEL.0.subscribe();
+        RUNTIME.spawn(async move {
+            loop {
+                if let Ok((StoreScpEvent::OnReplicated, data)) = receiver.recv().await {
+                    handler.call(Ok(data), ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+        });
+    }
+
+    /**
+     * Apply the configured retention policy once, independent of the background sweeper.
+     * 
+     * @param dryRun - Only report the studies that would be purged (default: `dryRun` of the policy)
+     * @returns Purged (or selected) studies in the order they were processed; studies that could not be deleted are not included
+     * @throws Error if no retention policy or no `indexPath` is configured
+     * 
+     * @example
+     * ```typescript
+     * const candidates = await scp.applyRetention(true);
+     * const bytes = candidates.reduce((sum, s) => sum + s.size, 0);
+     * console.log(`${candidates.length} studies (${bytes} bytes) would be purged`);
+     * ```
+     */
+    #[napi]
+    pub async fn apply_retention(&self, dry_run: Option<bool>) -> napi::Result<Vec<PurgedStudy>> {
+        let policy = self
+            .retention
+            .clone()
+            .ok_or_else(|| napi::Error::from_reason("No retention policy configured"))?;
+        let dry_run = dry_run.or(policy.dry_run).unwrap_or(false);
+        let scope = retention::RetentionScope::from_args(self)
+            .map_err(|e| napi::Error::from_reason(format!("Retention sweep failed: {}", e)))?;
+        retention::sweep(&scope, &policy, dry_run)
+            .await
+            .map_err(|e| napi::Error::from_reason(format!("Retention sweep failed: {}", e)))
+    }
+
+    /**
+     * List all items in the quarantine area of the configured storage backend.
+     * 
+     * @returns Sidecar records of all quarantined items, oldest first
+     * 
+     * @example
+     * ```typescript
+     * const items = await scp.listQuarantined();
+     * for (const item of items) {
+     *   console.log(item.id, item.callingAeTitle, item.error);
+     * }
+     * ```
+     */
+    #[napi]
+    pub async fn list_quarantined(&self) -> napi::Result<Vec<QuarantineRecord>> {
+        let targets = store_async::StorageTargets::from_args(self)
+            .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+        quarantine::list(targets.primary().backend.as_ref())
+            .await
+            .map_err(|e| napi::Error::from_reason(format!("Failed to list quarantined items: {}", e)))
+    }
+
+    /**
+     * Re-process a quarantined item.
+     * 
+     * The raw data set is parsed again and, on success, written to its regular
+     * location and removed from the quarantine area. An `OnFileStored` event is
+     * emitted for the stored instance. `onBeforeStore` is not invoked.
+     * 
+     * @param id - Quarantine item identifier
+     * @param transferSyntaxUid - Transfer syntax (UID or name) to parse the data set with,
+     *   overriding the negotiated one recorded in the sidecar
+     * @returns Details of the stored instance
+     * @throws Error if the item still cannot be parsed or stored
+     * 
+     * @example
+     * ```typescript
+     * // The sender announced the wrong transfer syntax
+     * const stored = await scp.reprocessQuarantined(item.id, 'ExplicitVRLittleEndian');
+     * console.log('Stored', stored.file);
+     * ```
+     */
+    #[napi]
+    pub async fn reprocess_quarantined(&self, id: String, transfer_syntax_uid: Option<String>) -> napi::Result<ScpEventDetails> {
+        use sop_classes::map_transfer_syntax_name;
+        let transfer_syntax_uid = transfer_syntax_uid
+            .map(|ts| map_transfer_syntax_name(&ts).map(String::from).unwrap_or(ts));
+        let details = store_async::reprocess_quarantined(self, &id, transfer_syntax_uid)
+            .await
+            .map_err(|e| napi::Error::from_reason(Report::from_error(e).to_string()))?;
+        StoreScp::emit_event(StoreScpEvent::OnFileStored, ScpEventData {
+            message: "File stored successfully".to_string(),
+            data: Some(details.clone()),
+        });
+        Ok(details)
+    }
+
+    /**
+     * Delete a quarantined item (raw data set and sidecar) from the storage backend.
+     * 
+     * @param id - Quarantine item identifier
+     */
+    #[napi]
+    pub async fn delete_quarantined(&self, id: String) -> napi::Result<()> {
+        let targets = store_async::StorageTargets::from_args(self)
+            .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+        quarantine::remove(targets.primary().backend.as_ref(), &id)
+            .await
+            .map_err(|e| napi::Error::from_reason(format!("Failed to delete quarantined item {}: {}", id, e)))
+    }
+
     /**
      * Register a callback to modify DICOM tags before files are saved.
      * 
@@ -923,6 +1317,41 @@ impl StoreScp {
         self.on_before_store = Some(Arc::new(callback));
     }
 
+    /**
+     * Register the callback that stores instances for 'Custom' storage targets.
+     *
+     * The callback receives the storage key, the serialized DICOM instance and the
+     * extracted metadata. The C-STORE request is acknowledged only after the returned
+     * promise resolves; if it rejects, the SCU receives a failure status (0xA700) and an
+     * OnError event is emitted. Must be registered before `start()` when a target uses
+     * the 'Custom' backend.
+     *
+     * Custom targets can only be written to. Quarantined items are also passed to the
+     * callback if the custom target is the primary target, but they cannot be listed or
+     * reprocessed from there.
+     *
+     * @param callback - Async function storing the instance
+     *
+     * @example
+     * ```typescript
+     * const scp = new StoreScp({ port: 11112, storageBackend: 'Custom', extractTags: ['PatientID', 'Modality'] });
+     *
+     * scp.onCustomStore(async (err, { key, data, metadata }) => {
+     *   if (err) throw err;
+     *   await blobService.upload(key, data, {
+     *     study: metadata.StudyInstanceUID,
+     *     modality: metadata.Modality
+     *   });
+     * });
+     *
+     * scp.start();
+     * ```
+     */
+    #[napi(ts_args_type = "callback: (err: Error | null, request: CustomStoreRequest) => Promise<void>")]
+    pub fn on_custom_store(&mut self, callback: ThreadsafeFunction<CustomStoreRequest, napi::bindgen_prelude::Promise<()>>) {
+        self.on_custom_store = Some(Arc::new(callback));
+    }
+
     fn emit_event(event: StoreScpEvent, data: ScpEventData) {
         let _ = EVENT_CHANNEL.0.send((event, data));
     }
@@ -932,6 +1361,7 @@ pub(crate) fn create_cstore_response(
     message_id: u16,
     sop_class_uid: &str,
     sop_instance_uid: &str,
+    status: u16,
 ) -> InMemDicomObject<StandardDataDictionary> {
     InMemDicomObject::command_from_element_iter([
         DataElement::new(
@@ -950,7 +1380,7 @@ pub(crate) fn create_cstore_response(
             VR::US,
             dicom_value!(U16, [0x0101]),
         ),
-        DataElement::new(tags::STATUS, VR::US, dicom_value!(U16, [0x0000])),
+        DataElement::new(tags::STATUS, VR::US, dicom_value!(U16, [status])),
         DataElement::new(
             tags::AFFECTED_SOP_INSTANCE_UID,
             VR::UI,
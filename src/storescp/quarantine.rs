@@ -0,0 +1,164 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::storage::StorageBackend;
+
+/// Key prefix (relative to the storage root) under which quarantined items are kept
+pub(crate) const QUARANTINE_PREFIX: &str = "quarantine";
+
+/// Status code sent in the C-STORE-RSP for quarantined instances (Error: Cannot understand)
+pub(crate) const STATUS_CANNOT_UNDERSTAND: u16 = 0xC000;
+
+type StorageResult<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/**
+ * Sidecar record describing a quarantined instance.
+ *
+ * Instances that cannot be parsed, are missing required UIDs or are rejected by
+ * `onBeforeStore` are not dropped. The raw received bytes are kept in the
+ * `quarantine/` area of the configured storage backend together with this record
+ * as a JSON sidecar (`quarantine/<id>.json`).
+ */
+#[napi(object)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuarantineRecord {
+    /// Unique identifier of the quarantined item
+    pub id: String,
+    /// Reason why the instance was quarantined
+    pub error: String,
+    /// AE title of the sending application
+    pub calling_ae_title: String,
+    /// Presentation context ID the data set was received on
+    pub presentation_context_id: u32,
+    /// Abstract syntax negotiated for the presentation context (if known)
+    pub abstract_syntax: Option<String>,
+    /// Transfer syntax negotiated for the presentation context (if known)
+    pub transfer_syntax_uid: Option<String>,
+    /// Affected SOP Class UID from the C-STORE request
+    pub sop_class_uid: Option<String>,
+    /// Affected SOP Instance UID from the C-STORE request
+    pub sop_instance_uid: Option<String>,
+    /// Time the instance was quarantined (milliseconds since UNIX epoch)
+    pub quarantined_at: i64,
+    /// Size of the raw data set in bytes
+    pub size: u32,
+    /// Storage key of the raw data set
+    pub data_key: String,
+}
+
+impl QuarantineRecord {
+    /// Create a record for a new quarantine item with a fresh identifier
+    pub(crate) fn new(error: String, calling_ae_title: String, presentation_context_id: u8, size: usize) -> Self {
+        let id = uuid::Uuid::new_v4().to_string();
+        let quarantined_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or_default();
+        QuarantineRecord {
+            data_key: data_key(&id),
+            id,
+            error,
+            calling_ae_title,
+            presentation_context_id: presentation_context_id as u32,
+            abstract_syntax: None,
+            transfer_syntax_uid: None,
+            sop_class_uid: None,
+            sop_instance_uid: None,
+            quarantined_at,
+            size: size as u32,
+        }
+    }
+}
+
+fn data_key(id: &str) -> String {
+    format!("{}/{}.bin", QUARANTINE_PREFIX, id)
+}
+
+fn sidecar_key(id: &str) -> String {
+    format!("{}/{}.json", QUARANTINE_PREFIX, id)
+}
+
+/// Reject identifiers that could escape the quarantine area
+fn check_id(id: &str) -> StorageResult<()> {
+    if id.is_empty() || id.contains(['/', '\\']) || id.contains("..") {
+        return Err(format!("invalid quarantine id: {}", id).into());
+    }
+    Ok(())
+}
+
+/// Store the raw data set and its JSON sidecar in the quarantine area
+pub(crate) async fn quarantine(backend: &dyn StorageBackend, record: &QuarantineRecord, data: &[u8]) -> StorageResult<()> {
+    let sidecar = serde_json::to_vec_pretty(record)?;
+    backend.store_file(&record.data_key, data).await?;
+    backend.store_file(&sidecar_key(&record.id), &sidecar).await?;
+    warn!("Quarantined instance {:?} as {}: {}", record.sop_instance_uid, record.id, record.error);
+    Ok(())
+}
+
+/// List all quarantined items, oldest first
+pub(crate) async fn list(backend: &dyn StorageBackend) -> StorageResult<Vec<QuarantineRecord>> {
+    let keys = backend.list_files(QUARANTINE_PREFIX).await?;
+    let mut records = Vec::new();
+    for key in keys.iter().filter(|k| k.ends_with(".json")) {
+        let sidecar = backend.read_file(key).await?;
+        match serde_json::from_slice::<QuarantineRecord>(&sidecar) {
+            Ok(record) => records.push(record),
+            Err(e) => warn!("Skipping unreadable quarantine sidecar {}: {}", key, e),
+        }
+    }
+    records.sort_by_key(|r| r.quarantined_at);
+    Ok(records)
+}
+
+/// Load the sidecar record and the raw data set of a quarantined item
+pub(crate) async fn load(backend: &dyn StorageBackend, id: &str) -> StorageResult<(QuarantineRecord, Vec<u8>)> {
+    check_id(id)?;
+    let sidecar = backend.read_file(&sidecar_key(id)).await?;
+    let record: QuarantineRecord = serde_json::from_slice(&sidecar)?;
+    let data = backend.read_file(&record.data_key).await?;
+    Ok((record, data))
+}
+
+/// Remove a quarantined item (raw data and sidecar)
+pub(crate) async fn remove(backend: &dyn StorageBackend, id: &str) -> StorageResult<()> {
+    check_id(id)?;
+    backend.delete_file(&data_key(id)).await?;
+    backend.delete_file(&sidecar_key(id)).await?;
+    info!("Removed quarantined item {}", id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::FilesystemBackend;
+
+    #[tokio::test]
+    async fn test_quarantine_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("quarantine-test-{}", uuid::Uuid::new_v4()));
+        let backend = FilesystemBackend { root: dir.to_string_lossy().to_string(), ..Default::default() };
+
+        let mut record = QuarantineRecord::new("missing SOP Instance UID".to_string(), "TEST-SCU".to_string(), 1, 4);
+        record.sop_instance_uid = Some("1.2.3".to_string());
+        quarantine(&backend, &record, &[1, 2, 3, 4]).await.unwrap();
+
+        let records = list(&backend).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id, record.id);
+        assert_eq!(records[0].calling_ae_title, "TEST-SCU");
+
+        let (loaded, data) = load(&backend, &record.id).await.unwrap();
+        assert_eq!(loaded.sop_instance_uid.as_deref(), Some("1.2.3"));
+        assert_eq!(data, vec![1, 2, 3, 4]);
+
+        assert!(load(&backend, "../secret").await.is_err());
+
+        remove(&backend, &record.id).await.unwrap();
+        assert!(list(&backend).await.unwrap().is_empty());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tracing::{debug, error, info, warn};
+
+use crate::index::{open_index, IndexedStudy};
+use crate::storescp::store_async::StorageTargets;
+use crate::storescp::{ScpEventData, ScpEventDetails, StorageBackendType, StoreScp, StoreScpEvent};
+
+/// Default time between two retention sweeps (1 hour)
+const DEFAULT_SWEEP_INTERVAL: u32 = 3600;
+
+const MILLIS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+/// Time a study's age is measured from
+#[napi(string_enum)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RetentionAgeBasis {
+    /// Time the last instance of the study was received
+    ReceivedTime,
+    /// StudyDate of the study (studies without StudyDate use the receive time)
+    StudyDate,
+}
+
+/**
+ * Retention rules applied to the studies stored by a StoreScp.
+ *
+ * Rules are evaluated against the embedded index (`indexPath` is required): first
+ * `maxAgeDays`, then `modalityQuotas`, then `maxTotalSize`. Quota and size rules purge
+ * the oldest studies first. Purged studies are deleted from every storage target
+ * (except 'Custom' targets) and from the index, and an `OnStudyPurged` event is emitted
+ * for each of them. A study with files that cannot be deleted stays in the index and is
+ * retried by the next sweep; an `OnError` event is emitted for it instead.
+ *
+ * @example
+ * ```typescript
+ * const scp = new StoreScp({
+ *   port: 11112,
+ *   outDir: './teaching',
+ *   indexPath: './teaching/index.sqlite',
+ *   retention: {
+ *     maxAgeDays: 90,
+ *     maxTotalSize: 500 * 1024 ** 3,
+ *     modalityQuotas: { MG: 100 * 1024 ** 3 },
+ *     sweepInterval: 900
+ *   }
+ * });
+ * ```
+ */
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    /// Purge studies older than this many days
+    pub max_age_days: Option<u32>,
+    /// Time the age is measured from (default: 'ReceivedTime')
+    pub age_basis: Option<RetentionAgeBasis>,
+    /// Keep the total size of all studies below this many bytes
+    pub max_total_size: Option<i64>,
+    /// Maximum size in bytes of the instances of each modality (e.g. `{ CT: 1e12 }`)
+    pub modality_quotas: Option<HashMap<String, i64>>,
+    /// Seconds between two background sweeps (default: 3600)
+    pub sweep_interval: Option<u32>,
+    /// Only report the studies that would be purged, without deleting them (default: false)
+    pub dry_run: Option<bool>,
+}
+
+/// Rule that caused a study to be purged
+#[napi(string_enum)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PurgeReason {
+    /// Older than `maxAgeDays`
+    MaxAge,
+    /// A modality exceeded its entry in `modalityQuotas`
+    ModalityQuota,
+    /// The total size exceeded `maxTotalSize`
+    MaxTotalSize,
+}
+
+/// A study purged (or, in dry-run mode, selected for purging) by a retention sweep
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct PurgedStudy {
+    /// Study Instance UID
+    pub study_instance_uid: String,
+    /// Patient ID
+    pub patient_id: Option<String>,
+    /// StudyDate (YYYYMMDD)
+    pub study_date: Option<String>,
+    /// Time the last instance was received (milliseconds since UNIX epoch)
+    pub received_at: i64,
+    /// Modalities of the series of the study
+    pub modalities: Vec<String>,
+    /// Number of instances
+    pub instances: u32,
+    /// Size of all instances in bytes
+    pub size: i64,
+    /// Rule that selected the study
+    pub reason: PurgeReason,
+    /// True if the study was only reported and not deleted
+    pub dry_run: bool,
+}
+
+/// YYYYMMDD of the day `days` after the Unix epoch (proleptic Gregorian calendar)
+fn civil_date(days: i64) -> String {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}{:02}{:02}", year, month, day)
+}
+
+/// Valid StudyDate of a study, if any
+fn study_date(study: &IndexedStudy) -> Option<&str> {
+    study
+        .study_date
+        .as_deref()
+        .map(str::trim)
+        .filter(|d| d.len() == 8 && d.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Select the studies to purge, in the order they should be purged
+pub(crate) fn select_studies(studies: &[IndexedStudy], policy: &RetentionPolicy, now: i64) -> Vec<(usize, PurgeReason)> {
+    let by_study_date = policy.age_basis == Some(RetentionAgeBasis::StudyDate);
+    let mut order: Vec<usize> = (0..studies.len()).collect();
+    if by_study_date {
+        order.sort_by_cached_key(|&i| {
+            let study = &studies[i];
+            let date = study_date(study)
+                .map(str::to_string)
+                .unwrap_or_else(|| civil_date(study.received_at.div_euclid(MILLIS_PER_DAY)));
+            (date, study.received_at)
+        });
+    } else {
+        order.sort_by_key(|&i| studies[i].received_at);
+    }
+
+    let mut selected = Vec::new();
+    let mut purged = HashSet::new();
+
+    if let Some(days) = policy.max_age_days {
+        let cutoff = now - i64::from(days) * MILLIS_PER_DAY;
+        let cutoff_date = civil_date(cutoff.div_euclid(MILLIS_PER_DAY));
+        for &i in &order {
+            let expired = match (by_study_date, study_date(&studies[i])) {
+                (true, Some(date)) => *date < *cutoff_date,
+                _ => studies[i].received_at < cutoff,
+            };
+            if expired && purged.insert(i) {
+                selected.push((i, PurgeReason::MaxAge));
+            }
+        }
+    }
+
+    let mut quotas: Vec<_> = policy.modality_quotas.iter().flatten().collect();
+    quotas.sort();
+    for (modality, &quota) in quotas {
+        let usage = |purged: &HashSet<usize>| -> i64 {
+            (0..studies.len())
+                .filter(|i| !purged.contains(i))
+                .filter_map(|i| studies[i].modality_sizes.get(modality))
+                .sum()
+        };
+        let mut used = usage(&purged);
+        for &i in &order {
+            if used <= quota {
+                break;
+            }
+            if let Some(size) = studies[i].modality_sizes.get(modality) {
+                if purged.insert(i) {
+                    used -= size;
+                    selected.push((i, PurgeReason::ModalityQuota));
+                }
+            }
+        }
+    }
+
+    if let Some(max_total_size) = policy.max_total_size {
+        let mut used: i64 = (0..studies.len()).filter(|i| !purged.contains(i)).map(|i| studies[i].size).sum();
+        for &i in &order {
+            if used <= max_total_size {
+                break;
+            }
+            if purged.insert(i) {
+                used -= studies[i].size;
+                selected.push((i, PurgeReason::MaxTotalSize));
+            }
+        }
+    }
+
+    selected
+}
+
+/// Delete the files of a study from all storage targets except 'Custom' ones.
+/// Returns the files that could not be deleted; files that are already gone do not count.
+async fn delete_study_files(targets: &StorageTargets, keys: &[String]) -> Vec<String> {
+    let mut failures = Vec::new();
+    for target in targets.iter().filter(|t| t.kind != StorageBackendType::Custom) {
+        for key in keys {
+            if let Err(e) = target.backend.delete_file(key).await {
+                // e.g. never replicated to a best-effort target, or deleted by an earlier sweep
+                if matches!(target.backend.exists(key).await, Ok(false)) {
+                    continue;
+                }
+                warn!("Could not delete {} from {}: {}", key, target.name, e);
+                failures.push(format!("{} in {}: {}", key, target.name, e));
+            }
+        }
+    }
+    failures
+}
+
+/// Index and storage targets of a StoreScp that a retention policy applies to
+pub(crate) struct RetentionScope {
+    index_path: String,
+    targets: StorageTargets,
+}
+
+impl RetentionScope {
+    pub fn from_args(args: &StoreScp) -> Result<Self, String> {
+        let index_path = args.index_path.clone().ok_or("Retention policies require indexPath")?;
+        let targets = StorageTargets::from_args(args).map_err(|e| e.to_string())?;
+        Ok(RetentionScope { index_path, targets })
+    }
+}
+
+/// Apply the retention policy once: select studies from the index, delete them from
+/// all storage targets and the index (unless `dry_run`) and emit OnStudyPurged events.
+///
+/// A study with files that could not be deleted stays in the index, so its size still counts
+/// and the next sweep retries it; an OnError event is emitted instead of OnStudyPurged.
+pub(crate) async fn sweep(scope: &RetentionScope, policy: &RetentionPolicy, dry_run: bool) -> Result<Vec<PurgedStudy>, String> {
+    let index = open_index(&scope.index_path)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default();
+
+    let studies = index.run(|db| db.studies_with_sizes()).await?;
+    let mut purged = Vec::new();
+    for (i, reason) in select_studies(&studies, policy, now) {
+        let study = &studies[i];
+        if !dry_run {
+            let study_instance_uid = study.study_instance_uid.clone();
+            let keys = index.run(move |db| db.study_storage_keys(&study_instance_uid)).await?;
+            let failures = delete_study_files(&scope.targets, &keys).await;
+            if !failures.is_empty() {
+                let message = format!(
+                    "Could not purge study {}: {} files could not be deleted, the study is kept in the index (first: {})",
+                    study.study_instance_uid,
+                    failures.len(),
+                    failures[0]
+                );
+                error!("{}", message);
+                StoreScp::emit_event(StoreScpEvent::OnError, ScpEventData {
+                    message: "Study purge failed".to_string(),
+                    data: Some(ScpEventDetails {
+                        study_instance_uid: Some(study.study_instance_uid.clone()),
+                        error: Some(message),
+                        ..Default::default()
+                    }),
+                });
+                continue;
+            }
+            let study_instance_uid = study.study_instance_uid.clone();
+            index.run(move |db| db.remove_study(&study_instance_uid)).await?;
+            info!("Purged study {} ({:?}, {} bytes)", study.study_instance_uid, reason, study.size);
+        } else {
+            debug!("Would purge study {} ({:?}, {} bytes)", study.study_instance_uid, reason, study.size);
+        }
+
+        let mut modalities: Vec<String> = study.modality_sizes.keys().filter(|m| !m.is_empty()).cloned().collect();
+        modalities.sort();
+        let record = PurgedStudy {
+            study_instance_uid: study.study_instance_uid.clone(),
+            patient_id: study.patient_id.clone(),
+            study_date: study.study_date.clone(),
+            received_at: study.received_at,
+            modalities,
+            instances: study.instances,
+            size: study.size,
+            reason,
+            dry_run,
+        };
+        StoreScp::emit_event(StoreScpEvent::OnStudyPurged, ScpEventData {
+            message: if dry_run { "Study would be purged" } else { "Study purged" }.to_string(),
+            data: Some(ScpEventDetails {
+                study_instance_uid: Some(record.study_instance_uid.clone()),
+                purged: Some(record.clone()),
+                ..Default::default()
+            }),
+        });
+        purged.push(record);
+    }
+    Ok(purged)
+}
+
+/// Background sweeper task, aborted when dropped
+pub(crate) struct Sweeper(tokio::task::JoinHandle<()>);
+
+impl Drop for Sweeper {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Spawn a task applying the retention policy every `sweepInterval` seconds, starting immediately
+pub(crate) fn spawn_sweeper(scope: RetentionScope, policy: RetentionPolicy) -> Sweeper {
+    Sweeper(tokio::spawn(run_sweeper(scope, policy)))
+}
+
+async fn run_sweeper(scope: RetentionScope, policy: RetentionPolicy) {
+    let interval = policy.sweep_interval.unwrap_or(DEFAULT_SWEEP_INTERVAL).max(1);
+    let dry_run = policy.dry_run.unwrap_or(false);
+    let mut ticker = tokio::time::interval(Duration::from_secs(u64::from(interval)));
+    loop {
+        ticker.tick().await;
+        match sweep(&scope, &policy, dry_run).await {
+            Ok(purged) if !purged.is_empty() => info!("Retention sweep selected {} studies", purged.len()),
+            Ok(_) => debug!("Retention sweep: nothing to purge"),
+            Err(e) => {
+                warn!("Retention sweep failed: {}", e);
+                StoreScp::emit_event(StoreScpEvent::OnError, ScpEventData {
+                    message: "Retention sweep failed".to_string(),
+                    data: Some(ScpEventDetails { error: Some(e), ..Default::default() }),
+                });
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn study(uid: &str, study_date: &str, received_at: i64, sizes: &[(&str, i64)]) -> IndexedStudy {
+        IndexedStudy {
+            study_instance_uid: uid.to_string(),
+            study_date: Some(study_date.to_string()),
+            received_at,
+            size: sizes.iter().map(|(_, s)| s).sum(),
+            modality_sizes: sizes.iter().map(|(m, s)| (m.to_string(), *s)).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_civil_date() {
+        assert_eq!(civil_date(0), "19700101");
+        assert_eq!(civil_date(19_797), "20240315");
+    }
+
+    #[test]
+    fn test_select_studies() {
+        let now = 20_000 * MILLIS_PER_DAY;
+        let studies = vec![
+            study("old", "20100101", now - 40 * MILLIS_PER_DAY, &[("CT", 100)]),
+            study("mg1", "20240101", now - 20 * MILLIS_PER_DAY, &[("MG", 50)]),
+            study("mg2", "20240102", now - 10 * MILLIS_PER_DAY, &[("MG", 50), ("SR", 1)]),
+            study("new", "20240103", now - MILLIS_PER_DAY, &[("CT", 100)]),
+        ];
+        let uids = |policy: &RetentionPolicy| -> Vec<(String, PurgeReason)> {
+            select_studies(&studies, policy, now)
+                .into_iter()
+                .map(|(i, reason)| (studies[i].study_instance_uid.clone(), reason))
+                .collect()
+        };
+
+        let by_age = RetentionPolicy { max_age_days: Some(30), ..Default::default() };
+        assert_eq!(uids(&by_age), vec![("old".to_string(), PurgeReason::MaxAge)]);
+
+        let by_study_date = RetentionPolicy { age_basis: Some(RetentionAgeBasis::StudyDate), ..by_age };
+        assert_eq!(uids(&by_study_date).len(), 4);
+
+        let quota = RetentionPolicy { modality_quotas: Some(HashMap::from([("MG".to_string(), 60)])), ..Default::default() };
+        assert_eq!(uids(&quota), vec![("mg1".to_string(), PurgeReason::ModalityQuota)]);
+
+        let total = RetentionPolicy { max_total_size: Some(160), ..quota };
+        assert_eq!(
+            uids(&total),
+            vec![("mg1".to_string(), PurgeReason::ModalityQuota), ("old".to_string(), PurgeReason::MaxTotalSize)]
+        );
+    }
+}
@@ -1,11 +1,10 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
 use dicom_dictionary_std::tags;
 use dicom_encoding::transfer_syntax::TransferSyntaxIndex;
-use dicom_object::{FileMetaTableBuilder, InMemDicomObject};
+use dicom_object::{FileMetaTable, FileMetaTableBuilder, InMemDicomObject};
 use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
 use dicom_core::{dicom_value, DataElement, VR};
 use dicom_ul::{
@@ -13,16 +12,20 @@ use dicom_ul::{
     pdu::{PDataValueType, PresentationContextResultReason},
     Pdu,
 };
-use snafu::{OptionExt, Report, ResultExt, Whatever};
+use snafu::{whatever, OptionExt, Report, ResultExt, Whatever};
 use tokio::sync::Mutex;
 use tokio::time::sleep;
 use tracing::{debug, info, warn, error};
 use serde::Serialize;
-use async_trait::async_trait;
 
+use crate::index::{open_index, IndexDb, InstanceRecord};
+use crate::storescp::quarantine::{self, QuarantineRecord, STATUS_CANNOT_UNDERSTAND};
+use crate::storescp::{StorageBackendType, StorageLocation, StoragePolicy, StorageTarget};
 use crate::storescp::{create_cecho_response, create_cstore_response, transfer::ABSTRACT_SYNTAXES, ScpEventDetails, StudyHierarchyData, SeriesHierarchyData, InstanceHierarchyData};
-use crate::utils::{build_s3_bucket, s3_put_object, CustomTag};
-use crate::utils::dicom_tags::{parse_tag, get_tag_scope, TagScope};
+use crate::storage::{content_hash, ContentAddressedBackend, CustomBackend, CustomStoreCallback, FilesystemBackend, ObjectAttributes, S3Backend, StorageBackend};
+use crate::storescp::S3MetadataOptions;
+use crate::utils::{s3_metadata_key, s3_sanitize_value, CustomTag};
+use crate::utils::dicom_tags::{is_phi_tag, parse_tag, get_tag_scope, TagScope};
 
 // New hierarchy for OnStudyCompleted event
 #[derive(Clone, Debug, Serialize)]
@@ -146,17 +149,15 @@ pub async fn run_store_async(
     scu_stream: tokio::net::TcpStream,
     args: &crate::storescp::StoreScp,
     on_file_stored: impl Fn(ScpEventDetails) + Send + 'static,
-    on_study_completed: Arc<Mutex<dyn Fn(StudyHierarchyData) + Send + 'static>>
+    on_study_completed: Arc<Mutex<dyn Fn(StudyHierarchyData) + Send + 'static>>,
+    on_quarantined: impl Fn(ScpEventDetails) + Send + 'static,
 ) -> Result<(), Whatever> {
     // Access fields directly instead of destructuring due to #[napi] wrapper
     let verbose = &args.verbose;
     let calling_ae_title = &args.calling_ae_title;
     let strict = &args.strict;
     let max_pdu_length = &args.max_pdu_length;
-    let out_dir = &args.out_dir;
     let study_timeout = &args.study_timeout;
-    let storage_backend = &args.storage_backend;
-    let s3_config = &args.s3_config;
     let store_with_file_meta = &args.store_with_file_meta;
     let extract_tags = &args.extract_tags;
     let extract_custom_tags = &args.extract_custom_tags;
@@ -245,10 +246,7 @@ pub async fn run_store_async(
     inner(
         association,
         *verbose,
-        out_dir,
         *study_timeout,
-        storage_backend,
-        s3_config,
         *store_with_file_meta,
         args,
         extract_tags,
@@ -256,6 +254,7 @@ pub async fn run_store_async(
         on_before_store,
         on_file_stored,
         on_study_completed,
+        on_quarantined,
     )
     .await?;
 
@@ -275,17 +274,15 @@ pub async fn run_store_async(
 async fn inner(
     mut association: ServerAssociation<tokio::net::TcpStream>,
     verbose: bool,
-    out_dir: &Option<String>,
     study_timeout: u32,
-    storage_backend: &crate::storescp::StorageBackendType,
-    s3_config: &Option<crate::storescp::S3Config>,
     store_with_file_meta: bool,
     args: &crate::storescp::StoreScp,
     extract_tags: &[String],
     extract_custom_tags: &[CustomTag],
     on_before_store: &Option<Arc<napi::threadsafe_function::ThreadsafeFunction<String, napi::bindgen_prelude::Promise<String>>>>,
     on_file_stored: impl Fn(ScpEventDetails) + Send + 'static,
-    on_study_completed: Arc<Mutex<dyn Fn(StudyHierarchyData) + Send + 'static>>
+    on_study_completed: Arc<Mutex<dyn Fn(StudyHierarchyData) + Send + 'static>>,
+    on_quarantined: impl Fn(ScpEventDetails) + Send + 'static,
 ) -> Result<(), Whatever>
 {
     let study_timeout_duration = Duration::from_secs(study_timeout as u64);
@@ -294,18 +291,14 @@ async fn inner(
     let mut msgid = 1;
     let mut sop_class_uid = "".to_string();
     let mut sop_instance_uid = "".to_string();
+    // why the last C-STORE-RQ command could not be used, its data set is quarantined
+    let mut command_error: Option<String> = None;
 
     // --- Storage backend selection ---
-    let storage_backend: Box<dyn StorageBackend> = match storage_backend {
-        crate::storescp::StorageBackendType::Filesystem => {
-            Box::new(FilesystemBackend { out_dir: out_dir.clone().unwrap() })
-        },
-        crate::storescp::StorageBackendType::S3 => {
-            let config = s3_config.clone().expect("S3 config required for S3 backend");
-            let bucket = build_s3_bucket(&config);
-            Box::new(S3Backend { bucket })
-        },
-    };
+    let storage_targets = StorageTargets::from_args(args)?;
+    let storage_backend = storage_targets.primary().backend.clone();
+    let index = open_configured_index(args)?;
+    let calling_ae_title = association.client_ae_title().to_string();
 
     loop {
         match association.receive().await {
@@ -366,27 +359,17 @@ async fn inner(
                                         "failed to send C-ECHO response object to SCU",
                                     )?;
                                 } else {
-                                    msgid = obj
-                                        .element(tags::MESSAGE_ID)
-                                        .whatever_context("Missing Message ID")?
-                                        .to_int()
-                                        .whatever_context("Message ID is not an integer")?;
-                                    sop_class_uid = obj
-                                        .element(tags::AFFECTED_SOP_CLASS_UID)
-                                        .whatever_context("missing Affected SOP Class UID")?
-                                        .to_str()
-                                        .whatever_context(
-                                            "could not retrieve Affected SOP Class UID",
-                                        )?
-                                        .to_string();
-                                    sop_instance_uid = obj
-                                        .element(tags::AFFECTED_SOP_INSTANCE_UID)
-                                        .whatever_context("missing Affected SOP Instance UID")?
-                                        .to_str()
-                                        .whatever_context(
-                                            "could not retrieve Affected SOP Instance UID",
-                                        )?
-                                        .to_string();
+                                    // an incomplete command fails its data set only, not the association
+                                    let (message_id, uids) = parse_store_command(&obj);
+                                    msgid = message_id.unwrap_or_default();
+                                    (sop_class_uid, sop_instance_uid, command_error) = match uids {
+                                        Ok((class_uid, instance_uid)) => (class_uid, instance_uid, None),
+                                        Err(e) => {
+                                            let e = Report::from_error(e).to_string();
+                                            warn!("Invalid C-STORE request: {}", e);
+                                            (String::new(), String::new(), Some(e))
+                                        }
+                                    };
                                 }
                                 instance_buffer.clear();
                             } else if data_value.value_type == PDataValueType::Data
@@ -398,57 +381,53 @@ async fn inner(
                                     .presentation_contexts()
                                     .iter()
                                     .find(|pc| pc.id == data_value.presentation_context_id)
-                                    .whatever_context("missing presentation context")?;
-                                let ts = &presentation_context.transfer_syntax;
-                                let transfer_syntax_uid = ts.to_string();
+                                    .cloned();
+                                let transfer_syntax_uid = presentation_context
+                                    .as_ref()
+                                    .map(|pc| pc.transfer_syntax.clone())
+                                    .unwrap_or_default();
+                                let ts = transfer_syntax_uid.as_str();
 
-                                let obj = InMemDicomObject::read_dataset_with_ts(
-                                    instance_buffer.as_slice(),
-                                    TransferSyntaxRegistry.get(ts).unwrap(),
-                                )
-                                .whatever_context("failed to read DICOM data object")?;
-                                let file_meta = FileMetaTableBuilder::new()
-                                    .media_storage_sop_class_uid(
-                                        obj.element(tags::SOP_CLASS_UID)
-                                            .whatever_context("missing SOP Class UID")?
-                                            .to_str()
-                                            .whatever_context("could not retrieve SOP Class UID")?,
-                                    )
-                                    .media_storage_sop_instance_uid(
-                                        obj.element(tags::SOP_INSTANCE_UID)
-                                            .whatever_context("missing SOP Instance UID")?
-                                            .to_str()
-                                            .whatever_context("missing SOP Instance UID")?,
-                                    )
-                                    .transfer_syntax(ts)
-                                    .build()
-                                    .whatever_context(
-                                        "failed to build DICOM meta file information",
-                                    )?;
+                                // Instances that cannot be parsed are quarantined instead of
+                                // aborting the whole association
+                                let parsed = match &command_error {
+                                    Some(e) => Err(e.clone()),
+                                    None => presentation_context
+                                        .as_ref()
+                                        .whatever_context::<_, Whatever>("missing presentation context")
+                                        .and_then(|_| parse_instance(&instance_buffer, ts))
+                                        .map_err(|e| Report::from_error(e).to_string()),
+                                };
+                                let ParsedInstance { obj, file_meta, study_instance_uid, series_instance_uid } = match parsed {
+                                    Ok(parsed) => parsed,
+                                    Err(e) => {
+                                        let mut record = QuarantineRecord::new(
+                                            e,
+                                            calling_ae_title.clone(),
+                                            data_value.presentation_context_id,
+                                            instance_buffer.len(),
+                                        );
+                                        record.abstract_syntax = presentation_context.as_ref().map(|pc| pc.abstract_syntax.clone());
+                                        record.transfer_syntax_uid = presentation_context.as_ref().map(|pc| pc.transfer_syntax.clone());
+                                        record.sop_class_uid = Some(sop_class_uid.clone()).filter(|uid| !uid.is_empty());
+                                        record.sop_instance_uid = Some(sop_instance_uid.clone()).filter(|uid| !uid.is_empty());
+                                        let quarantined =
+                                            quarantine_instance(storage_backend.as_ref(), record, &instance_buffer, &on_quarantined).await;
+                                        send_cstore_response(
+                                            &mut association,
+                                            data_value.presentation_context_id,
+                                            msgid,
+                                            &sop_class_uid,
+                                            &sop_instance_uid,
+                                            quarantine_status(&quarantined),
+                                        )
+                                        .await?;
+                                        continue;
+                                    }
+                                };
 
-                                // read important study and series instance UIDs for saving the file
-                                let study_instance_uid = obj
-                                    .element(tags::STUDY_INSTANCE_UID)
-                                    .whatever_context("missing STUDY INSTANCE UID")?
-                                    .to_str()
-                                    .whatever_context(
-                                        "could not retrieve Affected STUDY INSTANCE UID",
-                                    )?
-                                    .to_string();
-                                let series_instance_uid = obj
-                                    .element(tags::SERIES_INSTANCE_UID)
-                                    .whatever_context("missing SERIES INSTANCE UID")?
-                                    .to_str()
-                                    .whatever_context(
-                                        "could not retrieve Affected SERIES INSTANCE UID",
-                                    )?
-                                    .to_string();
-
-                                // write the files to the current directory with their SOPInstanceUID as filenames
-                                let mut file_path = PathBuf::from(out_dir.as_ref().expect("Output directory must be set"));
-                                file_path.push(study_instance_uid.to_string());
-                                file_path.push(series_instance_uid.to_string());
-                                file_path.push(sop_instance_uid.trim_end_matches('\0').to_string() + ".dcm");
+                                // write the files with their SOPInstanceUID as filenames
+                                let storage_key = instance_storage_key(&study_instance_uid, &series_instance_uid, &sop_instance_uid);
 
                                 // Extract metadata as flat tags BEFORE saving
                                 let mut tags = if !extract_tags.is_empty() || !extract_custom_tags.is_empty() {
@@ -460,6 +439,7 @@ async fn inner(
                                 // Call on_before_store callback if provided
                                 // This allows modification of tags before saving (e.g., anonymization)
                                 let mut obj_to_save = obj.clone();
+                                let mut rejection: Option<String> = None;
                                 if let Some(callback_arc) = on_before_store {
                                     info!("on_before_store callback is set");
                                     if let Some(ref extracted_tags) = tags {
@@ -539,6 +519,7 @@ async fn inner(
                                                     }
                                                     Err(promise_err) => {
                                                         error!("Promise rejected: {:?}", promise_err);
+                                                        rejection = Some(format!("rejected by onBeforeStore: {}", promise_err.reason));
                                                     }
                                                 }
                                             }
@@ -552,27 +533,78 @@ async fn inner(
                                 } else {
                                     info!("No on_before_store callback set");
                                 }
-                                
-                                let obj_for_file = obj_to_save.clone();
-                                let file_obj = obj_for_file.with_exact_meta(file_meta);
-                                let mut dicom_bytes = Vec::new();
-                                if store_with_file_meta {
-                                    // Write complete DICOM file with file meta header
-                                    file_obj.write_all(&mut dicom_bytes).whatever_context("could not serialize DICOM object")?;
-                                } else {
-                                    // Write dataset-only (more efficient, standard for PACS)
-                                    file_obj.write_dataset_with_ts(&mut dicom_bytes, TransferSyntaxRegistry.get(ts).unwrap()).whatever_context("could not serialize DICOM object")?;
+
+                                if let Some(reason) = rejection {
+                                    let mut record = QuarantineRecord::new(
+                                        reason,
+                                        calling_ae_title.clone(),
+                                        data_value.presentation_context_id,
+                                        instance_buffer.len(),
+                                    );
+                                    record.abstract_syntax = presentation_context.as_ref().map(|pc| pc.abstract_syntax.clone());
+                                    record.transfer_syntax_uid = Some(transfer_syntax_uid.clone());
+                                    record.sop_class_uid = Some(sop_class_uid.clone());
+                                    record.sop_instance_uid = Some(sop_instance_uid.clone());
+                                    let quarantined =
+                                        quarantine_instance(storage_backend.as_ref(), record, &instance_buffer, &on_quarantined).await;
+                                    send_cstore_response(
+                                        &mut association,
+                                        data_value.presentation_context_id,
+                                        msgid,
+                                        &sop_class_uid,
+                                        &sop_instance_uid,
+                                        quarantine_status(&quarantined),
+                                    )
+                                    .await?;
+                                    continue;
                                 }
-                                let storage_key = file_path.strip_prefix(std::path::Path::new(out_dir.as_ref().unwrap()))
-                                    .unwrap_or(&file_path)
-                                    .to_string_lossy()
-                                    .replace('\\', "/");
-                                storage_backend.store_file(&storage_key, &dicom_bytes).await.whatever_context("failed to store file")?;
-                                info!("Stored {}", storage_key);
-                                let file_path_str = match &args.storage_backend {
-                                    crate::storescp::StorageBackendType::Filesystem => file_path.display().to_string(),
-                                    crate::storescp::StorageBackendType::S3 => format!("s3://{}/{}", args.s3_config.as_ref().unwrap().bucket, storage_key),
+                                
+                                let dicom_bytes = serialize_instance(obj_to_save.clone(), file_meta, store_with_file_meta)?;
+                                let size = dicom_bytes.len();
+                                let hash = storage_targets.content_hash(&dicom_bytes);
+                                // Only acknowledge the instance once all required targets confirmed the write
+                                let attributes = ObjectAttributes {
+                                    dicom_tags: instance_metadata(
+                                        tags.as_ref(),
+                                        [&study_instance_uid, &series_instance_uid, &sop_instance_uid, &sop_class_uid, &transfer_syntax_uid],
+                                    ),
+                                    ..object_attributes(&obj_to_save, &transfer_syntax_uid, &args.s3_metadata)
                                 };
+                                let stored = storage_targets.store(&storage_key, dicom_bytes, &attributes).await.map_err(|e| e.to_string());
+                                let locations = match stored {
+                                    Ok(locations) => locations,
+                                    Err(message) => {
+                                        error!("{}", message);
+                                        crate::storescp::StoreScp::emit_event(crate::storescp::StoreScpEvent::OnError, crate::storescp::ScpEventData {
+                                            message: "Storage failed".to_string(),
+                                            data: Some(ScpEventDetails {
+                                                file: Some(storage_targets.primary().location(&storage_key)),
+                                                sop_instance_uid: Some(sop_instance_uid.clone()),
+                                                sop_class_uid: Some(sop_class_uid.clone()),
+                                                error: Some(message),
+                                                ..Default::default()
+                                            }),
+                                        });
+                                        send_cstore_response(
+                                            &mut association,
+                                            data_value.presentation_context_id,
+                                            msgid,
+                                            &sop_class_uid,
+                                            &sop_instance_uid,
+                                            STATUS_OUT_OF_RESOURCES,
+                                        )
+                                        .await?;
+                                        continue;
+                                    }
+                                };
+                                info!("Stored {}", storage_key);
+                                let file_path_str = storage_targets.primary().location(&storage_key);
+                                if let Some(index) = &index {
+                                    let record = InstanceRecord::new(&obj_to_save, &transfer_syntax_uid, &storage_key, &file_path_str, size);
+                                    if let Err(e) = index.run(move |db| db.index_instance(&record)).await {
+                                        warn!("Failed to index {}: {}", storage_key, e);
+                                    }
+                                }
 
 
                                 // Emit the OnFileStored event with flat tags
@@ -586,6 +618,10 @@ async fn inner(
                                     tags,
                                     error: None,
                                     study: None,
+                                    quarantine: None,
+                                    locations: Some(locations),
+                                    content_hash: hash,
+                                    purged: None,
                                 });
 
                                 // Update global study store with hierarchy
@@ -695,35 +731,15 @@ async fn inner(
                                     }
                                 }
 
-                                // send C-STORE-RSP object
-                                // commands are always in implicit VR LE
-                                let ts =
-                                    dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN
-                                        .erased();
-
-                                let obj = create_cstore_response(
+                                send_cstore_response(
+                                    &mut association,
+                                    data_value.presentation_context_id,
                                     msgid,
                                     &sop_class_uid,
                                     &sop_instance_uid,
-                                );
-
-                                let mut obj_data = Vec::new();
-
-                                obj.write_dataset_with_ts(&mut obj_data, &ts)
-                                    .whatever_context("could not write response object")?;
-
-                                let pdu_response = Pdu::PData {
-                                    data: vec![dicom_ul::pdu::PDataValue {
-                                        presentation_context_id: data_value.presentation_context_id,
-                                        value_type: PDataValueType::Command,
-                                        is_last: true,
-                                        data: obj_data,
-                                    }],
-                                };
-                                association
-                                    .send(&pdu_response)
-                                    .await
-                                    .whatever_context("failed to send response object to SCU")?;
+                                    0x0000,
+                                )
+                                .await?;
                             }
                         }
                     }
@@ -765,49 +781,643 @@ async fn inner(
     Ok(())
 }
 
+/// Data set of a received instance, decoded and checked for the UIDs needed to store it
+pub(crate) struct ParsedInstance {
+    pub obj: InMemDicomObject,
+    pub file_meta: FileMetaTable,
+    pub study_instance_uid: String,
+    pub series_instance_uid: String,
+}
+
+/// Message ID, Affected SOP Class UID and Affected SOP Instance UID of a C-STORE-RQ command.
+/// The Message ID is returned on its own so the request can be answered even if a UID is missing.
+fn parse_store_command(obj: &InMemDicomObject) -> (Option<u16>, Result<(String, String), Whatever>) {
+    let message_id = obj.element(tags::MESSAGE_ID).ok().and_then(|e| e.to_int::<u16>().ok());
+    let uids = (|| {
+        message_id.whatever_context("missing Message ID")?;
+        let sop_class_uid = obj
+            .element(tags::AFFECTED_SOP_CLASS_UID)
+            .whatever_context("missing Affected SOP Class UID")?
+            .to_str()
+            .whatever_context("could not retrieve Affected SOP Class UID")?
+            .to_string();
+        let sop_instance_uid = obj
+            .element(tags::AFFECTED_SOP_INSTANCE_UID)
+            .whatever_context("missing Affected SOP Instance UID")?
+            .to_str()
+            .whatever_context("could not retrieve Affected SOP Instance UID")?
+            .to_string();
+        Ok((sop_class_uid, sop_instance_uid))
+    })();
+    (message_id, uids)
+}
 
+/// Decode a received data set and build its file meta information
+pub(crate) fn parse_instance(data: &[u8], ts_uid: &str) -> Result<ParsedInstance, Whatever> {
+    let ts = TransferSyntaxRegistry
+        .get(ts_uid)
+        .with_whatever_context(|| format!("unknown transfer syntax {}", ts_uid))?;
+    let obj = InMemDicomObject::read_dataset_with_ts(data, ts)
+        .whatever_context("failed to read DICOM data object")?;
+    let file_meta = FileMetaTableBuilder::new()
+        .media_storage_sop_class_uid(
+            obj.element(tags::SOP_CLASS_UID)
+                .whatever_context("missing SOP Class UID")?
+                .to_str()
+                .whatever_context("could not retrieve SOP Class UID")?,
+        )
+        .media_storage_sop_instance_uid(
+            obj.element(tags::SOP_INSTANCE_UID)
+                .whatever_context("missing SOP Instance UID")?
+                .to_str()
+                .whatever_context("missing SOP Instance UID")?,
+        )
+        .transfer_syntax(ts_uid)
+        .build()
+        .whatever_context("failed to build DICOM meta file information")?;
+
+    // read important study and series instance UIDs for saving the file
+    let study_instance_uid = obj
+        .element(tags::STUDY_INSTANCE_UID)
+        .whatever_context("missing STUDY INSTANCE UID")?
+        .to_str()
+        .whatever_context("could not retrieve Affected STUDY INSTANCE UID")?
+        .to_string();
+    let series_instance_uid = obj
+        .element(tags::SERIES_INSTANCE_UID)
+        .whatever_context("missing SERIES INSTANCE UID")?
+        .to_str()
+        .whatever_context("could not retrieve Affected SERIES INSTANCE UID")?
+        .to_string();
+
+    Ok(ParsedInstance { obj, file_meta, study_instance_uid, series_instance_uid })
+}
+
+/// Whether `uid` only has digits and dots and at most 64 characters, so it is safe to use in a storage key
+pub(crate) fn is_valid_uid(uid: &str) -> bool {
+    (1..=64).contains(&uid.len()) && uid.bytes().all(|b| b.is_ascii_digit() || b == b'.')
+}
+
+/// Storage key of an instance relative to the storage root: `<study>/<series>/<sop>.dcm`
+pub(crate) fn instance_storage_key(study_instance_uid: &str, series_instance_uid: &str, sop_instance_uid: &str) -> String {
+    format!(
+        "{}/{}/{}.dcm",
+        study_instance_uid,
+        series_instance_uid,
+        sop_instance_uid.trim_end_matches('\0')
+    )
+}
+
+/// Serialize an instance either as complete DICOM file or as data set only
+pub(crate) fn serialize_instance(obj: InMemDicomObject, file_meta: FileMetaTable, store_with_file_meta: bool) -> Result<Vec<u8>, Whatever> {
+    let file_obj = obj.with_exact_meta(file_meta);
+    let mut dicom_bytes = Vec::new();
+    if store_with_file_meta {
+        // Write complete DICOM file with file meta header
+        file_obj.write_all(&mut dicom_bytes).whatever_context("could not serialize DICOM object")?;
+    } else {
+        // Write dataset-only (more efficient, standard for PACS)
+        let ts = TransferSyntaxRegistry
+            .get(file_obj.meta().transfer_syntax())
+            .whatever_context("unknown transfer syntax")?;
+        file_obj.write_dataset_with_ts(&mut dicom_bytes, ts).whatever_context("could not serialize DICOM object")?;
+    }
+    Ok(dicom_bytes)
+}
+
+/// Re-run a quarantined item through parsing and storage.
+///
+/// On success the instance is written to its regular location and removed from the
+/// quarantine area. `onBeforeStore` is not invoked for reprocessed items.
+pub(crate) async fn reprocess_quarantined(
+    args: &crate::storescp::StoreScp,
+    id: &str,
+    transfer_syntax_uid: Option<String>,
+) -> Result<ScpEventDetails, Whatever> {
+    let storage_targets = StorageTargets::from_args(args)?;
+    let storage_backend = storage_targets.primary().backend.clone();
+    let (record, data) = match quarantine::load(storage_backend.as_ref(), id).await {
+        Ok(item) => item,
+        Err(e) => whatever!("could not load quarantined item {}: {}", id, e),
+    };
+    let ts_uid = transfer_syntax_uid
+        .or(record.transfer_syntax_uid)
+        .whatever_context("no transfer syntax known for quarantined item")?;
+
+    let ParsedInstance { obj, file_meta, study_instance_uid, series_instance_uid } = parse_instance(&data, &ts_uid)?;
+    let sop_instance_uid = file_meta.media_storage_sop_instance_uid().to_string();
+    let sop_class_uid = file_meta.media_storage_sop_class_uid().to_string();
+    let tags = if !args.extract_tags.is_empty() || !args.extract_custom_tags.is_empty() {
+        Some(extract_tags_flat(&obj, &args.extract_tags, &args.extract_custom_tags))
+    } else {
+        None
+    };
+
+    let storage_key = instance_storage_key(&study_instance_uid, &series_instance_uid, &sop_instance_uid);
+    let dicom_bytes = serialize_instance(obj.clone(), file_meta, args.store_with_file_meta)?;
+    let size = dicom_bytes.len();
+    let hash = storage_targets.content_hash(&dicom_bytes);
+    let attributes = ObjectAttributes {
+        dicom_tags: instance_metadata(
+            tags.as_ref(),
+            [&study_instance_uid, &series_instance_uid, &sop_instance_uid, &sop_class_uid, &ts_uid],
+        ),
+        ..object_attributes(&obj, &ts_uid, &args.s3_metadata)
+    };
+    let locations = storage_targets.store(&storage_key, dicom_bytes, &attributes).await?;
+    info!("Stored {} from quarantined item {}", storage_key, id);
+    let file = storage_targets.primary().location(&storage_key);
+    let index = open_configured_index(args)?;
+    if let Some(index) = index {
+        let record = InstanceRecord::new(&obj, &ts_uid, &storage_key, &file, size);
+        if let Err(e) = index.run(move |db| db.index_instance(&record)).await {
+            warn!("Failed to index {}: {}", storage_key, e);
+        }
+    }
+    if let Err(e) = quarantine::remove(storage_backend.as_ref(), id).await {
+        warn!("Could not remove quarantined item {}: {}", id, e);
+    }
+
+    Ok(ScpEventDetails {
+        file: Some(file),
+        sop_instance_uid: Some(sop_instance_uid),
+        sop_class_uid: Some(sop_class_uid),
+        transfer_syntax_uid: Some(ts_uid),
+        study_instance_uid: Some(study_instance_uid),
+        series_instance_uid: Some(series_instance_uid),
+        tags,
+        locations: Some(locations),
+        content_hash: hash,
+        ..Default::default()
+    })
+}
+
+/// Open the index configured with `indexPath`, if any
+fn open_configured_index(args: &crate::storescp::StoreScp) -> Result<Option<Arc<IndexDb>>, Whatever> {
+    match &args.index_path {
+        Some(path) => match open_index(path) {
+            Ok(index) => Ok(Some(index)),
+            Err(e) => whatever!("could not open index {}: {}", path, e),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Status code sent in the C-STORE-RSP when a required storage target could not be written (Refused: Out of resources)
+const STATUS_OUT_OF_RESOURCES: u16 = 0xA700;
+
+/// Send a C-STORE-RSP with the given status
+async fn send_cstore_response(
+    association: &mut ServerAssociation<tokio::net::TcpStream>,
+    presentation_context_id: u8,
+    msgid: u16,
+    sop_class_uid: &str,
+    sop_instance_uid: &str,
+    status: u16,
+) -> Result<(), Whatever> {
+    // commands are always in implicit VR LE
+    let ts = dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN.erased();
+
+    let obj = create_cstore_response(msgid, sop_class_uid, sop_instance_uid, status);
+
+    let mut obj_data = Vec::new();
+
+    obj.write_dataset_with_ts(&mut obj_data, &ts)
+        .whatever_context("could not write response object")?;
+
+    let pdu_response = Pdu::PData {
+        data: vec![dicom_ul::pdu::PDataValue {
+            presentation_context_id,
+            value_type: PDataValueType::Command,
+            is_last: true,
+            data: obj_data,
+        }],
+    };
+    association
+        .send(&pdu_response)
+        .await
+        .whatever_context("failed to send response object to SCU")
+}
 
+/// Move a received data set into the quarantine area and emit the OnQuarantined event.
+/// If it cannot be written, an OnError event is emitted instead and the error returned.
+async fn quarantine_instance(
+    storage_backend: &dyn StorageBackend,
+    record: QuarantineRecord,
+    data: &[u8],
+    on_quarantined: &impl Fn(ScpEventDetails),
+) -> Result<(), String> {
+    if let Err(e) = quarantine::quarantine(storage_backend, &record, data).await {
+        let message = format!("Failed to quarantine instance {:?}: {}", record.sop_instance_uid, e);
+        error!("{}", message);
+        crate::storescp::StoreScp::emit_event(crate::storescp::StoreScpEvent::OnError, crate::storescp::ScpEventData {
+            message: "Quarantine failed".to_string(),
+            data: Some(ScpEventDetails {
+                sop_instance_uid: record.sop_instance_uid,
+                sop_class_uid: record.sop_class_uid,
+                error: Some(message.clone()),
+                ..Default::default()
+            }),
+        });
+        return Err(message);
+    }
+    on_quarantined(ScpEventDetails {
+        sop_instance_uid: record.sop_instance_uid.clone(),
+        sop_class_uid: record.sop_class_uid.clone(),
+        transfer_syntax_uid: record.transfer_syntax_uid.clone(),
+        error: Some(record.error.clone()),
+        quarantine: Some(record),
+        ..Default::default()
+    });
+    Ok(())
+}
 
+/// Status answering a data set that could not be stored: Cannot understand once it is quarantined,
+/// Out of resources if it was not, so the SCU sends it again instead of dropping it
+fn quarantine_status(quarantined: &Result<(), String>) -> u16 {
+    match quarantined {
+        Ok(()) => STATUS_CANNOT_UNDERSTAND,
+        Err(_) => STATUS_OUT_OF_RESOURCES,
+    }
+}
 
-// StorageBackend trait and implementations for Filesystem and S3
-#[async_trait]
-pub trait StorageBackend: Send + Sync {
-    async fn store_file(&self, path: &str, data: &[u8]) -> std::result::Result<(), Box<dyn std::error::Error>>;
+/// Create the storage backend for a storage target (`custom_store` is used by 'Custom' targets)
+pub(crate) fn build_storage_backend(
+    target: &StorageTarget,
+    custom_store: Option<&Arc<CustomStoreCallback>>,
+) -> Result<Arc<dyn StorageBackend>, Whatever> {
+    let backend: Arc<dyn StorageBackend> = match target.backend {
+        StorageBackendType::Filesystem => {
+            let out_dir = target.out_dir.clone().whatever_context("Output directory required for Filesystem backend")?;
+            Arc::new(FilesystemBackend {
+                root: out_dir,
+                options: target.filesystem_options.clone().unwrap_or_default(),
+            })
+        },
+        StorageBackendType::S3 => {
+            let config = target.s3_config.as_ref().whatever_context("S3 config required for S3 backend")?;
+            match S3Backend::new(config) {
+                Ok(backend) => Arc::new(backend),
+                Err(e) => whatever!("{}", e),
+            }
+        },
+        StorageBackendType::Custom => {
+            let callback = custom_store.whatever_context("onCustomStore callback required for Custom backend")?;
+            Arc::new(CustomBackend { callback: callback.clone() })
+        },
+    };
+    if target.content_addressed.unwrap_or(false) {
+        if target.backend == StorageBackendType::Custom {
+            whatever!("Custom backend does not support contentAddressed");
+        }
+        return Ok(Arc::new(ContentAddressedBackend::new(backend)));
+    }
+    Ok(backend)
 }
 
-pub struct FilesystemBackend {
-    pub out_dir: String,
+/// A storage target together with its backend instance
+pub(crate) struct TargetBackend {
+    pub name: String,
+    pub kind: StorageBackendType,
+    pub policy: StoragePolicy,
+    pub content_addressed: bool,
+    pub backend: Arc<dyn StorageBackend>,
 }
 
-#[async_trait]
-impl StorageBackend for FilesystemBackend {
-    async fn store_file(&self, path: &str, data: &[u8]) -> std::result::Result<(), Box<dyn std::error::Error>> {
-        let full_path = std::path::Path::new(&self.out_dir).join(path);
-        if let Some(parent) = full_path.parent() {
-            std::fs::create_dir_all(parent)?;
+impl TargetBackend {
+    pub fn new(target: &StorageTarget, custom_store: Option<&Arc<CustomStoreCallback>>) -> Result<Self, Whatever> {
+        Ok(TargetBackend {
+            name: target.name.clone().unwrap_or_else(|| format!("{:?}", target.backend)),
+            kind: target.backend.clone(),
+            policy: target.policy.clone().unwrap_or(StoragePolicy::Required),
+            content_addressed: target.content_addressed.unwrap_or(false),
+            backend: build_storage_backend(target, custom_store)?,
+        })
+    }
+
+    /// Location of a stored instance as reported in events (file path or `s3://` URL)
+    pub fn location(&self, storage_key: &str) -> String {
+        self.backend.location(storage_key)
+    }
+
+    fn storage_location(&self, storage_key: &str, stored: bool) -> StorageLocation {
+        StorageLocation {
+            name: self.name.clone(),
+            backend: self.kind.clone(),
+            policy: self.policy.clone(),
+            location: self.location(storage_key),
+            stored,
         }
-        std::fs::write(full_path, data)?;
-        Ok(())
     }
 }
 
-pub struct S3Backend {
-    pub bucket: s3::bucket::Bucket,
+/// Default tags written as S3 user metadata
+const DEFAULT_S3_METADATA_TAGS: [&str; 4] = ["Modality", "StudyDate", "SOPClassUID", "TransferSyntaxUID"];
+
+/// Maximum number of tags S3 accepts per object
+const MAX_S3_OBJECT_TAGS: usize = 10;
+
+/// Content type, metadata and object tags of a stored instance (applied by S3 targets only).
+///
+/// Unknown tags, empty values and PHI tags (unless allowed) are skipped.
+pub(crate) fn object_attributes(obj: &InMemDicomObject, transfer_syntax_uid: &str, options: &S3MetadataOptions) -> ObjectAttributes {
+    let allow_phi = options.allow_phi.unwrap_or(false);
+    let values = |names: &[String]| -> Vec<(String, String)> {
+        names
+            .iter()
+            .filter_map(|name| {
+                let tag = parse_tag(name).ok()?;
+                if !allow_phi && is_phi_tag(tag) {
+                    return None;
+                }
+                let value = if tag == tags::TRANSFER_SYNTAX_UID {
+                    transfer_syntax_uid.to_string()
+                } else {
+                    obj.element(tag).ok()?.to_str().ok()?.to_string()
+                };
+                let value = s3_sanitize_value(&value);
+                (!value.is_empty()).then(|| (name.clone(), value))
+            })
+            .collect()
+    };
+    let metadata_tags = options
+        .metadata_tags
+        .clone()
+        .unwrap_or_else(|| DEFAULT_S3_METADATA_TAGS.iter().map(|t| t.to_string()).collect());
+    let object_tags = options.object_tags.clone().unwrap_or_default();
+    ObjectAttributes {
+        content_type: Some("application/dicom".to_string()),
+        metadata: values(&metadata_tags)
+            .into_iter()
+            .map(|(name, value)| (s3_metadata_key(&name), value))
+            .collect(),
+        tags: values(&object_tags)
+            .into_iter()
+            .map(|(name, value)| (s3_sanitize_value(&name), value))
+            .take(MAX_S3_OBJECT_TAGS)
+            .collect(),
+        dicom_tags: HashMap::new(),
+    }
+}
+
+/// Extracted tags of an instance together with its identifying UIDs
+/// (study, series, SOP instance, SOP class and transfer syntax), as passed to custom backends
+fn instance_metadata(tags: Option<&HashMap<String, String>>, uids: [&String; 5]) -> HashMap<String, String> {
+    let mut metadata = tags.cloned().unwrap_or_default();
+    let names = ["StudyInstanceUID", "SeriesInstanceUID", "SOPInstanceUID", "SOPClassUID", "TransferSyntaxUID"];
+    for (name, uid) in names.into_iter().zip(uids) {
+        metadata.insert(name.to_string(), uid.clone());
+    }
+    metadata
+}
+
+/// Fan-out of received instances to all storage targets of a StoreScp
+pub(crate) struct StorageTargets {
+    targets: Vec<TargetBackend>,
 }
 
-#[async_trait]
-impl StorageBackend for S3Backend {
-    async fn store_file(&self, path: &str, data: &[u8]) -> std::result::Result<(), Box<dyn std::error::Error>> {
-        let key = path.replace("\\", "/");
-        let bucket = self.bucket.clone();
-        let data = data.to_vec();
-        let s3_result = s3_put_object(&bucket, &key, &data).await;
-        match s3_result {
-            Ok(()) => Ok(()),
-            Err(_e) => {
-                error!("Failed to upload file to S3: {}", key);
-                Err(format!("S3 upload failed: {}", key).into())
+impl StorageTargets {
+    pub fn from_args(args: &crate::storescp::StoreScp) -> Result<Self, Whatever> {
+        let mut targets = Vec::with_capacity(args.storage_targets.len());
+        for target in &args.storage_targets {
+            targets.push(TargetBackend::new(target, args.on_custom_store.as_ref())?);
+        }
+        if targets.is_empty() {
+            whatever!("no storage target configured");
+        }
+        Ok(StorageTargets { targets })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TargetBackend> {
+        self.targets.iter()
+    }
+
+    /// The primary target: the first required target, or the first target if all are best-effort.
+    /// Quarantined items and reported file paths refer to this target.
+    pub fn primary(&self) -> &TargetBackend {
+        self.targets
+            .iter()
+            .find(|t| t.policy == StoragePolicy::Required)
+            .unwrap_or(&self.targets[0])
+    }
+
+    /// Hex SHA-256 of an instance, reported in events if any target is content-addressed
+    pub fn content_hash(&self, data: &[u8]) -> Option<String> {
+        self.targets.iter().any(|t| t.content_addressed).then(|| content_hash(data))
+    }
+
+    /// Write an instance to all targets.
+    ///
+    /// Required targets are written before returning and any failure fails the store. The copies
+    /// already written to other required targets are then deleted again, unless the instance existed
+    /// there before (e.g. a retried C-STORE). Targets that cannot tell whether it existed (Custom
+    /// targets) are rolled back as well; a failed delete is logged. Best-effort targets are
+    /// replicated in the background once all required writes succeeded; they emit an OnReplicated
+    /// event on success and an OnError event on failure.
+    pub async fn store(&self, storage_key: &str, data: Vec<u8>, attributes: &ObjectAttributes) -> Result<Vec<StorageLocation>, Whatever> {
+        let data = Arc::new(data);
+        let required = self.targets.iter().filter(|t| t.policy == StoragePolicy::Required);
+        let rollback = required.clone().count() > 1;
+        let mut written = Vec::new();
+        for target in required {
+            let existed = rollback
+                && target.backend.exists(storage_key).await.unwrap_or_else(|e| {
+                    warn!("Could not check for {} in {}, it will be rolled back if the store fails: {}", storage_key, target.name, e);
+                    false
+                });
+            if let Err(e) = target.backend.store_object(storage_key, &data, attributes).await {
+                Self::rollback(storage_key, &written).await;
+                whatever!("failed to store file {} in {}: {}", storage_key, target.name, e);
+            }
+            if !existed {
+                written.push(target);
             }
         }
+
+        let mut locations = Vec::with_capacity(self.targets.len());
+        for target in &self.targets {
+            match target.policy {
+                StoragePolicy::Required => locations.push(target.storage_location(storage_key, true)),
+                StoragePolicy::BestEffort => {
+                    let backend = target.backend.clone();
+                    let name = target.name.clone();
+                    let replica = target.storage_location(storage_key, true);
+                    let key = storage_key.to_string();
+                    let data = data.clone();
+                    let attributes = attributes.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = backend.store_object(&key, &data, &attributes).await {
+                            let message = format!("Replication of {} to {} failed: {}", key, name, e);
+                            warn!("{}", message);
+                            crate::storescp::StoreScp::emit_event(crate::storescp::StoreScpEvent::OnError, crate::storescp::ScpEventData {
+                                message: "Replication failed".to_string(),
+                                data: Some(ScpEventDetails {
+                                    file: Some(replica.location),
+                                    error: Some(message),
+                                    ..Default::default()
+                                }),
+                            });
+                        } else {
+                            debug!("Replicated {} to {}", key, name);
+                            crate::storescp::StoreScp::emit_event(crate::storescp::StoreScpEvent::OnReplicated, crate::storescp::ScpEventData {
+                                message: "Replication completed".to_string(),
+                                data: Some(ScpEventDetails {
+                                    file: Some(replica.location.clone()),
+                                    locations: Some(vec![replica]),
+                                    ..Default::default()
+                                }),
+                            });
+                        }
+                    });
+                    locations.push(target.storage_location(storage_key, false));
+                },
+            }
+        }
+        Ok(locations)
+    }
+
+    /// Delete the copies of a failed store from the required targets already written
+    async fn rollback(storage_key: &str, written: &[&TargetBackend]) {
+        for target in written {
+            match target.backend.delete_file(storage_key).await {
+                Ok(()) => debug!("Rolled back {} in {}", storage_key, target.name),
+                Err(e) => warn!("Failed to roll back {} in {}: {}", storage_key, target.name, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{StorageObjectMetadata, StorageResult};
+
+    #[test]
+    fn test_parse_store_command() {
+        use dicom_core::{DataElement, PrimitiveValue, VR};
+        let mut obj = InMemDicomObject::from_element_iter([
+            DataElement::new(tags::MESSAGE_ID, VR::US, PrimitiveValue::from(7_u16)),
+            DataElement::new(tags::AFFECTED_SOP_CLASS_UID, VR::UI, PrimitiveValue::from("1.2.840.10008.5.1.4.1.1.2")),
+        ]);
+        let (message_id, uids) = parse_store_command(&obj);
+        assert_eq!(message_id, Some(7));
+        assert!(Report::from_error(uids.unwrap_err()).to_string().contains("Affected SOP Instance UID"));
+
+        obj.put(DataElement::new(tags::AFFECTED_SOP_INSTANCE_UID, VR::UI, PrimitiveValue::from("1.2.3")));
+        let (_, uids) = parse_store_command(&obj);
+        assert_eq!(uids.unwrap(), ("1.2.840.10008.5.1.4.1.1.2".to_string(), "1.2.3".to_string()));
+
+        obj.remove_element(tags::MESSAGE_ID);
+        let (message_id, uids) = parse_store_command(&obj);
+        assert_eq!(message_id, None);
+        assert!(uids.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_required_targets_rollback() {
+        let root = std::env::temp_dir().join(format!("node-dicom-targets-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        // a file in place of the root directory makes every write to the second target fail
+        std::fs::write(root.join("blocked"), b"").unwrap();
+        let target = |name: &str| TargetBackend {
+            name: name.to_string(),
+            kind: StorageBackendType::Filesystem,
+            policy: StoragePolicy::Required,
+            content_addressed: false,
+            backend: Arc::new(FilesystemBackend { root: root.join(name).display().to_string(), ..Default::default() }),
+        };
+        let targets = StorageTargets { targets: vec![target("first"), target("blocked")] };
+        let first = &targets.targets[0].backend;
+        first.store_file("kept.dcm", b"old").await.unwrap();
+
+        assert!(targets.store("new.dcm", b"new".to_vec(), &ObjectAttributes::default()).await.is_err());
+        assert!(targets.store("kept.dcm", b"new".to_vec(), &ObjectAttributes::default()).await.is_err());
+        assert!(!first.exists("new.dcm").await.unwrap());
+        assert!(first.exists("kept.dcm").await.unwrap());
+
+        std::fs::remove_dir_all(root).unwrap();
     }
-}
\ No newline at end of file
+
+    /// Filesystem storage that cannot check for existing objects, like a Custom target
+    struct NoExistsBackend(FilesystemBackend);
+
+    #[async_trait::async_trait]
+    impl StorageBackend for NoExistsBackend {
+        async fn store_file(&self, path: &str, data: &[u8]) -> StorageResult<()> {
+            self.0.store_file(path, data).await
+        }
+        async fn read_file(&self, path: &str) -> StorageResult<Vec<u8>> {
+            self.0.read_file(path).await
+        }
+        async fn read_range(&self, path: &str, start: u64, end: Option<u64>) -> StorageResult<Vec<u8>> {
+            self.0.read_range(path, start, end).await
+        }
+        async fn list_files(&self, prefix: &str) -> StorageResult<Vec<String>> {
+            self.0.list_files(prefix).await
+        }
+        async fn delete_file(&self, path: &str) -> StorageResult<()> {
+            self.0.delete_file(path).await
+        }
+        async fn exists(&self, _path: &str) -> StorageResult<bool> {
+            Err("Checking existence is not supported".into())
+        }
+        async fn metadata(&self, path: &str) -> StorageResult<StorageObjectMetadata> {
+            self.0.metadata(path).await
+        }
+        fn location(&self, path: &str) -> String {
+            self.0.location(path)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_required_targets_rollback_without_exists() {
+        let root = std::env::temp_dir().join(format!("node-dicom-targets-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("blocked"), b"").unwrap();
+        let target = |name: &str, backend: Arc<dyn StorageBackend>| TargetBackend {
+            name: name.to_string(),
+            kind: StorageBackendType::Custom,
+            policy: StoragePolicy::Required,
+            content_addressed: false,
+            backend,
+        };
+        let first = FilesystemBackend { root: root.join("first").display().to_string(), ..Default::default() };
+        let blocked = FilesystemBackend { root: root.join("blocked").display().to_string(), ..Default::default() };
+        let targets = StorageTargets {
+            targets: vec![
+                target("first", Arc::new(NoExistsBackend(first.clone()))),
+                target("blocked", Arc::new(blocked)),
+            ],
+        };
+
+        // the second target fails, the copy in the first one is removed again
+        assert!(targets.store("new.dcm", b"new".to_vec(), &ObjectAttributes::default()).await.is_err());
+        assert!(!first.exists("new.dcm").await.unwrap());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_quarantine_status() {
+        let root = std::env::temp_dir().join(format!("node-dicom-quarantine-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("blocked"), b"").unwrap();
+        let record = || QuarantineRecord::new("unreadable".to_string(), "SCU".to_string(), 1, 4);
+        let events = std::sync::Mutex::new(0);
+        let on_quarantined = |_: ScpEventDetails| *events.lock().unwrap() += 1;
+
+        let backend = FilesystemBackend { root: root.join("store").display().to_string(), ..Default::default() };
+        let quarantined = quarantine_instance(&backend, record(), b"DICM", &on_quarantined).await;
+        assert_eq!(quarantine_status(&quarantined), STATUS_CANNOT_UNDERSTAND);
+        assert_eq!(*events.lock().unwrap(), 1);
+
+        // neither stored nor quarantined: the SCU is asked to retry
+        let blocked = FilesystemBackend { root: root.join("blocked").display().to_string(), ..Default::default() };
+        let quarantined = quarantine_instance(&blocked, record(), b"DICM", &on_quarantined).await;
+        assert!(quarantined.is_err());
+        assert_eq!(quarantine_status(&quarantined), STATUS_OUT_OF_RESOURCES);
+        assert_eq!(*events.lock().unwrap(), 1);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
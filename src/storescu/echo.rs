@@ -0,0 +1,360 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use dicom_core::{dicom_value, DataElement, VR};
+use dicom_dictionary_std::{tags, uids};
+use dicom_encoding::TransferSyntaxIndex;
+use dicom_object::{InMemDicomObject, StandardDataDictionary};
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+use dicom_ul::{
+    pdu::{PDataValue, PDataValueType, UserVariableItem},
+    Pdu,
+};
+use tracing::{debug, warn};
+
+use crate::findscu::DimseAssociation;
+use crate::getscu::association::{
+    user_identity, AssociationRequest, EstablishError, ProposedContext, RoleSelectingAssociation,
+};
+use crate::utils::TlsOptions;
+
+/// Options of a C-ECHO request
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct EchoOptions {
+    /// Calling AE title (default: STORE-SCU)
+    pub calling_ae_title: Option<String>,
+    /// Called AE title (default: taken from the address, e.g. `ANY-SCP@host:port`)
+    pub called_ae_title: Option<String>,
+    /// Maximum PDU length proposed to the remote AE (default: 16384)
+    pub max_pdu_length: Option<u32>,
+    /// Connection and response timeout in milliseconds (default: no timeout)
+    pub timeout: Option<u32>,
+    /// User identity: username
+    pub username: Option<String>,
+    /// User identity: password (used together with username)
+    pub password: Option<String>,
+    /// User identity: Kerberos service ticket
+    pub kerberos_service_ticket: Option<String>,
+    /// User identity: SAML assertion
+    pub saml_assertion: Option<String>,
+    /// User identity: JSON Web Token
+    pub jwt: Option<String>,
+    /// Secure the association with TLS (DICOM TLS port, commonly 2762)
+    pub tls: Option<TlsOptions>,
+}
+
+/// Outcome of a C-ECHO request
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct EchoResult {
+    /// True if the association was accepted and the C-ECHO returned status 0000H
+    pub success: bool,
+    /// Status of the C-ECHO response, if one was received
+    pub status: Option<u32>,
+    /// Time from connecting until the C-ECHO response, in milliseconds
+    pub round_trip_time: f64,
+    /// Maximum PDU length accepted by the remote AE
+    pub max_pdu_length: Option<u32>,
+    /// Implementation class UID announced by the remote AE
+    pub implementation_class_uid: Option<String>,
+    /// Implementation version name announced by the remote AE
+    pub implementation_version_name: Option<String>,
+    /// Reason given by the remote AE for rejecting the association
+    pub rejection_reason: Option<String>,
+    /// Error message if the verification failed for another reason
+    pub error: Option<String>,
+}
+
+fn echo_req_command(message_id: u16) -> InMemDicomObject<StandardDataDictionary> {
+    InMemDicomObject::command_from_element_iter([
+        // SOP Class UID
+        DataElement::new(
+            tags::AFFECTED_SOP_CLASS_UID,
+            VR::UI,
+            dicom_value!(Str, uids::VERIFICATION),
+        ),
+        // command field
+        DataElement::new(tags::COMMAND_FIELD, VR::US, dicom_value!(U16, [0x0030])),
+        // message ID
+        DataElement::new(tags::MESSAGE_ID, VR::US, dicom_value!(U16, [message_id])),
+        // data set type
+        DataElement::new(
+            tags::COMMAND_DATA_SET_TYPE,
+            VR::US,
+            dicom_value!(U16, [0x0101]),
+        ),
+    ])
+}
+
+/// Await `operation`, failing with `what` if it takes longer than `timeout`
+async fn within<T, E: From<String>>(
+    timeout: Option<Duration>,
+    what: &str,
+    operation: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, operation)
+            .await
+            .map_err(|_| E::from(format!("Timed out {}", what)))?,
+        None => operation.await,
+    }
+}
+
+/// Negotiate a Verification-only association with `addr` and send a C-ECHO-RQ
+async fn run_echo(addr: &str, options: EchoOptions, result: &mut EchoResult) -> Result<(), String> {
+    let start_time = Instant::now();
+    let timeout = options.timeout.map(|ms| Duration::from_millis(ms as u64));
+
+    let request = AssociationRequest {
+        calling_ae_title: options.calling_ae_title.unwrap_or_else(|| "STORE-SCU".to_string()),
+        called_ae_title: options.called_ae_title,
+        max_pdu_length: options.max_pdu_length.unwrap_or(16384),
+        contexts: vec![ProposedContext {
+            abstract_syntax: uids::VERIFICATION.to_string(),
+            transfer_syntaxes: vec![uids::IMPLICIT_VR_LITTLE_ENDIAN.to_string(), uids::EXPLICIT_VR_LITTLE_ENDIAN.to_string()],
+            scp_role: false,
+        }],
+        user_identity: user_identity(
+            options.username.as_deref(),
+            options.password.as_deref(),
+            options.kerberos_service_ticket.as_deref(),
+            options.saml_assertion.as_deref(),
+            options.jwt.as_deref(),
+        ),
+        async_operations_window: None,
+        tls: options.tls,
+    };
+
+    let mut scu = match within(timeout, "establishing the association", RoleSelectingAssociation::establish(addr, request)).await {
+        Ok(scu) => scu,
+        Err(EstablishError::Rejected(reason)) => {
+            let error = format!("Association rejected: {}", reason);
+            result.rejection_reason = Some(reason);
+            return Err(error);
+        }
+        Err(EstablishError::Failed(e)) => return Err(format!("Could not establish association: {}", e)),
+    };
+
+    result.max_pdu_length = Some(scu.acceptor_max_pdu_length());
+    for item in scu.user_variables() {
+        match item {
+            UserVariableItem::ImplementationClassUID(uid) => {
+                result.implementation_class_uid = Some(uid.trim_end_matches('\0').to_string());
+            }
+            UserVariableItem::ImplementationVersionName(name) => {
+                result.implementation_version_name = Some(name.trim_end_matches('\0').to_string());
+            }
+            _ => {}
+        }
+    }
+
+    let pc = scu
+        .presentation_contexts()
+        .first()
+        .cloned()
+        .ok_or_else(|| "Verification SOP class was not accepted".to_string())?;
+    let ts = TransferSyntaxRegistry
+        .get(&pc.transfer_syntax)
+        .ok_or_else(|| format!("Unsupported transfer syntax {}", pc.transfer_syntax))?;
+
+    let mut cmd_data = Vec::with_capacity(128);
+    echo_req_command(1)
+        .write_dataset_with_ts(&mut cmd_data, &dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN.erased())
+        .map_err(|e| format!("Could not write C-ECHO-RQ: {}", e))?;
+    debug!("Sending C-ECHO-RQ (presentation context {}, {})", pc.id, ts.name());
+
+    scu.send(&Pdu::PData {
+        data: vec![PDataValue {
+            presentation_context_id: pc.id,
+            value_type: PDataValueType::Command,
+            is_last: true,
+            data: cmd_data,
+        }],
+    })
+    .await
+    .map_err(|e| format!("Could not send C-ECHO-RQ: {}", e))?;
+
+    let rsp_pdu = within(timeout, "waiting for the C-ECHO-RSP", scu.receive())
+        .await
+        .map_err(|e| format!("Could not receive C-ECHO-RSP: {}", e))?;
+    result.round_trip_time = start_time.elapsed().as_secs_f64() * 1000.0;
+
+    let status = match rsp_pdu {
+        Pdu::PData { data } if !data.is_empty() => {
+            let cmd_obj = InMemDicomObject::read_dataset_with_ts(
+                &data[0].data[..],
+                &dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN.erased(),
+            )
+            .map_err(|e| format!("Could not read C-ECHO-RSP: {}", e))?;
+            cmd_obj
+                .element(tags::STATUS)
+                .ok()
+                .and_then(|e| e.to_int::<u16>().ok())
+                .ok_or_else(|| "C-ECHO-RSP without status".to_string())?
+        }
+        pdu => return Err(format!("Unexpected response: {:?}", pdu)),
+    };
+    result.status = Some(status as u32);
+
+    if let Err(e) = scu.release().await {
+        warn!("Failed to release association: {}", e);
+    }
+
+    match status {
+        0 => Ok(()),
+        _ => Err(format!("C-ECHO failed with status {:04X}H", status)),
+    }
+}
+
+/**
+ * Verify connectivity to a remote AE with a C-ECHO (DICOM "ping").
+ *
+ * Only the Verification SOP class is proposed, so no files are needed. The promise always
+ * resolves; check `success`, `rejectionReason` and `error` for the outcome.
+ * User identity negotiation works like in `StoreScu`. With `tls`, the association runs over a
+ * TLS connection whose peer certificate is verified against the system roots and `caCertPath`.
+ *
+ * @param addr - Address of the remote AE, e.g. `ANY-SCP@127.0.0.1:4446` or `127.0.0.1:4446`
+ * @param options - AE titles, timeout, user identity and TLS
+ *
+ * @example
+ * ```typescript
+ * const result = await echo('PACS@10.0.0.5:104', { callingAeTitle: 'MY-SCU', timeout: 5000 });
+ * if (result.success) {
+ *   console.log(`Echo OK in ${result.roundTripTime.toFixed(1)} ms (${result.implementationVersionName})`);
+ * } else {
+ *   console.error(result.rejectionReason ?? result.error);
+ * }
+ *
+ * const secure = await echo('PACS@pacs.example.org:2762', { tls: { caCertPath: './hospital-ca.pem' } });
+ * ```
+ */
+#[napi]
+pub async fn echo(addr: String, options: Option<EchoOptions>) -> napi::Result<EchoResult> {
+    let start_time = Instant::now();
+    let mut result = EchoResult::default();
+    match run_echo(&addr, options.unwrap_or_default(), &mut result).await {
+        Ok(()) => result.success = true,
+        Err(e) => {
+            warn!("C-ECHO to {} failed: {}", addr, e);
+            if result.rejection_reason.is_none() {
+                result.error = Some(e);
+            }
+        }
+    }
+    if result.status.is_none() {
+        result.round_trip_time = start_time.elapsed().as_secs_f64() * 1000.0;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom_ul::pdu::{AssociationAC, PresentationContextResult, PresentationContextResultReason};
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use tokio_native_tls::{native_tls, TlsAcceptor};
+
+    async fn read_test_pdu(socket: &mut (impl AsyncRead + Unpin)) -> Pdu {
+        let mut buffer = vec![0u8; 6];
+        socket.read_exact(&mut buffer).await.unwrap();
+        let length = u32::from_be_bytes([buffer[2], buffer[3], buffer[4], buffer[5]]) as usize;
+        buffer.resize(6 + length, 0);
+        socket.read_exact(&mut buffer[6..]).await.unwrap();
+        dicom_ul::read_pdu(&buffer[..], 1 << 20, false).unwrap().unwrap()
+    }
+
+    async fn write_test_pdu(socket: &mut (impl AsyncWrite + Unpin), pdu: &Pdu) {
+        let mut buffer = Vec::new();
+        dicom_ul::write_pdu(&mut buffer, pdu).unwrap();
+        socket.write_all(&buffer).await.unwrap();
+    }
+
+    /// Fake SCP on TLS accepting a Verification association and answering one C-ECHO
+    async fn tls_echo_scp(listener: tokio::net::TcpListener) {
+        let identity = native_tls::Identity::from_pkcs8(
+            &std::fs::read("__test__/fixtures/tls/cert.pem").unwrap(),
+            &std::fs::read("__test__/fixtures/tls/key.pem").unwrap(),
+        )
+        .unwrap();
+        let acceptor = TlsAcceptor::from(native_tls::TlsAcceptor::new(identity).unwrap());
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            // a client not trusting the certificate gives up in the handshake
+            let Ok(mut socket) = acceptor.accept(socket).await else {
+                continue;
+            };
+            let Pdu::AssociationRQ(rq) = read_test_pdu(&mut socket).await else {
+                panic!("expected an A-ASSOCIATE-RQ");
+            };
+            let ac = AssociationAC {
+                protocol_version: 1,
+                calling_ae_title: rq.calling_ae_title,
+                called_ae_title: rq.called_ae_title,
+                application_context_name: rq.application_context_name,
+                presentation_contexts: vec![PresentationContextResult {
+                    id: rq.presentation_contexts[0].id,
+                    reason: PresentationContextResultReason::Acceptance,
+                    transfer_syntax: uids::IMPLICIT_VR_LITTLE_ENDIAN.to_string(),
+                }],
+                user_variables: vec![
+                    UserVariableItem::MaxLength(16384),
+                    UserVariableItem::ImplementationVersionName("TLS-SCP".to_string()),
+                ],
+            };
+            write_test_pdu(&mut socket, &Pdu::AssociationAC(ac)).await;
+            let Pdu::PData { data } = read_test_pdu(&mut socket).await else {
+                panic!("expected a P-DATA-TF");
+            };
+            let rsp = InMemDicomObject::command_from_element_iter([
+                DataElement::new(tags::COMMAND_FIELD, VR::US, dicom_value!(U16, [0x8030])),
+                DataElement::new(tags::MESSAGE_ID_BEING_RESPONDED_TO, VR::US, dicom_value!(U16, [1])),
+                DataElement::new(tags::COMMAND_DATA_SET_TYPE, VR::US, dicom_value!(U16, [0x0101])),
+                DataElement::new(tags::STATUS, VR::US, dicom_value!(U16, [0])),
+            ]);
+            let mut rsp_data = Vec::new();
+            rsp.write_dataset_with_ts(&mut rsp_data, &dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN.erased())
+                .unwrap();
+            let value = PDataValue { data: rsp_data, ..data[0].clone() };
+            write_test_pdu(&mut socket, &Pdu::PData { data: vec![value] }).await;
+            assert!(matches!(read_test_pdu(&mut socket).await, Pdu::ReleaseRQ));
+            write_test_pdu(&mut socket, &Pdu::ReleaseRP).await;
+            return;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_echo_tls() {
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = format!("TLS-SCP@localhost:{}", listener.local_addr().unwrap().port());
+        let scp = tokio::spawn(tls_echo_scp(listener));
+        let options = |ca_cert_path: Option<&str>| EchoOptions {
+            timeout: Some(10_000),
+            tls: Some(TlsOptions { ca_cert_path: ca_cert_path.map(str::to_string), ..Default::default() }),
+            ..Default::default()
+        };
+
+        let mut result = EchoResult::default();
+        let error = run_echo(&addr, options(None), &mut result).await.unwrap_err();
+        assert!(error.contains("TLS handshake"), "{}", error);
+
+        let mut result = EchoResult::default();
+        run_echo(&addr, options(Some("__test__/fixtures/tls/cert.pem")), &mut result).await.unwrap();
+        assert_eq!(result.status, Some(0));
+        assert_eq!(result.implementation_version_name.as_deref(), Some("TLS-SCP"));
+        scp.await.unwrap();
+    }
+
+    #[test]
+    fn test_echo_req_command() {
+        let cmd = echo_req_command(7);
+        assert_eq!(cmd.element(tags::COMMAND_FIELD).unwrap().to_int::<u16>().unwrap(), 0x0030);
+        assert_eq!(cmd.element(tags::MESSAGE_ID).unwrap().to_int::<u16>().unwrap(), 7);
+        assert_eq!(cmd.element(tags::COMMAND_DATA_SET_TYPE).unwrap().to_int::<u16>().unwrap(), 0x0101);
+        assert_eq!(
+            cmd.element(tags::AFFECTED_SOP_CLASS_UID).unwrap().to_str().unwrap().trim_end_matches('\0'),
+            uids::VERIFICATION
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! Send manifest recording the status of each file of a StoreScu transfer, so that an
+//! interrupted transfer can be resumed without sending acknowledged files again.
+//!
+//! The manifest is a JSON Lines file: every status change is appended as one entry and
+//! the last entry of a file and SOP instance wins, so a crash loses at most the line being
+//! written. It is compacted to one entry per file and SOP instance when a transfer opens it.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Status of a file in the manifest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ManifestStatus {
+    /// Queued, not acknowledged yet
+    Pending,
+    /// Acknowledged by the SCP with a success or warning status
+    Sent,
+    /// Failed in the last attempt
+    Failed,
+    /// Not sent because of the filter or onBeforeSend
+    Skipped,
+}
+
+/// One line of the manifest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestEntry {
+    /// File name as reported in events (path, `s3://` key or buffer name)
+    pub file: String,
+    pub status: ManifestStatus,
+    pub sop_instance_uid: String,
+    /// DIMSE status of the C-STORE response, if one was received
+    pub dimse_status: Option<u16>,
+    pub error: Option<String>,
+    /// Number of C-STORE attempts over all transfers
+    pub attempts: u32,
+    /// Time of the status change (milliseconds since UNIX epoch)
+    pub updated_at: i64,
+}
+
+pub struct Manifest {
+    /// Last entry of each file and SOP Instance UID
+    entries: Mutex<HashMap<(String, String), ManifestEntry>>,
+    /// Files and SOP Instance UIDs acknowledged before the manifest was opened
+    sent_files: HashSet<String>,
+    sent_instances: HashSet<String>,
+    writer: Mutex<File>,
+}
+
+impl Manifest {
+    /// Open or create the manifest, compacting the entries of previous transfers
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let mut entries = HashMap::new();
+        if path.exists() {
+            let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            for line in BufReader::new(file).lines() {
+                let line = line.map_err(|e| format!("{}: {}", path.display(), e))?;
+                // an interrupted write leaves a truncated last line
+                match serde_json::from_str::<ManifestEntry>(&line) {
+                    Ok(entry) => {
+                        entries.insert((entry.file.clone(), entry.sop_instance_uid.clone()), entry);
+                    }
+                    Err(_) if line.trim().is_empty() => {}
+                    Err(e) => warn!("Ignoring invalid manifest entry in {}: {}", path.display(), e),
+                }
+            }
+        }
+
+        let mut compacted = String::new();
+        let mut sent_files = HashSet::new();
+        let mut sent_instances = HashSet::new();
+        for entry in entries.values() {
+            if entry.status == ManifestStatus::Sent {
+                sent_files.insert(entry.file.clone());
+                sent_instances.insert(entry.sop_instance_uid.clone());
+            }
+            compacted.push_str(&serde_json::to_string(entry).map_err(|e| e.to_string())?);
+            compacted.push('\n');
+        }
+        let temp_path = PathBuf::from(format!("{}.tmp", path.display()));
+        std::fs::write(&temp_path, compacted)
+            .and_then(|_| std::fs::rename(&temp_path, path))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let writer = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Manifest {
+            entries: Mutex::new(entries),
+            sent_files,
+            sent_instances,
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// Whether the file was acknowledged by the SCP in a previous attempt
+    pub fn is_sent(&self, file: &str) -> bool {
+        self.sent_files.contains(file)
+    }
+
+    /// Whether a file with this SOP Instance UID was acknowledged by the SCP in a previous attempt
+    pub fn is_instance_sent(&self, sop_instance_uid: &str) -> bool {
+        self.sent_instances.contains(sop_instance_uid.trim_end_matches(['\0', ' ']))
+    }
+
+    /// Append a status change of a file
+    pub fn record(
+        &self,
+        file: &str,
+        sop_instance_uid: &str,
+        status: ManifestStatus,
+        dimse_status: Option<u16>,
+        error: Option<String>,
+    ) {
+        let sop_instance_uid = sop_instance_uid.trim_end_matches(['\0', ' ']);
+        let key = (file.to_string(), sop_instance_uid.to_string());
+        let mut entries = self.entries.lock().unwrap();
+        let attempts = entries.get(&key).map(|entry| entry.attempts).unwrap_or_default()
+            + matches!(status, ManifestStatus::Sent | ManifestStatus::Failed) as u32;
+        let entry = ManifestEntry {
+            file: file.to_string(),
+            status,
+            sop_instance_uid: sop_instance_uid.to_string(),
+            dimse_status,
+            error,
+            attempts,
+            updated_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as i64)
+                .unwrap_or_default(),
+        };
+        let line = serde_json::to_string(&entry).unwrap_or_default() + "\n";
+        if let Err(e) = self.writer.lock().unwrap().write_all(line.as_bytes()) {
+            warn!("Could not update manifest for {}: {}", file, e);
+        }
+        entries.insert(key, entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_resume() {
+        let path = std::env::temp_dir().join(format!("manifest-test-{}.jsonl", uuid::Uuid::new_v4()));
+        let manifest = Manifest::open(&path).unwrap();
+        manifest.record("a.dcm", "1.1", ManifestStatus::Pending, None, None);
+        manifest.record("b.dcm", "1.2", ManifestStatus::Pending, None, None);
+        manifest.record("a.dcm", "1.1", ManifestStatus::Failed, Some(0xA700), Some("Out of resources".to_string()));
+        manifest.record("a.dcm", "1.1", ManifestStatus::Sent, Some(0), None);
+        manifest.record("b.dcm", "1.2", ManifestStatus::Failed, None, Some("Connection reset".to_string()));
+        drop(manifest);
+
+        // truncated line left by an interrupted write
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"file\":\"c.dcm\",\"sta").unwrap();
+        drop(file);
+
+        let manifest = Manifest::open(&path).unwrap();
+        assert!(manifest.is_sent("a.dcm"));
+        assert!(!manifest.is_sent("b.dcm"));
+        assert!(!manifest.is_sent("c.dcm"));
+        assert!(manifest.is_instance_sent("1.1\0"));
+        assert!(!manifest.is_instance_sent("1.2"));
+        assert_eq!(manifest.entries.lock().unwrap()[&("a.dcm".to_string(), "1.1".to_string())].attempts, 2);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        // a buffer named by its position holds another instance when the transfer is resumed
+        manifest.record("a.dcm", "1.3", ManifestStatus::Pending, None, None);
+        drop(manifest);
+        let manifest = Manifest::open(&path).unwrap();
+        assert!(manifest.is_instance_sent("1.1"));
+        assert!(!manifest.is_instance_sent("1.3"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 3);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
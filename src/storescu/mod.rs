@@ -1,4 +1,4 @@
-use napi::bindgen_prelude::{AsyncTask, Object};
+use napi::bindgen_prelude::{AsyncTask, Buffer, Object, Promise};
 use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode};
 use napi::{Env, Result as NapiResult};
 use serde::{Deserialize, Serialize};
@@ -12,17 +12,26 @@ use tracing::{error, info, warn, Level};
 use indicatif::{ProgressBar, ProgressStyle};
 use snafu::prelude::*;
 use snafu::{Report, Whatever};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 use transfer_syntax::TransferSyntaxIndex;
 use walkdir::WalkDir;
 use std::sync::Arc;
-use tokio::sync::{Mutex, broadcast};
+use tokio::sync::{Mutex, broadcast, watch};
+use crate::storage::{envelope, S3Backend, StorageBackend};
 use crate::utils::S3Config;
+use crate::getscu::association::{user_identity, AssociationRequest, ProposedContext, RoleSelectingAssociation};
 
 mod store_async;
+mod rules;
+mod manifest;
+mod status;
+pub mod echo;
+
+/// Presentation context IDs are odd numbers up to 255
+const MAX_PRESENTATION_CONTEXTS: u32 = 128;
 
 /**
  * Events emitted by the DICOM C-STORE SCU client during file transfer operations.
@@ -61,6 +70,8 @@ pub enum StoreScuEvent {
     OnFileSent,
     /// An error occurred while sending a specific file
     OnFileError,
+    /// A file was skipped by the filter or by onBeforeSend (emitted for each file)
+    OnFileSkipped,
     /// All files have been transferred successfully (emitted once at the end)
     OnTransferCompleted,
     /// A general error occurred during the transfer operation
@@ -105,10 +116,54 @@ pub struct FileSendingData {
     pub sop_class_uid: String,
 }
 
+/**
+ * Category of a DIMSE status code.
+ */
+#[napi(string_enum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StatusCategory {
+    Success,
+    Warning,
+    Failure,
+    Cancel,
+    Pending,
+}
+
+/**
+ * C-STORE response of the SCP for a file.
+ * 
+ * @example
+ * ```typescript
+ * scu.addListener(StoreScuEvent.OnFileSent, (_err, event) => {
+ *   const response = event.data?.response;
+ *   if (response?.category === 'Warning') {
+ *     // B000: the SCP coerced data elements
+ *     console.warn(response.meaning, response.offendingElements, response.errorComment);
+ *   }
+ * });
+ * ```
+ */
+#[napi(object)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DimseResponse {
+    /// Status (0000,0900) as sent by the SCP
+    pub status: u32,
+    pub category: StatusCategory,
+    /// Meaning of the status for C-STORE, e.g. "Coercion of Data Elements"
+    pub meaning: String,
+    /// Error Comment (0000,0902)
+    pub error_comment: Option<String>,
+    /// Offending Element (0000,0901), as tag names or `GGGGEEEE`
+    pub offending_elements: Option<Vec<String>>,
+    /// Error ID (0000,0903)
+    pub error_id: Option<u32>,
+}
+
 /**
  * Event data for OnFileSent event.
  * 
- * Emitted when a file has been successfully sent.
+ * Emitted when a file has been successfully sent, including when the SCP
+ * answered with a warning status.
  */
 #[napi(object)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -123,7 +178,16 @@ pub struct FileSentData {
     pub file: String,
     pub sop_instance_uid: String,
     pub sop_class_uid: String,
+    /// Transfer syntax negotiated for the presentation context the file was sent on
     pub transfer_syntax: String,
+    /// Transfer syntax of the file as read from its source
+    pub file_transfer_syntax: String,
+    /// Whether the file was transcoded to the negotiated transfer syntax
+    pub transcoded: bool,
+    /// Tags changed by `coerceTags` or `onBeforeSend`, with their new value
+    pub coerced_tags: Option<HashMap<String, String>>,
+    /// Response of the SCP, with a Success or Warning category
+    pub response: DimseResponse,
     pub duration_seconds: f64,
 }
 
@@ -147,6 +211,31 @@ pub struct FileErrorData {
     pub sop_instance_uid: Option<String>,
     pub sop_class_uid: Option<String>,
     pub file_transfer_syntax: Option<String>,
+    /// Response of the SCP, if the file was refused by it
+    pub response: Option<DimseResponse>,
+}
+
+/**
+ * Event data for OnFileSkipped event.
+ * 
+ * Emitted when a file is not sent because it does not pass the `filter` option
+ * or `onBeforeSend` returned null.
+ */
+#[napi(object)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileSkippedEvent {
+    pub message: String,
+    pub data: Option<FileSkippedData>,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileSkippedData {
+    pub file: String,
+    pub sop_instance_uid: String,
+    pub sop_class_uid: String,
+    /// "filter" or "onBeforeSend"
+    pub reason: String,
 }
 
 /**
@@ -166,7 +255,15 @@ pub struct TransferCompletedEvent {
 pub struct TransferCompletedData {
     pub total_files: u32,
     pub successful: u32,
+    /// Files stored with a warning status (not included in `successful`)
+    pub warning: u32,
     pub failed: u32,
+    /// Files not sent because of the filter or onBeforeSend
+    pub skipped: u32,
+    /// Files not sent again because the manifest records them as sent
+    pub already_sent: u32,
+    /// Files not sent because the transfer was cancelled
+    pub cancelled: u32,
     pub duration_seconds: f64,
 }
 
@@ -176,6 +273,8 @@ pub struct SendCallbacks {
     pub on_file_sending: Option<Arc<ThreadsafeFunction<FileSendingEvent, ()>>>,
     pub on_file_sent: Option<Arc<ThreadsafeFunction<FileSentEvent, ()>>>,
     pub on_file_error: Option<Arc<ThreadsafeFunction<FileErrorEvent, ()>>>,
+    pub on_file_skipped: Option<Arc<ThreadsafeFunction<FileSkippedEvent, ()>>>,
+    pub on_before_send: Option<Arc<ThreadsafeFunction<String, Promise<Option<String>>>>>,
     pub on_transfer_completed: Option<Arc<ThreadsafeFunction<TransferCompletedEvent, ()>>>,
 }
 
@@ -183,6 +282,19 @@ pub struct SendCallbacks {
 enum FileSource {
     Local(PathBuf),
     S3(String), // S3 key/path
+    /// In-memory file (with or without file meta header), identified by name in events
+    Memory { name: String, data: Arc<Vec<u8>> },
+}
+
+impl FileSource {
+    /// Name of the source in logs and events
+    fn display_name(&self) -> String {
+        match self {
+            FileSource::Local(path) => path.display().to_string(),
+            FileSource::S3(key) => format!("s3://{}", key),
+            FileSource::Memory { name, .. } => name.clone(),
+        }
+    }
 }
 
 /**
@@ -277,6 +389,9 @@ pub struct StoreScu {
     /// fail file transfer if it cannot be done without transcoding
     // hide option if transcoding is disabled [default: true]
     never_transcode: bool,
+    /// transfer syntaxes proposed after the file's own, in order of preference
+    /// [default: Explicit VR Little Endian, Implicit VR Little Endian]
+    transfer_syntaxes: Vec<String>,
     /// accept files with any SOP class UID in storage, not only those specified in the presentation contexts
     ignore_sop_class: bool,
     /// User Identity username
@@ -290,9 +405,27 @@ pub struct StoreScu {
     /// User Identity JWT
     jwt: Option<String>,
     /// Dispatch these many service users to send files in parallel
-    concurrency: Option<u32>
+    concurrency: Option<u32>,
+    /// the maximum number of presentation contexts proposed per association (range 1..=128) [default: 128]
+    max_presentation_contexts: u32,
+    /// release and re-establish the association after these many files
+    max_operations_per_association: Option<u32>,
+    /// maximum number of outstanding C-STORE requests proposed per association [default: 1]
+    async_operations_window: u16,
+    /// filter and tag coercion applied to each data set before it is sent
+    send_rules: rules::SendRules,
+    /// path of the manifest recording the status of each file
+    manifest: Option<String>,
+    /// number of retry passes for failed files [default: 0]
+    max_retries: u32,
+    /// delay before the first retry pass in milliseconds [default: 1000]
+    retry_delay_ms: u32,
+    /// cancellation requested by `cancel()`, a new channel for every `send()` so that starting a
+    /// transfer does not clear a cancellation meant for one still running
+    cancel: Arc<std::sync::Mutex<watch::Sender<Option<CancelMode>>>>,
 }
 
+#[derive(Clone)]
 struct DicomFile {
     /// File source (local path or S3 key)
     source: FileSource,
@@ -310,6 +443,15 @@ struct DicomFile {
     data: Option<Vec<u8>>,
 }
 
+/// Which transfer syntaxes a file may be sent in besides its own
+#[derive(Clone)]
+struct TransferSyntaxPolicy {
+    /// fail file transfer if it cannot be done without transcoding
+    never_transcode: bool,
+    /// transfer syntaxes proposed after the file's own, in order of preference
+    fallbacks: Vec<String>,
+}
+
 #[derive(Debug, Snafu)]
 enum Error {
     /// Could not initialize SCU
@@ -347,9 +489,6 @@ enum Error {
     WriteDataset {
         source: Box<dicom_object::WriteError>,
     },
-    ReadDataset {
-        source: dicom_object::ReadError,
-    },
     MissingAttribute {
         tag: Tag,
         source: dicom_object::AccessError,
@@ -357,7 +496,28 @@ enum Error {
     ConvertField {
         tag: Tag,
         source: dicom_core::value::ConvertValueError,
-    }
+    },
+    /// Could not access storage: {message}
+    Storage {
+        message: String,
+    },
+    /// Could not read {path}: {message}
+    ReadStoredObject {
+        path: String,
+        message: String,
+    },
+    /// No supported files to transfer
+    NoSupportedFiles,
+    /// Transfer cancelled
+    Cancelled,
+    /// Association error: {message}
+    Association {
+        message: String,
+    },
+    /// Could not open manifest {message}
+    Manifest {
+        message: String,
+    },
 }
 
 /**
@@ -371,7 +531,9 @@ pub enum ResultStatus {
     /// Transfer completed successfully
     Success,
     /// Transfer failed with an error
-    Error
+    Error,
+    /// Transfer stopped by `cancel()`, the results cover the files handled until then
+    Cancelled,
 }
 
 /**
@@ -388,6 +550,9 @@ pub enum ResultStatus {
  *   } else {
  *     console.error('✗', result.message);
  *   }
+ *   for (const [sopInstanceUid, file] of Object.entries(result.files ?? {})) {
+ *     if (file.status === 'Warning') console.warn(sopInstanceUid, file.response?.meaning);
+ *   }
  * }
  * ```
  */
@@ -397,7 +562,78 @@ pub struct ResultObject {
     /// Status of the transfer operation
     pub status: ResultStatus,
     /// Descriptive message about the result
-    pub message: String
+    pub message: String,
+    /// Outcome of each file of the transfer, keyed by SOP Instance UID (as read from the file)
+    pub files: Option<HashMap<String, FileResult>>,
+}
+
+/**
+ * What happens to the C-STOREs in flight when a transfer is cancelled.
+ */
+#[napi(string_enum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelMode {
+    /// Wait for the responses of the C-STOREs in flight, then release the associations
+    Graceful,
+    /// Abort the associations at once, the files in flight are reported as cancelled
+    Abort,
+}
+
+/**
+ * Outcome of a file sent by StoreScu.
+ */
+#[napi(string_enum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileResultStatus {
+    /// Stored by the SCP
+    Sent,
+    /// Stored by the SCP with a warning status
+    Warning,
+    /// Refused by the SCP or not sent because of an error
+    Failed,
+    /// Not sent because of the filter or onBeforeSend
+    Skipped,
+    /// Not sent, or its C-STORE aborted, because the transfer was cancelled
+    Cancelled,
+}
+
+/**
+ * Outcome of a file in the results of `send()`.
+ */
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct FileResult {
+    pub file: String,
+    pub sop_class_uid: String,
+    pub status: FileResultStatus,
+    /// Response of the SCP, if one was received
+    pub response: Option<DimseResponse>,
+    /// Error or skip reason
+    pub error: Option<String>,
+}
+
+/**
+ * Tag conditions selecting the data sets sent by StoreScu.
+ * 
+ * Keys are tag names or hex tags, values are matched as in C-FIND:
+ * `\`-separated alternatives with `*` and `?` wildcards, tested against the
+ * whole value and each of its values. Absent attributes have an empty value.
+ * 
+ * @example
+ * ```typescript
+ * const filter: TagFilter = {
+ *   include: { Modality: 'CT\\MR' },
+ *   exclude: { ImageType: 'LOCALIZER', SOPClassUID: '1.2.840.10008.5.1.4.1.1.88.67' }
+ * };
+ * ```
+ */
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct TagFilter {
+    /// Send only data sets matching all of these conditions
+    pub include: Option<HashMap<String, String>>,
+    /// Skip data sets matching any of these conditions
+    pub exclude: Option<HashMap<String, String>>,
 }
 
 /**
@@ -455,6 +691,9 @@ pub struct StoreScuOptions {
     pub fail_first: Option<bool>,
     /// Fail transfer if transcoding would be required (default: true)
     pub never_transcode: Option<bool>,
+    /// Transfer syntaxes proposed after each file's own, in order of preference, as names (e.g. "JPEG2000Lossless") or UIDs (default: Explicit and Implicit VR Little Endian)
+    #[napi(ts_type = "Array<'ImplicitVRLittleEndian' | 'ExplicitVRLittleEndian' | 'ExplicitVRBigEndian' | 'DeflatedExplicitVRLittleEndian' | 'JPEGBaseline' | 'JPEGExtended' | 'JPEGLossless' | 'JPEGLosslessNonHierarchical' | 'JPEGLSLossless' | 'JPEGLSLossy' | 'JPEG2000Lossless' | 'JPEG2000' | 'RLELossless' | 'MPEG2MainProfile' | 'MPEG2MainProfileHighLevel' | 'MPEG4AVCH264HighProfile' | 'MPEG4AVCH264BDCompatibleHighProfile' | (string & {})>")]
+    pub transfer_syntaxes: Option<Vec<String>>,
     /// Accept files with any SOP class UID, not only those in presentation contexts (default: false)
     pub ignore_sop_class: Option<bool>,
     /// User Identity username for authentication
@@ -469,6 +708,23 @@ pub struct StoreScuOptions {
     pub jwt: Option<String>,
     /// Number of parallel connections for concurrent file transfer (default: 1)
     pub concurrency: Option<u32>,
+    /// Maximum number of presentation contexts proposed per association, range 1-128; larger batches are split across several associations (default: 128)
+    pub max_presentation_contexts: Option<u32>,
+    /// Release and re-establish the association after this many files (default: unlimited)
+    pub max_operations_per_association: Option<u32>,
+    /// Maximum number of C-STORE requests outstanding on each association, range 1-65535; above 1 the
+    /// Asynchronous Operations Window is proposed, with a fallback to one at a time if the SCP does not accept it (default: 1)
+    pub async_operations_window: Option<u32>,
+    /// Tag conditions selecting the data sets to send; skipped files emit onFileSkipped
+    pub filter: Option<TagFilter>,
+    /// Tag values set on each data set before it is sent (e.g. { PatientID: 'EXT-001' }); an empty string clears the value
+    pub coerce_tags: Option<HashMap<String, String>>,
+    /// Path of a JSON Lines file recording the status of each file; files recorded as sent are skipped when the transfer is resumed
+    pub manifest: Option<String>,
+    /// Number of times failed files are sent again after the other files (default: 0)
+    pub max_retries: Option<u32>,
+    /// Delay before the first retry in milliseconds, doubled for each further retry up to 60s (default: 1000)
+    pub retry_delay_ms: Option<u32>,
     /// S3 configuration for reading files from S3 storage (required if using S3 paths)
     pub s3_config: Option<S3Config>
 }
@@ -516,7 +772,10 @@ impl StoreScu {
      * ```
      */
     #[napi(constructor)]
-    pub fn new(options: StoreScuOptions) -> Self {
+    pub fn new(options: StoreScuOptions) -> napi::Result<Self> {
+        if let Some(s3_config) = &options.s3_config {
+            crate::utils::build_s3_bucket(s3_config).map_err(napi::Error::from_reason)?;
+        }
         let file_sources: Vec<FileSource> = vec![];
         let mut verbose: bool = false;
         if options.verbose.is_some() {
@@ -542,6 +801,23 @@ impl StoreScu {
         if options.never_transcode.is_some() {
             never_transcode = options.never_transcode.unwrap();
         }
+        let mut transfer_syntaxes: Vec<String> = vec![
+            uids::EXPLICIT_VR_LITTLE_ENDIAN.to_string(),
+            uids::IMPLICIT_VR_LITTLE_ENDIAN.to_string(),
+        ];
+        if let Some(names) = options.transfer_syntaxes {
+            use crate::storescp::sop_classes::map_transfer_syntax_name;
+            transfer_syntaxes = vec![];
+            for name in names {
+                let uid = map_transfer_syntax_name(&name).unwrap_or(&name);
+                if TransferSyntaxRegistry.get(uid).is_none() {
+                    return Err(napi::Error::from_reason(format!("Unsupported transfer syntax: {}", name)));
+                }
+                if !transfer_syntaxes.iter().any(|ts| ts == uid) {
+                    transfer_syntaxes.push(uid.to_string());
+                }
+            }
+        }
         let mut ignore_sop_class: bool = false;
         if options.ignore_sop_class.is_some() {
             ignore_sop_class = options.ignore_sop_class.unwrap();
@@ -550,6 +826,26 @@ impl StoreScu {
         if options.concurrency.is_some() {
             concurrency = Some(options.concurrency.unwrap());
         }
+        let max_presentation_contexts = options.max_presentation_contexts.unwrap_or(MAX_PRESENTATION_CONTEXTS);
+        if !(1..=MAX_PRESENTATION_CONTEXTS).contains(&max_presentation_contexts) {
+            return Err(napi::Error::from_reason(format!(
+                "maxPresentationContexts must be between 1 and {}",
+                MAX_PRESENTATION_CONTEXTS
+            )));
+        }
+        if options.max_operations_per_association == Some(0) {
+            return Err(napi::Error::from_reason("maxOperationsPerAssociation must be at least 1"));
+        }
+        let async_operations_window = options.async_operations_window.unwrap_or(1);
+        if !(1..=u16::MAX as u32).contains(&async_operations_window) {
+            return Err(napi::Error::from_reason(format!(
+                "asyncOperationsWindow must be between 1 and {}",
+                u16::MAX
+            )));
+        }
+        let (include, exclude) = options.filter.map(|f| (f.include, f.exclude)).unwrap_or_default();
+        let send_rules = rules::SendRules::new(include, exclude, options.coerce_tags)
+            .map_err(napi::Error::from_reason)?;
 
         // Only set global logger if not already set (it can only be set once per process)
         // Use RUST_LOG env var if set, otherwise use verbose flag
@@ -569,7 +865,7 @@ impl StoreScu {
                 .finish(),
         );
 
-        StoreScu {
+        Ok(StoreScu {
             addr: options.addr,
             file_sources: file_sources,
             s3_config: options.s3_config,
@@ -580,14 +876,23 @@ impl StoreScu {
             max_pdu_length: max_pdu_length,
             fail_first: fail_first,
             never_transcode: never_transcode,
+            transfer_syntaxes,
             ignore_sop_class: ignore_sop_class,
             username: options.username.or(None),
             password: options.password.or(None),
             kerberos_service_ticket: options.kerberos_service_ticket.or(None),
             saml_assertion: options.saml_assertion.or(None),
             jwt: options.jwt.or(None),
-            concurrency: concurrency
-        }
+            concurrency: concurrency,
+            max_presentation_contexts,
+            max_operations_per_association: options.max_operations_per_association,
+            async_operations_window: async_operations_window as u16,
+            send_rules,
+            manifest: options.manifest,
+            max_retries: options.max_retries.unwrap_or(0),
+            retry_delay_ms: options.retry_delay_ms.unwrap_or(1000),
+            cancel: Arc::new(std::sync::Mutex::new(watch::channel(None).0)),
+        })
     }
 
     /**
@@ -685,6 +990,61 @@ impl StoreScu {
         }
     }
 
+    /**
+     * Add an in-memory DICOM file to the transfer queue.
+     * 
+     * The buffer may hold a complete DICOM file or a data set without file meta
+     * group (as written by StoreScp). SOP class and transfer syntax are detected
+     * from its content, and it goes through the same negotiation, transcoding and
+     * progress events as files on disk. The buffer is copied, so it can be reused
+     * after this call.
+     * 
+     * @param buffer - DICOM file or data set bytes
+     * @param name - Name reported as `file` in events (default: `buffer:<n>`)
+     * 
+     * @example
+     * ```typescript
+     * const scu = new StoreScu({ addr: '192.168.1.100:11112' });
+     * const response = await fetch('https://example.com/wado/instance.dcm');
+     * scu.addBuffer(Buffer.from(await response.arrayBuffer()), 'instance.dcm');
+     * await scu.send();
+     * ```
+     */
+    #[napi]
+    pub fn add_buffer(&mut self, buffer: Buffer, name: Option<String>) {
+        let name = name.unwrap_or_else(|| format!("buffer:{}", self.file_sources.len() + 1));
+        self.file_sources.push(FileSource::Memory { name, data: Arc::new(buffer.to_vec()) });
+    }
+
+    /**
+     * Add an opened DicomFile to the transfer queue.
+     * 
+     * The current state of the file is serialized when this method is called, so
+     * modifications made with `updateTags()` before it are sent, and later ones are not.
+     * 
+     * @param dicomFile - DicomFile opened with `open()` or `openJson()`
+     * @param name - Name reported as `file` in events (default: `dicomfile:<n>`)
+     * @throws Error if the file is not opened
+     * 
+     * @example
+     * ```typescript
+     * const file = new DicomFile();
+     * await file.open('input.dcm');
+     * file.updateTags({ PatientName: 'ANONYMOUS' });
+     * 
+     * const scu = new StoreScu({ addr: '192.168.1.100:11112' });
+     * scu.addDicomFile(file, 'anonymized.dcm');
+     * await scu.send();
+     * ```
+     */
+    #[napi]
+    pub fn add_dicom_file(&mut self, dicom_file: &crate::object::DicomFile, name: Option<String>) -> napi::Result<()> {
+        let data = dicom_file.to_bytes()?;
+        let name = name.unwrap_or_else(|| format!("dicomfile:{}", self.file_sources.len() + 1));
+        self.file_sources.push(FileSource::Memory { name, data: Arc::new(data) });
+        Ok(())
+    }
+
     /**
      * Clear all files from the transfer queue.
      * 
@@ -749,7 +1109,7 @@ impl StoreScu {
      * 1. Validate all queued files
      * 2. Establish association with the SCP
      * 3. Negotiate presentation contexts
-     * 4. Transfer each file
+     * 4. Apply `filter`, `coerceTags` and `onBeforeSend`, then transfer each file
      * 5. Optionally transcode if needed (unless `neverTranscode` is true)
      * 6. Release the association
      * 
@@ -822,22 +1182,26 @@ impl StoreScu {
      * ```
      */
     #[napi(
-        ts_args_type = "callbacks?: { onTransferStarted?: (err: Error | null, event: TransferStartedEvent) => void, onFileSending?: (err: Error | null, event: FileSendingEvent) => void, onFileSent?: (err: Error | null, event: FileSentEvent) => void, onFileError?: (err: Error | null, event: FileErrorEvent) => void, onTransferCompleted?: (err: Error | null, event: TransferCompletedEvent) => void }",
+        ts_args_type = "callbacks?: { onTransferStarted?: (err: Error | null, event: TransferStartedEvent) => void, onFileSending?: (err: Error | null, event: FileSendingEvent) => void, onFileSent?: (err: Error | null, event: FileSentEvent) => void, onFileError?: (err: Error | null, event: FileErrorEvent) => void, onFileSkipped?: (err: Error | null, event: FileSkippedEvent) => void, onBeforeSend?: (err: Error | null, tagsJson: string) => Promise<string | null>, onTransferCompleted?: (err: Error | null, event: TransferCompletedEvent) => void }",
         ts_return_type = "Promise<Array<ResultObject>>"
     )]
     pub fn send(&self, env: Env, callbacks: Option<Object>) -> NapiResult<AsyncTask<StoreScuHandler>> {
-        let (on_transfer_started, on_file_sending, on_file_sent, on_file_error, on_transfer_completed) = 
+        let (on_transfer_started, on_file_sending, on_file_sent, on_file_error, on_file_skipped, on_before_send, on_transfer_completed) = 
             if let Some(callbacks_obj) = callbacks {
                 (
                     callbacks_obj.get::<ThreadsafeFunction<TransferStartedEvent, ()>>("onTransferStarted")?,
                     callbacks_obj.get::<ThreadsafeFunction<FileSendingEvent, ()>>("onFileSending")?,
                     callbacks_obj.get::<ThreadsafeFunction<FileSentEvent, ()>>("onFileSent")?,
                     callbacks_obj.get::<ThreadsafeFunction<FileErrorEvent, ()>>("onFileError")?,
+                    callbacks_obj.get::<ThreadsafeFunction<FileSkippedEvent, ()>>("onFileSkipped")?,
+                    callbacks_obj.get::<ThreadsafeFunction<String, Promise<Option<String>>>>("onBeforeSend")?,
                     callbacks_obj.get::<ThreadsafeFunction<TransferCompletedEvent, ()>>("onTransferCompleted")?,
                 )
             } else {
-                (None, None, None, None, None)
+                (None, None, None, None, None, None, None)
             };
+        let (cancel, _) = watch::channel(None);
+        *self.cancel.lock().unwrap() = cancel.clone();
         
         Ok(
         AsyncTask::new(StoreScuHandler {
@@ -851,6 +1215,7 @@ impl StoreScu {
             max_pdu_length: self.max_pdu_length,
             fail_first: self.fail_first,
             never_transcode: self.never_transcode,
+            transfer_syntaxes: self.transfer_syntaxes.clone(),
             ignore_sop_class: self.ignore_sop_class,
             username: self.username.clone(),
             password: self.password.clone(),
@@ -858,13 +1223,52 @@ impl StoreScu {
             saml_assertion: self.saml_assertion.clone(),
             jwt: self.jwt.clone(),
             concurrency: self.concurrency,
+            max_presentation_contexts: self.max_presentation_contexts,
+            max_operations_per_association: self.max_operations_per_association,
+            async_operations_window: self.async_operations_window,
+            send_rules: self.send_rules.clone(),
+            manifest: self.manifest.clone(),
+            max_retries: self.max_retries,
+            retry_delay_ms: self.retry_delay_ms,
+            cancel: Arc::new(std::sync::Mutex::new(cancel)),
             on_transfer_started: on_transfer_started.map(Arc::new),
             on_file_sending: on_file_sending.map(Arc::new),
             on_file_sent: on_file_sent.map(Arc::new),
             on_file_error: on_file_error.map(Arc::new),
+            on_file_skipped: on_file_skipped.map(Arc::new),
+            on_before_send: on_before_send.map(Arc::new),
             on_transfer_completed: on_transfer_completed.map(Arc::new),
         }))
     }
+
+    /**
+     * Cancel the transfer started by the last `send()`.
+     * 
+     * No new files are sent. With the default 'Graceful' mode the C-STOREs in flight
+     * complete and the associations are released; with 'Abort' the associations are
+     * aborted at once. `send()` then resolves with the status 'Cancelled' and the files
+     * that were not sent reported as 'Cancelled'. A manifest keeps them pending, so the
+     * transfer can be resumed later. A `send()` started earlier on the same instance is
+     * not affected.
+     * 
+     * @param mode - What happens to the C-STOREs in flight (default: 'Graceful')
+     * 
+     * @example
+     * ```typescript
+     * const controller = new AbortController();
+     * controller.signal.addEventListener('abort', () => scu.cancel());
+     * setTimeout(() => controller.abort(), 30_000);
+     * 
+     * const [result] = await scu.send();
+     * if (result.status === 'Cancelled') {
+     *   console.log('Transfer cancelled after 30 s');
+     * }
+     * ```
+     */
+    #[napi]
+    pub fn cancel(&self, mode: Option<CancelMode>) {
+        self.cancel.lock().unwrap().send_replace(Some(mode.unwrap_or(CancelMode::Graceful)));
+    }
 }
 
 pub struct StoreScuHandler {
@@ -878,6 +1282,7 @@ pub struct StoreScuHandler {
     max_pdu_length: u32,
     fail_first: bool,
     never_transcode: bool,
+    transfer_syntaxes: Vec<String>,
     ignore_sop_class: bool,
     username: Option<String>,
     password: Option<String>,
@@ -885,10 +1290,20 @@ pub struct StoreScuHandler {
     saml_assertion: Option<String>,
     jwt: Option<String>,
     concurrency: Option<u32>,
+    max_presentation_contexts: u32,
+    max_operations_per_association: Option<u32>,
+    async_operations_window: u16,
+    send_rules: rules::SendRules,
+    manifest: Option<String>,
+    max_retries: u32,
+    retry_delay_ms: u32,
+    cancel: Arc<std::sync::Mutex<watch::Sender<Option<CancelMode>>>>,
     on_transfer_started: Option<Arc<ThreadsafeFunction<TransferStartedEvent, ()>>>,
     on_file_sending: Option<Arc<ThreadsafeFunction<FileSendingEvent, ()>>>,
     on_file_sent: Option<Arc<ThreadsafeFunction<FileSentEvent, ()>>>,
     on_file_error: Option<Arc<ThreadsafeFunction<FileErrorEvent, ()>>>,
+    on_file_skipped: Option<Arc<ThreadsafeFunction<FileSkippedEvent, ()>>>,
+    on_before_send: Option<Arc<ThreadsafeFunction<String, Promise<Option<String>>>>>,
     on_transfer_completed: Option<Arc<ThreadsafeFunction<TransferCompletedEvent, ()>>>,
 }
 
@@ -909,6 +1324,7 @@ impl napi::Task for StoreScuHandler {
             max_pdu_length: self.max_pdu_length,
             fail_first: self.fail_first,
             never_transcode: self.never_transcode,
+            transfer_syntaxes: self.transfer_syntaxes.clone(),
             ignore_sop_class: self.ignore_sop_class,
             username: self.username.clone(),
             password: self.password.clone(),
@@ -916,6 +1332,14 @@ impl napi::Task for StoreScuHandler {
             saml_assertion: self.saml_assertion.clone(),
             jwt: self.jwt.clone(),
             concurrency: self.concurrency,
+            max_presentation_contexts: self.max_presentation_contexts,
+            max_operations_per_association: self.max_operations_per_association,
+            async_operations_window: self.async_operations_window,
+            send_rules: self.send_rules.clone(),
+            manifest: self.manifest.clone(),
+            max_retries: self.max_retries,
+            retry_delay_ms: self.retry_delay_ms,
+            cancel: self.cancel.clone(),
         };
 
         let rt = tokio::runtime::Builder::new_multi_thread()
@@ -927,6 +1351,8 @@ impl napi::Task for StoreScuHandler {
             on_file_sending: self.on_file_sending.clone(),
             on_file_sent: self.on_file_sent.clone(),
             on_file_error: self.on_file_error.clone(),
+            on_file_skipped: self.on_file_skipped.clone(),
+            on_before_send: self.on_before_send.clone(),
         };
 
         let on_transfer_started = self.on_transfer_started.clone();
@@ -969,6 +1395,7 @@ async fn run_async(
         max_pdu_length,
         fail_first,
         mut never_transcode,
+        transfer_syntaxes,
         ignore_sop_class,
         username,
         password,
@@ -976,6 +1403,14 @@ async fn run_async(
         saml_assertion,
         jwt,
         concurrency,
+        max_presentation_contexts,
+        max_operations_per_association,
+        async_operations_window,
+        send_rules,
+        manifest,
+        max_retries,
+        retry_delay_ms,
+        cancel,
     } = args;
 
     // never transcode if the feature is disabled
@@ -987,32 +1422,80 @@ async fn run_async(
         info!("Establishing association with '{}'...", &addr);
     }
     
+    // Setup S3 storage once and share it across all tasks (memory-efficient)
+    let storage: Option<Arc<dyn StorageBackend>> = match &s3_config {
+        Some(config) => Some(Arc::new(S3Backend::new(config).map_err(|message| Error::Storage { message })?)),
+        None => None,
+    };
+
     // Expand S3 folders if needed
-    let expanded_sources = if s3_config.is_some() {
-        expand_s3_sources(file_sources, &s3_config, verbose).await?
-    } else {
-        file_sources
+    let expanded_sources = match &storage {
+        Some(storage) => expand_s3_sources(file_sources, storage.as_ref(), verbose).await?,
+        None => file_sources,
+    };
+
+    // Resume from the manifest, skipping files the SCP already acknowledged
+    let manifest = match manifest {
+        Some(path) => Some(manifest::Manifest::open(Path::new(&path)).map_err(|message| Error::Manifest { message })?),
+        None => None,
     };
+    // buffers are named by their position unless given a name, so they are matched by SOP Instance UID once read
+    let (expanded_sources, already_sent): (Vec<FileSource>, Vec<FileSource>) = expanded_sources
+        .into_iter()
+        .partition(|source| {
+            matches!(source, FileSource::Memory { .. })
+                || !manifest.as_ref().is_some_and(|m| m.is_sent(&source.display_name()))
+        });
+    let mut already_sent = already_sent.len();
     
-    // Clone s3_config for check_files (will be moved into blocking task)
-    let s3_config_clone = s3_config.clone();
+    // Clone storage for check_files (will be moved into blocking task)
+    let check_storage = storage.clone();
+    let policy = Arc::new(TransferSyntaxPolicy {
+        never_transcode,
+        fallbacks: transfer_syntaxes,
+    });
+    let check_policy = policy.clone();
     
-    let (dicom_files, presentation_contexts) =
-        tokio::task::spawn_blocking(move || check_files(expanded_sources, s3_config_clone, verbose, never_transcode))
+    let (mut dicom_files, presentation_contexts) =
+        tokio::task::spawn_blocking(move || check_files(expanded_sources, check_storage, verbose, &check_policy))
             .await
             .unwrap();
+    if let Some(manifest) = &manifest {
+        let checked = dicom_files.len();
+        dicom_files.retain(|file| {
+            !(matches!(file.source, FileSource::Memory { .. }) && manifest.is_instance_sent(&file.sop_instance_uid))
+        });
+        already_sent += checked - dicom_files.len();
+    }
+    if verbose && already_sent > 0 {
+        info!("Resuming transfer, {} files already sent", already_sent);
+    }
+    // nothing left to send is fine when resuming a completed transfer
+    if dicom_files.is_empty() && already_sent == 0 {
+        return Err(Error::NoSupportedFiles);
+    }
     let num_files = dicom_files.len();
-    let dicom_files = Arc::new(Mutex::new(dicom_files));
-    let mut tasks = tokio::task::JoinSet::new();
+    if let Some(manifest) = &manifest {
+        for file in &dicom_files {
+            manifest.record(&file.source.display_name(), &file.sop_instance_uid, manifest::ManifestStatus::Pending, None, None);
+        }
+    }
+    let (groups, unassigned) = group_files(dicom_files, presentation_contexts.clone(), max_presentation_contexts as usize);
+    let mut groups = Arc::new(groups);
+    if verbose && groups.len() > 1 {
+        info!("Splitting transfer across {} sets of presentation contexts", groups.len());
+    }
     
     // Track transfer statistics
-    let successful_count = Arc::new(Mutex::new(0u32));
-    let failed_count = Arc::new(Mutex::new(0u32));
+    let outcomes = Arc::new(store_async::TransferOutcomes {
+        manifest,
+        cancellation: store_async::Cancellation::new(cancel.lock().unwrap().subscribe()),
+        ..Default::default()
+    });
+    report_unassigned_files(&unassigned, &callbacks, &outcomes).await;
+    let send_rules = Arc::new(send_rules);
     let start_time = std::time::Instant::now();
     
-    // Setup S3 bucket once and share it across all tasks (memory-efficient)
-    let s3_bucket = s3_config.as_ref().map(|config| Arc::new(crate::utils::build_s3_bucket(config)));
-
     let progress_bar;
     if !verbose {
         progress_bar = Some(Arc::new(Mutex::new(ProgressBar::new(num_files as u64))));
@@ -1029,149 +1512,290 @@ async fn run_async(
         progress_bar = None;
     }
 
-    for _ in 0..concurrency.unwrap_or(1) {
-        let pbx = progress_bar.clone();
-        let d_files = dicom_files.clone();
-        let s3_bucket = s3_bucket.clone();
-        let pc = presentation_contexts.clone();
-        let addr = addr.clone();
-        let jwt = jwt.clone();
-        let saml_assertion = saml_assertion.clone();
-        let kerberos_service_ticket = kerberos_service_ticket.clone();
-        let username = username.clone();
-        let password = password.clone();
-        let called_ae_title = called_ae_title.clone();
-        let calling_ae_title = calling_ae_title.clone();
-        let callbacks_clone = callbacks.clone();
-        let on_transfer_started_clone = on_transfer_started.clone();
-        let successful_count_clone = successful_count.clone();
-        let failed_count_clone = failed_count.clone();
-        tasks.spawn(async move {
-            // Emit OnTransferStarted event before moves
-            if let Some(cb) = &on_transfer_started_clone {
-                cb.call(Ok(TransferStartedEvent {
-                    message: "Transfer started".to_string(),
-                    data: Some(TransferStartedData {
-                        total_files: num_files as u32,
-                    }),
-                }), ThreadsafeFunctionCallMode::NonBlocking);
-            }
-            
-            let mut scu_init = ClientAssociationOptions::new()
-                .calling_ae_title(calling_ae_title)
-                .max_pdu_length(max_pdu_length);
+    // Emit OnTransferStarted event
+    if let Some(cb) = &on_transfer_started {
+        cb.call(Ok(TransferStartedEvent {
+            message: "Transfer started".to_string(),
+            data: Some(TransferStartedData {
+                total_files: num_files as u32,
+            }),
+        }), ThreadsafeFunctionCallMode::NonBlocking);
+    }
 
-            for (storage_sop_class_uid, transfer_syntax) in &pc {
-                scu_init = scu_init.with_presentation_context(storage_sop_class_uid, vec![transfer_syntax]);
-            }
+    // ClientAssociationOptions cannot propose the Asynchronous Operations Window
+    let windowed_request = (async_operations_window > 1).then(|| AssociationRequest {
+        calling_ae_title: calling_ae_title.clone(),
+        called_ae_title: called_ae_title.clone(),
+        max_pdu_length,
+        contexts: Vec::new(),
+        user_identity: user_identity(
+            username.as_deref(),
+            password.as_deref(),
+            kerberos_service_ticket.as_deref(),
+            saml_assertion.as_deref(),
+            jwt.as_deref(),
+        ),
+        async_operations_window: Some((async_operations_window, async_operations_window)),
+        tls: None,
+    });
 
-            if let Some(called_ae_title) = called_ae_title {
-                scu_init = scu_init.called_ae_title(called_ae_title);
-            }
+    let mut scu_options = ClientAssociationOptions::new()
+        .calling_ae_title(calling_ae_title)
+        .max_pdu_length(max_pdu_length);
 
-            if let Some(username) = username {
-                scu_init = scu_init.username(username);
-            }
+    if let Some(called_ae_title) = called_ae_title {
+        scu_options = scu_options.called_ae_title(called_ae_title);
+    }
 
-            if let Some(password) = password {
-                scu_init = scu_init.password(password);
-            }
+    if let Some(username) = username {
+        scu_options = scu_options.username(username);
+    }
 
-            if let Some(kerberos_service_ticket) = kerberos_service_ticket {
-                scu_init = scu_init.kerberos_service_ticket(kerberos_service_ticket);
-            }
+    if let Some(password) = password {
+        scu_options = scu_options.password(password);
+    }
 
-            if let Some(saml_assertion) = saml_assertion {
-                scu_init = scu_init.saml_assertion(saml_assertion);
-            }
+    if let Some(kerberos_service_ticket) = kerberos_service_ticket {
+        scu_options = scu_options.kerberos_service_ticket(kerberos_service_ticket);
+    }
+
+    if let Some(saml_assertion) = saml_assertion {
+        scu_options = scu_options.saml_assertion(saml_assertion);
+    }
+
+    if let Some(jwt) = jwt {
+        scu_options = scu_options.jwt(jwt);
+    }
 
-            if let Some(jwt) = jwt {
-                scu_init = scu_init.jwt(jwt);
+    let mut attempt = 0;
+    loop {
+        let mut tasks = tokio::task::JoinSet::new();
+        for _ in 0..concurrency.unwrap_or(1) {
+            let pbx = progress_bar.clone();
+            let groups = groups.clone();
+            let storage = storage.clone();
+            let policy = policy.clone();
+            let addr = addr.clone();
+            let scu_options = scu_options.clone();
+            let windowed_request = windowed_request.clone();
+            let callbacks_clone = callbacks.clone();
+            let send_rules = send_rules.clone();
+            let outcomes = outcomes.clone();
+            tasks.spawn(async move {
+                // one association per batch, proposing only the presentation contexts of its group
+                while !outcomes.cancellation.is_cancelled() {
+                    let Some((group, d_files)) = next_batch(&groups, max_operations_per_association).await else {
+                        break;
+                    };
+                    let established = match &windowed_request {
+                        Some(request) => {
+                            let request = AssociationRequest {
+                                contexts: groups[group]
+                                    .presentation_contexts
+                                    .iter()
+                                    .map(|(storage_sop_class_uid, transfer_syntaxes)| ProposedContext {
+                                        abstract_syntax: storage_sop_class_uid.clone(),
+                                        transfer_syntaxes: transfer_syntaxes.clone(),
+                                        scp_role: false,
+                                    })
+                                    .collect(),
+                                ..request.clone()
+                            };
+                            RoleSelectingAssociation::establish(&addr, request)
+                                .await
+                                .map(|scu| store_async::StoreAssociation::Windowed { scu, requested: async_operations_window })
+                                .map_err(|e| Error::Association { message: e.to_string() })
+                        }
+                        None => {
+                            let mut scu_init = scu_options.clone();
+                            for (storage_sop_class_uid, transfer_syntaxes) in &groups[group].presentation_contexts {
+                                scu_init = scu_init.with_presentation_context(storage_sop_class_uid.clone(), transfer_syntaxes.clone());
+                            }
+                            scu_init
+                                .establish_with_async(&addr)
+                                .await
+                                .map(store_async::StoreAssociation::Standard)
+                                .map_err(Box::from)
+                                .context(ScuSnafu)
+                        }
+                    };
+
+                    let scu = match established {
+                        Ok(scu) => scu,
+                        Err(e) if fail_first => return Err(e),
+                        Err(e) => {
+                            // the files of this batch cannot be sent, report them and go on with the next one
+                            let error = Report::from_error(e).to_string();
+                            error!("{}", error);
+                            let files: Vec<DicomFile> = d_files.lock().await.drain(..).collect();
+                            for file in &files {
+                                store_async::report_file_error(
+                                    file,
+                                    "Could not establish association",
+                                    error.clone(),
+                                    None,
+                                    true,
+                                    &callbacks_clone,
+                                    &outcomes,
+                                )
+                                .await;
+                                if let Some(pb) = pbx.as_ref() {
+                                    pb.lock().await.inc(1);
+                                }
+                            }
+                            continue;
+                        }
+                    };
+
+                    let result = store_async::inner(
+                        scu,
+                        d_files.clone(),
+                        storage.clone(),
+                        pbx.as_ref(),
+                        fail_first,
+                        verbose,
+                        &policy,
+                        ignore_sop_class,
+                        &callbacks_clone,
+                        &send_rules,
+                        &outcomes,
+                    )
+                    .await;
+                    match result {
+                        Ok(()) | Err(Error::Cancelled) => {}
+                        Err(e) if fail_first => return Err(e),
+                        // the association was lost, the rest of the batch is sent over a new one
+                        Err(e) => error!("{}", Report::from_error(e)),
+                    }
+                    // left over after an error or a cancellation
+                    if !Arc::ptr_eq(&d_files, &groups[group].files) {
+                        let rest: Vec<DicomFile> = d_files.lock().await.drain(..).collect();
+                        groups[group].files.lock().await.extend(rest);
+                    }
+                }
+
+                Ok::<(), Error>(())
+            });
+        }
+        while let Some(result) = tasks.join_next().await {
+            if let Err(e) = result {
+                error!("{}", Report::from_error(e));
+                if fail_first {
+                    std::process::exit(-2);
+                }
             }
+        }
 
-            let scu = scu_init
-                .establish_with_async(&addr)
-                .await
-                .map_err(Box::from)
-                .context(ScuSnafu)?;
-
-            store_async::inner(
-                scu,
-                d_files,
-                s3_bucket,
-                pbx.as_ref(),
-                fail_first,
-                verbose,
-                never_transcode,
-                ignore_sop_class,
-                &callbacks_clone,
-                successful_count_clone,
-                failed_count_clone,
-            )
-            .await?;
-
-            Ok::<(), Error>(())
-        });
+        let retries = std::mem::take(&mut *outcomes.retries.lock().await);
+        if retries.is_empty() || attempt == max_retries || outcomes.cancellation.is_cancelled() {
+            break;
+        }
+        attempt += 1;
+        let delay = ((retry_delay_ms as u64) << (attempt - 1).min(16)).min(60_000.max(retry_delay_ms as u64));
+        if verbose {
+            info!("Retrying {} failed files in {}ms (retry {} of {})", retries.len(), delay, attempt, max_retries);
+        }
+        if !store_async::wait_for_retry(retries.len(), Duration::from_millis(delay), &outcomes).await {
+            break;
+        }
+        if let Some(pb) = progress_bar.as_ref() {
+            pb.lock().await.inc_length(retries.len() as u64);
+        }
+        let retries = retries
+            .into_iter()
+            .map(|file| DicomFile { pc_selected: None, ts_selected: None, ..file })
+            .collect();
+        let (retry_groups, unassigned) = group_files(retries, presentation_contexts.clone(), max_presentation_contexts as usize);
+        report_unassigned_files(&unassigned, &callbacks, &outcomes).await;
+        groups = Arc::new(retry_groups);
     }
-    while let Some(result) = tasks.join_next().await {
-        if let Err(e) = result {
-            error!("{}", Report::from_error(e));
-            if fail_first {
-                std::process::exit(-2);
+
+    let cancelled = outcomes.cancellation.is_cancelled();
+    if cancelled {
+        for group in groups.iter() {
+            let files: Vec<DicomFile> = group.files.lock().await.drain(..).collect();
+            for file in &files {
+                outcomes
+                    .record(file, FileResultStatus::Cancelled, None, Some("Transfer cancelled".to_string()))
+                    .await;
             }
         }
+        if verbose {
+            info!("Transfer cancelled");
+        }
     }
 
     if let Some(pb) = progress_bar {
-        pb.lock().await.finish_with_message("done")
+        pb.lock().await.finish_with_message(if cancelled { "cancelled" } else { "done" })
     };
 
     let duration = start_time.elapsed();
-    let successful = *successful_count.lock().await;
-    let failed = *failed_count.lock().await;
+    let successful = *outcomes.successful.lock().await;
+    let warning = *outcomes.warning.lock().await;
+    let failed = *outcomes.failed.lock().await;
+    let skipped = *outcomes.skipped.lock().await;
+    let results = std::mem::take(&mut *outcomes.results.lock().await);
+    let cancelled_files = results
+        .values()
+        .filter(|result| result.status == FileResultStatus::Cancelled)
+        .count() as u32;
 
     // Emit OnTransferCompleted event
     if let Some(cb) = &on_transfer_completed {
         cb.call(Ok(TransferCompletedEvent {
-            message: "All files transferred successfully".to_string(),
+            message: if cancelled {
+                "Transfer cancelled".to_string()
+            } else {
+                "All files transferred successfully".to_string()
+            },
             data: Some(TransferCompletedData {
                 total_files: num_files as u32,
                 successful,
+                warning,
                 failed,
+                skipped,
+                already_sent: already_sent as u32,
+                cancelled: cancelled_files,
                 duration_seconds: duration.as_secs_f64(),
             }),
         }), ThreadsafeFunctionCallMode::NonBlocking);
     }
 
+    if cancelled {
+        return Ok(vec![ResultObject {
+            status: ResultStatus::Cancelled,
+            message: format!("Transfer cancelled, {} files not sent", cancelled_files),
+            files: Some(results),
+        }]);
+    }
     Ok(vec![ResultObject {
         status: ResultStatus::Success,
         message: "All files sent successfully".to_string(),
+        files: Some(results),
     }])
 }
 
 async fn expand_s3_sources(
     sources: Vec<FileSource>,
-    s3_config: &Option<S3Config>,
+    storage: &dyn StorageBackend,
     verbose: bool,
 ) -> Result<Vec<FileSource>, Error> {
-    if s3_config.is_none() {
-        return Ok(sources);
-    }
-    
-    let config = s3_config.as_ref().unwrap();
-    let bucket = crate::utils::build_s3_bucket(config);
-    
     let mut expanded = Vec::new();
     
     for source in sources {
         match source {
-            FileSource::Local(path) => expanded.push(FileSource::Local(path)),
+            source @ (FileSource::Local(_) | FileSource::Memory { .. }) => expanded.push(source),
             FileSource::S3(key) => {
-                // List all objects with this prefix (recursively)
+                // A key naming a single object is sent as is
+                if !key.is_empty() && matches!(storage.exists(&key).await, Ok(true)) {
+                    expanded.push(FileSource::S3(key));
+                    continue;
+                }
+                // Otherwise list all objects below this folder (recursively)
                 if verbose {
                     info!("Listing S3 objects with prefix: '{}'", key);
                 }
-                let s3_result = crate::utils::s3_list_objects(&bucket, &key).await;
+                let s3_result = storage.list_files(&key).await;
                 let objects = match s3_result {
                     Ok(objs) => {
                         if verbose {
@@ -1224,7 +1848,7 @@ async fn expand_s3_sources(
     Ok(expanded)
 }
 
-fn store_req_command(
+pub(crate) fn store_req_command(
     storage_sop_class_uid: &str,
     storage_sop_instance_uid: &str,
     message_id: u16,
@@ -1258,47 +1882,132 @@ fn store_req_command(
 }
 
 
+/// Files sent over associations proposing the same presentation contexts
+struct AssociationGroup {
+    /// abstract syntax and transfer syntaxes of each presentation context
+    presentation_contexts: Vec<(String, Vec<String>)>,
+    /// files still to be sent
+    files: Arc<Mutex<Vec<DicomFile>>>,
+}
+
+/// Split the presentation contexts into groups of at most `max_presentation_contexts`,
+/// keeping those of the same SOP class together unless they exceed the limit on their own,
+/// and assign each file to the group proposing its SOP class and transfer syntax.
+///
+/// Files whose SOP class and transfer syntax are not proposed by any group are returned apart.
+fn group_files(
+    dicom_files: Vec<DicomFile>,
+    mut presentation_contexts: Vec<(String, Vec<String>)>,
+    max_presentation_contexts: usize,
+) -> (Vec<AssociationGroup>, Vec<DicomFile>) {
+    presentation_contexts.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut chunks: Vec<Vec<(String, Vec<String>)>> = vec![];
+    for sop_class in presentation_contexts.chunk_by(|a, b| a.0 == b.0) {
+        for part in sop_class.chunks(max_presentation_contexts) {
+            match chunks.last_mut() {
+                Some(chunk) if chunk.len() + part.len() <= max_presentation_contexts => chunk.extend_from_slice(part),
+                _ => chunks.push(part.to_vec()),
+            }
+        }
+    }
+    let group_of: HashMap<(&str, &str), usize> = chunks
+        .iter()
+        .enumerate()
+        .flat_map(|(i, pcs)| pcs.iter().map(move |(sop, ts)| ((sop.as_str(), ts[0].as_str()), i)))
+        .collect();
+    let mut files: Vec<Vec<DicomFile>> = chunks.iter().map(|_| vec![]).collect();
+    let mut unassigned = vec![];
+    for file in dicom_files {
+        match group_of.get(&(file.sop_class_uid.as_str(), file.file_transfer_syntax.as_str())) {
+            Some(&group) => files[group].push(file),
+            None => unassigned.push(file),
+        }
+    }
+    let groups = chunks
+        .into_iter()
+        .zip(files)
+        .map(|(presentation_contexts, files)| AssociationGroup {
+            presentation_contexts,
+            files: Arc::new(Mutex::new(files)),
+        })
+        .collect();
+    (groups, unassigned)
+}
+
+/// Report the files left out of every association group as failed
+async fn report_unassigned_files(
+    files: &[DicomFile],
+    callbacks: &store_async::StoreCallbacks,
+    outcomes: &store_async::TransferOutcomes,
+) {
+    for file in files {
+        store_async::report_file_error(
+            file,
+            "No presentation context proposed for file",
+            format!("No presentation context for SOP class {} in {}", file.sop_class_uid, file.file_transfer_syntax),
+            None,
+            false,
+            callbacks,
+            outcomes,
+        ).await;
+    }
+}
+
+/// Take the files to send over the next association from the first group with files left.
+///
+/// Without a limit of operations, the whole queue of the group is shared with the other service users.
+async fn next_batch(
+    groups: &[AssociationGroup],
+    max_operations: Option<u32>,
+) -> Option<(usize, Arc<Mutex<Vec<DicomFile>>>)> {
+    for (i, group) in groups.iter().enumerate() {
+        let mut files = group.files.lock().await;
+        if files.is_empty() {
+            continue;
+        }
+        return Some(match max_operations {
+            Some(max) => {
+                let at = files.len().saturating_sub(max as usize);
+                (i, Arc::new(Mutex::new(files.split_off(at))))
+            }
+            None => (i, group.files.clone()),
+        });
+    }
+    None
+}
+
 fn check_files(
     sources: Vec<FileSource>,
-    s3_config: Option<S3Config>,
+    storage: Option<Arc<dyn StorageBackend>>,
     verbose: bool,
-    never_transcode: bool,
-) -> (Vec<DicomFile>, HashSet<(String, String)>) {
+    policy: &TransferSyntaxPolicy,
+) -> (Vec<DicomFile>, Vec<(String, Vec<String>)>) {
     let mut dicom_files: Vec<DicomFile> = vec![];
-    let mut presentation_contexts = HashSet::new();
-
-    // Setup S3 bucket if needed
-    let bucket = s3_config.as_ref().map(|config| crate::utils::build_s3_bucket(config));
+    // one presentation context per SOP class and file transfer syntax,
+    // proposing the file's transfer syntax first and then the fallbacks
+    let mut presentation_contexts: Vec<(String, Vec<String>)> = vec![];
+    let mut proposed = HashSet::new();
 
     for source in sources {
-        let display_name = match &source {
-            FileSource::Local(path) => path.display().to_string(),
-            FileSource::S3(key) => format!("s3://{}", key),
-        };
+        let display_name = source.display_name();
 
         if verbose {
             info!("Checking file '{}'...", display_name);
         }
 
-        match check_file_source(&source, bucket.as_ref()) {
+        match check_file_source(&source, storage.as_deref()) {
             Ok(dicom_file) => {
-                presentation_contexts.insert((
-                    dicom_file.sop_class_uid.to_string(),
-                    dicom_file.file_transfer_syntax.clone(),
-                ));
-
-                // also accept uncompressed transfer syntaxes
-                // as mandated by the standard
-                // (though it might not always be able to fulfill this)
-                if !never_transcode {
-                    presentation_contexts.insert((
-                        dicom_file.sop_class_uid.to_string(),
-                        uids::EXPLICIT_VR_LITTLE_ENDIAN.to_string(),
-                    ));
-                    presentation_contexts.insert((
-                        dicom_file.sop_class_uid.to_string(),
-                        uids::IMPLICIT_VR_LITTLE_ENDIAN.to_string(),
-                    ));
+                let key = (dicom_file.sop_class_uid.to_string(), dicom_file.file_transfer_syntax.clone());
+                if proposed.insert(key.clone()) {
+                    let mut proposed_ts = vec![key.1.clone()];
+                    proposed_ts.extend(
+                        policy
+                            .fallbacks
+                            .iter()
+                            .filter(|ts| **ts != key.1 && can_convert(&key.1, ts, policy.never_transcode))
+                            .cloned(),
+                    );
+                    presentation_contexts.push((key.0, proposed_ts));
                 }
 
                 dicom_files.push(dicom_file);
@@ -1309,26 +2018,61 @@ fn check_files(
         }
     }
 
-    if dicom_files.is_empty() {
-        eprintln!("No supported files to transfer");
-        std::process::exit(-1);
-    }
     (dicom_files, presentation_contexts)
 }
 
-fn check_file_source(source: &FileSource, bucket: Option<&s3::Bucket>) -> Result<DicomFile, Error> {
+fn check_file_source(source: &FileSource, storage: Option<&dyn StorageBackend>) -> Result<DicomFile, Error> {
     match source {
+        FileSource::Local(path) if envelope::is_encoded_file(path) => check_encoded_file(path),
+        FileSource::Local(path) if is_dataset_only(path) => check_dataset_file(path),
         FileSource::Local(path) => check_file(path),
-        FileSource::S3(key) => check_s3_file(key, bucket.unwrap()),
+        FileSource::S3(key) => check_s3_file(key, storage.expect("S3 storage should be available for S3 files")),
+        FileSource::Memory { name, data } => check_file_data(data, source.clone(), name.clone()),
     }
 }
 
-fn check_s3_file(key: &str, bucket: &s3::Bucket) -> Result<DicomFile, Error> {
+/// Whether a local file holds a bare data set, without preamble and file meta group
+/// (as written by StoreScp unless `storeWithFileMeta` is enabled)
+fn is_dataset_only(path: &Path) -> bool {
+    use std::io::Read;
+    let mut header = Vec::with_capacity(133);
+    match std::fs::File::open(path).and_then(|file| file.take(133).read_to_end(&mut header)) {
+        Ok(_) => !crate::utils::dataset::has_file_meta(&header),
+        Err(_) => false,
+    }
+}
+
+/// Check a data set stored without file meta group
+fn check_dataset_file(path: &Path) -> Result<DicomFile, Error> {
+    let data = std::fs::read(path).map_err(|e| Error::ReadFilePath {
+        path: path.display().to_string(),
+        source: Box::new(dicom_object::ReadError::ReadFile {
+            filename: path.to_path_buf(),
+            source: e,
+            backtrace: std::backtrace::Backtrace::capture(),
+        }),
+    })?;
+    check_file_data(&data, FileSource::Local(path.to_path_buf()), path.display().to_string())
+}
+
+/// Check a compressed or encrypted file written by the filesystem storage
+fn check_encoded_file(path: &Path) -> Result<DicomFile, Error> {
+    let rt = tokio::runtime::Handle::current();
+    let data = rt.block_on(envelope::read_encoded_file(path)).map_err(|e| Error::ReadFilePath {
+        path: path.display().to_string(),
+        source: Box::new(dicom_object::ReadError::ReadFile {
+            filename: path.to_path_buf(),
+            source: std::io::Error::other(e.to_string()),
+            backtrace: std::backtrace::Backtrace::capture(),
+        }),
+    })?;
+    check_file_data(&data, FileSource::Local(path.to_path_buf()), path.display().to_string())
+}
+
+fn check_s3_file(key: &str, storage: &dyn StorageBackend) -> Result<DicomFile, Error> {
     // Download file data from S3 temporarily to read metadata
     let rt = tokio::runtime::Handle::current();
-    let s3_result = rt.block_on(async {
-        crate::utils::s3_get_object(bucket, key).await
-    });
+    let s3_result = rt.block_on(storage.read_file(key));
     let data = match s3_result {
         Ok(d) => d,
         Err(_e) => {
@@ -1345,70 +2089,18 @@ fn check_s3_file(key: &str, bucket: &s3::Bucket) -> Result<DicomFile, Error> {
             });
         }
     };
+    check_file_data(&data, FileSource::S3(key.to_string()), format!("s3://{}", key))
+}
 
-    // Auto-detect file format by checking for DICM magic bytes at position 128
-    // Full DICOM files have: 128-byte preamble + "DICM" (4 bytes) + meta header
-    // Dataset-only files start directly with DICOM data elements
-    let has_dicm_magic = data.len() > 132 && &data[128..132] == b"DICM";
-    
-    let (storage_sop_class_uid, storage_sop_instance_uid, transfer_syntax_uid) = 
-        if has_dicm_magic {
-            // Full DICOM file with meta header
-            let dicom_file = dicom_object::from_reader(&data[..])
-                .map_err(Box::from)
-                .context(ReadFilePathSnafu {
-                    path: format!("s3://{}", key),
-                })?;
-            let meta = dicom_file.meta();
-            (
-                meta.media_storage_sop_class_uid.clone(),
-                meta.media_storage_sop_instance_uid.clone(),
-                meta.transfer_syntax.trim_end_matches('\0').to_string(),
-            )
-        } else {
-                // Dataset-only file (no meta header) - read as InMemDicomObject
-                use dicom_dictionary_std::tags;
-                use dicom_encoding::TransferSyntaxIndex;
-                
-                // Try reading as dataset with explicit VR little endian (most common)
-                let obj = InMemDicomObject::read_dataset_with_ts(
-                    &data[..],
-                    &dicom_transfer_syntax_registry::entries::EXPLICIT_VR_LITTLE_ENDIAN.erased(),
-                )
-                .or_else(|_| {
-                    // Fall back to implicit VR little endian
-                    InMemDicomObject::read_dataset_with_ts(
-                        &data[..],
-                        &dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN.erased(),
-                    )
-                })
-                .map_err(Box::from)
-                .context(ReadFilePathSnafu {
-                    path: format!("s3://{}", key),
-                })?;
-                
-                // Extract metadata from dataset attributes
-                let sop_class_uid = obj
-                    .element(tags::SOP_CLASS_UID)
-                    .context(MissingAttributeSnafu { tag: tags::SOP_CLASS_UID })?
-                    .to_str()
-                    .context(ConvertFieldSnafu { tag: tags::SOP_CLASS_UID })?
-                    .to_string();
-                    
-                let sop_instance_uid = obj
-                    .element(tags::SOP_INSTANCE_UID)
-                    .context(MissingAttributeSnafu { tag: tags::SOP_INSTANCE_UID })?
-                    .to_str()
-                    .context(ConvertFieldSnafu { tag: tags::SOP_INSTANCE_UID })?
-                    .to_string();
-                
-                // Assume explicit VR little endian as transfer syntax for dataset-only files
-                let transfer_syntax = uids::EXPLICIT_VR_LITTLE_ENDIAN.to_string();
-                
-                (sop_class_uid, sop_instance_uid, transfer_syntax)
-        };
-    
-    let transfer_syntax_uid = transfer_syntax_uid.trim_end_matches('\0');
+/// Read the identifying attributes of a file loaded into memory (with or without file meta header)
+fn check_file_data(data: &[u8], source: FileSource, display: String) -> Result<DicomFile, Error> {
+    // Full DICOM file, or data set with the transfer syntax inferred from its content
+    let dicom_file = crate::utils::dataset::read_stored_object(data)
+        .map_err(|message| Error::ReadStoredObject { path: display, message })?;
+    let meta = dicom_file.meta();
+    let storage_sop_class_uid = &meta.media_storage_sop_class_uid;
+    let storage_sop_instance_uid = &meta.media_storage_sop_instance_uid;
+    let transfer_syntax_uid = meta.transfer_syntax.trim_end_matches('\0');
     let ts = TransferSyntaxRegistry
         .get(transfer_syntax_uid)
         .with_context(|| UnsupportedFileTransferSyntaxSnafu {
@@ -1417,9 +2109,9 @@ fn check_s3_file(key: &str, bucket: &s3::Bucket) -> Result<DicomFile, Error> {
     
     // Don't cache data - we'll download again during send to save memory
     Ok(DicomFile {
-        source: FileSource::S3(key.to_string()),
-        sop_class_uid: storage_sop_class_uid.to_string(),
-        sop_instance_uid: storage_sop_instance_uid.to_string(),
+        source,
+        sop_class_uid: storage_sop_class_uid.trim_end_matches('\0').to_string(),
+        sop_instance_uid: storage_sop_instance_uid.trim_end_matches('\0').to_string(),
         file_transfer_syntax: String::from(ts.uid()),
         ts_selected: None,
         pc_selected: None,
@@ -1452,8 +2144,8 @@ fn check_file(file: &Path) -> Result<DicomFile, Error> {
         })?;
     Ok(DicomFile {
         source: FileSource::Local(file.to_path_buf()),
-        sop_class_uid: storage_sop_class_uid.to_string(),
-        sop_instance_uid: storage_sop_instance_uid.to_string(),
+        sop_class_uid: storage_sop_class_uid.trim_end_matches('\0').to_string(),
+        sop_instance_uid: storage_sop_instance_uid.trim_end_matches('\0').to_string(),
         file_transfer_syntax: String::from(ts.uid()),
         ts_selected: None,
         pc_selected: None,
@@ -1461,11 +2153,32 @@ fn check_file(file: &Path) -> Result<DicomFile, Error> {
     })
 }
 
+/// Whether a file in transfer syntax `from` can be sent in transfer syntax `to`.
+///
+/// Re-encoding between codec-free transfer syntaxes is always possible;
+/// anything else requires transcoding, which must be allowed and supported by both codecs.
+fn can_convert(from: &str, to: &str, never_transcode: bool) -> bool {
+    if from == to {
+        return true;
+    }
+    let (Some(from), Some(to)) = (TransferSyntaxRegistry.get(from), TransferSyntaxRegistry.get(to)) else {
+        return false;
+    };
+    if from.is_codec_free() && to.is_codec_free() {
+        return true;
+    }
+    !never_transcode && from.can_decode_all() && (to.is_codec_free() || to.pixel_data_writer().is_some())
+}
+
+/// Select the presentation context and transfer syntax to send a file with.
+///
+/// The file's own transfer syntax is preferred, then codec-free re-encoding,
+/// then transcoding, each into the accepted transfer syntax ranked highest among the fallbacks.
 fn check_presentation_contexts(
     file: &DicomFile,
     pcs: &[dicom_ul::pdu::PresentationContextNegotiated],
     ignore_sop_class: bool,
-    never_transcode: bool,
+    policy: &TransferSyntaxPolicy,
 ) -> Result<(dicom_ul::pdu::PresentationContextNegotiated, String), Error> {
     let file_ts = TransferSyntaxRegistry
         .get(&file.file_transfer_syntax)
@@ -1473,49 +2186,35 @@ fn check_presentation_contexts(
             uid: file.file_transfer_syntax.to_string(),
         })?;
 
-    // Try to find an exact match for the file's transfer syntax first
-    let exact_match_pc = pcs.iter().find(|pc| pc.transfer_syntax == file_ts.uid());
-
-    if let Some(pc) = exact_match_pc {
-        return Ok((pc.clone(), pc.transfer_syntax.clone()));
-    }
+    let candidates = || {
+        pcs.iter()
+            .filter(move |pc| ignore_sop_class || pc.abstract_syntax == file.sop_class_uid)
+    };
+    let preference = |pc: &&dicom_ul::pdu::PresentationContextNegotiated| {
+        policy
+            .fallbacks
+            .iter()
+            .position(|ts| *ts == pc.transfer_syntax)
+            .unwrap_or(policy.fallbacks.len())
+    };
 
-    let pc = pcs.iter().find(|pc| {
-        if !ignore_sop_class && pc.abstract_syntax != file.sop_class_uid {
-            return false;
-        }
-        // Check support for this transfer syntax.
-        // If it is the same as the file, we're good.
+    // Try to find an exact match for the file's transfer syntax first
+    let pc = candidates()
+        .find(|pc| pc.transfer_syntax == file_ts.uid())
         // Otherwise, uncompressed data set encoding
-        // and native pixel data is required on both ends.
-        let ts = &pc.transfer_syntax;
-        ts == file_ts.uid()
-            || TransferSyntaxRegistry
-                .get(&pc.transfer_syntax)
-                .filter(|ts| file_ts.is_codec_free() && ts.is_codec_free())
-                .map(|_| true)
-                .unwrap_or(false)
-    });
-
-    let pc = match pc {
-        Some(pc) => pc,
-        None => {
-            if never_transcode || !file_ts.can_decode_all() {
-                NoPresentationContextSnafu.fail()?
-            }
-
-            // Else, if transcoding is possible, we go for it.
-            pcs.iter()
-                .filter(|pc| ignore_sop_class || pc.abstract_syntax == file.sop_class_uid)
-                // accept explicit VR little endian
-                .find(|pc| pc.transfer_syntax == uids::EXPLICIT_VR_LITTLE_ENDIAN)
-                .or_else(||
-                // accept implicit VR little endian
-                pcs.iter()
-                    .find(|pc| pc.transfer_syntax == uids::IMPLICIT_VR_LITTLE_ENDIAN))
-                .context(NoPresentationContextSnafu)?
-        }
-    };
+        // and native pixel data is required on both ends
+        .or_else(|| {
+            candidates()
+                .filter(|pc| can_convert(file_ts.uid(), &pc.transfer_syntax, true))
+                .min_by_key(preference)
+        })
+        // Else, if transcoding is possible, we go for it
+        .or_else(|| {
+            candidates()
+                .filter(|pc| can_convert(file_ts.uid(), &pc.transfer_syntax, policy.never_transcode))
+                .min_by_key(preference)
+        })
+        .context(NoPresentationContextSnafu)?;
 
     let ts = TransferSyntaxRegistry
         .get(&pc.transfer_syntax)
@@ -1560,4 +2259,125 @@ fn into_ts(
     } else {
         Ok(dicom_file)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom_ul::pdu::{PresentationContextNegotiated, PresentationContextResultReason};
+
+    fn accepted(id: u8, abstract_syntax: &str, transfer_syntax: &str) -> PresentationContextNegotiated {
+        PresentationContextNegotiated {
+            id,
+            reason: PresentationContextResultReason::Acceptance,
+            abstract_syntax: abstract_syntax.to_string(),
+            transfer_syntax: transfer_syntax.to_string(),
+        }
+    }
+
+    fn file(transfer_syntax: &str) -> DicomFile {
+        file_of_class(uids::CT_IMAGE_STORAGE, transfer_syntax)
+    }
+
+    fn file_of_class(sop_class_uid: &str, transfer_syntax: &str) -> DicomFile {
+        DicomFile {
+            source: FileSource::Local(PathBuf::from("image.dcm")),
+            sop_class_uid: sop_class_uid.to_string(),
+            sop_instance_uid: "1.2.3".to_string(),
+            file_transfer_syntax: transfer_syntax.to_string(),
+            ts_selected: None,
+            pc_selected: None,
+            data: None,
+        }
+    }
+
+    /// Explicit VR Big Endian (retired, but still codec-free)
+    const EXPLICIT_VR_BIG_ENDIAN: &str = "1.2.840.10008.1.2.2";
+
+    #[test]
+    fn test_check_presentation_contexts() {
+        let policy = |never_transcode: bool, fallbacks: &[&str]| TransferSyntaxPolicy {
+            never_transcode,
+            fallbacks: fallbacks.iter().map(|ts| ts.to_string()).collect(),
+        };
+        let transcode = policy(false, &[uids::EXPLICIT_VR_LITTLE_ENDIAN, uids::IMPLICIT_VR_LITTLE_ENDIAN]);
+        let never_transcode = policy(true, &[uids::EXPLICIT_VR_LITTLE_ENDIAN, uids::IMPLICIT_VR_LITTLE_ENDIAN]);
+        let pcs = vec![
+            accepted(1, uids::MR_IMAGE_STORAGE, uids::RLE_LOSSLESS),
+            accepted(3, uids::CT_IMAGE_STORAGE, uids::IMPLICIT_VR_LITTLE_ENDIAN),
+            accepted(5, uids::CT_IMAGE_STORAGE, uids::EXPLICIT_VR_LITTLE_ENDIAN),
+        ];
+
+        // the exact match must also match the SOP class
+        let (pc, ts) = check_presentation_contexts(&file(uids::RLE_LOSSLESS), &pcs, false, &transcode).unwrap();
+        assert_eq!((pc.id, ts.as_str()), (5, uids::EXPLICIT_VR_LITTLE_ENDIAN));
+        assert!(check_presentation_contexts(&file(uids::RLE_LOSSLESS), &pcs, false, &never_transcode).is_err());
+        let (pc, _) = check_presentation_contexts(&file(uids::RLE_LOSSLESS), &pcs, true, &never_transcode).unwrap();
+        assert_eq!(pc.id, 1);
+
+        // codec-free re-encoding follows the order of preference
+        let (pc, _) = check_presentation_contexts(&file(EXPLICIT_VR_BIG_ENDIAN), &pcs, false, &never_transcode).unwrap();
+        assert_eq!(pc.id, 5);
+        let reversed = policy(true, &[uids::IMPLICIT_VR_LITTLE_ENDIAN, uids::EXPLICIT_VR_LITTLE_ENDIAN]);
+        let (pc, _) = check_presentation_contexts(&file(EXPLICIT_VR_BIG_ENDIAN), &pcs, false, &reversed).unwrap();
+        assert_eq!(pc.id, 3);
+    }
+
+    #[tokio::test]
+    async fn test_group_files() {
+        let files = vec![
+            file_of_class(uids::MR_IMAGE_STORAGE, uids::EXPLICIT_VR_LITTLE_ENDIAN),
+            file_of_class(uids::CT_IMAGE_STORAGE, uids::EXPLICIT_VR_LITTLE_ENDIAN),
+            file_of_class(uids::MR_IMAGE_STORAGE, uids::RLE_LOSSLESS),
+            file_of_class(uids::CT_IMAGE_STORAGE, uids::EXPLICIT_VR_LITTLE_ENDIAN),
+            file_of_class(uids::MR_IMAGE_STORAGE, uids::EXPLICIT_VR_LITTLE_ENDIAN),
+            file_of_class(uids::CT_IMAGE_STORAGE, uids::RLE_LOSSLESS),
+        ];
+        let contexts = vec![
+            (uids::MR_IMAGE_STORAGE.to_string(), vec![uids::EXPLICIT_VR_LITTLE_ENDIAN.to_string()]),
+            (uids::CT_IMAGE_STORAGE.to_string(), vec![uids::EXPLICIT_VR_LITTLE_ENDIAN.to_string()]),
+            (uids::MR_IMAGE_STORAGE.to_string(), vec![uids::RLE_LOSSLESS.to_string()]),
+        ];
+
+        let (groups, unassigned) = group_files(files, contexts, 2);
+        assert_eq!(groups.len(), 2);
+        // contexts of the same SOP class stay together, even if the previous group has room left
+        assert_eq!(groups[0].presentation_contexts.len(), 1);
+        assert!(groups[1].presentation_contexts.iter().all(|(sop, _)| sop == uids::MR_IMAGE_STORAGE));
+        assert_eq!(groups[0].files.lock().await.len(), 2);
+        assert_eq!(groups[1].files.lock().await.len(), 3);
+        // no presentation context for CT in RLE Lossless
+        assert_eq!(unassigned.len(), 1);
+        assert_eq!(unassigned[0].file_transfer_syntax, uids::RLE_LOSSLESS);
+
+        let (group, batch) = next_batch(&groups, Some(2)).await.unwrap();
+        assert_eq!((group, batch.lock().await.len()), (0, 2));
+        let (group, batch) = next_batch(&groups, Some(2)).await.unwrap();
+        assert_eq!((group, batch.lock().await.len()), (1, 2));
+        let (group, batch) = next_batch(&groups, None).await.unwrap();
+        assert_eq!(group, 1);
+        assert!(Arc::ptr_eq(&batch, &groups[1].files));
+        batch.lock().await.clear();
+        assert!(next_batch(&groups, None).await.is_none());
+
+        // a SOP class with more contexts than the limit is split on its own
+        let contexts = vec![
+            (uids::MR_IMAGE_STORAGE.to_string(), vec![uids::EXPLICIT_VR_LITTLE_ENDIAN.to_string()]),
+            (uids::MR_IMAGE_STORAGE.to_string(), vec![uids::RLE_LOSSLESS.to_string()]),
+        ];
+        let (groups, _) = group_files(vec![], contexts, 1);
+        assert_eq!(groups.len(), 2);
+    }
+
+    #[test]
+    fn test_check_memory_source() {
+        let data = std::fs::read("__test__/fixtures/test.dcm").unwrap();
+        let source = FileSource::Memory { name: "scan.dcm".to_string(), data: Arc::new(data) };
+        let file = check_file_source(&source, None).unwrap();
+        assert_eq!(file.sop_class_uid, uids::CT_IMAGE_STORAGE);
+        assert_eq!(file.file_transfer_syntax, uids::EXPLICIT_VR_LITTLE_ENDIAN);
+        assert_eq!(file.source.display_name(), "scan.dcm");
+
+        let source = FileSource::Memory { name: "bad".to_string(), data: Arc::new(b"garbage".to_vec()) };
+        assert!(check_file_source(&source, None).is_err());
+    }
+}
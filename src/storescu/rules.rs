@@ -0,0 +1,254 @@
+//! Filtering and tag coercion applied to each data set before it is sent.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use dicom_core::dictionary::{DataDictionary, DataDictionaryEntry};
+use dicom_core::value::C;
+use dicom_core::{header::Tag, DataElement, PrimitiveValue, VR};
+use dicom_dictionary_std::{tags, StandardDataDictionary};
+use dicom_object::InMemDicomObject;
+
+use crate::utils::parse_tag;
+
+/// Tag conditions and static coercions configured on StoreScu
+#[derive(Debug, Clone, Default)]
+pub struct SendRules {
+    /// Send only data sets matching all of these conditions
+    include: Vec<(Tag, String)>,
+    /// Skip data sets matching any of these conditions
+    exclude: Vec<(Tag, String)>,
+    /// Values set on every data set, keyed by the name given in the options
+    coerce: Vec<(String, Tag, String)>,
+}
+
+impl SendRules {
+    pub fn new(
+        include: Option<HashMap<String, String>>,
+        exclude: Option<HashMap<String, String>>,
+        coerce: Option<HashMap<String, String>>,
+    ) -> Result<Self, String> {
+        let conditions = |map: Option<HashMap<String, String>>| {
+            map.unwrap_or_default()
+                .into_iter()
+                .map(|(name, pattern)| Ok((parse_tag(&name)?, pattern)))
+                .collect::<Result<Vec<_>, String>>()
+        };
+        let coerce = coerce
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, value)| {
+                let tag = coercible_tag(&name)?;
+                parse_value(default_vr(tag), &value).map_err(|e| format!("Cannot coerce tag {}: {}", name, e))?;
+                Ok((name, tag, value))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(SendRules {
+            include: conditions(include)?,
+            exclude: conditions(exclude)?,
+            coerce,
+        })
+    }
+
+    /// Whether the data set passes the include and exclude conditions
+    pub fn accepts(&self, obj: &InMemDicomObject) -> bool {
+        self.include.iter().all(|(tag, pattern)| matches(&value_of(obj, *tag), pattern))
+            && !self.exclude.iter().any(|(tag, pattern)| matches(&value_of(obj, *tag), pattern))
+    }
+
+    /// Apply the static coercions, returning the tags whose value changed.
+    /// Values that do not fit the VR of the attribute in the data set are skipped with their error.
+    pub fn coerce(&self, obj: &mut InMemDicomObject) -> (HashMap<String, String>, Vec<String>) {
+        let mut changed = HashMap::new();
+        let mut errors = Vec::new();
+        for (name, tag, value) in &self.coerce {
+            match put_value(obj, *tag, value) {
+                Ok(true) => {
+                    changed.insert(name.clone(), value.clone());
+                }
+                Ok(false) => {}
+                Err(e) => errors.push(format!("Cannot coerce tag {}: {}", name, e)),
+            }
+        }
+        (changed, errors)
+    }
+}
+
+/// Parse a tag that may be coerced: file meta, SOP Class UID and pixel data are
+/// fixed by the negotiated presentation context and the transfer syntax,
+/// and SOP Instance UID identifies the file in events, results and the manifest
+pub fn coercible_tag(name: &str) -> Result<Tag, String> {
+    let tag = parse_tag(name)?;
+    if tag.0 == 0x0002 || tag == tags::SOP_CLASS_UID || tag == tags::SOP_INSTANCE_UID || tag == tags::PIXEL_DATA {
+        return Err(format!("Cannot coerce tag: {}", name));
+    }
+    Ok(tag)
+}
+
+/// Apply tag changes returned by `onBeforeSend`, returning the tags whose value changed.
+/// Tags that cannot be coerced are skipped with their error.
+pub fn apply_changes(
+    obj: &mut InMemDicomObject,
+    changes: HashMap<String, String>,
+) -> (HashMap<String, String>, Vec<String>) {
+    let mut changed = HashMap::new();
+    let mut errors = Vec::new();
+    for (name, value) in changes {
+        match coercible_tag(&name) {
+            Ok(tag) => match put_value(obj, tag, &value) {
+                Ok(true) => {
+                    changed.insert(name, value);
+                }
+                Ok(false) => {}
+                Err(e) => errors.push(format!("Cannot coerce tag {}: {}", name, e)),
+            },
+            Err(e) => errors.push(e),
+        }
+    }
+    (changed, errors)
+}
+
+/// Top-level attributes with a textual value, keyed by dictionary name (or `GGGGEEEE`)
+pub fn text_attributes(obj: &InMemDicomObject) -> HashMap<String, String> {
+    obj.iter()
+        .filter(|e| !matches!(e.vr(), VR::SQ | VR::OB | VR::OW | VR::OF | VR::OD | VR::OL | VR::OV | VR::UN))
+        .filter_map(|e| {
+            let tag = e.header().tag;
+            let name = StandardDataDictionary
+                .by_tag(tag)
+                .map(|entry| entry.alias().to_string())
+                .unwrap_or_else(|| format!("{:04X}{:04X}", tag.0, tag.1));
+            let value = e.to_str().ok()?;
+            Some((name, value.trim_end_matches(['\0', ' ']).to_string()))
+        })
+        .collect()
+}
+
+/// Value of an attribute without padding, empty if absent
+pub fn value_of(obj: &InMemDicomObject, tag: Tag) -> String {
+    obj.element(tag)
+        .ok()
+        .and_then(|e| e.to_str().ok())
+        .map(|v| v.trim_end_matches(['\0', ' ']).to_string())
+        .unwrap_or_default()
+}
+
+/// Set the value of an attribute, keeping its VR if present. Returns false if unchanged.
+fn put_value(obj: &mut InMemDicomObject, tag: Tag, value: &str) -> Result<bool, String> {
+    let current = obj.element(tag).ok();
+    if current.is_some() && value_of(obj, tag) == value {
+        return Ok(false);
+    }
+    let vr = current.map(|e| e.vr()).unwrap_or_else(|| default_vr(tag));
+    let value = parse_value(vr, value)?;
+    obj.put(DataElement::new(tag, vr, value));
+    Ok(true)
+}
+
+/// VR of an attribute that is not in the data set yet
+fn default_vr(tag: Tag) -> VR {
+    StandardDataDictionary.by_tag(tag).map(|entry| entry.vr.relaxed()).unwrap_or(VR::LO)
+}
+
+/// Value of an attribute with the given VR: text as is, binary numbers parsed from
+/// their `\`-separated decimal form. Other binary VRs cannot be given as text.
+fn parse_value(vr: VR, value: &str) -> Result<PrimitiveValue, String> {
+    fn numbers<T: FromStr>(vr: VR, value: &str) -> Result<C<T>, String> {
+        value
+            .split('\\')
+            .map(|v| v.trim().parse().map_err(|_| format!("'{}' is not a valid {} value", v, vr)))
+            .collect()
+    }
+    if value.is_empty() {
+        return Ok(PrimitiveValue::Empty);
+    }
+    Ok(match vr {
+        VR::AE | VR::AS | VR::CS | VR::DA | VR::DS | VR::DT | VR::IS | VR::LO | VR::LT | VR::PN | VR::SH | VR::ST
+        | VR::TM | VR::UC | VR::UI | VR::UR | VR::UT => PrimitiveValue::from(value),
+        VR::US => PrimitiveValue::U16(numbers(vr, value)?),
+        VR::SS => PrimitiveValue::I16(numbers(vr, value)?),
+        VR::UL => PrimitiveValue::U32(numbers(vr, value)?),
+        VR::SL => PrimitiveValue::I32(numbers(vr, value)?),
+        VR::UV => PrimitiveValue::U64(numbers(vr, value)?),
+        VR::SV => PrimitiveValue::I64(numbers(vr, value)?),
+        VR::FL => PrimitiveValue::F32(numbers(vr, value)?),
+        VR::FD => PrimitiveValue::F64(numbers(vr, value)?),
+        _ => return Err(format!("{} values cannot be set from text", vr)),
+    })
+}
+
+/// Attribute matching as in C-FIND: `\`-separated alternatives with `*` and `?` wildcards,
+/// tested against the whole value and each of its values. Absent attributes are empty.
+fn matches(value: &str, pattern: &str) -> bool {
+    pattern.split('\\').any(|alternative| {
+        wildcard(value.as_bytes(), alternative.as_bytes())
+            || (value.contains('\\') && value.split('\\').any(|v| wildcard(v.as_bytes(), alternative.as_bytes())))
+    })
+}
+
+fn wildcard(value: &[u8], pattern: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => value.is_empty(),
+        Some((b'*', rest)) => (0..=value.len()).any(|i| wildcard(&value[i..], rest)),
+        Some((b'?', rest)) => !value.is_empty() && wildcard(&value[1..], rest),
+        Some((c, rest)) => value.first() == Some(c) && wildcard(&value[1..], rest),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object() -> InMemDicomObject {
+        InMemDicomObject::from_element_iter([
+            DataElement::new(tags::MODALITY, VR::CS, PrimitiveValue::from("CT")),
+            DataElement::new(tags::IMAGE_TYPE, VR::CS, PrimitiveValue::from("ORIGINAL\\PRIMARY\\LOCALIZER")),
+            DataElement::new(tags::PATIENT_ID, VR::LO, PrimitiveValue::from("PAT1")),
+        ])
+    }
+
+    fn map(entries: &[(&str, &str)]) -> Option<HashMap<String, String>> {
+        Some(entries.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())
+    }
+
+    #[test]
+    fn test_filter() {
+        let obj = object();
+        let rules = |include, exclude| SendRules::new(include, exclude, None).unwrap();
+        assert!(rules(None, None).accepts(&obj));
+        assert!(rules(map(&[("Modality", "CT\\MR")]), None).accepts(&obj));
+        assert!(!rules(map(&[("Modality", "MR")]), None).accepts(&obj));
+        assert!(!rules(None, map(&[("ImageType", "LOCALIZER")])).accepts(&obj));
+        assert!(!rules(None, map(&[("ImageType", "*LOCAL*")])).accepts(&obj));
+        assert!(rules(None, map(&[("Modality", "SR"), ("InstitutionName", "?*")])).accepts(&obj));
+        assert!(SendRules::new(map(&[("NotATag", "x")]), None, None).is_err());
+        assert!(SendRules::new(None, None, map(&[("SOPClassUID", "1.2")])).is_err());
+        assert!(SendRules::new(None, None, map(&[("SOPInstanceUID", "1.2")])).is_err());
+    }
+
+    #[test]
+    fn test_coerce() {
+        let mut obj = object();
+        let rules = SendRules::new(None, None, map(&[("PatientID", "EXT1"), ("InstitutionName", "PARTNER")])).unwrap();
+        let (changed, errors) = rules.coerce(&mut obj);
+        assert_eq!((changed.len(), errors.len()), (2, 0));
+        assert_eq!(value_of(&obj, tags::PATIENT_ID), "EXT1");
+        assert_eq!(obj.element(tags::INSTITUTION_NAME).unwrap().vr(), VR::LO);
+        assert!(rules.coerce(&mut obj).0.is_empty());
+
+        let (changed, errors) = apply_changes(&mut obj, map(&[("PatientID", "EXT1"), ("TransferSyntaxUID", "1.2")]).unwrap());
+        assert!(changed.is_empty());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(text_attributes(&obj).get("InstitutionName").map(String::as_str), Some("PARTNER"));
+
+        // binary numbers are parsed for their VR, other binary values are refused
+        let (changed, errors) = apply_changes(&mut obj, map(&[("Rows", "512"), ("PixelSpacing", "0.5\\0.5")]).unwrap());
+        assert_eq!((changed.len(), errors.len()), (2, 0));
+        assert_eq!(obj.element(tags::ROWS).unwrap().value().primitive(), Some(&PrimitiveValue::from(512_u16)));
+        assert_eq!(value_of(&obj, tags::PIXEL_SPACING), "0.5\\0.5");
+        let (changed, errors) = apply_changes(&mut obj, map(&[("Rows", "large"), ("IconImageSequence", "x")]).unwrap());
+        assert_eq!((changed.len(), errors.len()), (0, 2));
+        assert_eq!(value_of(&obj, tags::ROWS), "512");
+        assert!(SendRules::new(None, None, map(&[("Columns", "wide")])).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+//! Decoding of the C-STORE response status (PS3.4 Table B.2-1 and PS3.7 Annex C).
+
+use dicom_core::dictionary::{DataDictionary, DataDictionaryEntry};
+use dicom_core::PrimitiveValue;
+use dicom_dictionary_std::{tags, StandardDataDictionary};
+use dicom_object::InMemDicomObject;
+
+use crate::storescu::{DimseResponse, StatusCategory};
+
+/// Category of a C-STORE status code
+pub fn category(status: u16) -> StatusCategory {
+    match status {
+        0x0000 => StatusCategory::Success,
+        0x0001 | 0x0107 | 0x0116 | 0xB000..=0xBFFF => StatusCategory::Warning,
+        0xFE00 => StatusCategory::Cancel,
+        0xFF00 | 0xFF01 => StatusCategory::Pending,
+        _ => StatusCategory::Failure,
+    }
+}
+
+/// Whether a failure status may clear up by itself, so the request is worth sending again
+pub fn is_transient(status: u16) -> bool {
+    matches!(status, 0xA700..=0xA7FF)
+}
+
+/// Meaning of a C-STORE status code
+pub fn meaning(status: u16) -> &'static str {
+    match status {
+        0x0000 => "Success",
+        0x0001 => "Requested optional Attributes are not supported",
+        0x0107 => "Attribute list error",
+        0x0116 => "Attribute Value out of range",
+        0xB000 => "Coercion of Data Elements",
+        0xB006 => "Elements Discarded",
+        0xB007 => "Data Set does not match SOP Class",
+        0xA700..=0xA7FF => "Refused: Out of Resources",
+        0xA900..=0xA9FF => "Error: Data Set does not match SOP Class",
+        0xC000..=0xCFFF => "Error: Cannot understand",
+        0x0110 => "Processing failure",
+        0x0111 => "Duplicate SOP Instance",
+        0x0117 => "Invalid SOP Instance",
+        0x0122 => "Refused: SOP Class not supported",
+        0x0124 => "Refused: Not authorized",
+        0x0210 => "Duplicate invocation",
+        0x0211 => "Unrecognized operation",
+        0x0212 => "Mistyped argument",
+        0x0213 => "Resource limitation",
+        0xFE00 => "Cancel",
+        0xFF00 | 0xFF01 => "Pending",
+        _ if category(status) == StatusCategory::Warning => "Warning",
+        _ => "Unknown failure",
+    }
+}
+
+/// Decode a response command with the given status
+pub fn decode(status: u16, cmd_obj: &InMemDicomObject) -> DimseResponse {
+    let error_comment = cmd_obj
+        .element(tags::ERROR_COMMENT)
+        .ok()
+        .and_then(|e| e.to_str().ok())
+        .map(|v| v.trim_end_matches(['\0', ' ']).to_string())
+        .filter(|v| !v.is_empty());
+    let offending_elements = cmd_obj.element(tags::OFFENDING_ELEMENT).ok().map(|e| match e.value().primitive() {
+        Some(PrimitiveValue::Tags(values)) => values
+            .iter()
+            .map(|tag| {
+                StandardDataDictionary
+                    .by_tag(*tag)
+                    .map(|entry| entry.alias().to_string())
+                    .unwrap_or_else(|| format!("{:04X}{:04X}", tag.0, tag.1))
+            })
+            .collect(),
+        _ => e.to_str().map(|v| vec![v.to_string()]).unwrap_or_default(),
+    });
+    let error_id = cmd_obj.element(tags::ERROR_ID).ok().and_then(|e| e.to_int::<u16>().ok());
+
+    DimseResponse {
+        status: status as u32,
+        category: category(status),
+        meaning: meaning(status).to_string(),
+        error_comment,
+        offending_elements,
+        error_id: error_id.map(u32::from),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom_core::{dicom_value, DataElement, Tag, VR};
+
+    #[test]
+    fn test_decode() {
+        let cmd_obj = InMemDicomObject::from_element_iter([
+            DataElement::new(tags::STATUS, VR::US, dicom_value!(U16, [0xB000])),
+            DataElement::new(
+                tags::OFFENDING_ELEMENT,
+                VR::AT,
+                PrimitiveValue::Tags([tags::PATIENT_ID, Tag(0x0009, 0x1001)].into()),
+            ),
+            DataElement::new(tags::ERROR_COMMENT, VR::LO, PrimitiveValue::from("Patient ID coerced ")),
+        ]);
+        let response = decode(0xB000, &cmd_obj);
+        assert_eq!(response.category, StatusCategory::Warning);
+        assert_eq!(response.meaning, "Coercion of Data Elements");
+        assert_eq!(response.error_comment.as_deref(), Some("Patient ID coerced"));
+        assert_eq!(response.offending_elements, Some(vec!["PatientID".to_string(), "00091001".to_string()]));
+        assert_eq!(response.error_id, None);
+
+        assert_eq!(category(0xA701), StatusCategory::Failure);
+        assert_eq!(meaning(0xA701), "Refused: Out of Resources");
+        assert!(is_transient(0xA701));
+        assert!(!is_transient(0x0122) && !is_transient(0xA900) && !is_transient(0xC000));
+        assert_eq!(category(0xB007), StatusCategory::Warning);
+        assert_eq!(category(0xFE00), StatusCategory::Cancel);
+    }
+}
@@ -1,24 +1,31 @@
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
+use std::time::Duration;
 
 use dicom_dictionary_std::tags;
 use dicom_encoding::TransferSyntaxIndex;
 use dicom_object::{open_file, FileDicomObject, InMemDicomObject};
 use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
-use dicom_ul::{
-    pdu::{PDataValue, PDataValueType},
-    ClientAssociation, Pdu,
-};
+use dicom_ul::{pdu::{PDataValue, PresentationContextNegotiated}, ClientAssociation, Pdu};
 use indicatif::ProgressBar;
+use napi::bindgen_prelude::Promise;
 use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode};
 use snafu::{OptionExt, Report, ResultExt};
-use tokio::{io::AsyncWriteExt, net::TcpStream, sync::Mutex};
+use tokio::{net::TcpStream, sync::{watch, Mutex}};
 use tracing::{debug, error, info, warn};
 
+use crate::findscu::{receive_message, send_encoded_message, DimseAssociation};
+use crate::getscu::association::RoleSelectingAssociation;
+use crate::storage::{envelope, StorageBackend};
+use crate::utils::dataset::read_stored_object;
+use crate::storescu::manifest::{Manifest, ManifestStatus};
+use crate::storescu::rules::{self, SendRules};
+use crate::storescu::status;
 use crate::storescu::{
-    check_presentation_contexts, into_ts, store_req_command, ConvertFieldSnafu, CreateCommandSnafu,
-    DicomFile, Error, FileSendingEvent, FileSendingData, FileSentEvent, FileSentData, 
-    FileErrorEvent, FileErrorData, FileSource, MissingAttributeSnafu, 
-    ReadDatasetSnafu, ReadFilePathSnafu, ScuSnafu, StoreScu, UnsupportedFileTransferSyntaxSnafu, WriteDatasetSnafu,
+    check_presentation_contexts, into_ts, is_dataset_only, store_req_command, ConvertFieldSnafu, CreateCommandSnafu,
+    CancelMode, DicomFile, DimseResponse, Error, FileResult, FileResultStatus, FileSendingEvent, FileSendingData, FileSentEvent, FileSentData, 
+    FileErrorEvent, FileErrorData, FileSkippedEvent, FileSkippedData, FileSource, StatusCategory, MissingAttributeSnafu, 
+    ReadFilePathSnafu, StoreScu, TransferSyntaxPolicy, UnsupportedFileTransferSyntaxSnafu, WriteDatasetSnafu,
 };
 
 #[derive(Clone)]
@@ -26,31 +33,342 @@ pub struct StoreCallbacks {
     pub on_file_sending: Option<Arc<ThreadsafeFunction<FileSendingEvent, ()>>>,
     pub on_file_sent: Option<Arc<ThreadsafeFunction<FileSentEvent, ()>>>,
     pub on_file_error: Option<Arc<ThreadsafeFunction<FileErrorEvent, ()>>>,
+    pub on_file_skipped: Option<Arc<ThreadsafeFunction<FileSkippedEvent, ()>>>,
+    pub on_before_send: Option<Arc<ThreadsafeFunction<String, Promise<Option<String>>>>>,
+}
+
+/// Cancellation of a transfer, requested by `StoreScu.cancel()`
+#[derive(Clone)]
+pub struct Cancellation(watch::Receiver<Option<CancelMode>>);
+
+impl Cancellation {
+    pub fn new(receiver: watch::Receiver<Option<CancelMode>>) -> Self {
+        Cancellation(receiver)
+    }
+
+    pub fn mode(&self) -> Option<CancelMode> {
+        *self.0.borrow()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.mode().is_some()
+    }
+
+    /// Resolves once the transfer is cancelled with the given mode, or any mode if None
+    pub async fn cancelled(&self, mode: Option<CancelMode>) {
+        let mut receiver = self.0.clone();
+        let closed = receiver
+            .wait_for(|m| m.is_some() && (mode.is_none() || *m == mode))
+            .await
+            .is_err();
+        if closed {
+            // the StoreScu is gone, nobody can cancel anymore
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+impl Default for Cancellation {
+    /// A transfer that is never cancelled
+    fn default() -> Self {
+        Cancellation(watch::channel(None).1)
+    }
+}
+
+/// Outcome of the files of a transfer, shared by all associations
+#[derive(Default)]
+pub struct TransferOutcomes {
+    pub successful: Mutex<u32>,
+    pub warning: Mutex<u32>,
+    pub failed: Mutex<u32>,
+    pub skipped: Mutex<u32>,
+    /// Files that failed in this pass and may be sent again
+    pub retries: Mutex<Vec<DicomFile>>,
+    pub manifest: Option<Manifest>,
+    /// Last outcome of each file, keyed by SOP Instance UID
+    pub results: Mutex<HashMap<String, FileResult>>,
+    pub cancellation: Cancellation,
+}
+
+impl TransferOutcomes {
+    /// Record the outcome of a file in the results and in the manifest, if any
+    pub async fn record(
+        &self,
+        file: &DicomFile,
+        status: FileResultStatus,
+        response: Option<&DimseResponse>,
+        error: Option<String>,
+    ) {
+        let file_name = file.source.display_name();
+        let sop_instance_uid = file.sop_instance_uid.trim_end_matches(['\0', ' ']).to_string();
+        if let Some(manifest) = &self.manifest {
+            let manifest_status = match status {
+                FileResultStatus::Sent | FileResultStatus::Warning => ManifestStatus::Sent,
+                FileResultStatus::Failed => ManifestStatus::Failed,
+                FileResultStatus::Skipped => ManifestStatus::Skipped,
+                // sent again when the transfer is resumed
+                FileResultStatus::Cancelled => ManifestStatus::Pending,
+            };
+            let dimse_status = response.map(|r| r.status as u16);
+            manifest.record(&file_name, &sop_instance_uid, manifest_status, dimse_status, error.clone());
+        }
+        self.results.lock().await.insert(
+            sop_instance_uid,
+            FileResult {
+                file: file_name,
+                sop_class_uid: file.sop_class_uid.clone(),
+                status,
+                response: response.cloned(),
+                error,
+            },
+        );
+    }
+}
+
+/// Association of a worker: negotiated by dicom-ul, or by hand to propose the
+/// Asynchronous Operations Window
+pub enum StoreAssociation {
+    Standard(ClientAssociation<TcpStream>),
+    Windowed {
+        scu: RoleSelectingAssociation,
+        /// Maximum number of outstanding C-STORE requests proposed
+        requested: u16,
+    },
+}
+
+impl StoreAssociation {
+    pub fn presentation_contexts(&self) -> &[PresentationContextNegotiated] {
+        match self {
+            StoreAssociation::Standard(scu) => scu.presentation_contexts(),
+            StoreAssociation::Windowed { scu, .. } => scu.presentation_contexts(),
+        }
+    }
+
+    /// Maximum number of outstanding C-STORE requests
+    pub fn window(&self) -> usize {
+        match self {
+            StoreAssociation::Standard(_) => 1,
+            StoreAssociation::Windowed { scu, requested } => negotiated_window(*requested, scu.async_operations_window()),
+        }
+    }
+
+    pub async fn release(self) {
+        let result = match self {
+            StoreAssociation::Standard(scu) => scu.release().await.map_err(|e| e.to_string()),
+            StoreAssociation::Windowed { scu, .. } => scu.release().await,
+        };
+        if let Err(e) = result {
+            warn!("Failed to release association: {}", e);
+        }
+    }
+
+    pub async fn abort(self) {
+        let _ = match self {
+            StoreAssociation::Standard(scu) => scu.abort().await.map_err(|e| e.to_string()),
+            StoreAssociation::Windowed { scu, .. } => scu.abort().await,
+        };
+    }
 }
 
-pub async fn send_file(
-    mut scu: ClientAssociation<TcpStream>,
+impl DimseAssociation for StoreAssociation {
+    async fn send(&mut self, pdu: &Pdu) -> Result<(), String> {
+        match self {
+            StoreAssociation::Standard(scu) => DimseAssociation::send(scu, pdu).await,
+            StoreAssociation::Windowed { scu, .. } => scu.send(pdu).await,
+        }
+    }
+
+    async fn receive(&mut self) -> Result<Pdu, String> {
+        match self {
+            StoreAssociation::Standard(scu) => DimseAssociation::receive(scu).await,
+            StoreAssociation::Windowed { scu, .. } => scu.receive().await,
+        }
+    }
+
+    fn acceptor_max_pdu_length(&self) -> u32 {
+        match self {
+            StoreAssociation::Standard(scu) => scu.acceptor_max_pdu_length(),
+            StoreAssociation::Windowed { scu, .. } => DimseAssociation::acceptor_max_pdu_length(scu),
+        }
+    }
+
+    fn received_values(&mut self) -> Option<&mut VecDeque<PDataValue>> {
+        match self {
+            // a single request is outstanding, no other response can follow its own
+            StoreAssociation::Standard(_) => None,
+            StoreAssociation::Windowed { scu, .. } => scu.received_values(),
+        }
+    }
+}
+
+/// Number of C-STORE requests that may be outstanding, given the window accepted by the SCP.
+/// SCPs differ in which of the two accepted values limits the requestor, so the smaller one
+/// is used (0 means unlimited). Without an accepted window requests are synchronous.
+fn negotiated_window(requested: u16, accepted: Option<(u16, u16)>) -> usize {
+    let Some((invoked, performed)) = accepted else {
+        return 1;
+    };
+    [invoked, performed]
+        .into_iter()
+        .filter(|&n| n != 0)
+        .fold(requested, u16::min)
+        .max(1) as usize
+}
+
+/// A C-STORE request waiting for its response
+struct PendingStore {
     file: DicomFile,
-    s3_bucket: Option<&s3::Bucket>,
-    message_id: u16,
-    progress_bar: Option<&Arc<tokio::sync::Mutex<ProgressBar>>>,
+    /// Transfer syntax the data set was sent in
+    transfer_syntax: String,
+    coerced_tags: HashMap<String, String>,
+    start_time: std::time::Instant,
+}
+
+/// What `inner` passes down to the handling of each file
+struct Worker<'a> {
+    storage: Option<&'a dyn StorageBackend>,
+    progress_bar: Option<&'a Arc<Mutex<ProgressBar>>>,
     verbose: bool,
-    fail_first: bool,
-    callbacks: &StoreCallbacks,
-    successful_count: Arc<Mutex<u32>>,
-    failed_count: Arc<Mutex<u32>>,
-) -> Result<ClientAssociation<TcpStream>, Error>
-{
-    let start_time = std::time::Instant::now();
-    
-    if let (Some(pc_selected), Some(ts_uid_selected)) = (file.pc_selected, file.ts_selected) {
-        // Emit OnFileSending event
-        let file_path = match &file.source {
-            FileSource::Local(path) => path.display().to_string(),
-            FileSource::S3(key) => format!("s3://{}", key),
+    callbacks: &'a StoreCallbacks,
+    rules: &'a SendRules,
+    outcomes: &'a TransferOutcomes,
+}
+
+/// What to do with a data set after the filter, the coercions and onBeforeSend
+enum Disposition {
+    /// Send it, with the tags that were changed
+    Send(HashMap<String, String>),
+    /// Do not send it, for this reason ("filter" or "onBeforeSend")
+    Skip(&'static str),
+    /// onBeforeSend failed, the file is counted as failed
+    Reject(String),
+}
+
+impl Worker<'_> {
+    async fn inc_progress(&self) {
+        if let Some(pb) = self.progress_bar.as_ref() {
+            pb.lock().await.inc(1)
+        };
+    }
+
+    /// Load the file, apply the send rules and send its C-STORE request.
+    /// Returns None if the file is not sent, its outcome being already reported.
+    async fn send_file(
+        &self,
+        scu: &mut StoreAssociation,
+        file: &DicomFile,
+        message_id: u16,
+    ) -> Result<Option<PendingStore>, Error> {
+        let start_time = std::time::Instant::now();
+        let (Some(pc_selected), Some(ts_uid_selected)) = (file.pc_selected.clone(), file.ts_selected.clone()) else {
+            self.inc_progress().await;
+            return Ok(None);
+        };
+        let file_path = file.source.display_name();
+
+        // Load DICOM file from source (local filesystem or S3)
+        let mut dicom_file: FileDicomObject<InMemDicomObject> = match &file.source {
+            FileSource::Local(path) if !envelope::is_encoded_file(path) && !is_dataset_only(path) => {
+                open_file(path)
+                    .map_err(Box::from)
+                    .context(ReadFilePathSnafu {
+                        path: path.display().to_string(),
+                    })?
+            }
+            source => {
+                let data = match source {
+                    // Compressed or encrypted file written by the filesystem storage
+                    FileSource::Local(path) if envelope::is_encoded_file(path) => match envelope::read_encoded_file(path).await {
+                        Ok(d) => d,
+                        Err(e) => {
+                            return Err(Error::ReadFilePath {
+                                path: path.display().to_string(),
+                                source: Box::new(dicom_object::ReadError::ReadFile {
+                                    filename: path.clone(),
+                                    source: std::io::Error::other(e.to_string()),
+                                    backtrace: std::backtrace::Backtrace::capture(),
+                                }),
+                            });
+                        }
+                    },
+                    // Data set written without file meta group
+                    FileSource::Local(path) => match tokio::fs::read(path).await {
+                        Ok(d) => d,
+                        Err(e) => {
+                            return Err(Error::ReadFilePath {
+                                path: path.display().to_string(),
+                                source: Box::new(dicom_object::ReadError::ReadFile {
+                                    filename: path.clone(),
+                                    source: e,
+                                    backtrace: std::backtrace::Backtrace::capture(),
+                                }),
+                            });
+                        }
+                    },
+                    FileSource::Memory { data, .. } => data.to_vec(),
+                    FileSource::S3(key) => {
+                        // Download S3 file on-demand to minimize memory usage
+                        let storage = self.storage.expect("S3 storage should be available for S3 files");
+                        let s3_result = storage.read_file(key).await;
+                        match s3_result {
+                            Ok(d) => d,
+                            Err(e) => {
+                                return Err(Error::ReadFilePath {
+                                    path: format!("s3://{}", key),
+                                    source: Box::new(dicom_object::ReadError::ReadFile {
+                                        filename: format!("s3://{}", key).into(),
+                                        source: std::io::Error::other(format!(
+                                            "Failed to download S3 file for sending: {}: {}",
+                                            key, e
+                                        )),
+                                        backtrace: std::backtrace::Backtrace::capture(),
+                                    }),
+                                });
+                            }
+                        }
+                    }
+                };
+
+                // Full DICOM file, or data set with the transfer syntax inferred from its content
+                read_stored_object(&data).map_err(|message| Error::ReadStoredObject {
+                    path: file_path.clone(),
+                    message,
+                })?
+            }
         };
         
-        if let Some(cb) = &callbacks.on_file_sending {
+        let coerced_tags = match apply_send_rules(&mut dicom_file, self.rules, self.callbacks).await {
+            Disposition::Send(changed) => changed,
+            Disposition::Skip(reason) => {
+                if self.verbose {
+                    info!("Skipping file {} ({})", file_path, reason);
+                }
+                *self.outcomes.skipped.lock().await += 1;
+                self.outcomes.record(file, FileResultStatus::Skipped, None, Some(reason.to_string())).await;
+                if let Some(cb) = &self.callbacks.on_file_skipped {
+                    cb.call(Ok(FileSkippedEvent {
+                        message: "File skipped".to_string(),
+                        data: Some(FileSkippedData {
+                            file: file_path,
+                            sop_instance_uid: file.sop_instance_uid.clone(),
+                            sop_class_uid: file.sop_class_uid.clone(),
+                            reason: reason.to_string(),
+                        }),
+                    }), ThreadsafeFunctionCallMode::NonBlocking);
+                }
+                self.inc_progress().await;
+                return Ok(None);
+            }
+            Disposition::Reject(error) => {
+                error!("{}: {}", file_path, error);
+                report_file_error(file, "Rejected by onBeforeSend", error, None, false, self.callbacks, self.outcomes).await;
+                self.inc_progress().await;
+                return Ok(None);
+            }
+        };
+
+        // Emit OnFileSending event
+        if let Some(cb) = &self.callbacks.on_file_sending {
             cb.call(Ok(FileSendingEvent {
                 message: "Sending file".to_string(),
                 data: Some(FileSendingData {
@@ -72,89 +390,6 @@ pub async fn send_file(
 
         let mut object_data = Vec::with_capacity(2048);
         
-        // Load DICOM file from source (local filesystem or S3)
-        let dicom_file: FileDicomObject<InMemDicomObject> = match &file.source {
-            FileSource::Local(path) => {
-                open_file(path)
-                    .map_err(Box::from)
-                    .context(ReadFilePathSnafu {
-                        path: path.display().to_string(),
-                    })?
-            }
-            FileSource::S3(key) => {
-                // Download S3 file on-demand to minimize memory usage
-                use crate::utils::s3_get_object;
-                let bucket = s3_bucket.expect("S3 bucket should be available for S3 files");
-                let s3_result = s3_get_object(bucket, key).await;
-                let data = match s3_result {
-                    Ok(d) => d,
-                    Err(_e) => {
-                        return Err(Error::ReadFilePath {
-                            path: format!("s3://{}", key),
-                            source: Box::new(dicom_object::ReadError::ReadFile {
-                                filename: format!("s3://{}", key).into(),
-                                source: std::io::Error::new(
-                                    std::io::ErrorKind::Other,
-                                    format!("Failed to download S3 file for sending: {}", key),
-                                ),
-                                backtrace: std::backtrace::Backtrace::capture(),
-                            }),
-                        });
-                    }
-                };
-                
-                // Auto-detect file format by checking for DICM magic bytes
-                let has_dicm_magic = data.len() > 132 && &data[128..132] == b"DICM";
-                
-                if !has_dicm_magic {
-                    // Dataset-only file (no DICOM meta header) - read as InMemDicomObject and create meta
-                    let obj = InMemDicomObject::read_dataset_with_ts(
-                        &data[..],
-                        &dicom_transfer_syntax_registry::entries::EXPLICIT_VR_LITTLE_ENDIAN.erased(),
-                    )
-                    .or_else(|_| {
-                        InMemDicomObject::read_dataset_with_ts(
-                            &data[..],
-                            &dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN.erased(),
-                        )
-                    })
-                    .context(ReadDatasetSnafu)?;
-                    
-                    // Create file meta information from dataset attributes
-                    use dicom_dictionary_std::tags;
-                    use dicom_object::FileMetaTableBuilder;
-                    
-                    let sop_class_uid = obj.element(tags::SOP_CLASS_UID)
-                        .context(MissingAttributeSnafu { tag: tags::SOP_CLASS_UID })?
-                        .to_str()
-                        .context(ConvertFieldSnafu { tag: tags::SOP_CLASS_UID })?
-                        .trim()
-                        .to_string();
-                    let sop_instance_uid = obj.element(tags::SOP_INSTANCE_UID)
-                        .context(MissingAttributeSnafu { tag: tags::SOP_INSTANCE_UID })?
-                        .to_str()
-                        .context(ConvertFieldSnafu { tag: tags::SOP_INSTANCE_UID })?
-                        .trim()
-                        .to_string();
-                    
-                    let meta = FileMetaTableBuilder::new()
-                        .media_storage_sop_class_uid(&sop_class_uid)
-                        .media_storage_sop_instance_uid(&sop_instance_uid)
-                        .transfer_syntax(dicom_transfer_syntax_registry::entries::EXPLICIT_VR_LITTLE_ENDIAN.uid())
-                        .build()
-                        .map_err(|e| Error::ReadDataset { 
-                            source: dicom_object::ReadError::ParseMetaDataSet { source: e } 
-                        })?;
-                    
-                    obj.with_exact_meta(meta)
-                } else {
-                    // Full DICOM file with meta header
-                    dicom_object::from_reader(&data[..])
-                        .context(ReadDatasetSnafu)?
-                }
-            }
-        };
-        
         let ts_selected = TransferSyntaxRegistry
             .get(&ts_uid_selected)
             .with_context(|| UnsupportedFileTransferSyntaxSnafu {
@@ -162,7 +397,7 @@ pub async fn send_file(
             })?;
 
         // transcode file if necessary
-        let dicom_file = into_ts(dicom_file, ts_selected, verbose)?;
+        let dicom_file = into_ts(dicom_file, ts_selected, self.verbose)?;
 
         dicom_file
             .write_dataset_with_ts(&mut object_data, ts_selected)
@@ -171,11 +406,8 @@ pub async fn send_file(
 
         let nbytes = cmd_data.len() + object_data.len();
 
-        if verbose {
-            let source_display = match &file.source {
-                FileSource::Local(path) => path.display().to_string(),
-                FileSource::S3(key) => format!("s3://{}", key),
-            };
+        if self.verbose {
+            let source_display = file.source.display_name();
             info!(
                 "Sending file {} (~ {} kB), uid={}, sop={}, ts={}",
                 source_display,
@@ -186,241 +418,568 @@ pub async fn send_file(
             );
         }
 
-        if nbytes < scu.acceptor_max_pdu_length().saturating_sub(100) as usize {
-            let pdu = Pdu::PData {
-                data: vec![
-                    PDataValue {
-                        presentation_context_id: pc_selected.id,
-                        value_type: PDataValueType::Command,
-                        is_last: true,
-                        data: cmd_data,
-                    },
-                    PDataValue {
-                        presentation_context_id: pc_selected.id,
-                        value_type: PDataValueType::Data,
-                        is_last: true,
-                        data: object_data,
-                    },
-                ],
-            };
-
-            scu.send(&pdu).await.map_err(Box::from).context(ScuSnafu)?;
-        } else {
-            let pdu = Pdu::PData {
-                data: vec![PDataValue {
-                    presentation_context_id: pc_selected.id,
-                    value_type: PDataValueType::Command,
-                    is_last: true,
-                    data: cmd_data,
-                }],
-            };
+        send_encoded_message(scu, pc_selected.id, cmd_data, Some(object_data))
+            .await
+            .map_err(|message| Error::Association { message })?;
 
-            scu.send(&pdu).await.map_err(Box::from).context(ScuSnafu)?;
+        Ok(Some(PendingStore {
+            file: file.clone(),
+            transfer_syntax: ts_uid_selected,
+            coerced_tags,
+            start_time,
+        }))
+    }
 
-            {
-                let mut pdata = scu.send_pdata(pc_selected.id).await;
-                pdata.write_all(&object_data).await.unwrap();
-                //.whatever_context("Failed to send C-STORE-RQ P-Data")?;
-            }
+    /// Report the outcome of a C-STORE from its response. Returns true if the file failed.
+    async fn complete(&self, request: PendingStore, cmd_obj: &InMemDicomObject) -> bool {
+        let file = &request.file;
+        self.inc_progress().await;
+        if self.verbose {
+            debug!("Full response: {:?}", cmd_obj);
         }
+        let status = match cmd_obj
+            .element(tags::STATUS)
+            .context(MissingAttributeSnafu { tag: tags::STATUS })
+            .and_then(|e| e.to_int::<u16>().context(ConvertFieldSnafu { tag: tags::STATUS }))
+        {
+            Ok(status) => status,
+            Err(e) => {
+                let error = Report::from_error(e).to_string();
+                error!("{}", error);
+                report_file_error(file, "Invalid C-STORE response", error, None, false, self.callbacks, self.outcomes).await;
+                return true;
+            }
+        };
+        let response = status::decode(status, cmd_obj);
+        let storage_sop_instance_uid = file
+            .sop_instance_uid
+            .trim_end_matches(|c: char| c.is_whitespace() || c == '\0');
 
-        if verbose {
-            debug!("Awaiting response...");
-        }
-
-        let rsp_pdu = scu.receive().await.map_err(Box::from).context(ScuSnafu)?;
-
-        match rsp_pdu {
-            Pdu::PData { data } => {
-                let data_value = &data[0];
-
-                let cmd_obj = InMemDicomObject::read_dataset_with_ts(
-                    &data_value.data[..],
-                    &dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN.erased(),
-                )
-                .context(ReadDatasetSnafu)?;
-                if verbose {
-                    debug!("Full response: {:?}", cmd_obj);
-                }
-                let status = cmd_obj
-                    .element(tags::STATUS)
-                    .context(MissingAttributeSnafu { tag: tags::STATUS })?
-                    .to_int::<u16>()
-                    .context(ConvertFieldSnafu { tag: tags::STATUS })?;
-                let storage_sop_instance_uid = file
-                    .sop_instance_uid
-                    .trim_end_matches(|c: char| c.is_whitespace() || c == '\0');
-
-                match status {
-                    // Success
-                    0 => {
-                        let elapsed = start_time.elapsed();
-                        if verbose {
-                            info!(
-                                "Successfully stored instance {} in {:.2}s",
-                                storage_sop_instance_uid,
-                                elapsed.as_secs_f64()
-                            );
-                        }
-                        
-                        // Increment successful count
-                        *successful_count.lock().await += 1;
-                        
-                        // Emit OnFileSent event
-                        let file_path = match &file.source {
-                            FileSource::Local(path) => path.display().to_string(),
-                            FileSource::S3(key) => format!("s3://{}", key),
-                        };
-                        
-                        if let Some(cb) = &callbacks.on_file_sent {
-                            cb.call(Ok(FileSentEvent {
-                                message: "File sent successfully".to_string(),
-                                data: Some(FileSentData {
-                                    file: file_path.clone(),
-                                    sop_instance_uid: file.sop_instance_uid.clone(),
-                                    sop_class_uid: file.sop_class_uid.clone(),
-                                    transfer_syntax: ts_uid_selected.to_string(),
-                                    duration_seconds: elapsed.as_secs_f64(),
-                                }),
-                            }), ThreadsafeFunctionCallMode::NonBlocking);
-                        }
-                    }
-                    // Warning
-                    1 | 0x0107 | 0x0116 | 0xB000..=0xBFFF => {
-                        warn!(
-                            "Possible issue storing instance `{}` (status code {:04X}H)",
-                            storage_sop_instance_uid, status
-                        );
-                    }
-                    0xFF00 | 0xFF01 => {
-                        warn!(
-                            "Possible issue storing instance `{}`: status is pending (status code {:04X}H)",
-                            storage_sop_instance_uid, status
-                        );
-                    }
-                    0xFE00 => {
-                        error!(
-                            "Could not store instance `{}`: operation cancelled",
-                            storage_sop_instance_uid
+        match response.category {
+            StatusCategory::Success | StatusCategory::Warning => {
+                let elapsed = request.start_time.elapsed();
+                let result_status = if response.category == StatusCategory::Success {
+                    if self.verbose {
+                        info!(
+                            "Successfully stored instance {} in {:.2}s",
+                            storage_sop_instance_uid,
+                            elapsed.as_secs_f64()
                         );
-                        if fail_first {
-                            let _ = scu.abort().await;
-                            std::process::exit(-2);
-                        }
-                    }
-                    _ => {
-                        let elapsed = start_time.elapsed();
-                        error!(
-                            "Failed to store instance `{}` (status code {:04X}H)",
-                            storage_sop_instance_uid, status
-                        );
-                        
-                        // Increment failed count
-                        *failed_count.lock().await += 1;
-                        
-                        // Emit OnFileError event
-                        let file_path = match &file.source {
-                            FileSource::Local(path) => path.display().to_string(),
-                            FileSource::S3(key) => format!("s3://{}", key),
-                        };
-                        
-                        if let Some(cb) = &callbacks.on_file_error {
-                            cb.call(Ok(FileErrorEvent {
-                                message: format!("Failed to store file (status code {:04X}H)", status),
-                                data: Some(FileErrorData {
-                                    file: file_path,
-                                    error: format!("Status code {:04X}H", status),
-                                    sop_instance_uid: Some(storage_sop_instance_uid.to_string()),
-                                    sop_class_uid: Some(file.sop_class_uid.clone()),
-                                    file_transfer_syntax: Some(file.file_transfer_syntax.clone()),
-                                }),
-                            }), ThreadsafeFunctionCallMode::NonBlocking);
-                        }
-                        
-                        if fail_first {
-                            let _ = scu.abort().await;
-                            std::process::exit(-2);
-                        }
                     }
+                    *self.outcomes.successful.lock().await += 1;
+                    FileResultStatus::Sent
+                } else {
+                    warn!(
+                        "Possible issue storing instance `{}`: {} (status code {:04X}H)",
+                        storage_sop_instance_uid, response.meaning, status
+                    );
+                    *self.outcomes.warning.lock().await += 1;
+                    FileResultStatus::Warning
+                };
+                self.outcomes.record(file, result_status, Some(&response), None).await;
+                
+                // Emit OnFileSent event
+                let file_path = file.source.display_name();
+                
+                if let Some(cb) = &self.callbacks.on_file_sent {
+                    cb.call(Ok(FileSentEvent {
+                        message: if result_status == FileResultStatus::Sent {
+                            "File sent successfully".to_string()
+                        } else {
+                            format!("File sent with warning: {}", response.meaning)
+                        },
+                        data: Some(FileSentData {
+                            file: file_path.clone(),
+                            sop_instance_uid: file.sop_instance_uid.clone(),
+                            sop_class_uid: file.sop_class_uid.clone(),
+                            transfer_syntax: request.transfer_syntax.to_string(),
+                            file_transfer_syntax: file.file_transfer_syntax.clone(),
+                            transcoded: request.transfer_syntax != file.file_transfer_syntax,
+                            coerced_tags: (!request.coerced_tags.is_empty()).then_some(request.coerced_tags),
+                            response,
+                            duration_seconds: elapsed.as_secs_f64(),
+                        }),
+                    }), ThreadsafeFunctionCallMode::NonBlocking);
                 }
+                false
             }
+            // Cancel and pending are not valid final C-STORE statuses either
+            StatusCategory::Failure | StatusCategory::Cancel | StatusCategory::Pending => {
+                error!(
+                    "Failed to store instance `{}`: {} (status code {:04X}H)",
+                    storage_sop_instance_uid, response.meaning, status
+                );
+                let error = match &response.error_comment {
+                    Some(comment) => format!("{} (status code {:04X}H): {}", response.meaning, status, comment),
+                    None => format!("{} (status code {:04X}H)", response.meaning, status),
+                };
+                // a refusal is final, only an SCP out of resources may accept the file later
+                report_file_error(
+                    file,
+                    "Failed to store file",
+                    error,
+                    Some(response),
+                    status::is_transient(status),
+                    self.callbacks,
+                    self.outcomes,
+                )
+                .await;
 
-            pdu @ Pdu::Unknown { .. }
-            | pdu @ Pdu::AssociationRQ { .. }
-            | pdu @ Pdu::AssociationAC { .. }
-            | pdu @ Pdu::AssociationRJ { .. }
-            | pdu @ Pdu::ReleaseRQ
-            | pdu @ Pdu::ReleaseRP
-            | pdu @ Pdu::AbortRQ { .. } => {
-                error!("Unexpected SCP response: {:?}", pdu);
-                let _ = scu.abort().await;
-                std::process::exit(-2);
+                true
             }
         }
     }
-    if let Some(pb) = progress_bar.as_ref() {
-        pb.lock().await.inc(1)
-    };
-    Ok(scu)
+
+    /// Report the requests still waiting for a response on a lost association as failed
+    async fn fail_pending(&self, pending: HashMap<u16, PendingStore>, error: &Error) {
+        for request in pending.into_values() {
+            let error = Report::from_error(error).to_string();
+            report_file_error(&request.file, "Failed to send file", error, None, true, self.callbacks, self.outcomes).await;
+            self.inc_progress().await;
+        }
+    }
+}
+
+/// Take the request a response is for from the outstanding ones, by its Message ID Being Responded To
+fn responded_request(
+    pending: &mut HashMap<u16, PendingStore>,
+    command: &InMemDicomObject,
+    window: usize,
+) -> Option<PendingStore> {
+    let responded_to = command
+        .element(tags::MESSAGE_ID_BEING_RESPONDED_TO)
+        .ok()
+        .and_then(|e| e.to_int::<u16>().ok());
+    match responded_to.and_then(|id| pending.remove(&id)) {
+        Some(request) => Some(request),
+        // without a window there is only one request the response can be for
+        None if window == 1 => pending.drain().next().map(|(_, request)| request),
+        None => {
+            warn!("Ignoring response to unknown message ID {:?}", responded_to);
+            None
+        }
+    }
 }
 
+/// Send the files of `d_files` over the association, keeping as many C-STORE requests
+/// outstanding as the negotiated window allows (one at a time without it)
 pub async fn inner(
-    mut scu: ClientAssociation<TcpStream>,
+    mut scu: StoreAssociation,
     d_files: Arc<Mutex<Vec<DicomFile>>>,
-    s3_bucket: Option<Arc<s3::Bucket>>,
+    storage: Option<Arc<dyn StorageBackend>>,
     progress_bar: Option<&Arc<tokio::sync::Mutex<ProgressBar>>>,
     fail_first: bool,
     verbose: bool,
-    never_transcode: bool,
+    policy: &TransferSyntaxPolicy,
     ignore_sop_class: bool,
     callbacks: &StoreCallbacks,
-    successful_count: Arc<Mutex<u32>>,
-    failed_count: Arc<Mutex<u32>>,
+    rules: &SendRules,
+    outcomes: &TransferOutcomes,
 ) -> Result<(), Error>
 {
-    let mut message_id = 1;
+    let worker = Worker {
+        storage: storage.as_deref(),
+        progress_bar,
+        verbose,
+        callbacks,
+        rules,
+        outcomes,
+    };
+    let window = scu.window();
+    if verbose {
+        if let StoreAssociation::Windowed { requested, .. } = &scu {
+            info!("Asynchronous operations window: {} (requested {})", window, requested);
+        }
+    }
+    // requests waiting for their response, by message ID
+    let mut pending: HashMap<u16, PendingStore> = HashMap::new();
+    let mut message_id: u16 = 1;
     loop {
-        let file = {
-            let mut files = d_files.lock().await;
-            files.pop()
-        };
-        let mut file = match file {
-            Some(file) => file,
-            None => break,
-        };
-        let r: Result<_, Error> = check_presentation_contexts(
-            &file,
-            scu.presentation_contexts(),
-            ignore_sop_class,
-            never_transcode,
-        );
-        match r {
-            Ok((pc, ts)) => {
-                if verbose {
-                    let source_display = match &file.source {
-                        FileSource::Local(path) => path.display().to_string(),
-                        FileSource::S3(key) => format!("s3://{}", key),
-                    };
-                    debug!(
-                        "{}: Selected presentation context: {:?}",
-                        source_display,
-                        pc
-                    );
+        // files left in `d_files` when cancelled are reported by the caller
+        while pending.len() < window && !outcomes.cancellation.is_cancelled() {
+            let file = d_files.lock().await.pop();
+            let Some(mut file) = file else {
+                break;
+            };
+            let r: Result<_, Error> = check_presentation_contexts(
+                &file,
+                scu.presentation_contexts(),
+                ignore_sop_class,
+                policy,
+            );
+            match r {
+                Ok((pc, ts)) => {
+                    if verbose {
+                        let source_display = file.source.display_name();
+                        debug!(
+                            "{}: Selected presentation context: {:?}",
+                            source_display,
+                            pc
+                        );
+                    }
+                    file.pc_selected = Some(pc);
+                    file.ts_selected = Some(ts);
+                }
+                Err(e) => {
+                    let error = Report::from_error(e).to_string();
+                    error!("{}", error);
+                    report_file_error(&file, "No accepted presentation context for file", error, None, false, callbacks, outcomes).await;
+                    if fail_first {
+                        let _ = scu.abort().await;
+                        std::process::exit(-2);
+                    }
                 }
-                file.pc_selected = Some(pc);
-                file.ts_selected = Some(ts);
             }
-            Err(e) => {
-                error!("{}", Report::from_error(e));
-                if fail_first {
+            match worker.send_file(&mut scu, &file, message_id).await {
+                Ok(Some(request)) => {
+                    pending.insert(message_id, request);
+                    // message ID 0 is avoided, some SCPs take it as missing
+                    message_id = message_id.checked_add(1).unwrap_or(1);
+                }
+                Ok(None) => {}
+                // the association cannot be used after an error, the caller establishes a new one
+                Err(e @ Error::Association { .. }) => {
+                    let error = Report::from_error(&e).to_string();
+                    report_file_error(&file, "Failed to send file", error, None, true, callbacks, outcomes).await;
+                    worker.inc_progress().await;
+                    worker.fail_pending(pending, &e).await;
                     let _ = scu.abort().await;
-                    std::process::exit(-2);
+                    return Err(e);
+                }
+                // the file could not be read or encoded, the association and the requests in flight are fine;
+                // only a failed S3 download may succeed in a retry
+                Err(e) => {
+                    let error = Report::from_error(&e).to_string();
+                    error!("{}", error);
+                    let retry = matches!(file.source, FileSource::S3(_));
+                    report_file_error(&file, "Failed to send file", error, None, retry, callbacks, outcomes).await;
+                    worker.inc_progress().await;
+                    if fail_first {
+                        let _ = scu.abort().await;
+                        std::process::exit(-2);
+                    }
+                }
+            }
+        }
+        if pending.is_empty() {
+            break;
+        }
+
+        if verbose {
+            debug!("Awaiting response ({} outstanding)...", pending.len());
+        }
+        let message = tokio::select! {
+            message = receive_message(&mut scu) => message,
+            _ = outcomes.cancellation.cancelled(Some(CancelMode::Abort)) => {
+                let _ = scu.abort().await;
+                for request in pending.into_values() {
+                    outcomes.record(&request.file, FileResultStatus::Cancelled, None, Some("Transfer cancelled".to_string())).await;
                 }
+                return Err(Error::Cancelled);
+            }
+        };
+        let message = match message {
+            Ok(message) => message,
+            Err(message) => {
+                let e = Error::Association { message };
+                worker.fail_pending(pending, &e).await;
+                let _ = scu.abort().await;
+                return Err(e);
             }
+        };
+        let Some(request) = responded_request(&mut pending, &message.command, window) else {
+            continue;
+        };
+        if worker.complete(request, &message.command).await && fail_first {
+            let _ = scu.abort().await;
+            std::process::exit(-2);
         }
-        scu = send_file(scu, file, s3_bucket.as_deref(), message_id, progress_bar, verbose, fail_first, callbacks, successful_count.clone(), failed_count.clone()).await?;
-        message_id += 1;
     }
-    let _ = scu.release().await;
+    if outcomes.cancellation.mode() == Some(CancelMode::Abort) {
+        let _ = scu.abort().await;
+    } else {
+        let _ = scu.release().await;
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Apply the filter, then the static coercions, then onBeforeSend, which sees the coerced values
+async fn apply_send_rules(obj: &mut InMemDicomObject, rules: &SendRules, callbacks: &StoreCallbacks) -> Disposition {
+    if !rules.accepts(obj) {
+        return Disposition::Skip("filter");
+    }
+    let (mut changed, errors) = rules.coerce(obj);
+    for e in errors {
+        warn!("coerceTags: {}", e);
+    }
+    if let Some(cb) = &callbacks.on_before_send {
+        let tags_json = serde_json::to_string(&rules::text_attributes(obj)).unwrap_or_default();
+        let result = match cb.call_async(Ok(tags_json)).await {
+            Ok(promise) => promise.await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(None) => return Disposition::Skip("onBeforeSend"),
+            Ok(Some(changes_json)) => {
+                let changes = match serde_json::from_str::<HashMap<String, String>>(&changes_json) {
+                    Ok(changes) => changes,
+                    Err(e) => return Disposition::Reject(format!("onBeforeSend returned invalid JSON: {}", e)),
+                };
+                let (callback_changed, errors) = rules::apply_changes(obj, changes);
+                for e in errors {
+                    warn!("onBeforeSend: {}", e);
+                }
+                changed.extend(callback_changed);
+            }
+            Err(e) => return Disposition::Reject(format!("onBeforeSend failed: {}", e.reason)),
+        }
+    }
+    Disposition::Send(changed)
+}
+
+/// Count a file that could not be sent as failed, record it in the manifest and emit OnFileError.
+/// With `retry`, the file is queued for the next retry pass.
+pub async fn report_file_error(
+    file: &DicomFile,
+    message: &str,
+    error: String,
+    response: Option<DimseResponse>,
+    retry: bool,
+    callbacks: &StoreCallbacks,
+    outcomes: &TransferOutcomes,
+) {
+    *outcomes.failed.lock().await += 1;
+    outcomes.record(file, FileResultStatus::Failed, response.as_ref(), Some(error.clone())).await;
+    if retry {
+        outcomes.retries.lock().await.push(file.clone());
+    }
+    if let Some(cb) = &callbacks.on_file_error {
+        let file_path = file.source.display_name();
+        cb.call(Ok(FileErrorEvent {
+            message: message.to_string(),
+            data: Some(FileErrorData {
+                file: file_path,
+                error,
+                sop_instance_uid: Some(file.sop_instance_uid.clone()),
+                sop_class_uid: Some(file.sop_class_uid.clone()),
+                file_transfer_syntax: Some(file.file_transfer_syntax.clone()),
+                response,
+            }),
+        }), ThreadsafeFunctionCallMode::NonBlocking);
+    }
+}
+
+/// Wait before sending the failed files again, returning false if the transfer is cancelled meanwhile.
+/// The retried files are counted again with their new outcome, unless the transfer is cancelled.
+pub async fn wait_for_retry(retries: usize, delay: Duration, outcomes: &TransferOutcomes) -> bool {
+    *outcomes.failed.lock().await -= retries as u32;
+    tokio::select! {
+        _ = tokio::time::sleep(delay) => true,
+        _ = outcomes.cancellation.cancelled(None) => {
+            // the files waiting for a retry stay failed
+            *outcomes.failed.lock().await += retries as u32;
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::getscu::association::{AssociationRequest, ProposedContext};
+    use dicom_core::{DataElement, PrimitiveValue, Tag, VR};
+    use dicom_ul::pdu::{AssociationAC, PDataValueType, PresentationContextResult, PresentationContextResultReason, UserVariableItem};
+
+    #[test]
+    fn test_negotiated_window() {
+        assert_eq!(negotiated_window(16, None), 1);
+        assert_eq!(negotiated_window(16, Some((16, 16))), 16);
+        assert_eq!(negotiated_window(16, Some((8, 0))), 8);
+        assert_eq!(negotiated_window(16, Some((0, 0))), 16);
+        assert_eq!(negotiated_window(16, Some((32, 1))), 1);
+    }
+
+    /// Read a PDU on the side of the fake SCP
+    async fn read_test_pdu(socket: &mut TcpStream) -> Pdu {
+        use tokio::io::AsyncReadExt;
+        let mut buffer = vec![0u8; 6];
+        socket.read_exact(&mut buffer).await.unwrap();
+        let length = u32::from_be_bytes([buffer[2], buffer[3], buffer[4], buffer[5]]) as usize;
+        buffer.resize(6 + length, 0);
+        socket.read_exact(&mut buffer[6..]).await.unwrap();
+        dicom_ul::read_pdu(&buffer[..], 1 << 20, false).unwrap().unwrap()
+    }
+
+    async fn write_test_pdu(socket: &mut TcpStream, pdu: &Pdu) {
+        use tokio::io::AsyncWriteExt;
+        let mut buffer = Vec::new();
+        dicom_ul::write_pdu(&mut buffer, pdu).unwrap();
+        socket.write_all(&buffer).await.unwrap();
+    }
+
+    fn store_command(command_field: u16, message_id_tag: Tag, message_id: u16, status: Option<u16>) -> Vec<u8> {
+        let mut command = InMemDicomObject::from_element_iter([
+            DataElement::new(tags::COMMAND_FIELD, VR::US, PrimitiveValue::from(command_field)),
+            DataElement::new(message_id_tag, VR::US, PrimitiveValue::from(message_id)),
+            DataElement::new(tags::COMMAND_DATA_SET_TYPE, VR::US, PrimitiveValue::from(0x0101_u16)),
+        ]);
+        if let Some(status) = status {
+            command.put(DataElement::new(tags::STATUS, VR::US, PrimitiveValue::from(status)));
+        }
+        let mut data = Vec::new();
+        command
+            .write_dataset_with_ts(&mut data, &dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN.erased())
+            .unwrap();
+        data
+    }
+
+    fn pending_store(name: &str) -> PendingStore {
+        PendingStore {
+            file: DicomFile {
+                source: FileSource::Local(name.into()),
+                sop_class_uid: dicom_dictionary_std::uids::CT_IMAGE_STORAGE.to_string(),
+                sop_instance_uid: "1.2.3".to_string(),
+                file_transfer_syntax: dicom_dictionary_std::uids::EXPLICIT_VR_LITTLE_ENDIAN.to_string(),
+                ts_selected: None,
+                pc_selected: None,
+                data: None,
+            },
+            transfer_syntax: dicom_dictionary_std::uids::EXPLICIT_VR_LITTLE_ENDIAN.to_string(),
+            coerced_tags: HashMap::new(),
+            start_time: std::time::Instant::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_responses_in_one_pdu() {
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // fake SCP answering both requests in one P-DATA-TF, the last one first
+        let scp = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let Pdu::AssociationRQ(rq) = read_test_pdu(&mut socket).await else {
+                panic!("expected an A-ASSOCIATE-RQ");
+            };
+            let ac = AssociationAC {
+                protocol_version: 1,
+                calling_ae_title: rq.calling_ae_title,
+                called_ae_title: rq.called_ae_title,
+                application_context_name: rq.application_context_name,
+                presentation_contexts: rq
+                    .presentation_contexts
+                    .iter()
+                    .map(|pc| PresentationContextResult {
+                        id: pc.id,
+                        reason: PresentationContextResultReason::Acceptance,
+                        transfer_syntax: pc.transfer_syntaxes[0].clone(),
+                    })
+                    .collect(),
+                user_variables: vec![UserVariableItem::MaxLength(16384), UserVariableItem::Unknown(0x53, vec![0, 2, 0, 2])],
+            };
+            write_test_pdu(&mut socket, &Pdu::AssociationAC(ac)).await;
+
+            let mut requests = Vec::new();
+            while requests.len() < 2 {
+                let Pdu::PData { data } = read_test_pdu(&mut socket).await else {
+                    panic!("expected a P-DATA-TF");
+                };
+                requests.extend(data);
+            }
+            let responses = requests
+                .iter()
+                .rev()
+                .map(|request| {
+                    let command = InMemDicomObject::read_dataset_with_ts(
+                        &request.data[..],
+                        &dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN.erased(),
+                    )
+                    .unwrap();
+                    let message_id = command.element(tags::MESSAGE_ID).unwrap().to_int::<u16>().unwrap();
+                    PDataValue {
+                        presentation_context_id: request.presentation_context_id,
+                        value_type: PDataValueType::Command,
+                        is_last: true,
+                        data: store_command(0x8001, tags::MESSAGE_ID_BEING_RESPONDED_TO, message_id, Some(0)),
+                    }
+                })
+                .collect();
+            write_test_pdu(&mut socket, &Pdu::PData { data: responses }).await;
+            assert!(matches!(read_test_pdu(&mut socket).await, Pdu::ReleaseRQ));
+            write_test_pdu(&mut socket, &Pdu::ReleaseRP).await;
+        });
+
+        let request = AssociationRequest {
+            calling_ae_title: "STORE-SCU".to_string(),
+            called_ae_title: None,
+            max_pdu_length: 16384,
+            contexts: vec![ProposedContext {
+                abstract_syntax: dicom_dictionary_std::uids::CT_IMAGE_STORAGE.to_string(),
+                transfer_syntaxes: vec![dicom_dictionary_std::uids::EXPLICIT_VR_LITTLE_ENDIAN.to_string()],
+                scp_role: false,
+            }],
+            user_identity: None,
+            async_operations_window: Some((2, 2)),
+            tls: None,
+        };
+        let scu = RoleSelectingAssociation::establish(&addr.to_string(), request).await.unwrap();
+        let mut scu = StoreAssociation::Windowed { scu, requested: 2 };
+        let window = scu.window();
+        assert_eq!(window, 2);
+        let pc_id = scu.presentation_contexts()[0].id;
+
+        let mut pending = HashMap::new();
+        for (message_id, name) in [(1, "first.dcm"), (2, "second.dcm")] {
+            send_encoded_message(&mut scu, pc_id, store_command(0x0001, tags::MESSAGE_ID, message_id, None), None)
+                .await
+                .unwrap();
+            pending.insert(message_id, pending_store(name));
+        }
+
+        let mut completed = Vec::new();
+        while !pending.is_empty() {
+            let message = tokio::time::timeout(Duration::from_secs(10), receive_message(&mut scu))
+                .await
+                .unwrap()
+                .unwrap();
+            let request = responded_request(&mut pending, &message.command, window).unwrap();
+            completed.push(request.file.source.display_name());
+        }
+        assert_eq!(completed, ["second.dcm", "first.dcm"]);
+        scu.release().await;
+        scp.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_mode() {
+        let (sender, receiver) = watch::channel(None);
+        let cancellation = Cancellation::new(receiver);
+        let abort = cancellation.cancelled(Some(CancelMode::Abort));
+        tokio::pin!(abort);
+        assert!(tokio::time::timeout(Duration::from_millis(50), &mut abort).await.is_err());
+
+        sender.send(Some(CancelMode::Graceful)).unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(50), &mut abort).await.is_err());
+        assert!(tokio::time::timeout(Duration::from_millis(50), cancellation.cancelled(None)).await.is_ok());
+
+        sender.send(Some(CancelMode::Abort)).unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(50), &mut abort).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_retry() {
+        let (sender, receiver) = watch::channel(None);
+        let outcomes = TransferOutcomes { cancellation: Cancellation::new(receiver), ..Default::default() };
+        *outcomes.failed.lock().await = 3;
+        assert!(wait_for_retry(2, Duration::from_millis(10), &outcomes).await);
+        assert_eq!(*outcomes.failed.lock().await, 1);
+
+        // cancelling during the wait leaves the files failed
+        let cancel = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            sender.send(Some(CancelMode::Graceful)).unwrap();
+        };
+        let (retry, _) = tokio::join!(wait_for_retry(1, Duration::from_secs(60), &outcomes), cancel);
+        assert!(!retry);
+        assert_eq!(*outcomes.failed.lock().await, 1);
+    }
+}
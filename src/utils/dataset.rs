@@ -0,0 +1,335 @@
+//! Reading stored objects with or without file meta information.
+//!
+//! StoreScp writes data sets without preamble and file meta group unless `storeWithFileMeta`
+//! is enabled, so the transfer syntax of such files has to be inferred from their content:
+//! the data set encoding from the header of the first element, and the compression of
+//! encapsulated pixel data from the first fragment.
+
+use std::cell::Cell;
+use std::io::Read;
+
+use dicom_dictionary_std::tags;
+use dicom_encoding::{TransferSyntax, TransferSyntaxIndex};
+use dicom_object::{DefaultDicomObject, FileMetaTable, FileMetaTableBuilder, InMemDicomObject};
+use dicom_parser::dataset::{DataSetReader, DataToken};
+use dicom_transfer_syntax_registry::{entries, TransferSyntaxRegistry};
+
+/// Whether the data starts with a preamble and the "DICM" prefix of the file meta group
+pub fn has_file_meta(data: &[u8]) -> bool {
+    data.len() > 132 && &data[128..132] == b"DICM"
+}
+
+/// Read a stored object, either a complete DICOM file or a data set without file meta group.
+///
+/// For data sets, the file meta group is created from the SOP Class and SOP Instance UIDs
+/// and the inferred transfer syntax.
+pub fn read_stored_object(data: &[u8]) -> Result<DefaultDicomObject, String> {
+    if has_file_meta(data) {
+        return dicom_object::from_reader(data).map_err(|e| e.to_string());
+    }
+    let (obj, ts_uid) = read_dataset(data)?;
+    with_dataset_meta(obj, ts_uid)
+}
+
+/// Read the attributes before the pixel data of a stored object from its first bytes.
+///
+/// Returns `None` if the data ends before the pixel data (or cannot be read this way), in
+/// which case more of the object is needed. Objects without pixel data are only complete
+/// when read with [`read_stored_object`].
+pub fn read_stored_header(data: &[u8]) -> Option<DefaultDicomObject> {
+    if has_file_meta(data) {
+        let mut source = &data[128..];
+        let meta = FileMetaTable::from_reader(&mut source).ok()?;
+        let ts = TransferSyntaxRegistry.get(meta.transfer_syntax())?;
+        let (obj, _) = read_until_pixel_data(source, ts)?;
+        return Some(obj.with_exact_meta(meta));
+    }
+    let (obj, ts_uid) = dataset_encodings(data).into_iter().find_map(|ts_uid| {
+        let (obj, fragment) = read_until_pixel_data(data, TransferSyntaxRegistry.get(ts_uid)?)?;
+        obj.element(tags::SOP_INSTANCE_UID).ok()?;
+        match fragment {
+            Some(fragment) if ts_uid == entries::EXPLICIT_VR_LITTLE_ENDIAN.uid() => Some((obj, compression_of(fragment)?)),
+            _ => Some((obj, ts_uid)),
+        }
+    })?;
+    with_dataset_meta(obj, ts_uid).ok()
+}
+
+/// Create the file meta group of a data set from its SOP Class and SOP Instance UIDs
+fn with_dataset_meta(obj: InMemDicomObject, ts_uid: &str) -> Result<DefaultDicomObject, String> {
+    let uid = |tag| {
+        obj.element(tag)
+            .ok()
+            .and_then(|e| e.to_str().ok())
+            .map(|v| v.trim_end_matches(['\0', ' ']).to_string())
+            .ok_or_else(|| format!("missing attribute {}", tag))
+    };
+    let meta = FileMetaTableBuilder::new()
+        .media_storage_sop_class_uid(uid(tags::SOP_CLASS_UID)?)
+        .media_storage_sop_instance_uid(uid(tags::SOP_INSTANCE_UID)?)
+        .transfer_syntax(ts_uid)
+        .build()
+        .map_err(|e| e.to_string())?;
+    Ok(obj.with_exact_meta(meta))
+}
+
+/// Byte slice reader whose position can be read while a parser owns it
+struct TrackedReader<'a> {
+    data: &'a [u8],
+    position: &'a Cell<usize>,
+}
+
+impl Read for TrackedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let start = self.position.get();
+        let n = (&self.data[start..]).read(buf)?;
+        self.position.set(start + n);
+        Ok(n)
+    }
+}
+
+/// Read the root data set up to the pixel data, together with the available bytes of the
+/// first fragment if the pixel data is encapsulated. Returns `None` if the data ends first.
+fn read_until_pixel_data<'a>(data: &'a [u8], ts: &TransferSyntax) -> Option<(InMemDicomObject, Option<&'a [u8]>)> {
+    let position = Cell::new(0);
+    let mut tokens = DataSetReader::new_with_ts(TrackedReader { data, position: &position }, ts).ok()?;
+    let mut depth = 0usize;
+    let (pixel_data, encapsulated) = loop {
+        let start = position.get();
+        match tokens.next()?.ok()? {
+            // anything from the pixel data on is not needed
+            DataToken::ElementHeader(header) if depth == 0 && header.tag >= tags::PIXEL_DATA => break (start, false),
+            DataToken::PixelSequenceStart if depth == 0 => break (start, true),
+            DataToken::SequenceStart { .. } | DataToken::PixelSequenceStart | DataToken::ItemStart { .. } => depth += 1,
+            DataToken::SequenceEnd | DataToken::ItemEnd => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    };
+
+    // basic offset table item, then the first fragment (if there is one)
+    let mut fragment = None;
+    if encapsulated {
+        let mut items = 0;
+        fragment = loop {
+            match tokens.next()?.ok()? {
+                DataToken::ItemStart { len } if items == 1 => {
+                    let start = position.get();
+                    let end = len.get().map_or(data.len(), |len| start + len as usize);
+                    break Some(&data[start..end.min(data.len())]);
+                }
+                DataToken::ItemEnd => items += 1,
+                DataToken::SequenceEnd => break Some(&[][..]),
+                _ => {}
+            }
+        };
+    }
+    drop(tokens);
+    let obj = InMemDicomObject::read_dataset_with_ts(&data[..pixel_data], ts).ok()?;
+    Some((obj, fragment))
+}
+
+/// Read a data set without file meta group and infer its transfer syntax
+pub fn read_dataset(data: &[u8]) -> Result<(InMemDicomObject, &'static str), String> {
+    let (obj, ts_uid) = dataset_encodings(data)
+        .into_iter()
+        .find_map(|ts_uid| {
+            let ts = TransferSyntaxRegistry.get(ts_uid)?;
+            let obj = InMemDicomObject::read_dataset_with_ts(data, ts).ok()?;
+            obj.element(tags::SOP_INSTANCE_UID).ok()?;
+            Some((obj, ts_uid))
+        })
+        .ok_or("not a readable DICOM data set")?;
+
+    // encapsulated pixel data is only valid with explicit VR little endian
+    let fragment = obj
+        .element(tags::PIXEL_DATA)
+        .ok()
+        .and_then(|e| e.value().fragments())
+        .map(|fragments| fragments.first().map(Vec::as_slice).unwrap_or_default());
+    match fragment {
+        Some(fragment) if ts_uid == entries::EXPLICIT_VR_LITTLE_ENDIAN.uid() => {
+            let ts_uid = compression_of(fragment)
+                .ok_or("could not infer the transfer syntax of the encapsulated pixel data")?;
+            Ok((obj, ts_uid))
+        }
+        _ => Ok((obj, ts_uid)),
+    }
+}
+
+/// Candidate data set encodings, most likely first given the header of the first element
+fn dataset_encodings(data: &[u8]) -> [&'static str; 4] {
+    let explicit_le = entries::EXPLICIT_VR_LITTLE_ENDIAN.uid();
+    let implicit_le = entries::IMPLICIT_VR_LITTLE_ENDIAN.uid();
+    let explicit_be = entries::EXPLICIT_VR_BIG_ENDIAN.uid();
+    let deflated = entries::DEFLATED_EXPLICIT_VR_LITTLE_ENDIAN.uid();
+    match data {
+        // group number in big endian byte order
+        [0, group, ..] if *group != 0 => [explicit_be, explicit_le, implicit_le, deflated],
+        // explicit VR after the tag
+        [_, _, _, _, a, b, ..] if a.is_ascii_uppercase() && b.is_ascii_uppercase() => {
+            [explicit_le, implicit_le, explicit_be, deflated]
+        }
+        _ => [implicit_le, explicit_le, explicit_be, deflated],
+    }
+}
+
+/// Infer the compression transfer syntax from the first fragment of encapsulated pixel data
+fn compression_of(fragment: &[u8]) -> Option<&'static str> {
+    match fragment {
+        [0xFF, 0xD8, ..] => jpeg_compression(fragment),
+        [0xFF, 0x4F, 0xFF, 0x51, ..] => jpeg2000_compression(fragment),
+        // JP2 file format, the codestream is in the contiguous codestream box
+        [0x00, 0x00, 0x00, 0x0C, b'j', b'P', ..] => fragment
+            .windows(4)
+            .position(|w| w == [0xFF, 0x4F, 0xFF, 0x51])
+            .and_then(|start| jpeg2000_compression(&fragment[start..])),
+        // RLE header: number of segments (1-15) followed by the offset of the first segment
+        _ if fragment.len() >= 64
+            && (1..=15).contains(&u32::from_le_bytes(fragment[0..4].try_into().ok()?))
+            && u32::from_le_bytes(fragment[4..8].try_into().ok()?) == 64 =>
+        {
+            Some(entries::RLE_LOSSLESS.uid())
+        }
+        _ => None,
+    }
+}
+
+/// JPEG process from the start of frame marker, telling lossless modes apart by the start of scan
+fn jpeg_compression(data: &[u8]) -> Option<&'static str> {
+    let mut frame = None;
+    let mut i = 2;
+    while i + 4 <= data.len() {
+        if data[i] != 0xFF {
+            return None;
+        }
+        let marker = data[i + 1];
+        let length = u16::from_be_bytes([data[i + 2], data[i + 3]]) as usize;
+        match marker {
+            0xC0 | 0xC1 | 0xC2 | 0xC3 | 0xF7 if frame.is_none() => frame = Some(marker),
+            0xDA => {
+                // Ss is the predictor for lossless JPEG and NEAR for JPEG-LS
+                let components = *data.get(i + 4)? as usize;
+                let ss = *data.get(i + 5 + 2 * components)?;
+                return match frame? {
+                    0xC0 => Some(entries::JPEG_BASELINE.uid()),
+                    0xC1 | 0xC2 => Some(entries::JPEG_EXTENDED.uid()),
+                    0xC3 if ss == 1 => Some(entries::JPEG_LOSSLESS_NON_HIERARCHICAL_FIRST_ORDER_PREDICTION.uid()),
+                    0xC3 => Some(entries::JPEG_LOSSLESS_NON_HIERARCHICAL.uid()),
+                    0xF7 if ss == 0 => Some(entries::JPEG_LS_LOSSLESS_IMAGE_COMPRESSION.uid()),
+                    0xF7 => Some(entries::JPEG_LS_LOSSY_IMAGE_COMPRESSION.uid()),
+                    _ => None,
+                };
+            }
+            _ => {}
+        }
+        i += 2 + length;
+    }
+    None
+}
+
+/// JPEG 2000 lossless or lossy from the wavelet transformation in the coding style marker
+fn jpeg2000_compression(codestream: &[u8]) -> Option<&'static str> {
+    let cod = codestream.windows(2).position(|w| w == [0xFF, 0x52])?;
+    // marker, Lcod, Scod, progression order, layers (2), multiple component transformation,
+    // decomposition levels, code-block width and height, code-block style, transformation
+    match codestream.get(cod + 13)? {
+        1 => Some(entries::JPEG_2000_IMAGE_COMPRESSION_LOSSLESS_ONLY.uid()),
+        _ => Some(entries::JPEG_2000_IMAGE_COMPRESSION.uid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom_core::value::PixelFragmentSequence;
+    use dicom_core::{DataElement, PrimitiveValue, VR};
+    use dicom_dictionary_std::uids;
+
+    fn dataset(ts_uid: &str) -> Vec<u8> {
+        let obj = InMemDicomObject::from_element_iter([
+            DataElement::new(tags::SOP_CLASS_UID, VR::UI, PrimitiveValue::from(uids::CT_IMAGE_STORAGE)),
+            DataElement::new(tags::SOP_INSTANCE_UID, VR::UI, PrimitiveValue::from("1.2.3.4")),
+            DataElement::new(tags::PATIENT_NAME, VR::PN, PrimitiveValue::from("Doe^John")),
+        ]);
+        let mut data = Vec::new();
+        obj.write_dataset_with_ts(&mut data, TransferSyntaxRegistry.get(ts_uid).unwrap())
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn test_read_dataset_encoding() {
+        for ts_uid in [
+            entries::EXPLICIT_VR_LITTLE_ENDIAN.uid(),
+            entries::IMPLICIT_VR_LITTLE_ENDIAN.uid(),
+            entries::EXPLICIT_VR_BIG_ENDIAN.uid(),
+        ] {
+            let file = read_stored_object(&dataset(ts_uid)).unwrap();
+            assert_eq!(file.meta().transfer_syntax(), ts_uid);
+            assert_eq!(file.meta().media_storage_sop_instance_uid(), "1.2.3.4");
+        }
+        assert!(read_stored_object(b"not dicom at all").is_err());
+    }
+
+    #[test]
+    fn test_read_stored_header() {
+        // data set with encapsulated JPEG baseline pixel data, cut off in the first fragment
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xC0, 0x00, 0x0B, 8, 0, 1, 0, 1, 1, 1, 0x11, 0];
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x08, 1, 1, 0, 0, 63, 0]);
+        jpeg.resize(2000, 0);
+        let explicit_le = TransferSyntaxRegistry.get(entries::EXPLICIT_VR_LITTLE_ENDIAN.uid()).unwrap();
+        let mut obj =
+            InMemDicomObject::read_dataset_with_ts(&dataset(explicit_le.uid())[..], explicit_le).unwrap();
+        obj.put(DataElement::new(tags::PIXEL_DATA, VR::OB, PixelFragmentSequence::new(vec![], vec![jpeg])));
+        let mut data = Vec::new();
+        obj.write_dataset_with_ts(&mut data, explicit_le).unwrap();
+
+        let file = read_stored_header(&data[..data.len() - 1000]).unwrap();
+        assert_eq!(file.meta().transfer_syntax(), entries::JPEG_BASELINE.uid());
+        assert_eq!(file.meta().media_storage_sop_instance_uid(), "1.2.3.4");
+        assert!(file.element(tags::PIXEL_DATA).is_err());
+        assert!(read_stored_header(&data[..40]).is_none());
+
+        // complete file with native pixel data, cut off in the pixel data
+        obj.put(DataElement::new(tags::PIXEL_DATA, VR::OW, PrimitiveValue::from(vec![0u8; 10_000])));
+        let meta = FileMetaTableBuilder::new()
+            .media_storage_sop_class_uid(uids::CT_IMAGE_STORAGE)
+            .media_storage_sop_instance_uid("1.2.3.4")
+            .transfer_syntax(uids::IMPLICIT_VR_LITTLE_ENDIAN)
+            .build()
+            .unwrap();
+        let mut data = Vec::new();
+        obj.with_exact_meta(meta).write_all(&mut data).unwrap();
+        let file = read_stored_header(&data[..1000]).unwrap();
+        assert_eq!(file.meta().transfer_syntax(), uids::IMPLICIT_VR_LITTLE_ENDIAN);
+        assert_eq!(file.element(tags::PATIENT_NAME).unwrap().to_str().unwrap(), "Doe^John");
+        assert!(file.element(tags::PIXEL_DATA).is_err());
+
+        // without pixel data, only the complete object tells that nothing is missing
+        assert!(read_stored_header(&dataset(entries::EXPLICIT_VR_LITTLE_ENDIAN.uid())).is_none());
+    }
+
+    #[test]
+    fn test_compression_of() {
+        // SOI, SOF0 (length 8 + 3 per component), SOS with one component
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xC0, 0x00, 0x0B, 8, 0, 1, 0, 1, 1, 1, 0x11, 0];
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x08, 1, 1, 0, 0, 63, 0]);
+        assert_eq!(compression_of(&jpeg), Some(entries::JPEG_BASELINE.uid()));
+        jpeg[3] = 0xC3;
+        jpeg[22] = 1;
+        assert_eq!(compression_of(&jpeg), Some(entries::JPEG_LOSSLESS_NON_HIERARCHICAL_FIRST_ORDER_PREDICTION.uid()));
+
+        let mut rle = vec![0u8; 80];
+        rle[0] = 1;
+        rle[4] = 64;
+        assert_eq!(compression_of(&rle), Some(entries::RLE_LOSSLESS.uid()));
+
+        // SOC, SIZ (truncated), COD with the 5-3 reversible transformation
+        let j2k = [0xFF, 0x4F, 0xFF, 0x51, 0, 0, 0xFF, 0x52, 0, 12, 0, 0, 0, 1, 0, 5, 4, 4, 0, 1];
+        assert_eq!(
+            compression_of(&j2k),
+            Some(entries::JPEG_2000_IMAGE_COMPRESSION_LOSSLESS_ONLY.uid())
+        );
+        assert_eq!(compression_of(&[1, 2, 3]), None);
+    }
+}
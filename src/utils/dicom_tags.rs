@@ -49,6 +49,53 @@ pub fn get_tag_scope(tag: Tag) -> TagScope {
     }
 }
 
+/// Whether a tag may identify the patient or staff (names, IDs, birth date, addresses,
+/// free-text comments), following the DICOM basic de-identification profile.
+/// Private tags are treated as PHI since their content is unknown.
+// retired tags (e.g. OtherPatientIDs) still occur in older data sets
+#[allow(deprecated)]
+pub(crate) fn is_phi_tag(tag: Tag) -> bool {
+    use dicom_dictionary_std::tags;
+    if tag.group() % 2 == 1 {
+        return true;
+    }
+    matches!(
+        tag,
+        tags::PATIENT_NAME
+            | tags::PATIENT_ID
+            | tags::PATIENT_BIRTH_DATE
+            | tags::PATIENT_BIRTH_TIME
+            | tags::PATIENT_ADDRESS
+            | tags::PATIENT_TELEPHONE_NUMBERS
+            | tags::PATIENT_MOTHER_BIRTH_NAME
+            | tags::OTHER_PATIENT_I_DS
+            | tags::OTHER_PATIENT_NAMES
+            | tags::PATIENT_COMMENTS
+            | tags::ADDITIONAL_PATIENT_HISTORY
+            | tags::MEDICAL_RECORD_LOCATOR
+            | tags::OCCUPATION
+            | tags::MILITARY_RANK
+            | tags::BRANCH_OF_SERVICE
+            | tags::ISSUER_OF_PATIENT_ID
+            | tags::ACCESSION_NUMBER
+            | tags::STUDY_ID
+            | tags::ADMISSION_ID
+            | tags::REFERRING_PHYSICIAN_NAME
+            | tags::PERFORMING_PHYSICIAN_NAME
+            | tags::PHYSICIANS_OF_RECORD
+            | tags::NAME_OF_PHYSICIANS_READING_STUDY
+            | tags::OPERATORS_NAME
+            | tags::REQUESTING_PHYSICIAN
+            | tags::RESPONSIBLE_PERSON
+            | tags::INSTITUTION_NAME
+            | tags::INSTITUTION_ADDRESS
+            | tags::STATION_NAME
+            | tags::DEVICE_SERIAL_NUMBER
+            | tags::IMAGE_COMMENTS
+            | tags::STUDY_COMMENTS
+    )
+}
+
 /// Parse tag from string (name, hex, or (GGGG,EEEE) format)
 /// Uses dicom-rs StandardDataDictionary for comprehensive tag support
 pub fn parse_tag(tag_str: &str) -> Result<Tag, String> {
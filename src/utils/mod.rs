@@ -1,8 +1,11 @@
 pub mod s3;
 pub mod dicom_tags;
 pub mod image_processing;
+pub mod dataset;
+pub mod tls;
 
 // Re-export commonly used items
-pub use s3::{S3Config, build_s3_bucket, check_s3_connectivity, s3_get_object, s3_put_object, s3_list_objects};
+pub use s3::{S3Bucket, S3Config, S3UploadOptions, build_s3_bucket, check_s3_connectivity, s3_get_object, s3_put_object, s3_upload, s3_list_objects, s3_delete_object, s3_get_object_range, s3_object_exists, s3_head_object, s3_metadata_key, s3_sanitize_value};
+pub use tls::TlsOptions;
 pub use dicom_tags::*;
 pub use image_processing::*;
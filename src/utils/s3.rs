@@ -1,6 +1,21 @@
-use s3::{Bucket, Region};
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use s3::command::{Command, HttpMethod, Multipart};
 use s3::creds::Credentials;
-use tracing::{info, error};
+use s3::error::S3Error;
+use s3::request::{Request, ResponseData, ResponseDataStream};
+use s3::serde_types::{
+    CompleteMultipartUploadData, HeadObjectResult, InitiateMultipartUploadResponse, ListBucketResult, Part,
+};
+use s3::{Bucket, Region};
+use time::OffsetDateTime;
+use tokio_native_tls::native_tls;
+use tokio_stream::StreamExt;
+
+use super::tls::read_pem;
+use crate::storage::ObjectAttributes;
+use tracing::{info, error, warn};
 use serde::{Serialize, Deserialize};
 
 /// S3 storage configuration
@@ -9,37 +24,241 @@ use serde::{Serialize, Deserialize};
 pub struct S3Config {
     /// S3 bucket name
     pub bucket: String,
-    /// AWS access key ID
-    pub access_key: String,
+    /// AWS access key ID. If omitted, credentials are taken from the environment
+    /// (`AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, `AWS_SESSION_TOKEN`) or the shared credentials file
+    pub access_key: Option<String>,
     /// AWS secret access key
-    pub secret_key: String,
-    /// S3 endpoint (e.g., "http://localhost:9000" for MinIO)
+    pub secret_key: Option<String>,
+    /// Session token for temporary credentials (STS)
+    pub session_token: Option<String>,
+    /// Profile in the shared credentials file (`~/.aws/credentials`) used when no access key is given
+    pub profile: Option<String>,
+    /// Region (default: "us-east-1")
+    pub region: Option<String>,
+    /// S3 endpoint (e.g., "http://localhost:9000" for MinIO). If omitted, the AWS endpoint of the region is used
     pub endpoint: Option<String>,
+    /// Use path-style addressing (`endpoint/bucket/key`) instead of virtual-hosted style (`bucket.endpoint/key`).
+    /// Default: true when a custom endpoint is set, false for AWS
+    pub path_style: Option<bool>,
+    /// Prefix prepended to all object keys (e.g. "archive/")
+    pub key_prefix: Option<String>,
+    /// Storage class for uploaded objects (e.g. "STANDARD_IA", "GLACIER_IR")
+    pub storage_class: Option<String>,
+    /// Objects of at least this size in MiB are uploaded with multipart upload (default: 16)
+    pub multipart_threshold_mb: Option<u32>,
+    /// Part size in MiB for multipart uploads (default: 8, minimum: 5)
+    pub multipart_part_size_mb: Option<u32>,
+    /// Number of retries of failed S3 requests, with exponential backoff (default: 3)
+    pub max_retries: Option<u32>,
+    /// PEM file with CA certificates trusted in addition to the system roots (e.g. the CA of an on-premises MinIO)
+    pub ca_cert_path: Option<String>,
+    /// Accept invalid TLS certificates and host names (testing only)
+    pub insecure_tls: Option<bool>,
+}
+
+/// S3 bucket and the HTTP client its requests are sent with.
+///
+/// rust-s3 signs the requests, but its own client cannot be given additional root
+/// certificates, so requests go through a client built from the TLS settings of the configuration.
+#[derive(Clone)]
+pub struct S3Bucket {
+    pub bucket: Bucket,
+    client: reqwest::Client,
+}
+
+impl S3Bucket {
+    pub fn name(&self) -> String {
+        self.bucket.name()
+    }
+
+    async fn request<'a>(&'a self, path: &'a str, command: Command<'a>) -> Result<S3Request<'a>, S3Error> {
+        self.bucket.credentials_refresh().await?;
+        Ok(S3Request {
+            bucket: self,
+            path,
+            command,
+            datetime: s3::utils::now_utc(),
+        })
+    }
+
+    /// Send a request; HTTP error statuses are returned as `S3Error::HttpFailWithBody`
+    async fn send(&self, path: &str, command: Command<'_>) -> Result<ResponseData, S3Error> {
+        self.request(path, command).await?.response_data(false).await
+    }
+}
+
+/// Request signed by rust-s3 and sent with the client of an [`S3Bucket`]
+struct S3Request<'a> {
+    bucket: &'a S3Bucket,
+    path: &'a str,
+    command: Command<'a>,
+    datetime: OffsetDateTime,
+}
+
+#[async_trait]
+impl Request for S3Request<'_> {
+    type Response = reqwest::Response;
+    type HeaderMap = reqwest::header::HeaderMap;
+
+    async fn response(&self) -> Result<reqwest::Response, S3Error> {
+        let method = match self.command.http_verb() {
+            HttpMethod::Delete => reqwest::Method::DELETE,
+            HttpMethod::Get => reqwest::Method::GET,
+            HttpMethod::Post => reqwest::Method::POST,
+            HttpMethod::Put => reqwest::Method::PUT,
+            HttpMethod::Head => reqwest::Method::HEAD,
+        };
+        let response = self
+            .bucket
+            .client
+            .request(method, self.url()?.as_str())
+            .headers(self.headers().await?)
+            .body(self.request_body()?)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            return Err(S3Error::HttpFailWithBody(status, response.text().await?));
+        }
+        Ok(response)
+    }
+
+    async fn response_data(&self, etag: bool) -> Result<ResponseData, S3Error> {
+        let response = self.response().await?;
+        let status_code = response.status().as_u16();
+        let headers: HashMap<String, String> = response
+            .headers()
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_str().unwrap_or_default().to_string()))
+            .collect();
+        let body = if etag {
+            headers.get("etag").cloned().unwrap_or_default().into()
+        } else {
+            response.bytes().await?
+        };
+        Ok(ResponseData::new(body, status_code, headers))
+    }
+
+    async fn response_data_to_writer<T: tokio::io::AsyncWrite + Send + Unpin + ?Sized>(
+        &self,
+        writer: &mut T,
+    ) -> Result<u16, S3Error> {
+        use tokio::io::AsyncWriteExt;
+        let response = self.response().await?;
+        let status_code = response.status().as_u16();
+        let mut stream = response.bytes_stream();
+        while let Some(item) = stream.next().await {
+            writer.write_all(&item?).await?;
+        }
+        Ok(status_code)
+    }
+
+    async fn response_data_to_stream(&self) -> Result<ResponseDataStream, S3Error> {
+        let response = self.response().await?;
+        let status_code = response.status().as_u16();
+        Ok(ResponseDataStream {
+            bytes: Box::pin(response.bytes_stream().map(|item| item.map_err(S3Error::Reqwest))),
+            status_code,
+        })
+    }
+
+    async fn response_header(&self) -> Result<(reqwest::header::HeaderMap, u16), S3Error> {
+        let response = self.response().await?;
+        Ok((response.headers().clone(), response.status().as_u16()))
+    }
+
+    fn datetime(&self) -> OffsetDateTime {
+        self.datetime
+    }
+
+    fn bucket(&self) -> Bucket {
+        self.bucket.bucket.clone()
+    }
+
+    fn command(&self) -> Command<'_> {
+        self.command.clone()
+    }
+
+    fn path(&self) -> String {
+        self.path.to_string()
+    }
+}
+
+/// HTTP client trusting the system roots and the CA certificates of `caCertPath`
+fn build_s3_client(config: &S3Config, timeout: Option<std::time::Duration>) -> Result<reqwest::Client, String> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(timeout) = timeout {
+        builder = builder.timeout(timeout);
+    }
+    if let Some(path) = &config.ca_cert_path {
+        let certificates = native_tls::Certificate::stack_from_pem(&read_pem(path, "CA certificate")?)
+            .map_err(|e| format!("Invalid CA certificate '{}': {}", path, e))?;
+        if certificates.is_empty() {
+            return Err(format!("No CA certificate found in '{}'", path));
+        }
+        for certificate in certificates {
+            let der = certificate
+                .to_der()
+                .map_err(|e| format!("Invalid CA certificate '{}': {}", path, e))?;
+            let certificate = reqwest::Certificate::from_der(&der)
+                .map_err(|e| format!("Invalid CA certificate '{}': {}", path, e))?;
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    if config.insecure_tls.unwrap_or(false) {
+        builder = builder.danger_accept_invalid_certs(true).danger_accept_invalid_hostnames(true);
+    }
+    builder
+        .build()
+        .map_err(|e| format!("Failed to configure TLS for bucket '{}': {}", config.bucket, e))
 }
 
 /// Build an S3 bucket instance from configuration
-pub fn build_s3_bucket(config: &S3Config) -> Bucket {
-    let endpoint = config.endpoint.clone().unwrap_or_else(|| "http://localhost:7070".to_string());
-    let region = Region::Custom {
-        region: "us-east-1".to_owned(),
-        endpoint,
+pub fn build_s3_bucket(config: &S3Config) -> Result<S3Bucket, String> {
+    let region_name = config.region.clone().unwrap_or_else(|| "us-east-1".to_string());
+    let region = match &config.endpoint {
+        Some(endpoint) => Region::Custom {
+            region: region_name,
+            endpoint: endpoint.clone(),
+        },
+        None => region_name
+            .parse::<Region>()
+            .map_err(|e| format!("Invalid S3 region '{}': {}", region_name, e))?,
     };
+
     let credentials = Credentials::new(
-        Some(&config.access_key),
-        Some(&config.secret_key),
+        config.access_key.as_deref(),
+        config.secret_key.as_deref(),
         None,
-        None,
-        None,
-    ).expect("Invalid S3 credentials");
+        config.session_token.as_deref(),
+        config.profile.as_deref(),
+    )
+    .map_err(|e| format!("No usable S3 credentials for bucket '{}': {}", config.bucket, e))?;
+    if credentials.access_key.is_some() && credentials.secret_key.is_none() {
+        return Err(format!("S3 secret key missing for bucket '{}'", config.bucket));
+    }
 
-    *Bucket::new(&config.bucket, region, credentials)
-            .expect("Failed to create S3 bucket")
-            .with_path_style()
+    let mut bucket = *Bucket::new(&config.bucket, region, credentials)
+        .map_err(|e| format!("Invalid S3 configuration for bucket '{}': {}", config.bucket, e))?;
+    if config.path_style.unwrap_or(config.endpoint.is_some()) {
+        bucket.set_path_style();
+    } else {
+        bucket.set_subdomain_style();
+    }
+    let client = build_s3_client(config, bucket.request_timeout)?;
+    Ok(S3Bucket { bucket, client })
 }
 
-/// Check S3 connectivity
-pub async fn check_s3_connectivity(bucket: &Bucket) {
-    match bucket.exists().await {
+/// Check S3 connectivity by listing at most one key of the bucket
+pub async fn check_s3_connectivity(bucket: &S3Bucket) {
+    let command = Command::ListObjectsV2 {
+        prefix: String::new(),
+        delimiter: None,
+        continuation_token: None,
+        start_after: None,
+        max_keys: Some(1),
+    };
+    match bucket.send("/", command).await {
         Ok(_) => {
             info!("S3 connectivity check succeeded for bucket: {}", bucket.name());
         },
@@ -51,13 +270,10 @@ pub async fn check_s3_connectivity(bucket: &Bucket) {
 
 /// Get object from S3
 pub async fn s3_get_object(
-    bucket: &Bucket,
+    bucket: &S3Bucket,
     path: &str,
 ) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
-    let response = match bucket.get_object(path).await {
-        Ok(r) => r,
-        Err(e) => return Err(Box::new(e)),
-    };
+    let response = bucket.send(path, Command::GetObject).await?;
     let code = response.status_code();
     if code == 200 {
         let bytes = response.bytes().to_vec();
@@ -68,40 +284,616 @@ pub async fn s3_get_object(
     }
 }
 
-/// Put object to S3
+/// Upload settings of an S3 bucket: multipart threshold, part size and retries
+#[derive(Debug, Clone)]
+pub struct S3UploadOptions {
+    /// Objects of at least this size (bytes) are uploaded in parts
+    pub multipart_threshold: usize,
+    /// Size of each part (bytes, at least 5 MiB)
+    pub part_size: usize,
+    /// Number of retries of a failed request after the first attempt
+    pub max_retries: u32,
+    /// Storage class of uploaded objects
+    pub storage_class: Option<String>,
+}
+
+const MIB: usize = 1024 * 1024;
+/// Smallest part size accepted by S3 (except for the last part)
+const MIN_PART_SIZE: usize = 5 * MIB;
+
+impl S3UploadOptions {
+    pub fn from_config(config: &S3Config) -> Self {
+        S3UploadOptions {
+            multipart_threshold: config.multipart_threshold_mb.unwrap_or(16) as usize * MIB,
+            part_size: (config.multipart_part_size_mb.unwrap_or(8) as usize * MIB).max(MIN_PART_SIZE),
+            max_retries: config.max_retries.unwrap_or(3),
+            storage_class: config.storage_class.clone(),
+        }
+    }
+}
+
+/// The uploaded object does not match the sent data
+#[derive(Debug)]
+struct IntegrityError(String);
+
+impl std::fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for IntegrityError {}
+
+/// Whether a failed request may succeed when repeated (network errors, throttling, server errors, ETag mismatch)
+fn is_transient(error: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    if error.is::<IntegrityError>() {
+        return true;
+    }
+    match error.downcast_ref::<S3Error>() {
+        Some(S3Error::HttpFailWithBody(code, _)) => *code >= 500 || *code == 408 || *code == 429,
+        Some(S3Error::Reqwest(_)) | Some(S3Error::Io(_)) => true,
+        _ => false,
+    }
+}
+
+/// Run an S3 request, retrying transient failures with exponential backoff and full jitter
+pub async fn s3_with_retries<T, F, Fut>(
+    max_retries: u32,
+    what: &str,
+    mut request: F,
+) -> Result<T, Box<dyn std::error::Error + Send + Sync>>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, Box<dyn std::error::Error + Send + Sync>>>,
+{
+    let mut attempt = 0;
+    loop {
+        match request().await {
+            Ok(result) => return Ok(result),
+            Err(e) if attempt < max_retries && is_transient(e.as_ref()) => {
+                attempt += 1;
+                // 200ms, 400ms, 800ms, ... capped at 10s, randomized to spread concurrent retries
+                let backoff = (200u64 << (attempt - 1).min(6)).min(10_000);
+                let delay = fastrand::u64(backoff / 2..=backoff);
+                warn!("{} failed (attempt {} of {}), retrying in {}ms: {}", what, attempt, max_retries + 1, delay, e);
+                tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Compare an ETag returned by S3 with the expected value.
+/// ETags that are not MD5 based (e.g. with SSE-KMS) cannot be verified and are accepted.
+fn verify_etag(path: &str, etag: Option<&str>, expected: &str) -> Result<(), IntegrityError> {
+    let Some(etag) = etag.map(|e| e.trim().trim_matches('"').to_ascii_lowercase()) else {
+        return Ok(());
+    };
+    let comparable = match expected.split_once('-') {
+        Some((_, parts)) => etag.ends_with(&format!("-{}", parts)),
+        None => etag.len() == 32 && etag.chars().all(|c| c.is_ascii_hexdigit()),
+    };
+    if comparable && etag != expected {
+        return Err(IntegrityError(format!(
+            "ETag mismatch for '{}': expected {}, got {}",
+            path, expected, etag
+        )));
+    }
+    Ok(())
+}
+
+/// Put object to S3, optionally with a storage class.
+/// The request carries a Content-MD5 header and the returned ETag is verified.
 pub async fn s3_put_object(
-    bucket: &Bucket,
+    bucket: &S3Bucket,
     path: &str,
     data: &[u8],
+    storage_class: Option<&str>,
+    attributes: &ObjectAttributes,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let response = match bucket.put_object(path, data).await {
-        Ok(r) => r,
-        Err(e) => return Err(Box::new(e)),
+    let mut headers = reqwest::header::HeaderMap::new();
+    if let Some(storage_class) = storage_class {
+        headers.insert("x-amz-storage-class", storage_class.parse()?);
+    }
+    for (key, value) in &attributes.metadata {
+        headers.insert(reqwest::header::HeaderName::try_from(format!("x-amz-meta-{}", key))?, value.parse()?);
+    }
+    if !attributes.tags.is_empty() {
+        headers.insert("x-amz-tagging", s3_tagging_header(&attributes.tags).parse()?);
+    }
+    let command = Command::PutObject {
+        content: data,
+        content_type: attributes.content_type.as_deref().unwrap_or("application/octet-stream"),
+        custom_headers: Some(headers),
+        multipart: None,
     };
+    let response = bucket.send(path, command).await?;
     let code = response.status_code();
     if code == 200 || code == 201 {
+        let headers = response.headers();
+        verify_etag(path, headers.get("etag").map(String::as_str), &format!("{:x}", md5::compute(data)))?;
         Ok(())
     } else {
         Err(format!("S3 put_object error: HTTP {}", code).into())
     }
 }
 
+/// Upload an object in parts of `part_size` bytes (content type, metadata and tags are set when the upload is initiated).
+///
+/// Every part is retried on its own. The ETag of each part and of the completed object is
+/// verified; the upload is aborted if a part cannot be stored or the upload cannot be completed,
+/// and the completed object is deleted if its ETag does not match.
+pub async fn s3_put_object_multipart(
+    bucket: &S3Bucket,
+    path: &str,
+    data: &[u8],
+    options: &S3UploadOptions,
+    attributes: &ObjectAttributes,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let content_type = attributes.content_type.as_deref().unwrap_or("application/octet-stream");
+    let mut initiate_bucket = bucket.clone();
+    if let Some(storage_class) = &options.storage_class {
+        initiate_bucket.bucket.extra_headers.insert("x-amz-storage-class", storage_class.parse()?);
+    }
+    for (key, value) in &attributes.metadata {
+        // keys and values are sanitized, so they are valid header names and values
+        initiate_bucket.bucket.add_header(&format!("x-amz-meta-{}", key), value);
+    }
+    if !attributes.tags.is_empty() {
+        initiate_bucket.bucket.add_header("x-amz-tagging", &s3_tagging_header(&attributes.tags));
+    }
+    let upload = s3_with_retries(options.max_retries, "S3 initiate multipart upload", || async {
+        let response = initiate_bucket
+            .send(path, Command::InitiateMultipartUpload { content_type })
+            .await?;
+        Ok(quick_xml::de::from_str::<InitiateMultipartUploadResponse>(response.as_str()?)?)
+    })
+    .await?;
+
+    let mut parts = Vec::new();
+    let mut part_digests = Vec::new();
+    for (index, chunk) in data.chunks(options.part_size.max(MIN_PART_SIZE)).enumerate() {
+        let part_number = index as u32 + 1;
+        let digest = md5::compute(chunk);
+        let what = format!("S3 upload of part {} of '{}'", part_number, path);
+        let part = s3_with_retries(options.max_retries, &what, || async {
+            let command = Command::PutObject {
+                content: chunk,
+                content_type,
+                custom_headers: None,
+                multipart: Some(Multipart::new(part_number, &upload.upload_id)),
+            };
+            let response = bucket.send(path, command).await?;
+            let etag = response.headers().get("etag").cloned().unwrap_or_default();
+            verify_etag(path, Some(&etag), &format!("{:x}", digest))?;
+            Ok(Part { part_number, etag })
+        })
+        .await;
+        match part {
+            Ok(part) => {
+                parts.push(part);
+                part_digests.extend_from_slice(&digest.0);
+            }
+            Err(e) => {
+                if let Err(abort_error) = s3_abort_multipart_upload(bucket, path, &upload.upload_id).await {
+                    warn!("Failed to abort multipart upload of '{}': {}", path, abort_error);
+                }
+                return Err(e);
+            }
+        }
+    }
+
+    let expected = format!("{:x}-{}", md5::compute(&part_digests), parts.len());
+    let completed = s3_with_retries(options.max_retries, "S3 complete multipart upload", || async {
+        let command = Command::CompleteMultipartUpload {
+            upload_id: &upload.upload_id,
+            data: CompleteMultipartUploadData { parts: parts.clone() },
+        };
+        Ok(bucket.send(path, command).await?)
+    })
+    .await;
+    let response = match completed {
+        Ok(response) => response,
+        Err(e) => {
+            if let Err(abort_error) = s3_abort_multipart_upload(bucket, path, &upload.upload_id).await {
+                warn!("Failed to abort multipart upload of '{}': {}", path, abort_error);
+            }
+            return Err(e);
+        }
+    };
+    let body = String::from_utf8_lossy(response.as_slice());
+    let etag = body
+        .split_once("<ETag>")
+        .and_then(|(_, rest)| rest.split_once("</ETag>"))
+        .map(|(etag, _)| etag.replace("&quot;", "").replace("&#34;", ""));
+    if let Err(e) = verify_etag(path, etag.as_deref(), &expected) {
+        // do not leave an object that does not match the data at its final key
+        if let Err(delete_error) = s3_delete_object(bucket, path).await {
+            warn!("Failed to delete '{}' after the integrity check failed: {}", path, delete_error);
+        }
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Upload an object with retries, in parts if it reaches the multipart threshold
+pub async fn s3_upload(
+    bucket: &S3Bucket,
+    path: &str,
+    data: &[u8],
+    options: &S3UploadOptions,
+    attributes: &ObjectAttributes,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if data.len() >= options.multipart_threshold && data.len() > options.part_size {
+        return s3_put_object_multipart(bucket, path, data, options, attributes).await;
+    }
+    let what = format!("S3 upload of '{}'", path);
+    s3_with_retries(options.max_retries, &what, || {
+        s3_put_object(bucket, path, data, options.storage_class.as_deref(), attributes)
+    })
+    .await
+}
+
+/// Make a string usable as user metadata key: lowercase letters, digits and `-`
+pub fn s3_metadata_key(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .collect()
+}
+
+/// Make a DICOM value usable as metadata or tag value.
+///
+/// Padding is trimmed, characters outside the set allowed in S3 tags
+/// (letters, digits, space and `+ - = . _ : / @`) are replaced by `_`, multi-valued
+/// elements are joined with `/` and the result is cut to 256 characters.
+pub fn s3_sanitize_value(value: &str) -> String {
+    value
+        .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+        .chars()
+        .map(|c| match c {
+            '\\' => '/',
+            c if c.is_ascii_alphanumeric() || " +-=._:/@".contains(c) => c,
+            _ => '_',
+        })
+        .take(256)
+        .collect()
+}
+
+/// URL-encoded `x-amz-tagging` header value for sanitized tags
+fn s3_tagging_header(tags: &[(String, String)]) -> String {
+    fn encode(value: &str) -> String {
+        value
+            .chars()
+            .map(|c| match c {
+                c if c.is_ascii_alphanumeric() || "-._".contains(c) => c.to_string(),
+                c => format!("%{:02X}", c as u32),
+            })
+            .collect()
+    }
+    tags.iter()
+        .map(|(key, value)| format!("{}={}", encode(key), encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
 /// List objects in S3 with given prefix
 pub async fn s3_list_objects(
-    bucket: &Bucket,
+    bucket: &S3Bucket,
     prefix: &str,
 ) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
-    let results = match bucket.list(prefix.to_string(), None).await {
-        Ok(r) => r,
-        Err(e) => return Err(Box::new(e)),
-    };
-    
     let mut objects = Vec::new();
-    for list in results {
-        for obj in list.contents {
-            objects.push(obj.key);
+    let mut continuation_token = None;
+    loop {
+        let command = Command::ListObjectsV2 {
+            prefix: prefix.to_string(),
+            delimiter: None,
+            continuation_token: continuation_token.take(),
+            start_after: None,
+            max_keys: None,
+        };
+        let response = bucket.send("/", command).await?;
+        let page: ListBucketResult = quick_xml::de::from_reader(response.as_slice())?;
+        objects.extend(page.contents.into_iter().map(|obj| obj.key));
+        continuation_token = page.next_continuation_token;
+        if continuation_token.is_none() {
+            break;
         }
     }
-    
     Ok(objects)
 }
+
+/// Abort a multipart upload and discard its parts
+async fn s3_abort_multipart_upload(bucket: &S3Bucket, path: &str, upload_id: &str) -> Result<(), S3Error> {
+    bucket.send(path, Command::AbortMultipartUpload { upload_id }).await?;
+    Ok(())
+}
+
+/// Delete object from S3
+pub async fn s3_delete_object(
+    bucket: &S3Bucket,
+    path: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let response = bucket.send(path, Command::DeleteObject).await?;
+    let code = response.status_code();
+    if code == 200 || code == 204 {
+        Ok(())
+    } else {
+        Err(format!("S3 delete_object error: HTTP {}", code).into())
+    }
+}
+
+/// Get a byte range of an object from S3 (`end` is inclusive, `None` reads to the end)
+pub async fn s3_get_object_range(
+    bucket: &S3Bucket,
+    path: &str,
+    start: u64,
+    end: Option<u64>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let response = bucket.send(path, Command::GetObjectRange { start, end }).await?;
+    let code = response.status_code();
+    if code == 200 || code == 206 {
+        Ok(response.bytes().to_vec())
+    } else {
+        Err(format!("S3 get_object_range error: HTTP {}", code).into())
+    }
+}
+
+/// Check whether an object exists in S3
+pub async fn s3_object_exists(
+    bucket: &S3Bucket,
+    path: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    match bucket.send(path, Command::HeadObject).await {
+        Ok(_) => Ok(true),
+        Err(S3Error::HttpFailWithBody(404, _)) => Ok(false),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+/// Get object metadata from S3 without downloading the content
+pub async fn s3_head_object(
+    bucket: &S3Bucket,
+    path: &str,
+) -> Result<HeadObjectResult, Box<dyn std::error::Error + Send + Sync>> {
+    let (headers, code) = bucket.request(path, Command::HeadObject).await?.response_header().await?;
+    if code == 200 {
+        Ok(HeadObjectResult::from(&headers))
+    } else {
+        Err(format!("S3 head_object error: HTTP {}", code).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio_native_tls::TlsAcceptor;
+
+    /// Response of the fake endpoint: status, headers and body
+    type FakeResponse = (u16, Vec<(&'static str, String)>, String);
+
+    fn test_config(endpoint: &str) -> S3Config {
+        S3Config {
+            bucket: "dicom".to_string(),
+            access_key: Some("test".to_string()),
+            secret_key: Some("test".to_string()),
+            session_token: None,
+            profile: None,
+            region: None,
+            endpoint: Some(endpoint.to_string()),
+            path_style: None,
+            key_prefix: None,
+            storage_class: None,
+            multipart_threshold_mb: None,
+            multipart_part_size_mb: None,
+            max_retries: Some(0),
+            ca_cert_path: None,
+            insecure_tls: None,
+        }
+    }
+
+    async fn answer_request<S, F>(mut socket: S, respond: &F)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+        F: Fn(&str, &str, &[u8]) -> FakeResponse,
+    {
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 65536];
+        let header_end = loop {
+            let n = socket.read(&mut chunk).await.unwrap();
+            if n == 0 {
+                return;
+            }
+            buffer.extend_from_slice(&chunk[..n]);
+            if let Some(position) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+                break position + 4;
+            }
+        };
+        let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+        let content_length = head
+            .lines()
+            .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse().unwrap()))
+            .unwrap_or(0);
+        while buffer.len() < header_end + content_length {
+            let n = socket.read(&mut chunk).await.unwrap();
+            buffer.extend_from_slice(&chunk[..n]);
+        }
+        let mut request_line = head.split(' ');
+        let (method, target) = (request_line.next().unwrap(), request_line.next().unwrap());
+        let (status, headers, body) = respond(method, target, &buffer[header_end..]);
+        let mut response = format!("HTTP/1.1 {} Fake\r\nContent-Length: {}\r\nConnection: close\r\n", status, body.len());
+        for (name, value) in headers {
+            response.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        response.push_str("\r\n");
+        response.push_str(&body);
+        socket.write_all(response.as_bytes()).await.unwrap();
+        socket.shutdown().await.unwrap();
+    }
+
+    /// Fake S3 endpoint answering each request (one per connection) with `respond(method, target, body)`
+    async fn fake_s3<F>(listener: TcpListener, tls: Option<TlsAcceptor>, respond: F)
+    where
+        F: Fn(&str, &str, &[u8]) -> FakeResponse,
+    {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            match &tls {
+                // a client not trusting the certificate gives up in the handshake
+                Some(acceptor) => {
+                    if let Ok(socket) = acceptor.accept(socket).await {
+                        answer_request(socket, &respond).await;
+                    }
+                }
+                None => answer_request(socket, &respond).await,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_ca() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let endpoint = format!("https://localhost:{}", listener.local_addr().unwrap().port());
+        let identity = native_tls::Identity::from_pkcs8(
+            &std::fs::read("__test__/fixtures/tls/cert.pem").unwrap(),
+            &std::fs::read("__test__/fixtures/tls/key.pem").unwrap(),
+        )
+        .unwrap();
+        let acceptor = TlsAcceptor::from(native_tls::TlsAcceptor::new(identity).unwrap());
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = requests.clone();
+        tokio::spawn(fake_s3(listener, Some(acceptor), move |method, target, _| {
+            log.lock().unwrap().push(format!("{} {}", method, target));
+            (200, vec![], "DICM".to_string())
+        }));
+
+        let bucket = build_s3_bucket(&test_config(&endpoint)).unwrap();
+        assert!(s3_get_object(&bucket, "a.dcm").await.is_err());
+        assert!(requests.lock().unwrap().is_empty());
+
+        let config = S3Config { ca_cert_path: Some("__test__/fixtures/tls/cert.pem".to_string()), ..test_config(&endpoint) };
+        let bucket = build_s3_bucket(&config).unwrap();
+        assert_eq!(s3_get_object(&bucket, "a.dcm").await.unwrap(), b"DICM");
+        assert_eq!(*requests.lock().unwrap(), vec!["GET /dicom/a.dcm"]);
+
+        let config = S3Config { ca_cert_path: Some("Cargo.toml".to_string()), ..test_config(&endpoint) };
+        assert!(build_s3_bucket(&config).is_err());
+    }
+
+    #[test]
+    fn test_sanitize_metadata() {
+        assert_eq!(s3_metadata_key("SOPClassUID"), "sopclassuid");
+        assert_eq!(s3_metadata_key("(0008,0060)"), "-0008-0060-");
+        assert_eq!(s3_sanitize_value("CT\\MR "), "CT/MR");
+        assert_eq!(s3_sanitize_value("1.2.840\0"), "1.2.840");
+        assert_eq!(s3_sanitize_value("Müller^Hans"), "M_ller_Hans");
+        assert_eq!(s3_sanitize_value(&"x".repeat(300)).len(), 256);
+        let tags = vec![("Modality".to_string(), "CT".to_string()), ("Body Part".to_string(), "A/B".to_string())];
+        assert_eq!(s3_tagging_header(&tags), "Modality=CT&Body%20Part=A%2FB");
+    }
+
+    #[test]
+    fn test_verify_etag() {
+        let md5 = format!("{:x}", md5::compute(b"data"));
+        assert!(verify_etag("key", Some(&format!("\"{}\"", md5)), &md5).is_ok());
+        assert!(verify_etag("key", Some("\"00000000000000000000000000000000\""), &md5).is_err());
+        // Not MD5 based (SSE-KMS) or missing: accepted
+        assert!(verify_etag("key", Some("\"kms-generated-etag\""), &md5).is_ok());
+        assert!(verify_etag("key", None, &md5).is_ok());
+        // Multipart ETags carry the number of parts
+        assert!(verify_etag("key", Some("\"abc-2\""), "abc-2").is_ok());
+        assert!(verify_etag("key", Some("\"abd-2\""), "abc-2").is_err());
+    }
+
+    /// Multipart upload of two parts against a fake endpoint answering the completion with `complete`;
+    /// returns the result and the requests after the parts
+    async fn multipart_upload_with(complete: FakeResponse) -> (bool, Vec<String>) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let endpoint = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = requests.clone();
+        tokio::spawn(fake_s3(listener, None, move |method, target, body| {
+            log.lock().unwrap().push(format!("{} {}", method, target));
+            match method {
+                "POST" if target.ends_with("?uploads") => (
+                    200,
+                    vec![],
+                    "<InitiateMultipartUploadResult><Bucket>dicom</Bucket><Key>big.dcm</Key>\
+                     <UploadId>U1</UploadId></InitiateMultipartUploadResult>"
+                        .to_string(),
+                ),
+                "PUT" => (200, vec![("ETag", format!("\"{:x}\"", md5::compute(body)))], String::new()),
+                "POST" => complete.clone(),
+                _ => (204, vec![], String::new()),
+            }
+        }));
+
+        let bucket = build_s3_bucket(&test_config(&endpoint)).unwrap();
+        let options = S3UploadOptions {
+            multipart_threshold: 0,
+            part_size: MIN_PART_SIZE,
+            max_retries: 0,
+            storage_class: None,
+        };
+        let data = vec![7u8; MIN_PART_SIZE + 1];
+        let result = s3_put_object_multipart(&bucket, "big.dcm", &data, &options, &ObjectAttributes::default()).await;
+        let requests = requests.lock().unwrap().clone();
+        assert_eq!(
+            requests[..3],
+            [
+                "POST /dicom/big.dcm?uploads",
+                "PUT /dicom/big.dcm?partNumber=1&uploadId=U1",
+                "PUT /dicom/big.dcm?partNumber=2&uploadId=U1",
+            ]
+        );
+        (result.is_ok(), requests[3..].to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_multipart_cleanup() {
+        let part_digest = md5::compute(vec![7u8; MIN_PART_SIZE]);
+        let last_digest = md5::compute([7u8]);
+        let etag = format!("{:x}-2", md5::compute([part_digest.0, last_digest.0].concat()));
+        let completed = |etag: &str| {
+            let body = format!("<CompleteMultipartUploadResult><ETag>&quot;{}&quot;</ETag></CompleteMultipartUploadResult>", etag);
+            (200, vec![], body)
+        };
+
+        let (ok, requests) = multipart_upload_with(completed(&etag)).await;
+        assert!(ok);
+        assert_eq!(requests, ["POST /dicom/big.dcm?uploadId=U1"]);
+
+        // completion fails: the upload is aborted
+        let (ok, requests) = multipart_upload_with((500, vec![], "InternalError".to_string())).await;
+        assert!(!ok);
+        assert_eq!(requests, ["POST /dicom/big.dcm?uploadId=U1", "DELETE /dicom/big.dcm?uploadId=U1"]);
+
+        // the completed object does not match: it is deleted
+        let (ok, requests) = multipart_upload_with(completed("00000000000000000000000000000000-2")).await;
+        assert!(!ok);
+        assert_eq!(requests, ["POST /dicom/big.dcm?uploadId=U1", "DELETE /dicom/big.dcm"]);
+    }
+
+    #[tokio::test]
+    async fn test_retries_only_transient_errors() {
+        let attempts = std::sync::atomic::AtomicU32::new(0);
+        let result: Result<(), _> = s3_with_retries(2, "test", || async {
+            attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Err(Box::new(S3Error::HttpFailWithBody(503, String::new())) as Box<dyn std::error::Error + Send + Sync>)
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 3);
+
+        attempts.store(0, std::sync::atomic::Ordering::SeqCst);
+        let result: Result<(), _> = s3_with_retries(2, "test", || async {
+            attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Err(Box::new(S3Error::HttpFailWithBody(403, String::new())) as Box<dyn std::error::Error + Send + Sync>)
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+}
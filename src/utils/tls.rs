@@ -0,0 +1,62 @@
+use tokio::net::TcpStream;
+use tokio_native_tls::{native_tls, TlsConnector, TlsStream};
+
+/// TLS configuration of a DICOM association (PS3.15 B.1 TLS Secure Transport Connection Profile)
+#[derive(Debug, Clone, Default)]
+#[napi(object)]
+pub struct TlsOptions {
+    /// PEM file with CA certificates trusted in addition to the system roots
+    pub ca_cert_path: Option<String>,
+    /// PEM file with the client certificate, for peers requiring mutual authentication
+    pub cert_path: Option<String>,
+    /// PEM file with the PKCS#8 private key of the client certificate
+    pub key_path: Option<String>,
+    /// Host name the peer certificate is verified against (default: host of the address)
+    pub server_name: Option<String>,
+    /// Accept invalid certificates and host names (testing only)
+    pub insecure: Option<bool>,
+}
+
+pub(crate) fn read_pem(path: &str, what: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("Could not read {} '{}': {}", what, path, e))
+}
+
+/// Build a TLS connector from the configuration
+pub fn tls_connector(options: &TlsOptions) -> Result<TlsConnector, String> {
+    let mut builder = native_tls::TlsConnector::builder();
+    if let Some(path) = &options.ca_cert_path {
+        let certificates = native_tls::Certificate::stack_from_pem(&read_pem(path, "CA certificate")?)
+            .map_err(|e| format!("Invalid CA certificate '{}': {}", path, e))?;
+        for certificate in certificates {
+            builder.add_root_certificate(certificate);
+        }
+    }
+    match (&options.cert_path, &options.key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let identity = native_tls::Identity::from_pkcs8(
+                &read_pem(cert_path, "client certificate")?,
+                &read_pem(key_path, "client key")?,
+            )
+            .map_err(|e| format!("Invalid client certificate '{}': {}", cert_path, e))?;
+            builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => return Err("certPath and keyPath must be given together".to_string()),
+    }
+    if options.insecure.unwrap_or(false) {
+        builder.danger_accept_invalid_certs(true).danger_accept_invalid_hostnames(true);
+    }
+    builder
+        .build()
+        .map(TlsConnector::from)
+        .map_err(|e| format!("Failed to configure TLS: {}", e))
+}
+
+/// Run the TLS handshake on a connected socket; `host` is verified unless `serverName` overrides it
+pub async fn connect_tls(socket: TcpStream, host: &str, options: &TlsOptions) -> Result<TlsStream<TcpStream>, String> {
+    let server_name = options.server_name.as_deref().unwrap_or(host);
+    tls_connector(options)?
+        .connect(server_name, socket)
+        .await
+        .map_err(|e| format!("TLS handshake with {} failed: {}", server_name, e))
+}
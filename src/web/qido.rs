@@ -9,6 +9,8 @@ use tokio::runtime::Runtime;
 use tokio::sync::RwLock;
 use warp::Filter;
 
+use crate::index::IndexDb;
+
 lazy_static::lazy_static! {
     // Global tokio runtime
     static ref RUNTIME: Runtime = Runtime::new().unwrap();
@@ -484,6 +486,10 @@ pub struct QidoServerConfig {
     /// If not specified, allows all origins (*) when CORS is enabled
     pub cors_allowed_origins: Option<String>,
     
+    /// Embedded index (see `StoreScp` option `indexPath`) used to answer queries
+    /// for which no handler is registered
+    pub index_path: Option<String>,
+    
     /// Enable verbose logging for debugging
     pub verbose: Option<bool>,
 }
@@ -591,8 +597,12 @@ impl QidoServer {
         let config = config.unwrap_or(QidoServerConfig {
             enable_cors: Some(false),
             cors_allowed_origins: None,
+            index_path: None,
             verbose: Some(false),
         });
+        if let Some(index_path) = &config.index_path {
+            crate::index::open_index(index_path).map_err(Error::from_reason)?;
+        }
         
         Ok(Self {
             port,
@@ -658,6 +668,10 @@ impl QidoServer {
         let series_handler = self.search_for_series_handler.clone();
         let study_instances_handler = self.search_for_study_instances_handler.clone();
         let series_instances_handler = self.search_for_series_instances_handler.clone();
+        let index = match &config.index_path {
+            Some(index_path) => Some(crate::index::open_index(index_path).map_err(Error::from_reason)?),
+            None => None,
+        };
         
         let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
         self.shutdown_tx = Some(shutdown_tx);
@@ -698,11 +712,17 @@ impl QidoServer {
                 warp::cors().allow_any_origin()
             };
             
+            let studies_index = index.clone();
+            let series_index = index.clone();
+            let study_instances_index = index.clone();
+            let series_instances_index = index;
+            
             // GET /studies - Search for Studies
             let studies_route = warp::path!("studies")
                 .and(warp::get())
                 .and(warp::query::<HashMap<String, String>>())
                 .and(warp::any().map(move || studies_handler.clone()))
+                .and(warp::any().map(move || studies_index.clone()))
                 .and_then(handle_search_for_studies);
             
             // GET /studies/{StudyInstanceUID}/series - Search for Series
@@ -710,6 +730,7 @@ impl QidoServer {
                 .and(warp::get())
                 .and(warp::query::<HashMap<String, String>>())
                 .and(warp::any().map(move || series_handler.clone()))
+                .and(warp::any().map(move || series_index.clone()))
                 .and_then(handle_search_for_series);
             
             // GET /studies/{StudyInstanceUID}/instances - Search for Instances in Study
@@ -717,6 +738,7 @@ impl QidoServer {
                 .and(warp::get())
                 .and(warp::query::<HashMap<String, String>>())
                 .and(warp::any().map(move || study_instances_handler.clone()))
+                .and(warp::any().map(move || study_instances_index.clone()))
                 .and_then(handle_search_for_study_instances);
             
             // GET /studies/{StudyInstanceUID}/series/{SeriesInstanceUID}/instances - Search for Instances in Series
@@ -724,6 +746,7 @@ impl QidoServer {
                 .and(warp::get())
                 .and(warp::query::<HashMap<String, String>>())
                 .and(warp::any().map(move || series_instances_handler.clone()))
+                .and(warp::any().map(move || series_instances_index.clone()))
                 .and_then(handle_search_for_series_instances);
             
             let routes = studies_route
@@ -759,16 +782,55 @@ impl QidoServer {
 // Route Handlers - Each properly typed for their query level
 // ============================================================================
 
+/// Convert query string parameters to JSON, typing `limit`, `offset` and `fuzzymatching`
+/// so they deserialize into the query structs instead of failing the whole query
+fn query_params_value(params: &HashMap<String, String>) -> serde_json::Value {
+    let map = params
+        .iter()
+        .map(|(key, value)| {
+            let value = match key.as_str() {
+                "limit" | "offset" => value.parse::<u32>().map(serde_json::Value::from).unwrap_or(serde_json::Value::Null),
+                "fuzzymatching" => serde_json::Value::Bool(value == "true"),
+                _ => serde_json::Value::String(value.clone()),
+            };
+            (key.clone(), value)
+        })
+        .collect();
+    serde_json::Value::Object(map)
+}
+
+/// Reply with results from the embedded index
+fn index_reply(
+    results: std::result::Result<Vec<DicomJsonAttributes>, String>,
+) -> warp::reply::WithStatus<warp::reply::Json> {
+    match results {
+        Ok(results) => warp::reply::with_status(warp::reply::json(&results), warp::http::StatusCode::OK),
+        Err(e) => warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({"error": format!("Index query failed: {}", e)})),
+            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        ),
+    }
+}
+
 /// Handler for GET /studies - Search for Studies
 async fn handle_search_for_studies(
     params: HashMap<String, String>,
     handler: Arc<RwLock<Option<Arc<SearchForStudiesHandler>>>>,
+    index: Option<Arc<IndexDb>>,
 ) -> std::result::Result<warp::reply::WithStatus<warp::reply::Json>, warp::Rejection> {
+    // Parse query parameters into SearchForStudiesQuery
+    let query: SearchForStudiesQuery = serde_json::from_value(query_params_value(&params))
+        .unwrap_or_default();
+    
     let handler_lock = handler.read().await;
     let handler_arc = match &*handler_lock {
         Some(h) => h.clone(),
         None => {
             drop(handler_lock);
+            // Without a registered handler, answer from the embedded index if configured
+            if let Some(index) = index {
+                return Ok(index_reply(index.run(move |db| db.search_studies(&query)).await));
+            }
             return Ok(warp::reply::with_status(
                 warp::reply::json(&serde_json::json!({"error": "No handler registered for Search for Studies"})),
                 warp::http::StatusCode::NOT_IMPLEMENTED,
@@ -777,10 +839,6 @@ async fn handle_search_for_studies(
     };
     drop(handler_lock);
     
-    // Parse query parameters into SearchForStudiesQuery
-    let query: SearchForStudiesQuery = serde_json::from_value(serde_json::to_value(&params).unwrap())
-        .unwrap_or_default();
-    
     // Call JS callback with async support
     let promise = handler_arc.call_async(Ok(query));
     
@@ -818,12 +876,28 @@ async fn handle_search_for_series(
     study_uid: String,
     params: HashMap<String, String>,
     handler: Arc<RwLock<Option<Arc<SearchForSeriesHandler>>>>,
+    index: Option<Arc<IndexDb>>,
 ) -> std::result::Result<warp::reply::WithStatus<warp::reply::Json>, warp::Rejection> {
+    // Parse query with StudyInstanceUID from path
+    let mut all_params = params.clone();
+    all_params.insert("StudyInstanceUID".to_string(), study_uid);
+    let query: SearchForSeriesQuery = serde_json::from_value(query_params_value(&all_params))
+        .unwrap_or_else(|_| {
+            SearchForSeriesQuery {
+                study_instance_uid: all_params.get("StudyInstanceUID").unwrap().clone(),
+                ..Default::default()
+            }
+        });
+    
     let handler_lock = handler.read().await;
     let handler_arc = match &*handler_lock {
         Some(h) => h.clone(),
         None => {
             drop(handler_lock);
+            // Without a registered handler, answer from the embedded index if configured
+            if let Some(index) = index {
+                return Ok(index_reply(index.run(move |db| db.search_series(&query)).await));
+            }
             return Ok(warp::reply::with_status(
                 warp::reply::json(&serde_json::json!({"error": "No handler registered for Search for Series"})),
                 warp::http::StatusCode::NOT_IMPLEMENTED,
@@ -832,16 +906,6 @@ async fn handle_search_for_series(
     };
     drop(handler_lock);
     
-    // Parse query with StudyInstanceUID from path
-    let mut all_params = params.clone();
-    all_params.insert("StudyInstanceUID".to_string(), study_uid);
-    let query: SearchForSeriesQuery = serde_json::from_value(serde_json::to_value(&all_params).unwrap())
-        .unwrap_or_else(|_| {
-            let mut q = SearchForSeriesQuery::default();
-            q.study_instance_uid = all_params.get("StudyInstanceUID").unwrap().clone();
-            q
-        });
-    
     // Call JS callback with async support
     let promise = handler_arc.call_async(Ok(query));
     
@@ -879,12 +943,27 @@ async fn handle_search_for_study_instances(
     study_uid: String,
     params: HashMap<String, String>,
     handler: Arc<RwLock<Option<Arc<SearchForStudyInstancesHandler>>>>,
+    index: Option<Arc<IndexDb>>,
 ) -> std::result::Result<warp::reply::WithStatus<warp::reply::Json>, warp::Rejection> {
+    let mut all_params = params.clone();
+    all_params.insert("StudyInstanceUID".to_string(), study_uid);
+    let query: SearchForStudyInstancesQuery = serde_json::from_value(query_params_value(&all_params))
+        .unwrap_or_else(|_| {
+            SearchForStudyInstancesQuery {
+                study_instance_uid: all_params.get("StudyInstanceUID").unwrap().clone(),
+                ..Default::default()
+            }
+        });
+    
     let handler_lock = handler.read().await;
     let handler_arc = match &*handler_lock {
         Some(h) => h.clone(),
         None => {
             drop(handler_lock);
+            // Without a registered handler, answer from the embedded index if configured
+            if let Some(index) = index {
+                return Ok(index_reply(index.run(move |db| db.search_study_instances(&query)).await));
+            }
             return Ok(warp::reply::with_status(
                 warp::reply::json(&serde_json::json!({"error": "No handler registered for Search for Study Instances"})),
                 warp::http::StatusCode::NOT_IMPLEMENTED,
@@ -893,15 +972,6 @@ async fn handle_search_for_study_instances(
     };
     drop(handler_lock);
     
-    let mut all_params = params.clone();
-    all_params.insert("StudyInstanceUID".to_string(), study_uid);
-    let query: SearchForStudyInstancesQuery = serde_json::from_value(serde_json::to_value(&all_params).unwrap())
-        .unwrap_or_else(|_| {
-            let mut q = SearchForStudyInstancesQuery::default();
-            q.study_instance_uid = all_params.get("StudyInstanceUID").unwrap().clone();
-            q
-        });
-    
     // Call JS callback with async support
     let promise = handler_arc.call_async(Ok(query));
     
@@ -940,12 +1010,29 @@ async fn handle_search_for_series_instances(
     series_uid: String,
     params: HashMap<String, String>,
     handler: Arc<RwLock<Option<Arc<SearchForSeriesInstancesHandler>>>>,
+    index: Option<Arc<IndexDb>>,
 ) -> std::result::Result<warp::reply::WithStatus<warp::reply::Json>, warp::Rejection> {
+    let mut all_params = params.clone();
+    all_params.insert("StudyInstanceUID".to_string(), study_uid);
+    all_params.insert("SeriesInstanceUID".to_string(), series_uid);
+    let query: SearchForSeriesInstancesQuery = serde_json::from_value(query_params_value(&all_params))
+        .unwrap_or_else(|_| {
+            SearchForSeriesInstancesQuery {
+                study_instance_uid: all_params.get("StudyInstanceUID").unwrap().clone(),
+                series_instance_uid: all_params.get("SeriesInstanceUID").unwrap().clone(),
+                ..Default::default()
+            }
+        });
+    
     let handler_lock = handler.read().await;
     let handler_arc = match &*handler_lock {
         Some(h) => h.clone(),
         None => {
             drop(handler_lock);
+            // Without a registered handler, answer from the embedded index if configured
+            if let Some(index) = index {
+                return Ok(index_reply(index.run(move |db| db.search_series_instances(&query)).await));
+            }
             return Ok(warp::reply::with_status(
                 warp::reply::json(&serde_json::json!({"error": "No handler registered for Search for Series Instances"})),
                 warp::http::StatusCode::NOT_IMPLEMENTED,
@@ -954,17 +1041,6 @@ async fn handle_search_for_series_instances(
     };
     drop(handler_lock);
     
-    let mut all_params = params.clone();
-    all_params.insert("StudyInstanceUID".to_string(), study_uid);
-    all_params.insert("SeriesInstanceUID".to_string(), series_uid);
-    let query: SearchForSeriesInstancesQuery = serde_json::from_value(serde_json::to_value(&all_params).unwrap())
-        .unwrap_or_else(|_| {
-            let mut q = SearchForSeriesInstancesQuery::default();
-            q.study_instance_uid = all_params.get("StudyInstanceUID").unwrap().clone();
-            q.series_instance_uid = all_params.get("SeriesInstanceUID").unwrap().clone();
-            q
-        });
-    
     // Call JS callback with async support
     let promise = handler_arc.call_async(Ok(query));
     
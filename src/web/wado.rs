@@ -3,12 +3,11 @@ use napi_derive::napi;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::runtime::Runtime;
-use tokio::fs::File;
-use tokio::io::AsyncReadExt;
 use warp::{Filter, Reply, reply::Response, http::StatusCode};
 use warp::hyper::body::Bytes;
 use dicom_object::open_file;
 
+use crate::storage::{ContentAddressedBackend, FilesystemBackend, S3Backend, StorageBackend};
 use crate::utils::S3Config;
 
 lazy_static::lazy_static! {
@@ -127,6 +126,14 @@ pub struct WadoServerConfig {
     /// Default rendering options for thumbnails
     pub thumbnail_options: Option<WadoRenderingOptions>,
     
+    /// Embedded index (see `StoreScp` option `indexPath`) used to look up the
+    /// instances of a study or series instead of scanning the storage
+    pub index_path: Option<String>,
+    
+    /// Resolve references written by content-addressed storage targets
+    /// (see `StorageTarget.contentAddressed`)
+    pub content_addressed: Option<bool>,
+    
     /// Enable verbose logging
     pub verbose: Option<bool>,
 }
@@ -153,13 +160,21 @@ impl WadoServer {
                 }
             }
             WadoStorageType::S3 => {
-                if config.s3_config.is_none() {
-                    return Err(Error::from_reason(
-                        "s3_config is required for S3 storage"
-                    ));
+                match &config.s3_config {
+                    Some(s3_config) => {
+                        crate::utils::build_s3_bucket(s3_config).map_err(Error::from_reason)?;
+                    }
+                    None => {
+                        return Err(Error::from_reason(
+                            "s3_config is required for S3 storage"
+                        ));
+                    }
                 }
             }
         }
+        if let Some(index_path) = &config.index_path {
+            crate::index::open_index(index_path).map_err(Error::from_reason)?;
+        }
         
         Ok(Self {
             port,
@@ -416,55 +431,43 @@ impl WadoServer {
 // Storage Backend Functions
 // ============================================================================
 
-async fn load_dicom_file(
-    study_uid: &str,
-    series_uid: &str,
-    instance_uid: &str,
-    config: Arc<WadoServerConfig>,
-) -> std::result::Result<Vec<u8>, String> {
-    match config.storage_type {
+/// Storage backend for the configured storage type
+fn wado_storage(config: &WadoServerConfig) -> std::result::Result<Arc<dyn StorageBackend>, String> {
+    let backend: Arc<dyn StorageBackend> = match config.storage_type {
         WadoStorageType::Filesystem => {
             let base_path = config.base_path.as_ref()
                 .ok_or_else(|| "Base path not configured for filesystem storage".to_string())?;
-            
-            let file_path = format!(
-                "{}/{}/{}/{}.dcm",
-                base_path, study_uid, series_uid, instance_uid
-            );
-            
-            if config.verbose.unwrap_or(false) {
-                println!("Loading DICOM file: {}", file_path);
-            }
-            
-            let mut file = File::open(&file_path).await
-                .map_err(|e| format!("Failed to open file {}: {}", file_path, e))?;
-            
-            let mut buffer = Vec::new();
-            file.read_to_end(&mut buffer).await
-                .map_err(|e| format!("Failed to read file: {}", e))?;
-            
-            Ok(buffer)
+            Arc::new(FilesystemBackend { root: base_path.clone(), ..Default::default() })
         }
         WadoStorageType::S3 => {
             let s3_config = config.s3_config.as_ref()
                 .ok_or_else(|| "S3 config not configured for S3 storage".to_string())?;
-            
-            let bucket = crate::utils::s3::build_s3_bucket(s3_config);
-            
-            let object_path = format!(
-                "{}/{}/{}.dcm",
-                study_uid, series_uid, instance_uid
-            );
-            
-            if config.verbose.unwrap_or(false) {
-                println!("Loading DICOM file from S3: {}", object_path);
-            }
-            
-            crate::utils::s3::s3_get_object(&bucket, &object_path)
-                .await
-                .map_err(|e| format!("Failed to get object from S3: {}", e))
+            Arc::new(S3Backend::new(s3_config)?)
         }
+    };
+    if config.content_addressed.unwrap_or(false) {
+        return Ok(Arc::new(ContentAddressedBackend::new(backend)));
+    }
+    Ok(backend)
+}
+
+async fn load_dicom_file(
+    study_uid: &str,
+    series_uid: &str,
+    instance_uid: &str,
+    config: Arc<WadoServerConfig>,
+) -> std::result::Result<Vec<u8>, String> {
+    let storage = wado_storage(&config)?;
+    let key = format!("{}/{}/{}.dcm", study_uid, series_uid, instance_uid);
+
+    if config.verbose.unwrap_or(false) {
+        println!("Loading DICOM file: {}", storage.location(&key));
     }
+
+    storage
+        .read_file(&key)
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", storage.location(&key), e))
 }
 
 async fn load_dicom_metadata(
@@ -532,64 +535,28 @@ async fn retrieve_study_handler(
     // Determine response format from Accept header
     let media_type = parse_accept_header(accept.as_deref());
     
-    match config.storage_type {
-        WadoStorageType::Filesystem => {
-            // Scan filesystem for all instances in study
-            let instances = scan_study_instances(
-                config.base_path.as_ref().unwrap(),
-                &study_uid,
-            ).await.map_err(|e| {
-                warp::reject::custom(WadoError { message: e })
-            })?;
-            
-            if instances.is_empty() {
-                return Ok(warp::reply::with_status(
-                    warp::reply::json(&serde_json::json!({
-                        "error": "Study not found or contains no instances"
-                    })),
-                    StatusCode::NOT_FOUND,
-                ).into_response());
-            }
-            
-            if config.verbose.unwrap_or(false) {
-                println!("Found {} instances in study {}", instances.len(), study_uid);
-            }
-            
-            // Build multipart response
-            build_multipart_response(instances, config.clone(), media_type)
-                .await
-                .map_err(|e| warp::reject::custom(WadoError { message: e }))
-        }
-        WadoStorageType::S3 => {
-            let s3_config = config.s3_config.as_ref()
-                .ok_or_else(|| warp::reject::custom(WadoError { 
-                    message: "S3 config not configured".to_string() 
-                }))?;
-            
-            // Scan S3 for all instances in study
-            let instances = scan_study_instances_s3(s3_config, &study_uid)
-                .await
-                .map_err(|e| warp::reject::custom(WadoError { message: e }))?;
-            
-            if instances.is_empty() {
-                return Ok(warp::reply::with_status(
-                    warp::reply::json(&serde_json::json!({
-                        "error": "Study not found or contains no instances"
-                    })),
-                    StatusCode::NOT_FOUND,
-                ).into_response());
-            }
-            
-            if config.verbose.unwrap_or(false) {
-                println!("Found {} instances in study {} (S3)", instances.len(), study_uid);
-            }
-            
-            // Build multipart response
-            build_multipart_response(instances, config.clone(), media_type)
-                .await
-                .map_err(|e| warp::reject::custom(WadoError { message: e }))
-        }
+    // Find all instances in study
+    let instances = find_instances(&config, &study_uid, None)
+        .await
+        .map_err(|e| warp::reject::custom(WadoError { message: e }))?;
+    
+    if instances.is_empty() {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "error": "Study not found or contains no instances"
+            })),
+            StatusCode::NOT_FOUND,
+        ).into_response());
+    }
+    
+    if config.verbose.unwrap_or(false) {
+        println!("Found {} instances in study {}", instances.len(), study_uid);
     }
+    
+    // Build multipart response
+    build_multipart_response(instances, config.clone(), media_type)
+        .await
+        .map_err(|e| warp::reject::custom(WadoError { message: e }))
 }
 
 async fn retrieve_series_handler(
@@ -605,65 +572,28 @@ async fn retrieve_series_handler(
     // Determine response format from Accept header
     let media_type = parse_accept_header(accept.as_deref());
     
-    match config.storage_type {
-        WadoStorageType::Filesystem => {
-            // Scan filesystem for all instances in series
-            let instances = scan_series_instances(
-                config.base_path.as_ref().unwrap(),
-                &study_uid,
-                &series_uid,
-            ).await.map_err(|e| {
-                warp::reject::custom(WadoError { message: e })
-            })?;
-            
-            if instances.is_empty() {
-                return Ok(warp::reply::with_status(
-                    warp::reply::json(&serde_json::json!({
-                        "error": "Series not found or contains no instances"
-                    })),
-                    StatusCode::NOT_FOUND,
-                ).into_response());
-            }
-            
-            if config.verbose.unwrap_or(false) {
-                println!("Found {} instances in series {}/{}", instances.len(), study_uid, series_uid);
-            }
-            
-            // Build multipart response
-            build_multipart_response(instances, config.clone(), media_type)
-                .await
-                .map_err(|e| warp::reject::custom(WadoError { message: e }))
-        }
-        WadoStorageType::S3 => {
-            let s3_config = config.s3_config.as_ref()
-                .ok_or_else(|| warp::reject::custom(WadoError { 
-                    message: "S3 config not configured".to_string() 
-                }))?;
-            
-            // Scan S3 for all instances in series
-            let instances = scan_series_instances_s3(s3_config, &study_uid, &series_uid)
-                .await
-                .map_err(|e| warp::reject::custom(WadoError { message: e }))?;
-            
-            if instances.is_empty() {
-                return Ok(warp::reply::with_status(
-                    warp::reply::json(&serde_json::json!({
-                        "error": "Series not found or contains no instances"
-                    })),
-                    StatusCode::NOT_FOUND,
-                ).into_response());
-            }
-            
-            if config.verbose.unwrap_or(false) {
-                println!("Found {} instances in series {}/{} (S3)", instances.len(), study_uid, series_uid);
-            }
-            
-            // Build multipart response
-            build_multipart_response(instances, config.clone(), media_type)
-                .await
-                .map_err(|e| warp::reject::custom(WadoError { message: e }))
-        }
+    // Find all instances in series
+    let instances = find_instances(&config, &study_uid, Some(&series_uid))
+        .await
+        .map_err(|e| warp::reject::custom(WadoError { message: e }))?;
+    
+    if instances.is_empty() {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "error": "Series not found or contains no instances"
+            })),
+            StatusCode::NOT_FOUND,
+        ).into_response());
+    }
+    
+    if config.verbose.unwrap_or(false) {
+        println!("Found {} instances in series {}/{}", instances.len(), study_uid, series_uid);
     }
+    
+    // Build multipart response
+    build_multipart_response(instances, config.clone(), media_type)
+        .await
+        .map_err(|e| warp::reject::custom(WadoError { message: e }))
 }
 
 async fn retrieve_instance_handler(
@@ -743,29 +673,10 @@ async fn retrieve_study_metadata_handler(
         println!("Retrieve study metadata: {}", study_uid);
     }
     
-    // Scan for all instances based on storage type
-    let instances = match config.storage_type {
-        WadoStorageType::Filesystem => {
-            let base_path = config.base_path.as_ref()
-                .ok_or_else(|| warp::reject::custom(WadoError { 
-                    message: "Base path not configured".to_string() 
-                }))?;
-            
-            scan_study_instances(base_path, &study_uid)
-                .await
-                .map_err(|e| warp::reject::custom(WadoError { message: e }))?
-        }
-        WadoStorageType::S3 => {
-            let s3_config = config.s3_config.as_ref()
-                .ok_or_else(|| warp::reject::custom(WadoError { 
-                    message: "S3 configuration not provided".to_string() 
-                }))?;
-            
-            scan_study_instances_s3(s3_config, &study_uid)
-                .await
-                .map_err(|e| warp::reject::custom(WadoError { message: e }))?
-        }
-    };
+    // Find all instances in study
+    let instances = find_instances(&config, &study_uid, None)
+        .await
+        .map_err(|e| warp::reject::custom(WadoError { message: e }))?;
     
     if instances.is_empty() {
         return Ok(warp::reply::with_status(
@@ -819,29 +730,10 @@ async fn retrieve_series_metadata_handler(
         println!("Retrieve series metadata: {}/{}", study_uid, series_uid);
     }
     
-    // Scan for all instances in series based on storage type
-    let instances = match config.storage_type {
-        WadoStorageType::Filesystem => {
-            let base_path = config.base_path.as_ref()
-                .ok_or_else(|| warp::reject::custom(WadoError { 
-                    message: "Base path not configured".to_string() 
-                }))?;
-            
-            scan_series_instances(base_path, &study_uid, &series_uid)
-                .await
-                .map_err(|e| warp::reject::custom(WadoError { message: e }))?
-        }
-        WadoStorageType::S3 => {
-            let s3_config = config.s3_config.as_ref()
-                .ok_or_else(|| warp::reject::custom(WadoError { 
-                    message: "S3 configuration not provided".to_string() 
-                }))?;
-            
-            scan_series_instances_s3(s3_config, &study_uid, &series_uid)
-                .await
-                .map_err(|e| warp::reject::custom(WadoError { message: e }))?
-        }
-    };
+    // Find all instances in series
+    let instances = find_instances(&config, &study_uid, Some(&series_uid))
+        .await
+        .map_err(|e| warp::reject::custom(WadoError { message: e }))?;
     
     if instances.is_empty() {
         return Ok(warp::reply::with_status(
@@ -1371,132 +1263,51 @@ async fn extract_frames(
     }
 }
 
-/// Scans filesystem storage for all instances in a study
-async fn scan_study_instances(
-    base_path: &str,
+/// List the (study, series, instance) UIDs of a study, or of one series in it.
+/// Uses the embedded index when `indexPath` is configured, otherwise scans the storage.
+async fn find_instances(
+    config: &WadoServerConfig,
     study_uid: &str,
+    series_uid: Option<&str>,
 ) -> std::result::Result<Vec<(String, String, String)>, String> {
-    let mut instances = Vec::new();
-    let study_path = format!("{}/{}", base_path, study_uid);
-    
-    let mut entries = tokio::fs::read_dir(&study_path).await
-        .map_err(|e| format!("Failed to read study directory: {}", e))?;
-    
-    while let Some(series_entry) = entries.next_entry().await
-        .map_err(|e| format!("Failed to read series entry: {}", e))? {
-        
-        if !series_entry.file_type().await
-            .map_err(|e| format!("Failed to get file type: {}", e))?.is_dir() {
-            continue;
-        }
-        
-        let series_uid = series_entry.file_name().to_string_lossy().to_string();
-        let series_path = series_entry.path();
-        
-        let mut series_entries = tokio::fs::read_dir(&series_path).await
-            .map_err(|e| format!("Failed to read series directory: {}", e))?;
-        
-        while let Some(instance_entry) = series_entries.next_entry().await
-            .map_err(|e| format!("Failed to read instance entry: {}", e))? {
-            
-            let file_name = instance_entry.file_name().to_string_lossy().to_string();
-            if file_name.ends_with(".dcm") {
-                let instance_uid = file_name.trim_end_matches(".dcm").to_string();
-                instances.push((study_uid.to_string(), series_uid.clone(), instance_uid));
-            }
-        }
+    if let Some(index_path) = &config.index_path {
+        let index = crate::index::open_index(index_path)?;
+        let (study_uid, series_uid) = (study_uid.to_string(), series_uid.map(str::to_string));
+        return Ok(index
+            .run(move |db| db.find_instances(&study_uid, series_uid.as_deref()))
+            .await?
+            .into_iter()
+            .map(|i| (i.study_instance_uid, i.series_instance_uid, i.sop_instance_uid))
+            .collect());
     }
-    
-    Ok(instances)
+    let storage = wado_storage(config)?;
+    scan_instances(storage.as_ref(), study_uid, series_uid).await
 }
 
-/// Scans filesystem storage for all instances in a series
-async fn scan_series_instances(
-    base_path: &str,
+/// Scans the storage for all instances in a study or series.
+/// Keys are expected in the form `{studyUID}/{seriesUID}/{instanceUID}.dcm`.
+async fn scan_instances(
+    storage: &dyn StorageBackend,
     study_uid: &str,
-    series_uid: &str,
+    series_uid: Option<&str>,
 ) -> std::result::Result<Vec<(String, String, String)>, String> {
-    let mut instances = Vec::new();
-    let series_path = format!("{}/{}/{}", base_path, study_uid, series_uid);
-    
-    let mut entries = tokio::fs::read_dir(&series_path).await
-        .map_err(|e| format!("Failed to read series directory: {}", e))?;
-    
-    while let Some(instance_entry) = entries.next_entry().await
-        .map_err(|e| format!("Failed to read instance entry: {}", e))? {
-        
-        let file_name = instance_entry.file_name().to_string_lossy().to_string();
-        if file_name.ends_with(".dcm") {
-            let instance_uid = file_name.trim_end_matches(".dcm").to_string();
-            instances.push((study_uid.to_string(), series_uid.to_string(), instance_uid));
-        }
-    }
-    
-    Ok(instances)
-}
+    let prefix = match series_uid {
+        Some(series_uid) => format!("{}/{}", study_uid, series_uid),
+        None => study_uid.to_string(),
+    };
+    let keys = storage.list_files(&prefix).await
+        .map_err(|e| format!("Failed to list {}: {}", storage.location(&prefix), e))?;
 
-/// Scans S3 storage for all instances in a study
-async fn scan_study_instances_s3(
-    s3_config: &crate::utils::S3Config,
-    study_uid: &str,
-) -> std::result::Result<Vec<(String, String, String)>, String> {
-    let bucket = crate::utils::s3::build_s3_bucket(s3_config);
-    let prefix = format!("{}/", study_uid);
-    
-    // List all objects with the study prefix
-    let objects = crate::utils::s3::s3_list_objects(&bucket, &prefix)
-        .await
-        .map_err(|e| format!("Failed to list S3 objects: {}", e))?;
-    
     let mut instances = Vec::new();
-    
-    for object_key in objects {
-        // Expected format: {studyUID}/{seriesUID}/{instanceUID}.dcm
-        if !object_key.ends_with(".dcm") {
-            continue;
-        }
-        
-        let parts: Vec<&str> = object_key.split('/').collect();
-        if parts.len() >= 3 {
-            let study = parts[0].to_string();
-            let series = parts[1].to_string();
-            let instance = parts[2].trim_end_matches(".dcm").to_string();
-            instances.push((study, series, instance));
+    for key in keys {
+        let parts: Vec<&str> = key.split('/').collect();
+        if let [study, series, file_name] = parts[..] {
+            if let Some(instance) = file_name.strip_suffix(".dcm") {
+                instances.push((study.to_string(), series.to_string(), instance.to_string()));
+            }
         }
     }
-    
-    Ok(instances)
-}
 
-/// Scans S3 storage for all instances in a series
-async fn scan_series_instances_s3(
-    s3_config: &crate::utils::S3Config,
-    study_uid: &str,
-    series_uid: &str,
-) -> std::result::Result<Vec<(String, String, String)>, String> {
-    let bucket = crate::utils::s3::build_s3_bucket(s3_config);
-    let prefix = format!("{}/{}/", study_uid, series_uid);
-    
-    // List all objects with the series prefix
-    let objects = crate::utils::s3::s3_list_objects(&bucket, &prefix)
-        .await
-        .map_err(|e| format!("Failed to list S3 objects: {}", e))?;
-    
-    let mut instances = Vec::new();
-    
-    for object_key in objects {
-        // Expected format: {studyUID}/{seriesUID}/{instanceUID}.dcm
-        if !object_key.ends_with(".dcm") {
-            continue;
-        }
-        
-        let parts: Vec<&str> = object_key.split('/').collect();
-        if parts.len() >= 3 {
-            let instance = parts[2].trim_end_matches(".dcm").to_string();
-            instances.push((study_uid.to_string(), series_uid.to_string(), instance));
-        }
-    }
-    
     Ok(instances)
 }
 
@@ -2035,6 +1846,7 @@ async fn render_dicom_image(
  *   storageType: 'Filesystem' | 'S3';
  *   basePath?: string;  // Required for Filesystem
  *   s3Config?: S3Config;  // Required for S3
+ *   indexPath?: string;  // Embedded index used to list study/series instances
  *   
  *   // CORS configuration
  *   enableCors?: boolean;  // Enable CORS headers (default: false)